@@ -0,0 +1,59 @@
+//! Captures build information for the `version` command: git commit, target triple, enabled cargo features
+//! and the nusb version.
+
+use std::process::Command;
+
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(output.stdout).ok()?.trim().to_string())
+}
+
+/// Locked nusb version, `None` when building without a Cargo.lock next to the manifest.
+fn nusb_version() -> Option<String> {
+    let lock = std::fs::read_to_string("Cargo.lock").ok()?;
+    let mut lines = lock.lines();
+    lines.find(|l| l.trim() == "name = \"nusb\"")?;
+    let version = lines.next()?.trim().strip_prefix("version = ")?;
+    Some(version.trim_matches('"').to_string())
+}
+
+/// Enabled cargo features as named in the manifest, comma separated.
+fn cargo_features() -> String {
+    let mut features = std::env::vars()
+        .filter_map(|(k, _)| {
+            Some(
+                k.strip_prefix("CARGO_FEATURE_")?
+                    .to_lowercase()
+                    .replace('_', "-"),
+            )
+        })
+        .collect::<Vec<_>>();
+    features.sort();
+    features.join(",")
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let unknown = || "unknown".to_string();
+    println!(
+        "cargo:rustc-env=BUILD_GIT_COMMIT={}",
+        git_commit().unwrap_or_else(unknown)
+    );
+    println!(
+        "cargo:rustc-env=BUILD_TARGET={}",
+        std::env::var("TARGET").unwrap_or_else(|_| unknown())
+    );
+    println!("cargo:rustc-env=BUILD_FEATURES={}", cargo_features());
+    println!(
+        "cargo:rustc-env=BUILD_NUSB_VERSION={}",
+        nusb_version().unwrap_or_else(unknown)
+    );
+}
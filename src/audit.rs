@@ -0,0 +1,161 @@
+//! `--audit`: proof of what a command changed, as a bit level diff of the registers it wrote.
+//!
+//! Only registers the command writes are captured: the value before its first write (taken from the command's
+//! own read where there is one, read separately otherwise) and the value read back after the command finished.
+//! Writes that leave a register as it was show up in `registers` but produce no `changes`.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::error::DongleError;
+use crate::policy::Policy;
+use crate::usb4604_ral::{REGISTERS, RegisterBus};
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct RegisterAudit {
+    /// Name from [REGISTERS], `None` for registers not listed there
+    pub name: Option<&'static str>,
+    pub addr: u16,
+    pub before: u8,
+    pub after: u8,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct BitChange {
+    pub name: Option<&'static str>,
+    pub addr: u16,
+    /// Bit number, 0 is the least significant bit
+    pub bit: u8,
+    pub old: bool,
+    pub new: bool,
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize)]
+pub struct AuditReport {
+    /// Every register written, in address order
+    pub registers: Vec<RegisterAudit>,
+    pub changes: Vec<BitChange>,
+}
+
+impl AuditReport {
+    fn new(registers: Vec<RegisterAudit>) -> Self {
+        let changes = registers
+            .iter()
+            .flat_map(|r| {
+                (0..8u8)
+                    .filter(move |bit| (r.before ^ r.after) & (1 << bit) != 0)
+                    .map(move |bit| BitChange {
+                        name: r.name,
+                        addr: r.addr,
+                        bit,
+                        old: r.before & (1 << bit) != 0,
+                        new: r.after & (1 << bit) != 0,
+                    })
+            })
+            .collect();
+        Self { registers, changes }
+    }
+}
+
+/// Forwards all accesses to another bus, remembering the value of every register before its first write.
+pub struct AuditBus<'a> {
+    inner: &'a dyn RegisterBus,
+    /// Last value read from registers not written yet
+    last_read: RefCell<BTreeMap<u16, u8>>,
+    before: RefCell<BTreeMap<u16, u8>>,
+}
+
+impl<'a> AuditBus<'a> {
+    pub fn new(inner: &'a dyn RegisterBus) -> Self {
+        Self {
+            inner,
+            last_read: RefCell::new(BTreeMap::new()),
+            before: RefCell::new(BTreeMap::new()),
+        }
+    }
+
+    /// Reads back every written register and diffs it against its value before the command.
+    pub fn finish(self) -> Result<AuditReport, DongleError> {
+        let registers = self
+            .before
+            .into_inner()
+            .into_iter()
+            .map(|(addr, before)| {
+                Ok(RegisterAudit {
+                    name: REGISTERS.iter().find(|r| r.1 == addr).map(|r| r.0),
+                    addr,
+                    before,
+                    after: self.inner.read_byte(addr)?,
+                })
+            })
+            .collect::<Result<_, DongleError>>()?;
+        Ok(AuditReport::new(registers))
+    }
+}
+
+impl RegisterBus for AuditBus<'_> {
+    fn read_byte(&self, addr: u16) -> Result<u8, DongleError> {
+        let value = self.inner.read_byte(addr)?;
+        if !self.before.borrow().contains_key(&addr) {
+            self.last_read.borrow_mut().insert(addr, value);
+        }
+        Ok(value)
+    }
+
+    fn write_byte(&self, addr: u16, value: u8) -> Result<(), DongleError> {
+        if !self.before.borrow().contains_key(&addr) {
+            let before = match self.last_read.borrow_mut().remove(&addr) {
+                Some(before) => before,
+                None => self.inner.read_byte(addr)?,
+            };
+            self.before.borrow_mut().insert(addr, before);
+        }
+        self.inner.write_byte(addr, value)
+    }
+
+    fn policy(&self) -> Policy {
+        self.inner.policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb4604_ral::{Gpio0_7Dir, Gpio0_7Output, MockBus, SmscReg, write_reg};
+
+    #[test]
+    fn only_written_registers_are_diffed() {
+        let bus = MockBus::new();
+        bus.set(Gpio0_7Dir::ADDR, 0b0000_0001);
+        bus.set(Gpio0_7Output::ADDR, 0b0000_0001);
+        let audit = AuditBus::new(&bus);
+        audit.read_byte(Gpio0_7Dir::ADDR).unwrap();
+        audit.write_byte(Gpio0_7Dir::ADDR, 0b0000_0011).unwrap();
+        audit.write_byte(Gpio0_7Dir::ADDR, 0b0000_0010).unwrap();
+        write_reg(&audit, Gpio0_7Output::from_value(0b0000_0001)).unwrap();
+
+        let report = audit.finish().unwrap();
+        assert_eq!(report.registers.len(), 2);
+        assert_eq!(
+            report.changes,
+            vec![
+                BitChange {
+                    name: Some("Gpio0_7Dir"),
+                    addr: Gpio0_7Dir::ADDR,
+                    bit: 0,
+                    old: true,
+                    new: false,
+                },
+                BitChange {
+                    name: Some("Gpio0_7Dir"),
+                    addr: Gpio0_7Dir::ADDR,
+                    bit: 1,
+                    old: false,
+                    new: true,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Latency micro-benchmark of a dongle (header pin toggle and register read round trips), and side by side
+//! comparison of two dongles, to find marginal units in a batch.
+//!
+//! Every toggle or read is one control transfer, so the numbers mostly reflect the USB path to the dongle:
+//! compare dongles on the same host port and hub for a fair result.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::dongle_hal_revc::{
+    HeaderPin, PinMode, PinState, gpio_header_get, gpio_header_get_mode, gpio_header_set_mode,
+};
+use crate::error::DongleError;
+use crate::sampler::set_header_out;
+use crate::usb4604_ral::{Gpio17_20Output, RegisterBus, read_reg, write_reg};
+
+/// Default number of toggles and reads per dongle.
+pub const DEFAULT_ITERATIONS: usize = 200;
+
+/// A sample taking longer than this many times the median is counted as an outlier.
+pub const OUTLIER_FACTOR: f64 = 3.0;
+
+/// Relative difference between two dongles above which a metric is flagged.
+pub const FLAG_THRESHOLD: f64 = 0.25;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize)]
+pub struct LatencyStats {
+    pub samples: usize,
+    pub min_us: f64,
+    pub median_us: f64,
+    pub mean_us: f64,
+    pub max_us: f64,
+    /// Samples slower than [OUTLIER_FACTOR] times the median
+    pub outliers: usize,
+}
+
+impl LatencyStats {
+    pub fn from_samples(samples: &[Duration]) -> Self {
+        if samples.is_empty() {
+            return Self::default();
+        }
+        let mut us = samples
+            .iter()
+            .map(|d| d.as_secs_f64() * 1e6)
+            .collect::<Vec<_>>();
+        us.sort_by(f64::total_cmp);
+        let median_us = if us.len() % 2 == 0 {
+            (us[us.len() / 2 - 1] + us[us.len() / 2]) / 2.0
+        } else {
+            us[us.len() / 2]
+        };
+        Self {
+            samples: us.len(),
+            min_us: us[0],
+            median_us,
+            mean_us: us.iter().sum::<f64>() / us.len() as f64,
+            max_us: us[us.len() - 1],
+            outliers: us
+                .iter()
+                .filter(|&&t| t > median_us * OUTLIER_FACTOR)
+                .count(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct BenchResult {
+    /// Writes toggling a header pin
+    pub toggle: LatencyStats,
+    /// Reads of the header output register
+    pub read: LatencyStats,
+}
+
+/// Times `iterations` toggles of `pin` and as many register reads, one transfer each.
+///
+/// The pin is temporarily switched to output, its mode and level are restored afterwards.
+pub fn bench(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    iterations: usize,
+) -> Result<BenchResult, DongleError> {
+    let prior_mode = gpio_header_get_mode(bus, pin)?;
+    let prior_out = read_reg::<Gpio17_20Output>(bus)?;
+    gpio_header_set_mode(bus, pin, PinMode::Output)?;
+
+    let mut out = prior_out;
+    let mut high = gpio_header_get(bus, pin)? == PinState::High;
+    let mut toggles = Vec::with_capacity(iterations);
+    let mut reads = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        high = !high;
+        set_header_out(&mut out, pin, high);
+        let start = Instant::now();
+        write_reg(bus, out)?;
+        toggles.push(start.elapsed());
+
+        let start = Instant::now();
+        read_reg::<Gpio17_20Output>(bus)?;
+        reads.push(start.elapsed());
+    }
+
+    write_reg(bus, prior_out)?;
+    gpio_header_set_mode(bus, pin, prior_mode)?;
+    Ok(BenchResult {
+        toggle: LatencyStats::from_samples(&toggles),
+        read: LatencyStats::from_samples(&reads),
+    })
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct MetricComparison {
+    /// e.g. `toggle_median`
+    pub metric: &'static str,
+    pub a_us: f64,
+    pub b_us: f64,
+    /// `(b - a) / a`, positive if the second dongle is slower
+    pub relative: f64,
+    /// Relative difference above [FLAG_THRESHOLD]
+    pub flagged: bool,
+}
+
+/// Compares median, mean and worst case latencies of two benchmark results.
+pub fn compare(a: &BenchResult, b: &BenchResult) -> Vec<MetricComparison> {
+    let metrics = [
+        ("toggle_median", a.toggle.median_us, b.toggle.median_us),
+        ("toggle_mean", a.toggle.mean_us, b.toggle.mean_us),
+        ("toggle_max", a.toggle.max_us, b.toggle.max_us),
+        ("read_median", a.read.median_us, b.read.median_us),
+        ("read_mean", a.read.mean_us, b.read.mean_us),
+        ("read_max", a.read.max_us, b.read.max_us),
+    ];
+    metrics
+        .into_iter()
+        .map(|(metric, a_us, b_us)| {
+            let relative = if a_us > 0.0 {
+                (b_us - a_us) / a_us
+            } else {
+                0.0
+            };
+            MetricComparison {
+                metric,
+                a_us,
+                b_us,
+                relative,
+                flagged: relative.abs() > FLAG_THRESHOLD,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb4604_ral::{Gpio17_20Dir, MockBus, SmscReg};
+
+    #[test]
+    fn stats_compare_and_bench_restores_pin() {
+        let ms = |v: &[u64]| {
+            v.iter()
+                .map(|&m| Duration::from_millis(m))
+                .collect::<Vec<_>>()
+        };
+        let stats = LatencyStats::from_samples(&ms(&[1, 1, 2, 1, 10]));
+        assert_eq!(stats.median_us, 1000.0);
+        assert_eq!(stats.max_us, 10_000.0);
+        assert_eq!(stats.outliers, 1);
+
+        let a = BenchResult {
+            toggle: stats,
+            read: stats,
+        };
+        let mut b = a;
+        b.read.median_us = 1500.0;
+        let comparison = compare(&a, &b);
+        let read_median = comparison
+            .iter()
+            .find(|c| c.metric == "read_median")
+            .unwrap();
+        assert_eq!(read_median.relative, 0.5);
+        assert!(read_median.flagged);
+        assert_eq!(comparison.iter().filter(|c| c.flagged).count(), 1);
+
+        let bus = MockBus::new();
+        bus.set(Gpio17_20Output::ADDR, 0b0000_1000);
+        let result = bench(&bus, HeaderPin::P0, 10).unwrap();
+        assert_eq!(result.toggle.samples, 10);
+        assert_eq!(result.read.samples, 10);
+        assert_eq!(bus.get(Gpio17_20Output::ADDR), 0b0000_1000);
+        assert_eq!(bus.get(Gpio17_20Dir::ADDR), 0);
+    }
+}
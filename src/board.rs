@@ -0,0 +1,170 @@
+//! Board profiles: USB IDs, register access interface, control requests and pin polarity of a board, so
+//! derivative boards built from the dongle design can be driven without code changes.
+//!
+//! The stock dongle is the built-in `reference` profile and is used unless `--profile <name>` selects another
+//! one from `board_profiles.toml` in the config directory (see [crate::config]). Every field is optional and
+//! defaults to the reference value, so a profile only lists what its board changes. The reference profile
+//! written out in full:
+//! ```toml
+//! [reference]
+//! # USB4604 bridge device, registers are accessed through it
+//! bridge = { vendor_id = 0x0424, product_id = 0x2530 }
+//! # UART on the same hub, its serial is printed on the label
+//! ftdi = { vendor_id = 0x0403, product_id = 0x6015 }
+//! # The USB4604 hub itself
+//! hub = { vendor_id = 0x0424, product_id = 0x4502 }
+//! # Register access interface number, picked from the interface descriptors if omitted
+//! # interface = 0
+//! # USB switch and power enable polarity, same as --switch-active-high and --power-active-high
+//! switch_active_high = false
+//! power_active_high = false
+//!
+//! [reference.control_protocol]
+//! request_read = 4
+//! request_write = 3
+//! index = 0
+//! timeout_ms = 500
+//! ```
+//!
+//! Pin assignment (which hub PIO drives which signal) is the same on all boards using this tool, only the
+//! polarity of the power and USB switch enables can be changed.
+//!
+//! With a profile selected, `[control_protocol]`, `switch_active_high` and `power_active_high` of the config
+//! file are ignored, the profile replaces them; flags and per-device settings still take precedence.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigError, config_dir};
+use crate::discovery::{
+    PRODUCT_BRIDGE_DEV, PRODUCT_FT234, PRODUCT_USB4604_HUB, VENDOR_FTDI, VENDOR_SMSC,
+};
+use crate::usb4604_ral::ControlProtocol;
+
+/// Name of the built-in profile of the stock dongle.
+pub const REFERENCE_NAME: &str = "reference";
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UsbId {
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+impl UsbId {
+    pub fn matches(&self, vendor_id: u16, product_id: u16) -> bool {
+        self.vendor_id == vendor_id && self.product_id == product_id
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BoardProfile {
+    pub bridge: UsbId,
+    pub ftdi: UsbId,
+    pub hub: UsbId,
+    /// Register access interface, `None` to pick it from the descriptors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<u8>,
+    pub control_protocol: ControlProtocol,
+    pub switch_active_high: bool,
+    pub power_active_high: bool,
+}
+
+impl BoardProfile {
+    pub const REFERENCE: Self = Self {
+        bridge: UsbId {
+            vendor_id: VENDOR_SMSC,
+            product_id: PRODUCT_BRIDGE_DEV,
+        },
+        ftdi: UsbId {
+            vendor_id: VENDOR_FTDI,
+            product_id: PRODUCT_FT234,
+        },
+        hub: UsbId {
+            vendor_id: VENDOR_SMSC,
+            product_id: PRODUCT_USB4604_HUB,
+        },
+        interface: None,
+        control_protocol: ControlProtocol::DEFAULT,
+        switch_active_high: false,
+        power_active_high: false,
+    };
+}
+
+impl Default for BoardProfile {
+    fn default() -> Self {
+        Self::REFERENCE
+    }
+}
+
+/// `board_profiles.toml` in the config directory.
+pub fn board_profiles_path() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("board_profiles.toml"))
+}
+
+/// Parses a profiles file, one table per profile name.
+pub fn parse_board_profiles(s: &str) -> Result<BTreeMap<String, BoardProfile>, ConfigError> {
+    toml::from_str(s).map_err(|e| ConfigError::Parse(e.to_string()))
+}
+
+/// Looks `name` up in the profiles file at `path`, [REFERENCE_NAME] is built in and needs no file.
+pub fn load_board_profile_from(path: &Path, name: &str) -> Result<BoardProfile, ConfigError> {
+    if name == REFERENCE_NAME {
+        return Ok(BoardProfile::REFERENCE);
+    }
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(ConfigError::Io(e)),
+    };
+    parse_board_profiles(&contents)?
+        .remove(name)
+        .ok_or_else(|| ConfigError::UnknownBoardProfile(name.to_string()))
+}
+
+pub fn load_board_profile(name: &str) -> Result<BoardProfile, ConfigError> {
+    load_board_profile_from(
+        &board_profiles_path().ok_or(ConfigError::NoConfigDir)?,
+        name,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documented_reference_matches_built_in_and_fields_default_to_it() {
+        let doc = include_str!("board.rs")
+            .lines()
+            .skip_while(|l| !l.starts_with("//! ```toml"))
+            .skip(1)
+            .take_while(|l| !l.starts_with("//! ```"))
+            .map(|l| l.trim_start_matches("//!").trim_start())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let profiles = parse_board_profiles(&doc).unwrap();
+        assert_eq!(profiles[REFERENCE_NAME], BoardProfile::REFERENCE);
+
+        let profiles = parse_board_profiles(
+            "[fork]\n\
+             bridge = { vendor_id = 0x1209, product_id = 0x0001 }\n\
+             interface = 1\n\
+             [fork.control_protocol]\n\
+             request_read = 0x41\n",
+        )
+        .unwrap();
+        let fork = profiles["fork"];
+        assert!(fork.bridge.matches(0x1209, 0x0001));
+        assert_eq!(fork.ftdi, BoardProfile::REFERENCE.ftdi);
+        assert_eq!(fork.interface, Some(1));
+        assert_eq!(fork.control_protocol.request_read, 0x41);
+        assert_eq!(
+            fork.control_protocol.request_write,
+            ControlProtocol::DEFAULT.request_write
+        );
+    }
+}
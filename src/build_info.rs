@@ -0,0 +1,87 @@
+//! Version and build details for support and CI, captured by `build.rs`.
+
+use std::fmt;
+
+use serde::Serialize;
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    /// Short git commit hash, `unknown` if built outside a git checkout
+    pub git_commit: &'static str,
+    pub target: &'static str,
+    /// Enabled cargo features, the crate does not define optional features yet
+    pub features: Vec<&'static str>,
+    /// Parts compiled in or out depending on the target platform, see [capabilities]
+    pub capabilities: Vec<Capability>,
+    /// Locked nusb version, USB behavior depends on it
+    pub nusb_version: &'static str,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct Capability {
+    pub name: &'static str,
+    pub enabled: bool,
+    pub note: &'static str,
+}
+
+/// What this binary supports beyond the commands available everywhere, from the same `cfg` checks that
+/// compile the code in.
+pub fn capabilities() -> Vec<Capability> {
+    vec![
+        Capability {
+            name: "json",
+            enabled: true,
+            note: "--json and JSON output formats, serde is always compiled in",
+        },
+        Capability {
+            name: "udev",
+            enabled: cfg!(target_os = "linux"),
+            note: "udev rule generation (udev command), Linux only",
+        },
+        Capability {
+            name: "doctor",
+            enabled: cfg!(target_os = "linux"),
+            note: "setup diagnostics and serial port lookup (doctor command), Linux only",
+        },
+        Capability {
+            name: "persist",
+            enabled: cfg!(unix),
+            note: "--persist background helper keeping the dongle open, Unix only",
+        },
+        Capability {
+            name: "signals",
+            enabled: cfg!(unix),
+            note: "Ctrl-C / SIGTERM handling of detach --run and force-sdp --auto-release-after, \
+                   and signal exit codes of --run commands, Unix only",
+        },
+    ]
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("BUILD_GIT_COMMIT"),
+        target: env!("BUILD_TARGET"),
+        features: env!("BUILD_FEATURES")
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .collect(),
+        capabilities: capabilities(),
+        nusb_version: env!("BUILD_NUSB_VERSION"),
+    }
+}
+
+impl fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mchp_gpio_ctl {} ({}) {}, nusb {}",
+            self.version, self.git_commit, self.target, self.nusb_version
+        )?;
+        if !self.features.is_empty() {
+            write!(f, ", features: {}", self.features.join(", "))?;
+        }
+        Ok(())
+    }
+}
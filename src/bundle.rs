@@ -0,0 +1,26 @@
+//! Everything a maintainer asks for in a support ticket, collected into one JSON document.
+
+use serde::Serialize;
+
+use crate::discovery::DongleInfo;
+use crate::error::DongleError;
+use crate::status::{StatusReport, status_report};
+use crate::usb4604_ral::{RegisterBus, RegisterValue, dump_registers};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DebugBundle {
+    pub version: &'static str,
+    /// Bridge, FTDI and hub descriptors
+    pub dongle: DongleInfo,
+    pub status: StatusReport,
+    pub registers: Vec<RegisterValue>,
+}
+
+pub fn debug_bundle(bus: &dyn RegisterBus, info: &DongleInfo) -> Result<DebugBundle, DongleError> {
+    Ok(DebugBundle {
+        version: env!("CARGO_PKG_VERSION"),
+        dongle: info.clone(),
+        status: status_report(bus, info)?,
+        registers: dump_registers(bus)?,
+    })
+}
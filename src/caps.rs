@@ -0,0 +1,108 @@
+//! Machine readable description of the CLI subcommands and what hardware they require.
+//!
+//! Everything but the hardware requirements is derived from the clap [Command] metadata, so new subcommands
+//! show up automatically. Requirements are declared per command by the caller, subcommands need what their
+//! parent needs and commands without a declaration work on every dongle.
+
+use clap::{ArgAction, Command};
+use serde::Serialize;
+
+/// Hardware a command needs beyond a dongle of any revision.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Requirement {
+    #[default]
+    None,
+    /// PCB RevC and up
+    RevC,
+    /// Relay variant, which is RevC and up too
+    Relay,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct ArgInfo {
+    pub name: String,
+    pub long: Option<String>,
+    pub short: Option<char>,
+    pub help: Option<String>,
+    pub required: bool,
+    pub takes_value: bool,
+    pub possible_values: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct CommandInfo {
+    pub name: String,
+    pub about: Option<String>,
+    pub args: Vec<ArgInfo>,
+    pub requires_revc: bool,
+    pub requires_relay: bool,
+    /// Whether the command works on the selected dongle, only set by [mark_available]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available: Option<bool>,
+    pub subcommands: Vec<CommandInfo>,
+}
+
+fn describe_arg(arg: &clap::Arg) -> ArgInfo {
+    ArgInfo {
+        name: arg.get_id().to_string(),
+        long: arg.get_long().map(str::to_string),
+        short: arg.get_short(),
+        help: arg.get_help().map(|h| h.to_string()),
+        required: arg.is_required_set(),
+        takes_value: matches!(arg.get_action(), ArgAction::Set | ArgAction::Append),
+        possible_values: arg
+            .get_possible_values()
+            .iter()
+            .filter(|v| !v.is_hide_set())
+            .map(|v| v.get_name().to_string())
+            .collect(),
+    }
+}
+
+fn describe_command(
+    cmd: &Command,
+    requirements: &[(&str, Requirement)],
+    parent: Requirement,
+) -> CommandInfo {
+    let requirement = match parent {
+        Requirement::None => requirements
+            .iter()
+            .find(|(name, _)| *name == cmd.get_name())
+            .map_or(Requirement::None, |(_, requirement)| *requirement),
+        parent => parent,
+    };
+    CommandInfo {
+        name: cmd.get_name().to_string(),
+        requires_revc: requirement != Requirement::None,
+        requires_relay: requirement == Requirement::Relay,
+        available: None,
+        about: cmd.get_about().map(|a| a.to_string()),
+        args: cmd
+            .get_arguments()
+            .filter(|a| !a.is_hide_set() && !a.is_global_set())
+            .filter(|a| !matches!(a.get_id().as_str(), "help" | "version"))
+            .map(describe_arg)
+            .collect(),
+        subcommands: cmd
+            .get_subcommands()
+            .filter(|c| c.get_name() != "help" && !c.is_hide_set())
+            .map(|c| describe_command(c, requirements, requirement))
+            .collect(),
+    }
+}
+
+/// Describes all subcommands of `cli`, with the hardware `requirements` declared by command name.
+pub fn describe_commands(cli: &Command, requirements: &[(&str, Requirement)]) -> Vec<CommandInfo> {
+    cli.get_subcommands()
+        .filter(|c| c.get_name() != "help" && !c.is_hide_set())
+        .map(|c| describe_command(c, requirements, Requirement::None))
+        .collect()
+}
+
+/// Marks every command as available or not on a dongle with the given hardware.
+pub fn mark_available(commands: &mut [CommandInfo], is_revc: bool, is_relay: bool) {
+    for c in commands {
+        c.available = Some((!c.requires_revc || is_revc) && (!c.requires_relay || is_relay));
+        mark_available(&mut c.subcommands, is_revc, is_relay);
+    }
+}
@@ -0,0 +1,514 @@
+//! Persistent user configuration, stored as TOML in the platform config directory:
+//! `$XDG_CONFIG_HOME/mchp_gpio_ctl/config.toml` (or `~/.config/...`) on Linux and macOS,
+//! `%APPDATA%\mchp_gpio_ctl\config.toml` on Windows.
+//!
+//! Example:
+//! ```toml
+//! # USB switch enable is wired non-inverting (custom carrier), same as --switch-active-high
+//! switch_active_high = true
+//! # Power enable is wired non-inverting, same as --power-active-high, check with verify-power
+//! power_active_high = true
+//! # Power on before connecting the USB switch, disconnect it before power off, same as --enforce-sequencing
+//! enforce_sequencing = true
+//!
+//! # Only for bridge firmware using non-standard vendor requests, omitted fields keep their defaults
+//! [control_protocol]
+//! request_read = 4
+//! request_write = 3
+//!
+//! [names]
+//! dut-a = "A10KL7X3"
+//! power-supply = "A10KL9Q1"
+//!
+//! # Defaults for one device, keyed by nickname or (partial) serial, applied when it is selected
+//! [profiles.dut-a]
+//! sdp_secs = 20
+//! soft_start_ms = 50
+//! switch_active_high = true
+//! # Slow SSR on this fixture, see crate::relay_dwell
+//! relay_min_dwell_ms = 500
+//! ```
+//!
+//! Derivative boards with other USB IDs or control requests are described in a separate board profiles file,
+//! see [crate::board].
+//!
+//! Settings that can come from several places are resolved as: command line flag, then the selected
+//! device's profile, then the global setting above (the board profile's with `--profile`), then the built-in
+//! default. `mchp_gpio_ctl profile show`
+//! prints the result.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::board::BoardProfile;
+use crate::discovery::{DongleInfo, SerialQuery};
+use crate::usb4604_ral::ControlProtocol;
+
+/// How long `sdp` forces SDP without a flag or profile setting.
+pub const DEFAULT_SDP_SECS: u64 = 10;
+
+/// Minimum time between two switchings of a relay without a flag or profile setting, well above the
+/// turn-on / turn-off time of the SSR (a few milliseconds) so a tight loop can not chatter it.
+pub const DEFAULT_RELAY_MIN_DWELL_MS: u64 = 100;
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Nickname to dongle serial (FTDI or bridge, possibly partial)
+    #[serde(default)]
+    pub names: BTreeMap<String, String>,
+    /// USB switch enable is active high instead of active low
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub switch_active_high: bool,
+    /// Power enable is active high instead of active low
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub power_active_high: bool,
+    /// Enforce power / USB switch ordering, see [crate::sequencing]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enforce_sequencing: bool,
+    /// Register access requests, see [ControlProtocol]
+    #[serde(default, skip_serializing_if = "ControlProtocol::is_default")]
+    pub control_protocol: ControlProtocol,
+    /// Per-device defaults by nickname or serial
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Defaults for one device, unset fields fall back to the global setting or built-in default.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// How long `sdp` forces SDP, in seconds
+    pub sdp_secs: Option<u64>,
+    /// Soft-start ramp of `on`, in milliseconds
+    pub soft_start_ms: Option<u64>,
+    pub switch_active_high: Option<bool>,
+    pub power_active_high: Option<bool>,
+    pub enforce_sequencing: Option<bool>,
+    /// Minimum time between two switchings of a relay, in milliseconds, 0 disables it
+    pub relay_min_dwell_ms: Option<u64>,
+}
+
+/// Where an effective setting comes from, in order of precedence.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SettingSource {
+    Flag,
+    Profile,
+    Config,
+    /// Board profile selected with `--profile`, replaces the config file setting
+    Board,
+    Default,
+}
+
+impl fmt::Display for SettingSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SettingSource::Flag => "flag",
+            SettingSource::Profile => "profile",
+            SettingSource::Config => "config",
+            SettingSource::Board => "board",
+            SettingSource::Default => "default",
+        })
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct Effective<T> {
+    pub value: T,
+    pub source: SettingSource,
+}
+
+/// `global` is the config file setting, or the board profile's with `--profile`.
+fn resolve<T>(
+    flag: Option<T>,
+    profile: Option<T>,
+    global: (Option<T>, SettingSource),
+    default: T,
+) -> Effective<T> {
+    let sources = [
+        (flag, SettingSource::Flag),
+        (profile, SettingSource::Profile),
+        global,
+    ];
+    sources
+        .into_iter()
+        .find_map(|(value, source)| {
+            Some(Effective {
+                value: value?,
+                source,
+            })
+        })
+        .unwrap_or(Effective {
+            value: default,
+            source: SettingSource::Default,
+        })
+}
+
+/// Command line flags taking part in the precedence, `None` where not given.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct SettingFlags {
+    pub sdp_secs: Option<u64>,
+    pub soft_start_ms: Option<u64>,
+    pub switch_active_high: Option<bool>,
+    pub power_active_high: Option<bool>,
+    pub enforce_sequencing: Option<bool>,
+    pub relay_min_dwell_ms: Option<u64>,
+}
+
+/// Settings after applying flag > profile > global config > built-in default.
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct EffectiveSettings {
+    pub sdp_secs: Effective<u64>,
+    /// `None` value: no soft-start
+    pub soft_start_ms: Effective<Option<u64>>,
+    pub switch_active_high: Effective<bool>,
+    pub power_active_high: Effective<bool>,
+    pub enforce_sequencing: Effective<bool>,
+    pub relay_min_dwell_ms: Effective<u64>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(String),
+    /// Neither XDG_CONFIG_HOME, HOME nor APPDATA is set
+    NoConfigDir,
+    InvalidName(String),
+    /// Nickname is already assigned to another dongle
+    NameTaken {
+        name: String,
+        serial: String,
+    },
+    UnknownName(String),
+    /// `--profile` names a board profile that is neither built in nor in the profiles file
+    UnknownBoardProfile(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "Config file IO error: {e}"),
+            ConfigError::Parse(e) => write!(f, "Failed to parse config file: {e}"),
+            ConfigError::NoConfigDir => {
+                write!(
+                    f,
+                    "Cannot determine config directory, set XDG_CONFIG_HOME or HOME"
+                )
+            }
+            ConfigError::InvalidName(name) => write!(
+                f,
+                "Invalid name '{name}', only letters, digits, '-' and '_' are allowed"
+            ),
+            ConfigError::NameTaken { name, serial } => {
+                write!(f, "Name '{name}' is already assigned to {serial}")
+            }
+            ConfigError::UnknownName(name) => write!(f, "No dongle named '{name}'"),
+            ConfigError::UnknownBoardProfile(name) => write!(
+                f,
+                "No board profile named '{name}', define it in board_profiles.toml in the config directory"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Directory holding the config file and other persistent state, `None` if it cannot be determined.
+pub fn config_dir() -> Option<PathBuf> {
+    let env_dir = |var| {
+        std::env::var_os(var)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    let dir = if cfg!(target_os = "windows") {
+        env_dir("APPDATA")
+    } else {
+        env_dir("XDG_CONFIG_HOME").or_else(|| env_dir("HOME").map(|h| h.join(".config")))
+    }?;
+    Some(dir.join("mchp_gpio_ctl"))
+}
+
+/// Path of the config file, `None` if no config directory can be determined.
+pub fn config_path() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("config.toml"))
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl Config {
+    /// Loads the config from [config_path], a missing file is an empty config.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_from(&config_path().ok_or(ConfigError::NoConfigDir)?)
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Self::from_toml(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(ConfigError::Io(e)),
+        }
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+        toml::from_str(s).map_err(|e| ConfigError::Parse(e.to_string()))
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        self.save_to(&config_path().ok_or(ConfigError::NoConfigDir)?)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), ConfigError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(ConfigError::Io)?;
+        }
+        let contents = toml::to_string(self).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        std::fs::write(path, contents).map_err(ConfigError::Io)
+    }
+
+    /// Assigns `name` to `serial`, replacing the previous name of that dongle, if any.
+    pub fn set_name(&mut self, name: &str, serial: &str) -> Result<(), ConfigError> {
+        if !is_valid_name(name) {
+            return Err(ConfigError::InvalidName(name.to_string()));
+        }
+        if let Some(other) = self.names.get(name).filter(|s| *s != serial) {
+            return Err(ConfigError::NameTaken {
+                name: name.to_string(),
+                serial: other.clone(),
+            });
+        }
+        self.names.retain(|_, s| s != serial);
+        self.names.insert(name.to_string(), serial.to_string());
+        Ok(())
+    }
+
+    /// Removes `name`, returning the serial it was assigned to.
+    pub fn remove_name(&mut self, name: &str) -> Result<String, ConfigError> {
+        self.names
+            .remove(name)
+            .ok_or_else(|| ConfigError::UnknownName(name.to_string()))
+    }
+
+    /// Profile of `dongle` with its key, keys are nicknames or (partial) serials; if several match,
+    /// the first key in alphabetical order wins.
+    pub fn profile_for(&self, dongle: &DongleInfo) -> Option<(&str, &Profile)> {
+        self.profiles.iter().find_map(|(key, profile)| {
+            let serial = self.names.get(key).unwrap_or(key);
+            let query = SerialQuery::resolve(std::slice::from_ref(dongle), serial);
+            dongle
+                .matches_serial(&query)
+                .then_some((key.as_str(), profile))
+        })
+    }
+
+    /// Resolves the settings for a device with `profile` (if any) and the given command line flags.
+    ///
+    /// `board` is the board profile selected with `--profile`, its pin polarity replaces the config file's.
+    pub fn effective_settings(
+        &self,
+        profile: Option<&Profile>,
+        flags: &SettingFlags,
+        board: Option<&BoardProfile>,
+    ) -> EffectiveSettings {
+        let profile = profile.cloned().unwrap_or_default();
+        let flag = |set: bool| set.then_some(true);
+        let (switch_active_high, power_active_high) = match board {
+            Some(board) => (
+                (Some(board.switch_active_high), SettingSource::Board),
+                (Some(board.power_active_high), SettingSource::Board),
+            ),
+            None => (
+                (flag(self.switch_active_high), SettingSource::Config),
+                (flag(self.power_active_high), SettingSource::Config),
+            ),
+        };
+        EffectiveSettings {
+            sdp_secs: resolve(
+                flags.sdp_secs,
+                profile.sdp_secs,
+                (None, SettingSource::Config),
+                DEFAULT_SDP_SECS,
+            ),
+            soft_start_ms: resolve(
+                flags.soft_start_ms.map(Some),
+                profile.soft_start_ms.map(Some),
+                (None, SettingSource::Config),
+                None,
+            ),
+            switch_active_high: resolve(
+                flags.switch_active_high,
+                profile.switch_active_high,
+                switch_active_high,
+                false,
+            ),
+            power_active_high: resolve(
+                flags.power_active_high,
+                profile.power_active_high,
+                power_active_high,
+                false,
+            ),
+            enforce_sequencing: resolve(
+                flags.enforce_sequencing,
+                profile.enforce_sequencing,
+                (flag(self.enforce_sequencing), SettingSource::Config),
+                false,
+            ),
+            relay_min_dwell_ms: resolve(
+                flags.relay_min_dwell_ms,
+                profile.relay_min_dwell_ms,
+                (None, SettingSource::Config),
+                DEFAULT_RELAY_MIN_DWELL_MS,
+            ),
+        }
+    }
+
+    /// Serial the nickname `name` refers to.
+    pub fn resolve_name(&self, name: &str) -> Result<&str, ConfigError> {
+        self.names
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| ConfigError::UnknownName(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_unique() {
+        let mut config = Config::default();
+        config.set_name("dut-a", "A10KL7X3").unwrap();
+        assert!(matches!(
+            config.set_name("dut-a", "A10KL9Q1"),
+            Err(ConfigError::NameTaken { .. })
+        ));
+        config.set_name("dut-a", "A10KL7X3").unwrap();
+        assert!(matches!(
+            config.set_name("dut a", "A10KL9Q1"),
+            Err(ConfigError::InvalidName(_))
+        ));
+    }
+
+    #[test]
+    fn rename_replaces_previous_name() {
+        let mut config = Config::default();
+        config.set_name("dut-a", "A10KL7X3").unwrap();
+        config.set_name("dut-b", "A10KL7X3").unwrap();
+        assert!(config.resolve_name("dut-a").is_err());
+        assert_eq!(config.resolve_name("dut-b").unwrap(), "A10KL7X3");
+        assert_eq!(config.remove_name("dut-b").unwrap(), "A10KL7X3");
+        assert!(config.names.is_empty());
+    }
+
+    #[test]
+    fn toml_round_trip() {
+        let mut config = Config::default();
+        config.set_name("power-supply", "A10KL9Q1").unwrap();
+        let toml = toml::to_string(&config).unwrap();
+        assert_eq!(Config::from_toml(&toml).unwrap(), config);
+    }
+
+    #[test]
+    fn switch_polarity_defaults_to_reference_schematic() {
+        assert!(!Config::from_toml("").unwrap().switch_active_high);
+        assert!(
+            !toml::to_string(&Config::default())
+                .unwrap()
+                .contains("switch_active_high")
+        );
+        let config = Config::from_toml("switch_active_high = true").unwrap();
+        assert!(config.switch_active_high);
+    }
+
+    #[test]
+    fn control_protocol_overrides_keep_other_defaults() {
+        assert!(Config::default().control_protocol.is_default());
+        let config =
+            Config::from_toml("[control_protocol]\nrequest_read = 0x84\nindex = 2").unwrap();
+        assert_eq!(
+            config.control_protocol,
+            ControlProtocol {
+                request_read: 0x84,
+                index: 2,
+                ..ControlProtocol::DEFAULT
+            }
+        );
+        assert_eq!(
+            Config::from_toml(&toml::to_string(&config).unwrap()).unwrap(),
+            config
+        );
+        assert!(Config::from_toml("[control_protocol]\nrequest = 1").is_err());
+    }
+
+    #[test]
+    fn profile_settings_sit_between_flags_and_global_config() {
+        use crate::discovery::UsbDevice;
+
+        let config = Config::from_toml(
+            "switch_active_high = true\n\
+             [names]\n\
+             dut-a = \"A10KL7X3\"\n\
+             [profiles.dut-a]\n\
+             sdp_secs = 20\n\
+             switch_active_high = false\n\
+             [profiles.OTHER]\n\
+             sdp_secs = 5\n",
+        )
+        .unwrap();
+        let dongle = DongleInfo {
+            bridge: UsbDevice {
+                serial_number: Some("A10KL7X3".into()),
+                ..UsbDevice::default()
+            },
+            ftdi: None,
+            hub: None,
+        };
+        let (key, profile) = config.profile_for(&dongle).unwrap();
+        assert_eq!(key, "dut-a");
+
+        let settings = config.effective_settings(Some(profile), &SettingFlags::default(), None);
+        assert_eq!(settings.sdp_secs.value, 20);
+        assert_eq!(settings.sdp_secs.source, SettingSource::Profile);
+        assert!(!settings.switch_active_high.value);
+        assert_eq!(settings.soft_start_ms.source, SettingSource::Default);
+
+        let flags = SettingFlags {
+            sdp_secs: Some(3),
+            switch_active_high: Some(true),
+            ..SettingFlags::default()
+        };
+        let settings = config.effective_settings(Some(profile), &flags, None);
+        assert_eq!(settings.sdp_secs.value, 3);
+        assert_eq!(settings.switch_active_high.source, SettingSource::Flag);
+
+        let settings = config.effective_settings(None, &SettingFlags::default(), None);
+        assert_eq!(settings.switch_active_high.source, SettingSource::Config);
+        let board = BoardProfile::default();
+        let settings = config.effective_settings(None, &SettingFlags::default(), Some(&board));
+        assert!(!settings.switch_active_high.value);
+        assert_eq!(settings.switch_active_high.source, SettingSource::Board);
+        assert_eq!(settings.sdp_secs.value, DEFAULT_SDP_SECS);
+    }
+
+    #[test]
+    fn flag_set_to_false_overrides_profile() {
+        let config = Config::from_toml("[profiles.dut-a]\npower_active_high = true\n").unwrap();
+        let profile = &config.profiles["dut-a"];
+        let flags = SettingFlags {
+            power_active_high: Some(false),
+            ..SettingFlags::default()
+        };
+        let settings = config.effective_settings(Some(profile), &flags, None);
+        assert!(!settings.power_active_high.value);
+        assert_eq!(settings.power_active_high.source, SettingSource::Flag);
+    }
+}
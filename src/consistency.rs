@@ -0,0 +1,78 @@
+//! `--strict-state`: combinations of dongle state that the hardware allows but that make no sense for a device
+//! under test, checked after every command and, for `apply`, on the fixture before anything is written.
+//!
+//! All combinations are listed in [INVALID_COMBINATIONS], they only involve RevC signals, so boards before
+//! RevC never violate them. USB data connected with power off is not one of them: it is what `off` leaves on
+//! every board with the switch in its default position, boards that must avoid it use
+//! [crate::sequencing].
+
+use crate::status::StatusReport;
+
+#[derive(Copy, Clone, Debug)]
+pub struct InvalidCombination {
+    /// Stable identifier, e.g. `sdp_without_data`
+    pub name: &'static str,
+    pub description: &'static str,
+    applies: fn(&StatusReport) -> bool,
+}
+
+pub const INVALID_COMBINATIONS: &[InvalidCombination] = &[
+    InvalidCombination {
+        name: "sdp_without_data",
+        description: "SDP is forced while the USB data lines are disconnected, the host never sees the \
+                      boot ROM and some hosts get confused on the next attach",
+        applies: |r| r.forcing_sdp == Some(true) && r.usb_switch_connected == Some(false),
+    },
+    InvalidCombination {
+        name: "cc_detached_with_data",
+        description: "CC is forced low (cable detached) while the USB data lines are connected",
+        applies: |r| r.forcing_cc_low == Some(true) && r.usb_switch_connected == Some(true),
+    },
+];
+
+/// Invalid combinations present in `report`, empty if it is consistent.
+pub fn violations(report: &StatusReport) -> Vec<&'static InvalidCombination> {
+    INVALID_COMBINATIONS
+        .iter()
+        .filter(|c| (c.applies)(report))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dongle_hal_revb::{PcbRevision, PowerState};
+    use crate::fixture::DesiredState;
+
+    #[test]
+    fn fixture_forcing_sdp_while_detaching_is_flagged() {
+        let current = StatusReport {
+            serial: "A1".into(),
+            power_state: PowerState::On,
+            power_on: true,
+            power_fault: Some(false),
+            pcb_revision: PcbRevision::RevC,
+            revision: PcbRevision::RevC.to_string(),
+            relay_variant: false,
+            relay_count: 0,
+            usb_switch_connected: Some(true),
+            forcing_sdp: Some(false),
+            forcing_cc_low: Some(false),
+            header_p0: None,
+            header_p1: None,
+        };
+        assert!(violations(&current).is_empty());
+
+        let desired =
+            DesiredState::from_toml("forcing_sdp = true\nusb_switch_connected = false").unwrap();
+        let names = violations(&desired.predict(&current))
+            .iter()
+            .map(|v| v.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["sdp_without_data"]);
+
+        // `off` from the default state
+        let desired = DesiredState::from_toml("power_on = false").unwrap();
+        assert!(violations(&desired.predict(&current)).is_empty());
+    }
+}
@@ -0,0 +1,240 @@
+//! `trace-cycle`: a full detach followed by a full attach, with the time of every electrical step and of the
+//! device behind the dongle disappearing and enumerating again, to line up with scope captures.
+//!
+//! Steps are the ones of `full-detach` (power off, USB switch off, CC low) and `full-attach` (power on,
+//! USB switch on, CC high), in that order. Times are taken when the register write returned, so they lag the
+//! edge on the board by one control transfer (about 1ms). Enumeration is seen by polling the OS device list,
+//! events are stamped when the poll noticed them, up to [POLL_INTERVAL] late.
+
+use std::fmt;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::discovery::{DeviceList, DongleInfo, UsbDevice};
+use crate::dongle_hal_revb::dev_power_ctl;
+use crate::dongle_hal_revc::{
+    PinMode, PinState, SlgPin, slg_io_set, slg_io_set_mode, usb_switch_configure, usb_switch_set,
+};
+use crate::error::DongleError;
+use crate::usb4604_ral::RegisterBus;
+
+/// Interval of the device list polls while waiting for removal and enumeration.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CycleEvent {
+    PowerOff,
+    UsbSwitchOff,
+    CcLow,
+    /// No device is enumerated behind the dongle anymore
+    DeviceRemoved,
+    PowerOn,
+    UsbSwitchOn,
+    CcHigh,
+    /// A device enumerated behind the dongle
+    DeviceEnumerated,
+    /// No device enumerated within the timeout, last event of the timeline
+    EnumerationTimeout,
+}
+
+/// Same names as in JSON.
+impl fmt::Display for CycleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CycleEvent::PowerOff => "power_off",
+            CycleEvent::UsbSwitchOff => "usb_switch_off",
+            CycleEvent::CcLow => "cc_low",
+            CycleEvent::DeviceRemoved => "device_removed",
+            CycleEvent::PowerOn => "power_on",
+            CycleEvent::UsbSwitchOn => "usb_switch_on",
+            CycleEvent::CcHigh => "cc_high",
+            CycleEvent::DeviceEnumerated => "device_enumerated",
+            CycleEvent::EnumerationTimeout => "enumeration_timeout",
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct TimelineEntry {
+    /// Milliseconds since the start of the cycle
+    pub t_ms: f64,
+    pub event: CycleEvent,
+    /// `vid:pid product` of the device, for device events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+}
+
+struct Timeline {
+    start: Instant,
+    entries: Vec<TimelineEntry>,
+}
+
+impl Timeline {
+    fn push(&mut self, event: CycleEvent, device: Option<&UsbDevice>) {
+        self.entries.push(TimelineEntry {
+            t_ms: self.start.elapsed().as_secs_f64() * 1000.0,
+            event,
+            device: device.map(describe),
+        });
+    }
+}
+
+fn describe(device: &UsbDevice) -> String {
+    format!(
+        "{:04x}:{:04x} {}",
+        device.vendor_id,
+        device.product_id,
+        device.product_string.as_deref().unwrap_or("")
+    )
+    .trim_end()
+    .to_string()
+}
+
+/// First device enumerated behind `dongle`, if any.
+fn downstream_device(
+    devices: DeviceList,
+    dongle: &DongleInfo,
+) -> Result<Option<UsbDevice>, DongleError> {
+    let all_devices = devices().map_err(DongleError::Usb)?;
+    Ok(dongle
+        .downstream_devices(&all_devices)
+        .first()
+        .copied()
+        .cloned())
+}
+
+/// Polls until no device is enumerated behind `dongle`, returns false if one still is after `timeout`.
+fn wait_removed(
+    devices: DeviceList,
+    dongle: &DongleInfo,
+    timeout: Duration,
+) -> Result<bool, DongleError> {
+    let start = Instant::now();
+    while downstream_device(devices, dongle)?.is_some() {
+        if start.elapsed() >= timeout {
+            return Ok(false);
+        }
+        sleep(POLL_INTERVAL);
+    }
+    Ok(true)
+}
+
+/// Polls until a device enumerates behind `dongle`, `None` if none did within `timeout`.
+fn wait_enumerated(
+    devices: DeviceList,
+    dongle: &DongleInfo,
+    timeout: Duration,
+) -> Result<Option<UsbDevice>, DongleError> {
+    let start = Instant::now();
+    loop {
+        if let Some(device) = downstream_device(devices, dongle)? {
+            return Ok(Some(device));
+        }
+        if start.elapsed() >= timeout {
+            return Ok(None);
+        }
+        sleep(POLL_INTERVAL);
+    }
+}
+
+/// Detaches, keeps the dongle detached for `off` (waiting for the device to disappear meanwhile), attaches and
+/// waits up to `enumeration_timeout` for a device to enumerate in `devices`.
+pub fn trace_cycle(
+    bus: &dyn RegisterBus,
+    devices: DeviceList,
+    dongle: &DongleInfo,
+    off: Duration,
+    enumeration_timeout: Duration,
+) -> Result<Vec<TimelineEntry>, DongleError> {
+    let was_enumerated = downstream_device(devices, dongle)?.is_some();
+    usb_switch_configure(bus)?;
+    slg_io_set_mode(bus, SlgPin::SlgIo1, PinMode::Output)?;
+    let mut timeline = Timeline {
+        start: Instant::now(),
+        entries: Vec::new(),
+    };
+    dev_power_ctl(bus, false)?;
+    timeline.push(CycleEvent::PowerOff, None);
+    usb_switch_set(bus, false)?;
+    timeline.push(CycleEvent::UsbSwitchOff, None);
+    slg_io_set(bus, SlgPin::SlgIo1, PinState::Low)?;
+    timeline.push(CycleEvent::CcLow, None);
+    let detached = Instant::now();
+    if was_enumerated && wait_removed(devices, dongle, off)? {
+        timeline.push(CycleEvent::DeviceRemoved, None);
+    }
+    sleep(off.saturating_sub(detached.elapsed()));
+
+    dev_power_ctl(bus, true)?;
+    timeline.push(CycleEvent::PowerOn, None);
+    usb_switch_set(bus, true)?;
+    timeline.push(CycleEvent::UsbSwitchOn, None);
+    slg_io_set(bus, SlgPin::SlgIo1, PinState::High)?;
+    timeline.push(CycleEvent::CcHigh, None);
+    match wait_enumerated(devices, dongle, enumeration_timeout)? {
+        Some(device) => timeline.push(CycleEvent::DeviceEnumerated, Some(&device)),
+        None => timeline.push(CycleEvent::EnumerationTimeout, None),
+    }
+    Ok(timeline.entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb4604_ral::{Gpio8_10Input, MockBus, SmscReg};
+
+    #[test]
+    fn cycle_without_a_device_ends_with_the_enumeration_timeout() {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio8_10Input::ADDR,
+            Gpio8_10Input::new()
+                .with_gpio9_in(true)
+                .with_gpio10_in(true)
+                .value(),
+        );
+        let dongle = DongleInfo {
+            bridge: UsbDevice::default(),
+            ftdi: None,
+            hub: None,
+        };
+        let timeline = trace_cycle(
+            &bus,
+            || Ok(Vec::new()),
+            &dongle,
+            Duration::ZERO,
+            Duration::ZERO,
+        )
+        .unwrap();
+        let events: Vec<_> = timeline.iter().map(|e| e.event).collect();
+        assert_eq!(
+            events,
+            [
+                CycleEvent::PowerOff,
+                CycleEvent::UsbSwitchOff,
+                CycleEvent::CcLow,
+                CycleEvent::PowerOn,
+                CycleEvent::UsbSwitchOn,
+                CycleEvent::CcHigh,
+                CycleEvent::EnumerationTimeout,
+            ]
+        );
+    }
+
+    #[test]
+    fn timeline_serializes_to_t_ms_and_event() {
+        let entry = TimelineEntry {
+            t_ms: 1.5,
+            event: CycleEvent::UsbSwitchOff,
+            device: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"t_ms":1.5,"event":"usb_switch_off"}"#
+        );
+        assert_eq!(entry.event.to_string(), "usb_switch_off");
+    }
+}
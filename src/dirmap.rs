@@ -0,0 +1,86 @@
+//! Direction (input / output) of every PIO the crate knows, under its board signal name.
+//!
+//! All `*Dir` registers are read once, so the map is a consistent snapshot. RevC-only pins are omitted on
+//! older boards, where they are not connected.
+
+use serde::Serialize;
+
+use crate::dongle_hal_revb::pcb_revision;
+use crate::dongle_hal_revc::PinMode;
+use crate::error::DongleError;
+use crate::pinmap::{PinGroup, pin_map};
+use crate::usb4604_ral::{Gpio0_7Dir, Gpio8_10Dir, Gpio17_20Dir, RegisterBus, read_reg};
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct PinDirection {
+    pub group: PinGroup,
+    pub name: &'static str,
+    pub pio: u8,
+    pub mode: PinMode,
+}
+
+/// Reads the direction of every known pin.
+pub fn direction_map(bus: &dyn RegisterBus) -> Result<Vec<PinDirection>, DongleError> {
+    let revision = pcb_revision(bus)?;
+    let dir0_7 = read_reg::<Gpio0_7Dir>(bus)?;
+    let dir8_10 = read_reg::<Gpio8_10Dir>(bus)?;
+    let dir17_20 = read_reg::<Gpio17_20Dir>(bus)?;
+    let is_output = |pio| match pio {
+        0 => dir0_7.gpio0_out_en(),
+        1 => dir0_7.gpio1_out_en(),
+        3 => dir0_7.gpio3_out_en(),
+        8 => dir8_10.gpio8_out_en(),
+        9 => dir8_10.gpio9_out_en(),
+        10 => dir8_10.gpio10_out_en(),
+        19 => dir17_20.gpio19_out_en(),
+        20 => dir17_20.gpio20_out_en(),
+        _ => unreachable!("PIO{pio} is not in the pin table"),
+    };
+    Ok(pin_map(revision)
+        .iter()
+        .map(|p| PinDirection {
+            group: p.group,
+            name: p.name,
+            pio: p.pio,
+            mode: if is_output(p.pio) {
+                PinMode::Output
+            } else {
+                PinMode::Input
+            },
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pinmap::PIN_MAP;
+    use crate::usb4604_ral::{Gpio8_10Input, MockBus, SmscReg};
+
+    #[test]
+    fn revc_only_pins_are_omitted_on_older_boards() {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio0_7Dir::ADDR,
+            Gpio0_7Dir::new().with_gpio0_out_en(true).value(),
+        );
+        let map = direction_map(&bus).unwrap();
+        let names = map.iter().map(|p| p.name).collect::<Vec<_>>();
+        assert_eq!(names, ["PWR_EN_N", "PWR_FAIL_N", "REVC_STRAP"]);
+        assert_eq!(map[0].mode, PinMode::Output);
+        assert_eq!(map[1].mode, PinMode::Input);
+
+        bus.set(
+            Gpio8_10Input::ADDR,
+            Gpio8_10Input::new().with_gpio9_in(true).value(),
+        );
+        bus.set(
+            Gpio17_20Dir::ADDR,
+            Gpio17_20Dir::new().with_gpio20_out_en(true).value(),
+        );
+        let map = direction_map(&bus).unwrap();
+        assert_eq!(map.len(), PIN_MAP.len());
+        let p1 = map.iter().find(|p| p.name == "P1").unwrap();
+        assert_eq!(p1.mode, PinMode::Output);
+    }
+}
@@ -0,0 +1,666 @@
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+use log::debug;
+use nusb::{Device, DeviceInfo, Interface, MaybeFuture};
+use serde::{Deserialize, Serialize};
+
+use crate::board::{BoardProfile, UsbId};
+use crate::error::DongleError;
+use crate::usb4604_ral::BridgeBus;
+
+pub const VENDOR_SMSC: u16 = 0x0424;
+pub const PRODUCT_BRIDGE_DEV: u16 = 0x2530;
+pub const PRODUCT_USB4604_HUB: u16 = 0x4502;
+
+pub const VENDOR_FTDI: u16 = 0x0403;
+pub const PRODUCT_FT234: u16 = 0x6015;
+
+/// Serial number of the USB4604 bridge device itself (the one GPIOs are controlled through).
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug, Serialize)]
+#[serde(transparent)]
+pub struct DongleSerial(pub String);
+
+/// Serial number of the FT234 UART sitting on the same hub, this is what users see as "the dongle serial".
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+pub struct FtdiSerial(pub String);
+
+macro_rules! impl_serial_newtype {
+    ($name:ident) => {
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = Infallible;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Ok(Self(s.to_string()))
+            }
+        }
+    };
+}
+
+impl_serial_newtype!(DongleSerial);
+impl_serial_newtype!(FtdiSerial);
+
+/// Serial a dongle is selected by, matched as a substring of the serial of its kind.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SerialQuery {
+    Ftdi(FtdiSerial),
+    Bridge(DongleSerial),
+}
+
+impl SerialQuery {
+    /// Kind of a serial given on the command line, which can be either: the FTDI serial if it is part of the
+    /// FTDI serial of any of `dongles`, the bridge serial otherwise.
+    pub fn resolve(dongles: &[DongleInfo], query: &str) -> Self {
+        let is_ftdi = dongles
+            .iter()
+            .filter_map(DongleInfo::ftdi_serial)
+            .any(|s| s.0.contains(query));
+        if is_ftdi {
+            SerialQuery::Ftdi(FtdiSerial(query.to_string()))
+        } else {
+            SerialQuery::Bridge(DongleSerial(query.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for SerialQuery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SerialQuery::Ftdi(serial) => serial.fmt(f),
+            SerialQuery::Bridge(serial) => serial.fmt(f),
+        }
+    }
+}
+
+/// Owned copy of the descriptor fields discovery cares about, so that dongle info can outlive the device list.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct UsbDevice {
+    pub bus_id: String,
+    pub port_chain: Vec<u8>,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer_string: Option<String>,
+    pub product_string: Option<String>,
+    pub serial_number: Option<String>,
+}
+
+impl From<&DeviceInfo> for UsbDevice {
+    fn from(d: &DeviceInfo) -> Self {
+        UsbDevice {
+            bus_id: d.bus_id().to_string(),
+            port_chain: d.port_chain().to_vec(),
+            vendor_id: d.vendor_id(),
+            product_id: d.product_id(),
+            manufacturer_string: d.manufacturer_string().map(str::to_string),
+            product_string: d.product_string().map(str::to_string),
+            serial_number: d.serial_number().map(str::to_string),
+        }
+    }
+}
+
+impl UsbDevice {
+    fn is(&self, vendor_id: u16, product_id: u16) -> bool {
+        self.vendor_id == vendor_id && self.product_id == product_id
+    }
+
+    /// USB location as `bus-port.port...`, e.g. `1-2.4`.
+    pub fn location(&self) -> String {
+        let ports = self.port_chain.iter().map(u8::to_string);
+        format!("{}-{}", self.bus_id, ports.collect::<Vec<_>>().join("."))
+    }
+
+    /// Port chain of the hub this device is plugged into.
+    fn parent_port_chain(&self) -> &[u8] {
+        &self.port_chain[..self.port_chain.len().saturating_sub(1)]
+    }
+}
+
+/// One dongle: the USB4604 bridge device plus its FTDI and hub siblings, if they were found.
+///
+/// Only the bridge is needed for control, a bridge alone (partial hardware, bare bridge on a bring-up board)
+/// is a dongle too: it is identified by the bridge serial or its USB location instead of the FTDI serial
+/// and is never detected as relay variant from the hub product string.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DongleInfo {
+    pub bridge: UsbDevice,
+    pub ftdi: Option<UsbDevice>,
+    pub hub: Option<UsbDevice>,
+}
+
+impl DongleInfo {
+    pub fn dongle_serial(&self) -> Option<DongleSerial> {
+        self.bridge.serial_number.clone().map(DongleSerial)
+    }
+
+    pub fn ftdi_serial(&self) -> Option<FtdiSerial> {
+        self.ftdi
+            .as_ref()
+            .and_then(|f| f.serial_number.clone())
+            .map(FtdiSerial)
+    }
+
+    /// Serial shown to the user, this is the FTDI one as it is the one printed on the label.
+    /// Without FTDI the bridge serial is shown and without that the USB location of the bridge.
+    pub fn display_serial(&self) -> String {
+        self.ftdi_serial()
+            .map(|s| s.0)
+            .or(self.dongle_serial().map(|s| s.0))
+            .unwrap_or_else(|| self.bridge.location())
+    }
+
+    /// Names of the siblings that were not found next to the bridge.
+    pub fn missing_siblings(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if self.ftdi.is_none() {
+            missing.push("FTDI");
+        }
+        if self.hub.is_none() {
+            missing.push("hub");
+        }
+        missing
+    }
+
+    pub fn hub_product_string(&self) -> &str {
+        self.hub
+            .as_ref()
+            .and_then(|h| h.product_string.as_deref())
+            .unwrap_or("")
+    }
+
+    /// Identifier for per-dongle files: FTDI serial, bridge serial or, if neither is available, USB location.
+    pub fn file_id(&self) -> String {
+        let id = self
+            .ftdi_serial()
+            .map(|s| s.0)
+            .or(self.dongle_serial().map(|s| s.0))
+            .unwrap_or_else(|| self.bridge.location());
+        id.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect()
+    }
+
+    pub fn is_relay_variant(&self) -> bool {
+        self.relay_count() > 0
+    }
+
+    /// Number of relays: 2 on the dual relay variant (driven by P0 and P1), 1 on the single relay variant (P0).
+    pub fn relay_count(&self) -> u8 {
+        let product = self.hub_product_string();
+        if product.contains("relay2") || product.contains("dual") {
+            2
+        } else if product.contains("relay") {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Returns true if the serial of the kind of `query` contains it.
+    pub fn matches_serial(&self, query: &SerialQuery) -> bool {
+        match query {
+            SerialQuery::Ftdi(query) => self.ftdi_serial().is_some_and(|s| s.0.contains(&query.0)),
+            SerialQuery::Bridge(query) => {
+                self.dongle_serial().is_some_and(|s| s.0.contains(&query.0))
+            }
+        }
+    }
+
+    /// Looks the bridge device up again by bus and port chain, returns None if it was disconnected.
+    pub fn find_device_info(&self) -> Result<Option<DeviceInfo>, nusb::Error> {
+        Ok(nusb::list_devices().wait()?.find(|d| {
+            d.bus_id() == self.bridge.bus_id
+                && d.port_chain() == self.bridge.port_chain
+                && self.bridge.is(d.vendor_id(), d.product_id())
+        }))
+    }
+
+    /// Opens the bridge and claims the register access interface of `board`, `None` if the dongle was
+    /// disconnected.
+    pub fn open_bus(&self, board: &BoardProfile) -> Result<Option<BridgeBus>, DongleError> {
+        let Some(device_info) = self.find_device_info().map_err(DongleError::Usb)? else {
+            return Ok(None);
+        };
+        let device = device_info.open().wait().map_err(DongleError::Usb)?;
+        let interface = claim_control_interface(&device, board.interface)?;
+        Ok(Some(BridgeBus::new(interface, board.control_protocol)))
+    }
+
+    /// Devices behind the dongle's hub other than the bridge and FTDI, i.e. the device under test.
+    pub fn downstream_devices<'a>(&self, all_devices: &'a [UsbDevice]) -> Vec<&'a UsbDevice> {
+        let hub_chain = self.bridge.parent_port_chain();
+        let is_sibling = |d: &UsbDevice| {
+            d.port_chain == self.bridge.port_chain
+                || self
+                    .ftdi
+                    .as_ref()
+                    .is_some_and(|f| f.port_chain == d.port_chain)
+        };
+        all_devices
+            .iter()
+            .filter(|d| {
+                d.bus_id == self.bridge.bus_id
+                    && d.port_chain.len() > hub_chain.len()
+                    && d.port_chain.starts_with(hub_chain)
+                    && !is_sibling(d)
+            })
+            .collect()
+    }
+}
+
+/// Groups bridge devices with FTDI and hub devices of `board` sitting on the same hub, bridges without
+/// siblings are kept as dongles of their own.
+pub fn pair_dongles(all_devices: &[UsbDevice], board: &BoardProfile) -> Vec<DongleInfo> {
+    all_devices
+        .iter()
+        .filter(|d| d.is(board.bridge.vendor_id, board.bridge.product_id))
+        .map(|bridge| {
+            let same_hub = bridge.parent_port_chain();
+            let sibling = |id: UsbId| {
+                all_devices
+                    .iter()
+                    .find(|d| {
+                        d.bus_id == bridge.bus_id
+                            && d.port_chain.starts_with(same_hub)
+                            && d.is(id.vendor_id, id.product_id)
+                    })
+                    .cloned()
+            };
+            let dongle = DongleInfo {
+                bridge: bridge.clone(),
+                ftdi: sibling(board.ftdi),
+                hub: sibling(board.hub),
+            };
+            let missing = dongle.missing_siblings();
+            if !missing.is_empty() {
+                debug!(
+                    "Bridge at {}: no {} found, identified as {}",
+                    bridge.location(),
+                    missing.join(" and "),
+                    dongle.display_serial()
+                );
+            }
+            dongle
+        })
+        .collect()
+}
+
+/// Source of the connected USB devices, [list_usb_devices] unless the devices are simulated.
+pub type DeviceList = fn() -> Result<Vec<UsbDevice>, nusb::Error>;
+
+/// Lists all connected USB devices.
+pub fn list_usb_devices() -> Result<Vec<UsbDevice>, nusb::Error> {
+    Ok(nusb::list_devices()
+        .wait()?
+        .map(|d| UsbDevice::from(&d))
+        .collect())
+}
+
+/// Lists only the bridge devices, without looking for their FTDI and hub siblings.
+///
+/// Faster with many devices connected, but gives less information: no FTDI (label) serial, so dongles
+/// are identified by the bridge serial and USB location, and no relay variant detection from the hub.
+pub fn list_bridges_only(board: &BoardProfile) -> Result<Vec<UsbDevice>, nusb::Error> {
+    Ok(nusb::list_devices()
+        .wait()?
+        .filter(|d| board.bridge.matches(d.vendor_id(), d.product_id()))
+        .map(|d| UsbDevice::from(&d))
+        .collect())
+}
+
+/// Lists all connected dongles of `board`.
+pub fn list_dongles(board: &BoardProfile) -> Result<Vec<DongleInfo>, nusb::Error> {
+    Ok(pair_dongles(&list_usb_devices()?, board))
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SelectError {
+    NoDevices,
+    /// Several devices connected and no serial provided
+    SerialRequired,
+    /// Serial provided does not match any of the devices
+    NoMatch,
+    /// Serial provided matches more than one device
+    Ambiguous,
+    /// Serial provided matches several devices reporting the very same serial (e.g. unprogrammed FTDI chips),
+    /// they can only be told apart by USB location
+    DuplicateSerial,
+}
+
+/// Picks one dongle, `serial` is matched partially against both FTDI and bridge serials.
+pub fn select_dongle<'a>(
+    dongles: &'a [DongleInfo],
+    serial: Option<&SerialQuery>,
+) -> Result<&'a DongleInfo, SelectError> {
+    if dongles.is_empty() {
+        return Err(SelectError::NoDevices);
+    }
+    let Some(serial) = serial else {
+        return if dongles.len() == 1 {
+            Ok(&dongles[0])
+        } else {
+            Err(SelectError::SerialRequired)
+        };
+    };
+    let matches = dongles
+        .iter()
+        .filter(|d| d.matches_serial(serial))
+        .collect::<Vec<_>>();
+    match matches.as_slice() {
+        [] => Err(SelectError::NoMatch),
+        [dongle] => Ok(dongle),
+        [first, rest @ ..]
+            if rest
+                .iter()
+                .all(|d| d.display_serial() == first.display_serial()) =>
+        {
+            Err(SelectError::DuplicateSerial)
+        }
+        _ => Err(SelectError::Ambiguous),
+    }
+}
+
+/// Picks the dongle whose bridge is at `location`, as printed by [UsbDevice::location].
+pub fn select_by_location<'a>(
+    dongles: &'a [DongleInfo],
+    location: &str,
+) -> Result<&'a DongleInfo, SelectError> {
+    dongles
+        .iter()
+        .find(|d| d.bridge.location() == location)
+        .ok_or(SelectError::NoMatch)
+}
+
+/// Picks the dongle whose paired hub reports exactly `product` as product string.
+///
+/// Only tells dongles apart when their hub descriptors were customized (e.g. per fixture), stock hubs all
+/// report the same string and bridges without hub sibling never match.
+pub fn select_by_hub_product<'a>(
+    dongles: &'a [DongleInfo],
+    product: &str,
+) -> Result<&'a DongleInfo, SelectError> {
+    let matches = dongles
+        .iter()
+        .filter(|d| d.hub.is_some() && d.hub_product_string() == product.trim())
+        .collect::<Vec<_>>();
+    match matches.as_slice() {
+        [] => Err(SelectError::NoMatch),
+        [dongle] => Ok(dongle),
+        _ => Err(SelectError::Ambiguous),
+    }
+}
+
+/// True if at least two dongles report the same serial, so serials alone cannot tell them apart.
+pub fn has_duplicate_serials(dongles: &[DongleInfo]) -> bool {
+    dongles.iter().enumerate().any(|(i, a)| {
+        dongles[i + 1..]
+            .iter()
+            .any(|b| a.display_serial() == b.display_serial())
+    })
+}
+
+/// Vendor specific interface class, used by the bridge for its register access interface.
+pub const CLASS_VENDOR_SPECIFIC: u8 = 0xFF;
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct InterfaceSummary {
+    pub number: u8,
+    pub alt_setting: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub num_endpoints: u8,
+}
+
+impl fmt::Display for InterfaceSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "interface {} alt {}: class 0x{:02X}, subclass 0x{:02X}, protocol 0x{:02X}, {} endpoint(s)",
+            self.number,
+            self.alt_setting,
+            self.class,
+            self.subclass,
+            self.protocol,
+            self.num_endpoints
+        )
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct ConfigurationLayout {
+    pub configuration_value: u8,
+    pub active: bool,
+    pub interfaces: Vec<InterfaceSummary>,
+}
+
+/// Reads all configurations of the device and their interfaces.
+pub fn device_layout(device: &Device) -> Vec<ConfigurationLayout> {
+    let active = device
+        .active_configuration()
+        .ok()
+        .map(|c| c.configuration_value());
+    device
+        .configurations()
+        .map(|c| ConfigurationLayout {
+            configuration_value: c.configuration_value(),
+            active: Some(c.configuration_value()) == active,
+            interfaces: c
+                .interface_alt_settings()
+                .map(|i| InterfaceSummary {
+                    number: i.interface_number(),
+                    alt_setting: i.alternate_setting(),
+                    class: i.class(),
+                    subclass: i.subclass(),
+                    protocol: i.protocol(),
+                    num_endpoints: i.num_endpoints(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Picks the interface used for register access from the active configuration.
+///
+/// Interface 0 is used if it is vendor specific (or the only one), otherwise the first vendor specific interface.
+pub fn control_interface_number(layout: &[ConfigurationLayout]) -> Result<u8, DongleError> {
+    let interfaces = layout
+        .iter()
+        .find(|c| c.active)
+        .map(|c| c.interfaces.as_slice())
+        .unwrap_or_default();
+    let vendor_specific = |i: &&InterfaceSummary| i.class == CLASS_VENDOR_SPECIFIC;
+    if interfaces
+        .iter()
+        .filter(|i| i.number == 0)
+        .any(|i| vendor_specific(&i))
+    {
+        return Ok(0);
+    }
+    if let Some(interface) = interfaces.iter().find(vendor_specific) {
+        return Ok(interface.number);
+    }
+    let mut numbers = interfaces.iter().map(|i| i.number).collect::<Vec<_>>();
+    numbers.dedup();
+    match numbers.as_slice() {
+        [] => Ok(0),
+        [single] => Ok(*single),
+        _ => Err(DongleError::NoControlInterface {
+            interfaces: interfaces.to_vec(),
+        }),
+    }
+}
+
+/// Inspects the active configuration and claims the register access interface, `interface` if given (see
+/// [BoardProfile::interface]).
+pub fn claim_control_interface(
+    device: &Device,
+    interface: Option<u8>,
+) -> Result<Interface, DongleError> {
+    let layout = device_layout(device);
+    for configuration in &layout {
+        debug!(
+            "configuration {}{}",
+            configuration.configuration_value,
+            if configuration.active {
+                " (active)"
+            } else {
+                ""
+            }
+        );
+        for interface in &configuration.interfaces {
+            debug!("  {interface}");
+        }
+    }
+    let number = match interface {
+        Some(number) => number,
+        None => control_interface_number(&layout)?,
+    };
+    debug!("using interface {number} for register access");
+    device
+        .claim_interface(number)
+        .wait()
+        .map_err(DongleError::Usb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(port_chain: &[u8], vendor_id: u16, product_id: u16) -> UsbDevice {
+        UsbDevice {
+            bus_id: "1".into(),
+            port_chain: port_chain.to_vec(),
+            vendor_id,
+            product_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn downstream_devices_exclude_dongle_parts() {
+        let all_devices = vec![
+            device(&[2], VENDOR_SMSC, PRODUCT_USB4604_HUB),
+            device(&[2, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV),
+            device(&[2, 2], VENDOR_FTDI, PRODUCT_FT234),
+            device(&[2, 3], 0x1234, 0x0001),
+            device(&[3], 0x1234, 0x0002),
+        ];
+        let dongles = pair_dongles(&all_devices, &BoardProfile::REFERENCE);
+        let downstream = dongles[0].downstream_devices(&all_devices);
+        assert_eq!(downstream, vec![&all_devices[3]]);
+    }
+
+    #[test]
+    fn bridge_without_siblings_is_a_dongle_identified_by_location() {
+        let all_devices = vec![
+            device(&[2, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV),
+            UsbDevice {
+                bus_id: "3".into(),
+                ..device(&[2, 2], VENDOR_FTDI, PRODUCT_FT234)
+            },
+        ];
+        let dongles = pair_dongles(&all_devices, &BoardProfile::REFERENCE);
+        assert_eq!(dongles.len(), 1);
+        let dongle = &dongles[0];
+        assert_eq!(dongle.missing_siblings(), vec!["FTDI", "hub"]);
+        assert_eq!(dongle.display_serial(), "1-2.1");
+        assert_eq!(dongle.file_id(), "1_2_1");
+        assert_eq!(dongle.relay_count(), 0);
+        assert_eq!(
+            select_dongle(&dongles, None).unwrap().bridge.port_chain,
+            vec![2, 1]
+        );
+
+        let with_serial = UsbDevice {
+            serial_number: Some("B0001".into()),
+            ..device(&[2, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV)
+        };
+        assert_eq!(pair_dongles(&[with_serial], &BoardProfile::REFERENCE)[0].display_serial(), "B0001");
+    }
+
+    #[test]
+    fn hub_product_selects_by_exact_string() {
+        let hub = |port_chain: &[u8], product: &str| UsbDevice {
+            product_string: Some(product.into()),
+            ..device(port_chain, VENDOR_SMSC, PRODUCT_USB4604_HUB)
+        };
+        let all_devices = vec![
+            hub(&[2], "RM dongle relay"),
+            device(&[2, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV),
+            hub(&[3], "RM dongle relay2"),
+            device(&[3, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV),
+            hub(&[4], "RM dongle relay2"),
+            device(&[4, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV),
+        ];
+        let dongles = pair_dongles(&all_devices, &BoardProfile::REFERENCE);
+        let selected = select_by_hub_product(&dongles, "RM dongle relay").unwrap();
+        assert_eq!(selected.bridge.port_chain, vec![2, 1]);
+        assert_eq!(
+            select_by_hub_product(&dongles, "RM dongle relay2").unwrap_err(),
+            SelectError::Ambiguous
+        );
+        assert_eq!(
+            select_by_hub_product(&dongles, "RM dongle").unwrap_err(),
+            SelectError::NoMatch
+        );
+    }
+
+    #[test]
+    fn identical_serials_are_told_apart_by_location() {
+        let ftdi = |port_chain: &[u8]| UsbDevice {
+            serial_number: Some("FT000001".into()),
+            ..device(port_chain, VENDOR_FTDI, PRODUCT_FT234)
+        };
+        let all_devices = vec![
+            device(&[2, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV),
+            ftdi(&[2, 2]),
+            device(&[3, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV),
+            ftdi(&[3, 2]),
+        ];
+        let dongles = pair_dongles(&all_devices, &BoardProfile::REFERENCE);
+        assert!(has_duplicate_serials(&dongles));
+        assert_eq!(
+            select_dongle(&dongles, Some(&SerialQuery::resolve(&dongles, "FT000001"))).unwrap_err(),
+            SelectError::DuplicateSerial
+        );
+        let second = select_by_location(&dongles, "1-3.1").unwrap();
+        assert_eq!(second.bridge.port_chain, vec![3, 1]);
+    }
+
+    #[test]
+    fn serial_is_matched_against_the_serial_of_its_kind() {
+        let with_serial = |serial: &str, port_chain: &[u8], vendor_id, product_id| UsbDevice {
+            serial_number: Some(serial.into()),
+            ..device(port_chain, vendor_id, product_id)
+        };
+        let all_devices = vec![
+            with_serial("AB12", &[2, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV),
+            with_serial("FT0001", &[2, 2], VENDOR_FTDI, PRODUCT_FT234),
+            with_serial("FT0001X", &[3, 1], VENDOR_SMSC, PRODUCT_BRIDGE_DEV),
+            with_serial("FT0002", &[3, 2], VENDOR_FTDI, PRODUCT_FT234),
+        ];
+        let dongles = pair_dongles(&all_devices, &BoardProfile::REFERENCE);
+        // the label serial wins over a bridge serial containing it
+        let query = SerialQuery::resolve(&dongles, "FT0001");
+        assert_eq!(query, SerialQuery::Ftdi(FtdiSerial("FT0001".into())));
+        let selected = select_dongle(&dongles, Some(&query)).unwrap();
+        assert_eq!(selected.bridge.port_chain, vec![2, 1]);
+        let query = SerialQuery::resolve(&dongles, "AB");
+        assert_eq!(query, SerialQuery::Bridge(DongleSerial("AB".into())));
+        let selected = select_dongle(&dongles, Some(&query)).unwrap();
+        assert_eq!(selected.bridge.port_chain, vec![2, 1]);
+    }
+}
@@ -0,0 +1,276 @@
+//! Linux setup diagnostics: udev rules, group membership and device node permissions, and which serial port
+//! (`/dev/ttyUSB*`) belongs to which dongle.
+
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use nusb::MaybeFuture;
+
+use crate::board::BoardProfile;
+use crate::discovery::{DongleInfo, list_dongles};
+
+const UDEV_RULES_DIR: &str = "/etc/udev/rules.d";
+const SYSFS_USB_DEVICES: &str = "/sys/bus/usb/devices";
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Diagnostic {
+    pub ok: bool,
+    pub message: String,
+}
+
+impl Diagnostic {
+    fn ok(message: impl Into<String>) -> Self {
+        Diagnostic {
+            ok: true,
+            message: message.into(),
+        }
+    }
+
+    fn problem(message: impl Into<String>) -> Self {
+        Diagnostic {
+            ok: false,
+            message: message.into(),
+        }
+    }
+}
+
+/// Runs all checks and returns one diagnostic per finding.
+pub fn run_checks(board: &BoardProfile) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    diagnostics.extend(check_udev_rules(Path::new(UDEV_RULES_DIR), board));
+    diagnostics.push(check_plugdev());
+    diagnostics.extend(check_device_nodes(board));
+    diagnostics.extend(check_ftdi_ports(board));
+    diagnostics
+}
+
+fn rule_matches(contents: &str, vid: u16, pid: u16) -> bool {
+    let contents = contents.to_ascii_lowercase();
+    contents.lines().any(|line| {
+        !line.trim_start().starts_with('#')
+            && line.contains(&format!("attrs{{idvendor}}==\"{vid:04x}\""))
+            && line.contains(&format!("attrs{{idproduct}}==\"{pid:04x}\""))
+    })
+}
+
+fn check_udev_rules(dir: &Path, board: &BoardProfile) -> Vec<Diagnostic> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return vec![Diagnostic::problem(format!(
+                "Cannot read {}: {e}",
+                dir.display()
+            ))];
+        }
+    };
+    let rule_files = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "rules"))
+        .filter_map(|p| fs::read_to_string(&p).ok().map(|c| (p, c)))
+        .collect::<Vec<(PathBuf, String)>>();
+    [("bridge", board.bridge), ("FTDI", board.ftdi)]
+        .iter()
+        .map(|(name, id)| {
+            let (vid, pid) = (&id.vendor_id, &id.product_id);
+            match rule_files
+                .iter()
+                .find(|(_, contents)| rule_matches(contents, *vid, *pid))
+            {
+                Some((path, _)) => Diagnostic::ok(format!(
+                    "udev rule for the {name} device ({vid:04x}:{pid:04x}) found in {}",
+                    path.display()
+                )),
+                None => Diagnostic::problem(format!(
+                    "No udev rule for the {name} device ({vid:04x}:{pid:04x}) in {}, install it with:\n  \
+                     mchp_gpio_ctl udev | sudo tee {}/70-rm_dongle.rules\n  \
+                     sudo udevadm control --reload-rules && sudo udevadm trigger",
+                    dir.display(),
+                    dir.display()
+                )),
+            }
+        })
+        .collect()
+}
+
+fn check_plugdev() -> Diagnostic {
+    let Some(user) = std::env::var("USER").ok().filter(|u| !u.is_empty()) else {
+        return Diagnostic::problem("Cannot determine current user, USER is not set");
+    };
+    let groups = fs::read_to_string("/etc/group").unwrap_or_default();
+    let in_plugdev = groups.lines().any(|line| {
+        let mut fields = line.split(':');
+        fields.next() == Some("plugdev")
+            && fields
+                .nth(2)
+                .is_some_and(|members| members.split(',').any(|m| m == user))
+    });
+    if in_plugdev {
+        Diagnostic::ok(format!("User '{user}' is in the plugdev group"))
+    } else {
+        Diagnostic::problem(format!(
+            "User '{user}' is not in the plugdev group, this is fine for local sessions (uaccess), \
+             otherwise run: sudo usermod -aG plugdev {user} and log in again"
+        ))
+    }
+}
+
+fn check_device_nodes(board: &BoardProfile) -> Vec<Diagnostic> {
+    let devices = match nusb::list_devices().wait() {
+        Ok(devices) => devices,
+        Err(e) => {
+            return vec![Diagnostic::problem(format!(
+                "Failed to list USB devices: {e}"
+            ))];
+        }
+    };
+    let bridges = devices
+        .filter(|d| board.bridge.matches(d.vendor_id(), d.product_id()))
+        .collect::<Vec<_>>();
+    if bridges.is_empty() {
+        return vec![Diagnostic::problem(
+            "No bridge devices found, connect a dongle to check device node permissions",
+        )];
+    }
+    bridges
+        .iter()
+        .map(|d| {
+            let node = format!(
+                "/dev/bus/usb/{:03}/{:03}",
+                d.busnum(),
+                d.device_address()
+            );
+            match OpenOptions::new().read(true).write(true).open(&node) {
+                Ok(_) => Diagnostic::ok(format!("{node} is accessible")),
+                Err(e) => Diagnostic::problem(format!(
+                    "{node} is not accessible ({e}), udev rules were probably not reloaded, run:\n  \
+                     sudo udevadm control --reload-rules && sudo udevadm trigger\n  \
+                     and reconnect the dongle"
+                )),
+            }
+        })
+        .collect()
+}
+
+/// Sysfs directories of the FTDI's interfaces, e.g. `/sys/bus/usb/devices/1-2.3:1.0`.
+fn ftdi_interface_dirs(sysfs: &Path, info: &DongleInfo) -> Vec<PathBuf> {
+    let Some(ftdi) = &info.ftdi else {
+        return Vec::new();
+    };
+    let prefix = format!("{}:", ftdi.location());
+    let Ok(entries) = fs::read_dir(sysfs) else {
+        return Vec::new();
+    };
+    let mut dirs = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|e| e.path())
+        .collect::<Vec<_>>();
+    dirs.sort();
+    dirs
+}
+
+fn tty_in(interface_dir: &Path) -> Option<PathBuf> {
+    let is_tty = |name: &str| name.starts_with("ttyUSB") || name.starts_with("ttyACM");
+    let names = |dir: &Path| {
+        fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+    // ftdi_sio puts the tty directly in the interface, cdc_acm in a `tty` subdirectory
+    names(interface_dir)
+        .into_iter()
+        .chain(names(&interface_dir.join("tty")))
+        .find(|name| is_tty(name))
+        .map(|name| Path::new("/dev").join(name))
+}
+
+fn ftdi_tty_path_in(sysfs: &Path, info: &DongleInfo) -> Option<PathBuf> {
+    ftdi_interface_dirs(sysfs, info)
+        .iter()
+        .find_map(|dir| tty_in(dir))
+}
+
+fn ftdi_driver_in(sysfs: &Path, info: &DongleInfo) -> Option<String> {
+    ftdi_interface_dirs(sysfs, info).iter().find_map(|dir| {
+        let driver = fs::read_link(dir.join("driver")).ok()?;
+        Some(driver.file_name()?.to_string_lossy().into_owned())
+    })
+}
+
+/// Serial port device node of the dongle's FTDI (e.g. `/dev/ttyUSB0`), matched by USB location;
+/// `None` if the FTDI was not found or is not bound to a serial driver.
+pub fn ftdi_tty_path(info: &DongleInfo) -> Option<PathBuf> {
+    ftdi_tty_path_in(Path::new(SYSFS_USB_DEVICES), info)
+}
+
+/// Kernel driver bound to the dongle's FTDI, usually `ftdi_sio`.
+pub fn ftdi_driver(info: &DongleInfo) -> Option<String> {
+    ftdi_driver_in(Path::new(SYSFS_USB_DEVICES), info)
+}
+
+/// One line explaining who owns the dongle's FTDI, `None` if the dongle has no FTDI.
+pub fn ftdi_port_note(info: &DongleInfo) -> Option<String> {
+    info.ftdi.as_ref()?;
+    Some(match (ftdi_driver(info), ftdi_tty_path(info)) {
+        (Some(driver), Some(tty)) => format!(
+            "FTDI serial port is {} (bound to {driver}), this is expected and does not affect control \
+             through the bridge",
+            tty.display()
+        ),
+        (Some(driver), None) => format!("FTDI is bound to {driver}, no serial port found"),
+        (None, _) => "FTDI is not bound to any driver, no serial port".to_string(),
+    })
+}
+
+fn check_ftdi_ports(board: &BoardProfile) -> Vec<Diagnostic> {
+    let Ok(dongles) = list_dongles(board) else {
+        return Vec::new();
+    };
+    dongles
+        .iter()
+        .filter_map(|d| {
+            let note = ftdi_port_note(d)?;
+            Some(Diagnostic::ok(format!(
+                "Dongle {}: {note}",
+                d.display_serial()
+            )))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::UsbDevice;
+
+    #[test]
+    fn ftdi_tty_is_found_by_usb_location() {
+        let sysfs = std::env::temp_dir().join(format!("mchp_sysfs_{}", std::process::id()));
+        fs::create_dir_all(sysfs.join("1-2.3:1.0/ttyUSB4")).unwrap();
+        fs::create_dir_all(sysfs.join("1-2.30:1.0/ttyUSB7")).unwrap();
+        fs::create_dir_all(sysfs.join("drivers/ftdi_sio")).unwrap();
+        std::os::unix::fs::symlink(
+            sysfs.join("drivers/ftdi_sio"),
+            sysfs.join("1-2.3:1.0/driver"),
+        )
+        .unwrap();
+        let info = DongleInfo {
+            bridge: UsbDevice::default(),
+            ftdi: Some(UsbDevice {
+                bus_id: "1".into(),
+                port_chain: vec![2, 3],
+                ..UsbDevice::default()
+            }),
+            hub: None,
+        };
+        let tty = ftdi_tty_path_in(&sysfs, &info);
+        let driver = ftdi_driver_in(&sysfs, &info);
+        fs::remove_dir_all(&sysfs).unwrap();
+        assert_eq!(tty, Some(PathBuf::from("/dev/ttyUSB4")));
+        assert_eq!(driver.as_deref(), Some("ftdi_sio"));
+    }
+}
@@ -1,49 +1,442 @@
-use nusb::Interface;
+use std::fmt;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
+use clap::ValueEnum;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::dongle_hal_revc::{PinMode, PinState};
+use crate::error::DongleError;
+use crate::hub_port::{DUT_HUB_PORT, HubPortPower, hub_port_power, hub_port_power_get};
+use crate::sequencing;
+use crate::signals::{ElectricalLevel, Signal};
 use crate::usb4604_ral::{
-    Gpio0_7Dir, Gpio0_7Output, Gpio8_10Dir, Gpio8_10Input, modify_reg, read_reg,
+    Gpio0_7Dir, Gpio0_7Input, Gpio0_7Output, Gpio8_10Dir, Gpio8_10Input, RegisterBus, modify_reg,
+    read_reg, write_reg,
 };
 
 // RevA and RevB board:
 // PIO0 - PWR_EN_N
 // PIO10 - PWR_FAIL_N
 
-/// Controls the power switch that provides power to a connected device.
-pub fn dev_power_ctl(interface: &Interface, pwr_on: bool) {
-    modify_reg::<Gpio0_7Dir, _>(interface, |dir| {
+/// Which of the two power gates in front of the device under test `on` / `off` switch.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerPath {
+    /// Board load switch enabled by PIO0 (PWR_EN_N): current limited, reports overcurrent on PWR_FAIL_N and
+    /// follows the external pull on PIO0 after reset (on, with the reference active low enable)
+    #[default]
+    Board,
+    /// The hub's port power output (PRTPWR) of the DUT port, forced through its port power select register:
+    /// no fault reporting, and it only cuts VBUS on boards routing that output to a port power switch
+    Hub,
+}
+
+/// Controls the power switch that provides power to a connected device, through the [PowerPath] of the bus
+/// policy.
+///
+/// With [sequencing] enforced, the USB switch is disconnected before power is turned off.
+pub fn dev_power_ctl(bus: &dyn RegisterBus, pwr_on: bool) -> Result<(), DongleError> {
+    dev_power_ctl_via(bus, bus.policy().power_path, pwr_on)
+}
+
+/// [dev_power_ctl] through an explicit `path`, the other gate is left as is.
+pub fn dev_power_ctl_via(
+    bus: &dyn RegisterBus,
+    path: PowerPath,
+    pwr_on: bool,
+) -> Result<(), DongleError> {
+    if !pwr_on && bus.policy().enforce_sequencing {
+        sequencing::before_power_off(bus)?;
+    }
+    if path == PowerPath::Hub {
+        return hub_port_power(bus, DUT_HUB_PORT, pwr_on);
+    }
+    let polarity = bus.policy().polarity;
+    modify_reg::<Gpio0_7Dir, _>(bus, |dir| {
+        dir.set_gpio0_out_en(true);
+    })?;
+    modify_reg::<Gpio0_7Output, _>(bus, |out| {
+        out.set_gpio0_out(Signal::PowerOn.level(polarity, pwr_on).bit());
+    })
+}
+
+/// Turns power off with no state checks, for the emergency kill switch.
+///
+/// The off level is latched before the pin is switched to output, so there is no glitch to on when
+/// the pin was an input before.
+pub fn emergency_power_off(bus: &dyn RegisterBus) -> Result<(), DongleError> {
+    let polarity = bus.policy().polarity;
+    modify_reg::<Gpio0_7Output, _>(bus, |out| {
+        out.set_gpio0_out(Signal::PowerOn.level(polarity, false).bit());
+    })?;
+    modify_reg::<Gpio0_7Dir, _>(bus, |dir| dir.set_gpio0_out_en(true))
+}
+
+/// Raw access to PIO0 (PWR_EN_N) for recovery and debugging, levels are electrical and the polarity of the bus
+/// policy is not applied: High turns power off with the reference active low enable, on with
+/// [crate::signals::Polarity::power_active_high].
+///
+/// The level is latched before the direction is changed.
+pub fn pwr_en_raw_set(
+    bus: &dyn RegisterBus,
+    mode: Option<PinMode>,
+    level: Option<PinState>,
+) -> Result<(), DongleError> {
+    if let Some(level) = level {
+        modify_reg::<Gpio0_7Output, _>(bus, |out| out.set_gpio0_out(level == PinState::High))?;
+    }
+    if let Some(mode) = mode {
+        modify_reg::<Gpio0_7Dir, _>(bus, |dir| dir.set_gpio0_out_en(mode == PinMode::Output))?;
+    }
+    Ok(())
+}
+
+/// Raw PIO0 direction and output latch level, see [pwr_en_raw_set].
+pub fn pwr_en_raw_get(bus: &dyn RegisterBus) -> Result<(PinMode, PinState), DongleError> {
+    let mode = if read_reg::<Gpio0_7Dir>(bus)?.gpio0_out_en() {
+        PinMode::Output
+    } else {
+        PinMode::Input
+    };
+    let level = if read_reg::<Gpio0_7Output>(bus)?.gpio0_out() {
+        PinState::High
+    } else {
+        PinState::Low
+    };
+    Ok((mode, level))
+}
+
+/// PWM period used by [soft_power_on].
+const SOFT_START_PERIOD: Duration = Duration::from_millis(5);
+
+/// Turns power on gradually by PWM-ing PWR_EN_N with a duty cycle increasing from 0 to 100% over `ramp`.
+///
+/// Every edge is a separate control transfer, so timing precision is limited by USB latency
+/// (around a millisecond per write at best). This is a crude inrush limiter, not a real soft-start.
+pub fn soft_power_on(bus: &dyn RegisterBus, ramp: Duration) -> Result<(), DongleError> {
+    modify_reg::<Gpio0_7Dir, _>(bus, |dir| {
         dir.set_gpio0_out_en(true);
-    });
-    modify_reg::<Gpio0_7Output, _>(interface, |out| {
-        out.set_gpio0_out(!pwr_on); // power switch is inverting
-    });
+    })?;
+    let mut out = read_reg::<Gpio0_7Output>(bus)?;
+    let on = Signal::PowerOn.level(bus.policy().polarity, true).bit();
+    let periods = (ramp.as_micros() / SOFT_START_PERIOD.as_micros()).max(1) as u32;
+    for i in 0..periods {
+        let on_time = SOFT_START_PERIOD * i / periods;
+        if !on_time.is_zero() {
+            out.set_gpio0_out(on);
+            write_reg(bus, out)?;
+            sleep(on_time);
+        }
+        out.set_gpio0_out(!on);
+        write_reg(bus, out)?;
+        sleep(SOFT_START_PERIOD - on_time);
+    }
+    out.set_gpio0_out(on);
+    write_reg(bus, out)
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum PowerState {
+    On,
+    Off,
+    /// PIO0 is not an output yet (nothing was written since reset), power follows the external pull on the pad
+    /// and the output latch does not reflect it
+    Unknown,
 }
 
-/// Returns true if power to a connected device is on, default is on in hardware.
-pub fn is_dev_power_on(interface: &Interface) -> bool {
-    // pin is pulled down with a resistor, even if called after reset (and PIO0 is an input), this should yield correct result
-    !read_reg::<Gpio0_7Output>(interface).gpio0_out()
+/// Returns the power state as driven by PIO0, or [PowerState::Unknown] if PIO0 was not configured as output yet.
+pub fn power_state(bus: &dyn RegisterBus) -> Result<PowerState, DongleError> {
+    if !read_reg::<Gpio0_7Dir>(bus)?.gpio0_out_en() {
+        return Ok(PowerState::Unknown);
+    }
+    let level = ElectricalLevel::from_bit(read_reg::<Gpio0_7Output>(bus)?.gpio0_out());
+    if Signal::PowerOn.is_active(bus.policy().polarity, level) {
+        Ok(PowerState::On)
+    } else {
+        Ok(PowerState::Off)
+    }
+}
+
+/// Switches power only if it is not already in the requested state (also configuring PIO0 as output),
+/// returns true if anything was written.
+pub fn dev_power_ensure(bus: &dyn RegisterBus, pwr_on: bool) -> Result<bool, DongleError> {
+    let desired = if pwr_on {
+        PowerState::On
+    } else {
+        PowerState::Off
+    };
+    let current = match bus.policy().power_path {
+        PowerPath::Board => power_state(bus)?,
+        PowerPath::Hub => match hub_port_power_get(bus, DUT_HUB_PORT)? {
+            HubPortPower::On => PowerState::On,
+            HubPortPower::Off => PowerState::Off,
+            HubPortPower::HostControlled => PowerState::Unknown,
+        },
+    };
+    if current == desired {
+        debug!("Power already {desired:?}, write skipped");
+        return Ok(false);
+    }
+    dev_power_ctl(bus, pwr_on)?;
+    Ok(true)
+}
+
+/// Returns true if power to a connected device is on through the [PowerPath] of the bus policy.
+///
+/// Before PIO0 is configured as output, the pad level set by the external pull is read and interpreted with the
+/// polarity of the bus policy.
+pub fn is_dev_power_on(bus: &dyn RegisterBus) -> Result<bool, DongleError> {
+    is_dev_power_on_via(bus, bus.policy().power_path)
+}
+
+pub fn is_dev_power_on_via(bus: &dyn RegisterBus, path: PowerPath) -> Result<bool, DongleError> {
+    match path {
+        PowerPath::Board => match power_state(bus)? {
+            PowerState::On => Ok(true),
+            PowerState::Off => Ok(false),
+            // output latch is meaningless while PIO0 is still an input after reset, the pad shows the pulled level
+            PowerState::Unknown => {
+                let level = ElectricalLevel::from_bit(read_reg::<Gpio0_7Input>(bus)?.gpio0_in());
+                Ok(Signal::PowerOn.is_active(bus.policy().polarity, level))
+            }
+        },
+        // a host controlled port is powered while the hub is configured
+        PowerPath::Hub => Ok(hub_port_power_get(bus, DUT_HUB_PORT)? != HubPortPower::Off),
+    }
 }
 
 /// Returns true if there is a power failure (most likely a short on the output to a device).
-pub fn is_dev_pwr_fault(interface: &Interface) -> bool {
-    modify_reg::<Gpio8_10Dir, _>(interface, |dir| {
+pub fn is_dev_pwr_fault(bus: &dyn RegisterBus) -> Result<bool, DongleError> {
+    modify_reg::<Gpio8_10Dir, _>(bus, |dir| {
         dir.set_gpio10_out_en(false);
-    });
-    // fault is inverted
-    !read_reg::<Gpio8_10Input>(interface).gpio10_in()
+    })?;
+    let level = ElectricalLevel::from_bit(read_reg::<Gpio8_10Input>(bus)?.gpio10_in());
+    Ok(Signal::PowerFault.is_active(bus.policy().polarity, level))
 }
 
-#[derive(Debug)]
+/// Like [is_dev_pwr_fault], but never changes pin direction, returns `None` if PIO10 is not an input.
+pub fn read_dev_pwr_fault(bus: &dyn RegisterBus) -> Result<Option<bool>, DongleError> {
+    if read_reg::<Gpio8_10Dir>(bus)?.gpio10_out_en() {
+        return Ok(None);
+    }
+    let level = ElectricalLevel::from_bit(read_reg::<Gpio8_10Input>(bus)?.gpio10_in());
+    Ok(Some(Signal::PowerFault.is_active(bus.policy().polarity, level)))
+}
+
+/// Like [is_dev_pwr_fault], but only reports a fault after `samples` consecutive fault reads, `interval` apart,
+/// to suppress single sample glitches during power transitions. Returns on the first fault-free read.
+pub fn is_dev_pwr_fault_debounced(
+    bus: &dyn RegisterBus,
+    samples: u32,
+    interval: Duration,
+) -> Result<bool, DongleError> {
+    for i in 0..samples.max(1) {
+        if i > 0 {
+            sleep(interval);
+        }
+        if !is_dev_pwr_fault(bus)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// How often [wait_fault_free] samples the fault pin.
+const FAULT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Consecutive fault reads [wait_fault_free] needs to consider power faulted.
+const FAULT_DEBOUNCE_SAMPLES: u32 = 3;
+
+/// Polls [is_dev_pwr_fault_debounced] until no fault was seen for `stable`, so transient inrush faults are ignored.
+///
+/// Returns false if that did not happen within `timeout`.
+pub fn wait_fault_free(
+    bus: &dyn RegisterBus,
+    stable: Duration,
+    timeout: Duration,
+) -> Result<bool, DongleError> {
+    let start = Instant::now();
+    let mut fault_free_since = start;
+    loop {
+        let now = Instant::now();
+        if is_dev_pwr_fault_debounced(bus, FAULT_DEBOUNCE_SAMPLES, FAULT_POLL_INTERVAL)? {
+            fault_free_since = now;
+        } else if now - fault_free_since >= stable {
+            return Ok(true);
+        }
+        if now - start >= timeout {
+            return Ok(false);
+        }
+        sleep(FAULT_POLL_INTERVAL);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize, ValueEnum)]
 pub enum PcbRevision {
+    /// RevA and RevB can not be told apart, both are accepted for it
+    #[value(name = "ab", aliases = ["reva", "revb", "revaorb"])]
     RevAorB,
+    #[value(name = "c", alias = "revc")]
     RevC,
 }
 
-pub fn pcb_revision(interface: &Interface) -> PcbRevision {
-    let is_revc = read_reg::<Gpio8_10Input>(interface).gpio9_in();
+impl PcbRevision {
+    /// Identifier for machine readable field and label values, the same as `pcb_revision` in JSON, kept when
+    /// the display name changes.
+    pub fn label(self) -> &'static str {
+        match self {
+            PcbRevision::RevAorB => "RevAorB",
+            PcbRevision::RevC => "RevC",
+        }
+    }
+}
+
+/// User facing name, stable for parsers, unlike the `Debug` representation.
+impl fmt::Display for PcbRevision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcbRevision::RevAorB => f.write_str("Rev A/B"),
+            PcbRevision::RevC => f.write_str("Rev C"),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ParsePcbRevisionError(pub String);
+
+impl fmt::Display for ParsePcbRevisionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unknown PCB revision '{}', expected one of reva, revb, revaorb, revc",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParsePcbRevisionError {}
+
+/// Case-insensitive, accepts the `Display` strings and the CLI names, for config and fixture files.
+impl FromStr for PcbRevision {
+    type Err = ParsePcbRevisionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let normalized = s
+            .chars()
+            .filter(|c| !c.is_whitespace() && *c != '/')
+            .collect::<String>()
+            .to_ascii_lowercase();
+        match normalized.as_str() {
+            "reva" | "revb" | "revaorb" | "revab" | "ab" => Ok(PcbRevision::RevAorB),
+            "revc" | "c" => Ok(PcbRevision::RevC),
+            _ => Err(ParsePcbRevisionError(s.to_string())),
+        }
+    }
+}
+
+pub fn pcb_revision(bus: &dyn RegisterBus) -> Result<PcbRevision, DongleError> {
+    let is_revc = read_reg::<Gpio8_10Input>(bus)?.gpio9_in();
     if is_revc {
-        PcbRevision::RevC
+        Ok(PcbRevision::RevC)
     } else {
-        PcbRevision::RevAorB
+        Ok(PcbRevision::RevAorB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Policy;
+    use crate::signals::Polarity;
+    use crate::usb4604_ral::{MockBus, SmscReg};
+
+    #[test]
+    fn pcb_revision_display_strings_are_stable() {
+        assert_eq!(PcbRevision::RevAorB.to_string(), "Rev A/B");
+        assert_eq!(PcbRevision::RevC.to_string(), "Rev C");
+        for revision in [PcbRevision::RevAorB, PcbRevision::RevC] {
+            let json = serde_json::to_string(&revision).unwrap();
+            assert_eq!(json, format!("\"{}\"", revision.label()));
+        }
+    }
+
+    #[test]
+    fn pcb_revision_parses_display_and_cli_names() {
+        for revision in [PcbRevision::RevAorB, PcbRevision::RevC] {
+            assert_eq!(revision.to_string().parse::<PcbRevision>(), Ok(revision));
+        }
+        for s in ["reva", "RevB", "REVAORB", "ab"] {
+            assert_eq!(s.parse::<PcbRevision>(), Ok(PcbRevision::RevAorB));
+        }
+        assert_eq!("RevC".parse::<PcbRevision>(), Ok(PcbRevision::RevC));
+        assert_eq!(
+            "revd".parse::<PcbRevision>(),
+            Err(ParsePcbRevisionError("revd".to_string()))
+        );
+    }
+
+    #[test]
+    fn debounced_fault_ignores_single_sample_glitch() {
+        let bus = MockBus::new();
+        // PWR_FAIL_N is active low: 0 is a fault sample
+        let ok = Gpio8_10Input::new().with_gpio10_in(true).value();
+        bus.set(Gpio8_10Input::ADDR, ok);
+        bus.script_reads(Gpio8_10Input::ADDR, &[0, ok, 0, 0, 0]);
+        assert!(!is_dev_pwr_fault_debounced(&bus, 3, Duration::ZERO).unwrap());
+        assert!(is_dev_pwr_fault_debounced(&bus, 3, Duration::ZERO).unwrap());
+        assert!(!is_dev_pwr_fault(&bus).unwrap());
+    }
+
+    #[test]
+    fn hub_power_path_leaves_board_switch_alone() {
+        let bus = MockBus::new();
+        dev_power_ctl_via(&bus, PowerPath::Hub, false).unwrap();
+        assert_eq!(bus.writes(), vec![(0x3C08, 0b0010_0000)]);
+        assert!(!is_dev_power_on_via(&bus, PowerPath::Hub).unwrap());
+        assert!(is_dev_power_on_via(&bus, PowerPath::Board).unwrap());
+        dev_power_ctl_via(&bus, PowerPath::Hub, true).unwrap();
+        assert!(is_dev_power_on_via(&bus, PowerPath::Hub).unwrap());
+        assert_eq!(bus.get(Gpio0_7Dir::ADDR), 0);
+    }
+
+    #[test]
+    fn active_high_power_enable_drives_pio0_high_for_on() {
+        let bus = MockBus::new();
+        bus.set_policy(Policy {
+            polarity: Polarity {
+                power_active_high: true,
+                ..Polarity::default()
+            },
+            ..Policy::default()
+        });
+        dev_power_ctl(&bus, true).unwrap();
+        assert_eq!(bus.get(Gpio0_7Output::ADDR) & 1, 1);
+        assert!(is_dev_power_on(&bus).unwrap());
+        emergency_power_off(&bus).unwrap();
+        assert_eq!(bus.get(Gpio0_7Output::ADDR) & 1, 0);
+        assert!(!is_dev_power_on(&bus).unwrap());
+    }
+
+    #[test]
+    fn unconfigured_power_enable_reads_the_pulled_pad_level() {
+        let bus = MockBus::new();
+        // PIO0 is an input after reset, pulled low: on with the reference polarity
+        assert!(is_dev_power_on(&bus).unwrap());
+        bus.set_policy(Policy {
+            polarity: Polarity {
+                power_active_high: true,
+                ..Polarity::default()
+            },
+            ..Policy::default()
+        });
+        assert!(!is_dev_power_on(&bus).unwrap());
+        bus.set(
+            Gpio0_7Input::ADDR,
+            Gpio0_7Input::new().with_gpio0_in(true).value(),
+        );
+        assert!(is_dev_power_on(&bus).unwrap());
+        assert!(bus.writes().is_empty());
     }
 }
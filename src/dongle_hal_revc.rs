@@ -6,19 +6,23 @@
 // PIO1 - USB_SWITCH_EN
 // PIO19 - GPIO header "0"
 // PIO20 - GPIO header "1"
-// PIO8 - SLG_IO0 (GPIO header "2", not marked)
-// PIO3 - SLG_IO1 (GPIO header "3", not marked)
+// PIO8 - SLG_IO0 (GPIO header "2", not marked), pulled down inside SLG
+// PIO3 - SLG_IO1 (GPIO header "3", not marked), pulled up inside SLG
 
+use crate::discovery::DongleInfo;
+use crate::dongle_hal_revb::{PcbRevision, is_dev_power_on, pcb_revision};
+use crate::error::DongleError;
+use crate::signals::{ElectricalLevel, Signal};
 use crate::usb4604_ral::{
     Gpio0_7Dir, Gpio0_7Input, Gpio0_7Output, Gpio8_10Dir, Gpio8_10Input, Gpio8_10Output,
-    Gpio17_20Dir, Gpio17_20Input, Gpio17_20Output,
-    modify_reg, read_reg,
+    Gpio17_20Dir, Gpio17_20Input, Gpio17_20Output, RegisterBus, modify_reg, read_reg,
 };
 use clap::ValueEnum;
-use colored::Colorize;
-use nusb::Interface;
+use log::debug;
+use serde::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum HeaderPin {
     #[value(alias = "P0")]
     P0,
@@ -26,13 +30,20 @@ pub enum HeaderPin {
     P1,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// The two SLG GreenPAK IOs, both dual use: each is a feature line and an unmarked GPIO header position on
+/// the same wire, so generic pin access and the feature clobber each other, see [crate::slg_claim].
+#[derive(Copy, Clone, PartialEq, Eq, Debug, ValueEnum)]
 pub enum SlgPin {
+    /// PIO8 - SLG_IO0, force SDP; also GPIO header position 2
+    #[value(name = "io0")]
     SlgIo0,
+    /// PIO3 - SLG_IO1, force CC low; also GPIO header position 3
+    #[value(name = "io1")]
     SlgIo1,
 }
 
-#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PinMode {
     #[value(alias = "Output")]
     Output,
@@ -40,7 +51,8 @@ pub enum PinMode {
     Input,
 }
 
-#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PinState {
     #[value(alias = "High")]
     High,
@@ -73,138 +85,507 @@ pub enum PinState {
 // }
 // }
 
-pub fn gpio_header_set_mode(interface: &Interface, pin: HeaderPin, mode: PinMode) {
+pub fn gpio_header_set_mode(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    mode: PinMode,
+) -> Result<(), DongleError> {
     let out_en = matches!(mode, PinMode::Output);
     match pin {
-        HeaderPin::P0 => {
-            modify_reg::<Gpio17_20Dir, _>(interface, |r| r.set_gpio19_out_en(out_en));
-        }
-        HeaderPin::P1 => {
-            modify_reg::<Gpio17_20Dir, _>(interface, |r| r.set_gpio20_out_en(out_en));
-        }
+        HeaderPin::P0 => modify_reg::<Gpio17_20Dir, _>(bus, |r| r.set_gpio19_out_en(out_en)),
+        HeaderPin::P1 => modify_reg::<Gpio17_20Dir, _>(bus, |r| r.set_gpio20_out_en(out_en)),
     }
 }
 
-pub fn gpio_header_get_mode(interface: &Interface, pin: HeaderPin) -> PinMode {
+pub fn gpio_header_get_mode(bus: &dyn RegisterBus, pin: HeaderPin) -> Result<PinMode, DongleError> {
     let is_output = match pin {
-        HeaderPin::P0 => read_reg::<Gpio17_20Dir>(interface).gpio19_out_en(),
-        HeaderPin::P1 => read_reg::<Gpio17_20Dir>(interface).gpio20_out_en(),
+        HeaderPin::P0 => read_reg::<Gpio17_20Dir>(bus)?.gpio19_out_en(),
+        HeaderPin::P1 => read_reg::<Gpio17_20Dir>(bus)?.gpio20_out_en(),
     };
     if is_output {
-        PinMode::Output
+        Ok(PinMode::Output)
     } else {
-        PinMode::Input
+        Ok(PinMode::Input)
     }
 }
 
-pub fn gpio_header_set(interface: &Interface, pin: HeaderPin, state: PinState) {
-    if gpio_header_get_mode(interface, pin) != PinMode::Output {
-        println!("{}: {pin:?}", "Cannot set pin in input mode".red());
-        return;
+/// Fails with [DongleError::PinInInputMode] without writing anything if the pin is not an output.
+pub fn gpio_header_set(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    state: PinState,
+) -> Result<(), DongleError> {
+    if gpio_header_get_mode(bus, pin)? != PinMode::Output {
+        let pin = match pin {
+            HeaderPin::P0 => "P0",
+            HeaderPin::P1 => "P1",
+        };
+        return Err(DongleError::PinInInputMode { pin, pull: None });
     }
+    gpio_header_set_latch(bus, pin, state)
+}
+
+/// Writes the output latch regardless of direction.
+///
+/// The latch is the level the pin drives while it is an output, the actually driven level is only
+/// the latch value when the pin is an output. For an input the write is harmless and takes effect as
+/// soon as the pin is switched to output, without a glitch to the old latch value.
+pub fn gpio_header_set_latch(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    state: PinState,
+) -> Result<(), DongleError> {
     let is_high = matches!(state, PinState::High);
     match pin {
-        HeaderPin::P0 => {
-            modify_reg::<Gpio17_20Output, _>(interface, |r| r.set_gpio19_out(is_high));
-        }
-        HeaderPin::P1 => {
-            modify_reg::<Gpio17_20Output, _>(interface, |r| r.set_gpio20_out(is_high));
-        }
+        HeaderPin::P0 => modify_reg::<Gpio17_20Output, _>(bus, |r| r.set_gpio19_out(is_high)),
+        HeaderPin::P1 => modify_reg::<Gpio17_20Output, _>(bus, |r| r.set_gpio20_out(is_high)),
     }
 }
 
-pub fn gpio_header_get(interface: &Interface, pin: HeaderPin) -> PinState {
-    let mode = gpio_header_get_mode(interface, pin);
+pub fn gpio_header_get(bus: &dyn RegisterBus, pin: HeaderPin) -> Result<PinState, DongleError> {
+    let mode = gpio_header_get_mode(bus, pin)?;
     let is_high = match pin {
         HeaderPin::P0 => match mode {
-            PinMode::Output => read_reg::<Gpio17_20Output>(interface).gpio19_out(),
-            PinMode::Input => read_reg::<Gpio17_20Input>(interface).gpio19_in(),
+            PinMode::Output => read_reg::<Gpio17_20Output>(bus)?.gpio19_out(),
+            PinMode::Input => read_reg::<Gpio17_20Input>(bus)?.gpio19_in(),
         },
         HeaderPin::P1 => match mode {
-            PinMode::Output => read_reg::<Gpio17_20Output>(interface).gpio20_out(),
-            PinMode::Input => read_reg::<Gpio17_20Input>(interface).gpio20_in(),
+            PinMode::Output => read_reg::<Gpio17_20Output>(bus)?.gpio20_out(),
+            PinMode::Input => read_reg::<Gpio17_20Input>(bus)?.gpio20_in(),
         },
     };
     if is_high {
-        PinState::High
+        Ok(PinState::High)
+    } else {
+        Ok(PinState::Low)
+    }
+}
+
+/// Reads the electrical level at the pad from the input register, also when the pin is an output.
+///
+/// Unlike [gpio_header_get], which returns the driven level for outputs, this shows contention:
+/// a driven pin reading back a different level is being overridden externally.
+pub fn gpio_header_get_pad(bus: &dyn RegisterBus, pin: HeaderPin) -> Result<PinState, DongleError> {
+    let input = read_reg::<Gpio17_20Input>(bus)?;
+    let is_high = match pin {
+        HeaderPin::P0 => input.gpio19_in(),
+        HeaderPin::P1 => input.gpio20_in(),
+    };
+    if is_high {
+        Ok(PinState::High)
     } else {
-        PinState::Low
+        Ok(PinState::Low)
+    }
+}
+
+/// Reads the state of several pins from a single snapshot of the bank registers,
+/// at most three register reads regardless of the number of pins.
+pub fn gpio_header_get_many(
+    bus: &dyn RegisterBus,
+    pins: &[HeaderPin],
+) -> Result<Vec<(HeaderPin, PinState)>, DongleError> {
+    let dir = read_reg::<Gpio17_20Dir>(bus)?;
+    let output = read_reg::<Gpio17_20Output>(bus)?;
+    let input = read_reg::<Gpio17_20Input>(bus)?;
+    let states = pins
+        .iter()
+        .map(|&pin| {
+            let is_high = match pin {
+                HeaderPin::P0 if dir.gpio19_out_en() => output.gpio19_out(),
+                HeaderPin::P0 => input.gpio19_in(),
+                HeaderPin::P1 if dir.gpio20_out_en() => output.gpio20_out(),
+                HeaderPin::P1 => input.gpio20_in(),
+            };
+            let state = if is_high {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            (pin, state)
+        })
+        .collect();
+    Ok(states)
+}
+
+/// Sets pin mode only if it differs from the current one, returns true if a write was issued.
+pub fn gpio_header_ensure_mode(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    mode: PinMode,
+) -> Result<bool, DongleError> {
+    if gpio_header_get_mode(bus, pin)? == mode {
+        return Ok(false);
+    }
+    gpio_header_set_mode(bus, pin, mode)?;
+    Ok(true)
+}
+
+/// Sets pin state only if it differs from the current one, returns true if a write was issued.
+pub fn gpio_header_ensure(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    state: PinState,
+) -> Result<bool, DongleError> {
+    if gpio_header_get(bus, pin)? == state {
+        return Ok(false);
+    }
+    gpio_header_set(bus, pin, state)?;
+    Ok(true)
+}
+
+/// Header pin driving relay `index` (starting from 1): relay 1 is P0, relay 2 (dual relay variant) is P1.
+pub fn relay_pin(index: u8) -> Option<HeaderPin> {
+    match index {
+        1 => Some(HeaderPin::P0),
+        2 => Some(HeaderPin::P1),
+        _ => None,
+    }
+}
+
+/// Relay variant detection as used by the relay commands: the number of relays from the hub product string
+/// (see [DongleInfo::relay_count]), 0 before PCB RevC, which has no relay. Logs which signal decided it.
+pub fn detect_relay_variant(bus: &dyn RegisterBus, info: &DongleInfo) -> Result<u8, DongleError> {
+    let Some(hub) = &info.hub else {
+        debug!(
+            "No hub found next to the bridge, no product string to detect the relay variant from"
+        );
+        return Ok(0);
+    };
+    let product = hub.product_string.as_deref().unwrap_or("");
+    let count = info.relay_count();
+    if count == 0 {
+        debug!(
+            "Hub product string '{product}' has no 'relay', 'relay2' or 'dual', no relay variant"
+        );
+        return Ok(0);
     }
+    if pcb_revision(bus)? != PcbRevision::RevC {
+        debug!(
+            "Hub product string '{product}' indicates {count} relay(s), ignored on PCB RevA or B"
+        );
+        return Ok(0);
+    }
+    debug!("Hub product string '{product}' indicates {count} relay(s)");
+    Ok(count)
 }
 
-pub fn slg_io_set_mode(interface: &Interface, pin: SlgPin, mode: PinMode) {
+/// Switching a driven IO to input releases it to the SLG internal pull. Returns a warning for the caller to
+/// show if that changes its level (e.g. SLG_IO0 driven high goes low, releasing SDP), `None` otherwise.
+pub fn slg_io_set_mode(
+    bus: &dyn RegisterBus,
+    pin: SlgPin,
+    mode: PinMode,
+) -> Result<Option<String>, DongleError> {
+    let warning = if mode == PinMode::Input && slg_io_get_mode(bus, pin)? == PinMode::Output {
+        pin.release_warning(slg_io_get(bus, pin)?)
+    } else {
+        None
+    };
     let out_en = matches!(mode, PinMode::Output);
     match pin {
-        SlgPin::SlgIo0 => {
-            modify_reg::<Gpio8_10Dir, _>(interface, |r| r.set_gpio8_out_en(out_en));
-        }
-        SlgPin::SlgIo1 => {
-            modify_reg::<Gpio0_7Dir, _>(interface, |r| r.set_gpio3_out_en(out_en));
-        }
+        SlgPin::SlgIo0 => modify_reg::<Gpio8_10Dir, _>(bus, |r| r.set_gpio8_out_en(out_en))?,
+        SlgPin::SlgIo1 => modify_reg::<Gpio0_7Dir, _>(bus, |r| r.set_gpio3_out_en(out_en))?,
     }
+    Ok(warning)
 }
 
-pub fn slg_io_get_mode(interface: &Interface, pin: SlgPin) -> PinMode {
+pub fn slg_io_get_mode(bus: &dyn RegisterBus, pin: SlgPin) -> Result<PinMode, DongleError> {
     let is_out_en = match pin {
-        SlgPin::SlgIo0 => read_reg::<Gpio8_10Dir>(interface).gpio8_out_en(),
-        SlgPin::SlgIo1 => read_reg::<Gpio0_7Dir>(interface).gpio3_out_en(),
+        SlgPin::SlgIo0 => read_reg::<Gpio8_10Dir>(bus)?.gpio8_out_en(),
+        SlgPin::SlgIo1 => read_reg::<Gpio0_7Dir>(bus)?.gpio3_out_en(),
     };
     if is_out_en {
-        PinMode::Output
+        Ok(PinMode::Output)
     } else {
-        PinMode::Input
+        Ok(PinMode::Input)
     }
 }
 
-pub fn slg_io_set(interface: &Interface, pin: SlgPin, state: PinState) {
-    if slg_io_get_mode(interface, pin) != PinMode::Output {
-        println!("{}: {pin:?}", "Cannot set pin in input mode".red());
-        return;
+/// Fails with [DongleError::PinInInputMode] without writing anything if the pin is not an output.
+pub fn slg_io_set(bus: &dyn RegisterBus, pin: SlgPin, state: PinState) -> Result<(), DongleError> {
+    if slg_io_get_mode(bus, pin)? != PinMode::Output {
+        return Err(DongleError::PinInInputMode {
+            pin: pin.signal_name(),
+            pull: Some(pin.pull()),
+        });
     }
     let is_high = matches!(state, PinState::High);
     match pin {
-        SlgPin::SlgIo0 => {
-            modify_reg::<Gpio8_10Output, _>(interface, |r| r.set_gpio8_out(is_high));
-        }
-        SlgPin::SlgIo1 => {
-            modify_reg::<Gpio0_7Output, _>(interface, |r| r.set_gpio3_out(is_high));
-        }
+        SlgPin::SlgIo0 => modify_reg::<Gpio8_10Output, _>(bus, |r| r.set_gpio8_out(is_high)),
+        SlgPin::SlgIo1 => modify_reg::<Gpio0_7Output, _>(bus, |r| r.set_gpio3_out(is_high)),
     }
 }
 
-pub fn slg_io_get(interface: &Interface, pin: SlgPin) -> PinState {
-    let mode = slg_io_get_mode(interface, pin);
+/// Configures the IO as output driving `state`, skipping writes if it already does,
+/// returns true if anything was written.
+pub fn slg_io_ensure(
+    bus: &dyn RegisterBus,
+    pin: SlgPin,
+    state: PinState,
+) -> Result<bool, DongleError> {
+    if slg_io_get_mode(bus, pin)? == PinMode::Output && slg_io_get(bus, pin)? == state {
+        debug!("{pin:?} already driving {state:?}, write skipped");
+        return Ok(false);
+    }
+    slg_io_set_mode(bus, pin, PinMode::Output)?;
+    slg_io_set(bus, pin, state)?;
+    Ok(true)
+}
+
+/// Driven level (output latch) for outputs, level at the pad for inputs.
+pub fn slg_io_get(bus: &dyn RegisterBus, pin: SlgPin) -> Result<PinState, DongleError> {
+    if slg_io_get_mode(bus, pin)? == PinMode::Input {
+        return slg_io_get_input(bus, pin);
+    }
     let is_high = match pin {
-        SlgPin::SlgIo0 => match mode {
-            PinMode::Output => read_reg::<Gpio8_10Output>(interface).gpio8_out(),
-            PinMode::Input => read_reg::<Gpio8_10Input>(interface).gpio8_in(),
-        },
-        SlgPin::SlgIo1 => match mode {
-            PinMode::Output => read_reg::<Gpio0_7Output>(interface).gpio3_out(),
-            PinMode::Input => read_reg::<Gpio0_7Input>(interface).gpio3_in(),
-        },
+        SlgPin::SlgIo0 => read_reg::<Gpio8_10Output>(bus)?.gpio8_out(),
+        SlgPin::SlgIo1 => read_reg::<Gpio0_7Output>(bus)?.gpio3_out(),
+    };
+    if is_high {
+        Ok(PinState::High)
+    } else {
+        Ok(PinState::Low)
+    }
+}
+
+/// Reads the electrical level at the pad from the input register, also when the IO is an output.
+///
+/// Unlike [slg_io_get], which returns the latched level for outputs, this shows an external pull or driver
+/// overriding the level the IO is driving.
+pub fn slg_io_get_input(bus: &dyn RegisterBus, pin: SlgPin) -> Result<PinState, DongleError> {
+    let is_high = match pin {
+        SlgPin::SlgIo0 => read_reg::<Gpio8_10Input>(bus)?.gpio8_in(),
+        SlgPin::SlgIo1 => read_reg::<Gpio0_7Input>(bus)?.gpio3_in(),
     };
     if is_high {
-        PinState::High
+        Ok(PinState::High)
     } else {
-        PinState::Low
+        Ok(PinState::Low)
     }
 }
 
-pub fn usb_switch_configure(interface: &Interface) {
-    modify_reg::<Gpio0_7Dir, _>(interface, |r| r.set_gpio1_out_en(true)); // USB switch
+pub fn usb_switch_configure(bus: &dyn RegisterBus) -> Result<(), DongleError> {
+    modify_reg::<Gpio0_7Dir, _>(bus, |r| r.set_gpio1_out_en(true)) // USB switch
+}
+
+/// With [crate::sequencing] enforced, connecting while power is off is refused with [DongleError::SwitchNeedsPower].
+pub fn usb_switch_set(bus: &dyn RegisterBus, is_connected: bool) -> Result<(), DongleError> {
+    if is_connected && bus.policy().enforce_sequencing && !is_dev_power_on(bus)? {
+        return Err(DongleError::SwitchNeedsPower);
+    }
+    let level = Signal::SwitchConnected.level(bus.policy().polarity, is_connected);
+    modify_reg::<Gpio0_7Output, _>(bus, |r| r.set_gpio1_out(level.bit()))
 }
 
-pub fn usb_switch_set(interface: &Interface, is_connected: bool) {
-    // 0 means the USB switch is connected to a device
-    modify_reg::<Gpio0_7Output, _>(interface, |r| r.set_gpio1_out(!is_connected));
+/// Configures the switch pin as output and sets it, skipping writes if it is already in that state,
+/// returns true if anything was written.
+pub fn usb_switch_ensure(bus: &dyn RegisterBus, is_connected: bool) -> Result<bool, DongleError> {
+    let is_output = read_reg::<Gpio0_7Dir>(bus)?.gpio1_out_en();
+    if is_output && usb_switch_is_connected(bus)? == is_connected {
+        debug!("USB switch already connected={is_connected}, write skipped");
+        return Ok(false);
+    }
+    usb_switch_configure(bus)?;
+    usb_switch_set(bus, is_connected)?;
+    Ok(true)
 }
 
-pub fn usb_switch_is_connected(interface: &Interface) -> bool {
-    let is_input = read_reg::<Gpio0_7Input>(interface).gpio1_in();
+pub fn usb_switch_is_connected(bus: &dyn RegisterBus) -> Result<bool, DongleError> {
+    let polarity = bus.policy().polarity;
+    let is_input = !read_reg::<Gpio0_7Dir>(bus)?.gpio1_out_en();
     if is_input {
-        !read_reg::<Gpio0_7Input>(interface).gpio1_in()
+        let level = ElectricalLevel::from_bit(read_reg::<Gpio0_7Input>(bus)?.gpio1_in());
+        Ok(Signal::SwitchConnected.is_active(polarity, level))
     } else {
-        !read_reg::<Gpio0_7Output>(interface).gpio1_out()
+        let level = ElectricalLevel::from_bit(read_reg::<Gpio0_7Output>(bus)?.gpio1_out());
+        Ok(Signal::SwitchConnected.is_active(polarity, level))
+    }
+}
+
+/// Driven intent and read-back level of the USB switch control line (PIO1), see [usb_switch_diag].
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct SwitchDiag {
+    pub mode: PinMode,
+    /// Electrical level in the output latch, driven while the pin is an output
+    pub latched: PinState,
+    /// Electrical level read back from the pad
+    pub pad: PinState,
+    /// Whether the latch asks for the switch to be connected, `None` while the pin is an input and drives nothing
+    pub intended_connected: Option<bool>,
+    /// Whether the pad level means connected
+    pub read_back_connected: bool,
+    /// The pin is an output and the pad does not follow the latch: the control line is shorted or overdriven
+    pub mismatch: bool,
+}
+
+/// Reads direction, output latch and input of the switch pin once each, without writing anything.
+///
+/// Unlike [usb_switch_is_connected], which reports the latch for an output and the pad for an input, both
+/// levels are reported, so a control line that does not follow the latch shows up as [SwitchDiag::mismatch].
+pub fn usb_switch_diag(bus: &dyn RegisterBus) -> Result<SwitchDiag, DongleError> {
+    let polarity = bus.policy().polarity;
+    let is_output = read_reg::<Gpio0_7Dir>(bus)?.gpio1_out_en();
+    let latched = ElectricalLevel::from_bit(read_reg::<Gpio0_7Output>(bus)?.gpio1_out());
+    let pad = ElectricalLevel::from_bit(read_reg::<Gpio0_7Input>(bus)?.gpio1_in());
+    let state = |level: ElectricalLevel| {
+        if level.bit() {
+            PinState::High
+        } else {
+            PinState::Low
+        }
+    };
+    Ok(SwitchDiag {
+        mode: if is_output {
+            PinMode::Output
+        } else {
+            PinMode::Input
+        },
+        latched: state(latched),
+        pad: state(pad),
+        intended_connected: is_output.then(|| Signal::SwitchConnected.is_active(polarity, latched)),
+        read_back_connected: Signal::SwitchConnected.is_active(polarity, pad),
+        mismatch: is_output && latched != pad,
+    })
+}
+
+/// Sets the USB switch to `connected` and returns the previous state, so it can be restored later.
+pub fn usb_switch_swap(bus: &dyn RegisterBus, connected: bool) -> Result<bool, DongleError> {
+    let previous = usb_switch_is_connected(bus)?;
+    // latch the level before enabling the output, so the switch does not glitch to a stale level
+    usb_switch_set(bus, connected)?;
+    usb_switch_configure(bus)?;
+    Ok(previous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Policy;
+    use crate::signals::Polarity;
+    use crate::usb4604_ral::{MockBus, SmscReg};
+
+    #[test]
+    fn active_high_switch_drives_pio1_high_when_connected() {
+        let bus = MockBus::new();
+        bus.set_policy(Policy {
+            polarity: Polarity {
+                switch_active_high: true,
+                ..Polarity::default()
+            },
+            ..Policy::default()
+        });
+        usb_switch_configure(&bus).unwrap();
+        usb_switch_set(&bus, true).unwrap();
+        assert_eq!(bus.get(Gpio0_7Output::ADDR) & 0b10, 0b10);
+        assert!(usb_switch_is_connected(&bus).unwrap());
+        usb_switch_set(&bus, false).unwrap();
+        assert_eq!(bus.get(Gpio0_7Output::ADDR) & 0b10, 0);
+        assert!(!usb_switch_is_connected(&bus).unwrap());
+    }
+
+    #[test]
+    fn usb_switch_swap_returns_previous_state() {
+        let bus = MockBus::new();
+        usb_switch_configure(&bus).unwrap();
+        usb_switch_set(&bus, true).unwrap();
+
+        assert!(usb_switch_swap(&bus, false).unwrap());
+        assert!(!usb_switch_is_connected(&bus).unwrap());
+        assert!(!usb_switch_swap(&bus, true).unwrap());
+        assert!(usb_switch_is_connected(&bus).unwrap());
+    }
+
+    #[test]
+    fn switch_diag_flags_a_pad_not_following_the_latch() {
+        let bus = MockBus::new();
+        usb_switch_configure(&bus).unwrap();
+        usb_switch_set(&bus, true).unwrap();
+        let latched = bus.reg::<Gpio0_7Output>().gpio1_out();
+        bus.set(
+            Gpio0_7Input::ADDR,
+            Gpio0_7Input::new().with_gpio1_in(latched).value(),
+        );
+        let writes = bus.writes().len();
+        let diag = usb_switch_diag(&bus).unwrap();
+        assert_eq!(diag.intended_connected, Some(true));
+        assert!(diag.read_back_connected);
+        assert!(!diag.mismatch);
+        assert_eq!(bus.writes().len(), writes);
+
+        bus.set(
+            Gpio0_7Input::ADDR,
+            Gpio0_7Input::new().with_gpio1_in(!latched).value(),
+        );
+        let diag = usb_switch_diag(&bus).unwrap();
+        assert!(!diag.read_back_connected);
+        assert!(diag.mismatch);
+    }
+
+    #[test]
+    fn gpio_header_get_many_decodes_by_mode() {
+        let bus = MockBus::new();
+        gpio_header_set_mode(&bus, HeaderPin::P0, PinMode::Output).unwrap();
+        gpio_header_set(&bus, HeaderPin::P0, PinState::High).unwrap();
+        let input = Gpio17_20Input::new().with_gpio20_in(true);
+        bus.set(Gpio17_20Input::ADDR, input.value());
+
+        let states = gpio_header_get_many(&bus, &[HeaderPin::P1, HeaderPin::P0]).unwrap();
+        assert_eq!(
+            states,
+            vec![
+                (HeaderPin::P1, PinState::High),
+                (HeaderPin::P0, PinState::High)
+            ]
+        );
+    }
+
+    #[test]
+    fn slg_io_input_read_ignores_direction() {
+        let bus = MockBus::new();
+        slg_io_set_mode(&bus, SlgPin::SlgIo1, PinMode::Output).unwrap();
+        slg_io_set(&bus, SlgPin::SlgIo1, PinState::High).unwrap();
+        assert_eq!(slg_io_get(&bus, SlgPin::SlgIo1).unwrap(), PinState::High);
+        assert_eq!(
+            slg_io_get_input(&bus, SlgPin::SlgIo1).unwrap(),
+            PinState::Low
+        );
+        bus.set(
+            Gpio0_7Input::ADDR,
+            Gpio0_7Input::new().with_gpio3_in(true).value(),
+        );
+        assert_eq!(
+            slg_io_get_input(&bus, SlgPin::SlgIo1).unwrap(),
+            PinState::High
+        );
+    }
+
+    #[test]
+    fn gpio_header_latch_applies_when_switched_to_output() {
+        let bus = MockBus::new();
+        gpio_header_set_latch(&bus, HeaderPin::P1, PinState::High).unwrap();
+        assert_eq!(
+            gpio_header_get_mode(&bus, HeaderPin::P1).unwrap(),
+            PinMode::Input
+        );
+        gpio_header_set_mode(&bus, HeaderPin::P1, PinMode::Output).unwrap();
+        assert_eq!(
+            gpio_header_get(&bus, HeaderPin::P1).unwrap(),
+            PinState::High
+        );
+    }
+
+    #[test]
+    fn gpio_header_get_pad_shows_contention() {
+        let bus = MockBus::new();
+        gpio_header_set_mode(&bus, HeaderPin::P0, PinMode::Output).unwrap();
+        gpio_header_set(&bus, HeaderPin::P0, PinState::High).unwrap();
+        assert_eq!(
+            gpio_header_get(&bus, HeaderPin::P0).unwrap(),
+            PinState::High
+        );
+        assert_eq!(
+            gpio_header_get_pad(&bus, HeaderPin::P0).unwrap(),
+            PinState::Low
+        );
     }
 }
@@ -0,0 +1,214 @@
+use std::fmt;
+use std::time::Duration;
+
+use nusb::transfer::TransferError;
+
+use crate::consistency::InvalidCombination;
+use crate::discovery::InterfaceSummary;
+use crate::dongle_hal_revc::SlgPin;
+use crate::slg::Pull;
+use crate::slg_claim::SlgPurpose;
+
+#[derive(Debug)]
+pub enum DongleError {
+    /// Control transfer reading or writing a register failed
+    Transfer { addr: u16, source: TransferError },
+    /// Register read returned no data
+    EmptyResponse { addr: u16 },
+    /// Opening the device or claiming an interface failed
+    Usb(nusb::Error),
+    /// Active configuration has several interfaces and none of them looks like the register access one
+    NoControlInterface { interfaces: Vec<InterfaceSummary> },
+    /// Power fault kept reappearing after power on
+    PowerUnstable { timeout: Duration },
+    /// No device showed up behind the dongle after attach
+    NotEnumerated {
+        timeout: Duration,
+        power_fault: bool,
+    },
+    /// Command needs hardware this dongle does not have
+    Unsupported(String),
+    /// Power is locked out for servicing, see [crate::lockout]
+    LockedOut { holder: String },
+    /// Device behind the dongle is enumerated, detaching could interrupt a transfer
+    DeviceEnumerated { device: String },
+    /// Level was not written because the pin is configured as input; `pull` is the SLG internal pull
+    /// deciding the level of released SLG IOs
+    PinInInputMode {
+        pin: &'static str,
+        pull: Option<Pull>,
+    },
+    /// Talking to the `--persist` helper failed, or it failed to access the device
+    Persist(String),
+    /// Relay command on a dongle without relays, P0/P1 would be driven with nothing attached
+    NotRelayVariant,
+    /// With `--strict-state`: the state after the command (or the one a fixture asks for) is an invalid combination
+    InconsistentState {
+        violations: Vec<&'static InvalidCombination>,
+    },
+    /// Register write attempted by a read-only command, see [crate::read_only]
+    ReadOnly { addr: u16 },
+    /// Bridge kept STALLing the control transfer, also after a retry
+    Stalled { addr: u16 },
+    /// Dual-use SLG IO is claimed for the other purpose, see [crate::slg_claim]
+    SlgPinClaimed { pin: SlgPin, by: SlgPurpose },
+    /// USB switch connect with power off while sequencing is enforced, see [crate::sequencing]
+    SwitchNeedsPower,
+    /// Command can cut or apply device power in ways the safety logic does not cover, needs `--expert`
+    ExpertRequired { consequence: &'static str },
+}
+
+impl DongleError {
+    /// Stable machine readable identifier of the error variant.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DongleError::Transfer { .. } => "transfer",
+            DongleError::EmptyResponse { .. } => "empty_response",
+            DongleError::Usb(_) => "usb",
+            DongleError::NoControlInterface { .. } => "no_control_interface",
+            DongleError::PowerUnstable { .. } => "power_unstable",
+            DongleError::NotEnumerated { .. } => "not_enumerated",
+            DongleError::Unsupported(_) => "unsupported",
+            DongleError::LockedOut { .. } => "locked_out",
+            DongleError::DeviceEnumerated { .. } => "device_enumerated",
+            DongleError::PinInInputMode { .. } => "pin_in_input_mode",
+            DongleError::Persist(_) => "persist",
+            DongleError::NotRelayVariant => "not_relay_variant",
+            DongleError::InconsistentState { .. } => "inconsistent_state",
+            DongleError::ReadOnly { .. } => "read_only",
+            DongleError::Stalled { .. } => "stalled",
+            DongleError::SlgPinClaimed { .. } => "slg_pin_claimed",
+            DongleError::SwitchNeedsPower => "switch_needs_power",
+            DongleError::ExpertRequired { .. } => "expert_required",
+        }
+    }
+}
+
+impl fmt::Display for DongleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DongleError::Transfer { addr, source } => {
+                write!(
+                    f,
+                    "Control transfer to register 0x{addr:04X} failed: {source}"
+                )
+            }
+            DongleError::EmptyResponse { addr } => {
+                write!(f, "Register 0x{addr:04X} read returned no data")
+            }
+            DongleError::Usb(e) => write!(f, "USB error: {e}"),
+            DongleError::NoControlInterface { interfaces } => {
+                write!(
+                    f,
+                    "No vendor specific interface found on the bridge, interfaces:"
+                )?;
+                for interface in interfaces {
+                    write!(f, "\n  {interface}")?;
+                }
+                Ok(())
+            }
+            DongleError::PowerUnstable { timeout } => {
+                write!(
+                    f,
+                    "Power did not become stable without a fault within {}ms",
+                    timeout.as_millis()
+                )
+            }
+            DongleError::NotEnumerated {
+                timeout,
+                power_fault,
+            } => {
+                write!(
+                    f,
+                    "Device did not enumerate within {}ms",
+                    timeout.as_millis()
+                )?;
+                if *power_fault {
+                    write!(f, ", power FAULT is asserted (probably short on VBUS?)")
+                } else {
+                    write!(f, ", no power fault")
+                }
+            }
+            DongleError::Unsupported(message) => write!(f, "{message}"),
+            DongleError::LockedOut { holder } => write!(
+                f,
+                "Power is locked out for servicing (by {holder}), run 'mchp_gpio_ctl lockout off' to release"
+            ),
+            DongleError::DeviceEnumerated { device } => write!(
+                f,
+                "Device {device} is enumerated behind the dongle, detaching could corrupt an ongoing transfer \
+                 (e.g. a firmware update), rerun with --force to detach anyway"
+            ),
+            DongleError::PinInInputMode { pin, pull } => {
+                write!(
+                    f,
+                    "Cannot set {pin} in input mode, configure it as output first"
+                )?;
+                if let Some(pull) = pull {
+                    let pull = format!("{pull:?}").to_lowercase();
+                    write!(f, " (released, it follows the SLG internal pull-{pull})")?;
+                }
+                Ok(())
+            }
+            DongleError::Persist(message) => write!(f, "Persist helper: {message}"),
+            DongleError::NotRelayVariant => write!(
+                f,
+                "This dongle is not a relay variant, relay commands are not available"
+            ),
+            DongleError::InconsistentState { violations } => {
+                write!(f, "Inconsistent dongle state (--strict-state):")?;
+                for violation in violations {
+                    write!(f, "\n  {}: {}", violation.name, violation.description)?;
+                }
+                Ok(())
+            }
+            DongleError::ReadOnly { addr } => write!(
+                f,
+                "Write to register 0x{addr:04X} refused, the command runs read-only"
+            ),
+            DongleError::Stalled { addr } => write!(
+                f,
+                "Bridge STALLed the control transfer to register 0x{addr:04X}, also when retried"
+            ),
+            DongleError::SlgPinClaimed { pin, by } => write!(
+                f,
+                "{} is in use as {}, run {} first",
+                pin.signal_name(),
+                by.describe(*pin),
+                by.release_hint(*pin)
+            ),
+            DongleError::SwitchNeedsPower => write!(
+                f,
+                "USB switch not connected, power is off and sequencing is enforced: turn power on first"
+            ),
+            DongleError::ExpertRequired { consequence } => {
+                write!(f, "{consequence}. Rerun with --expert if you are sure")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DongleError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DongleError::Transfer { source, .. } => Some(source),
+            DongleError::Usb(e) => Some(e),
+            DongleError::EmptyResponse { .. }
+            | DongleError::NoControlInterface { .. }
+            | DongleError::PowerUnstable { .. }
+            | DongleError::NotEnumerated { .. }
+            | DongleError::Unsupported(_)
+            | DongleError::LockedOut { .. }
+            | DongleError::DeviceEnumerated { .. }
+            | DongleError::PinInInputMode { .. }
+            | DongleError::Persist(_)
+            | DongleError::NotRelayVariant
+            | DongleError::InconsistentState { .. }
+            | DongleError::ReadOnly { .. }
+            | DongleError::Stalled { .. }
+            | DongleError::SlgPinClaimed { .. }
+            | DongleError::SwitchNeedsPower
+            | DongleError::ExpertRequired { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! `explain`: the USB control transfers a command issues, for testing a reimplementation of the bridge side.
+//!
+//! The command runs against a simulated dongle instead of the hardware, every register access is turned into
+//! the control transfer the [crate::usb4604_ral::ControlProtocol] in use would send. The simulated registers
+//! start out as after reset (all 0), except for the inputs read by every command: the PIO9 revision strap
+//! (per `--revision`) and PIO10 without a power fault. Commands branching on register contents (e.g. `--ensure`)
+//! take the branch this state leads to, and reads return the last written value, as the real registers do.
+//!
+//! Nothing outside the simulated dongle is touched: SLG claims live in a directory removed after the command,
+//! no device is ever enumerated behind the dongle, relays are switched without a minimum dwell, and commands
+//! acting on the host otherwise (`lockout on/off`, `detach --run`, `hub-info`, `port-diag`) are refused.
+//!
+//! The command runs in real time, waits (`sdp`, `power-cycle`) are waited for and commands running until
+//! interrupted (`monitor`, `gpio-hold`) have to be interrupted.
+
+use std::cell::RefCell;
+
+use serde::Serialize;
+
+use crate::dongle_hal_revb::PcbRevision;
+use crate::error::DongleError;
+use crate::policy::Policy;
+use crate::usb4604_ral::{
+    ControlProtocol, ControlTransfer, Gpio8_10Input, MockBus, REGISTERS, RegisterBus, SmscReg,
+};
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct ExplainedTransfer {
+    #[serde(flatten)]
+    pub transfer: ControlTransfer,
+    /// Name from [REGISTERS], `None` for registers not listed there
+    pub register: Option<&'static str>,
+    /// Byte the simulated dongle returned, for reads
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub returns: Option<u8>,
+}
+
+/// Register bus of a simulated dongle, remembering the control transfer of every access.
+pub struct ExplainBus {
+    registers: MockBus,
+    protocol: ControlProtocol,
+    transfers: RefCell<Vec<ExplainedTransfer>>,
+    on_transfer: Box<dyn Fn(&ExplainedTransfer)>,
+}
+
+impl ExplainBus {
+    /// Simulated dongle of `revision` accessed with `protocol`, see the module documentation; `on_transfer` is
+    /// called for every access as it happens.
+    pub fn new(
+        revision: PcbRevision,
+        protocol: ControlProtocol,
+        on_transfer: impl Fn(&ExplainedTransfer) + 'static,
+    ) -> Self {
+        let registers = MockBus::new();
+        registers.set(
+            Gpio8_10Input::ADDR,
+            Gpio8_10Input::new()
+                .with_gpio9_in(revision == PcbRevision::RevC)
+                .with_gpio10_in(true)
+                .value(),
+        );
+        Self {
+            registers,
+            protocol,
+            transfers: RefCell::new(Vec::new()),
+            on_transfer: Box::new(on_transfer),
+        }
+    }
+
+    pub fn into_transfers(self) -> Vec<ExplainedTransfer> {
+        self.transfers.into_inner()
+    }
+
+    fn push(&self, transfer: ControlTransfer, returns: Option<u8>) {
+        let explained = ExplainedTransfer {
+            register: REGISTERS
+                .iter()
+                .find(|r| r.1 == transfer.value)
+                .map(|r| r.0),
+            transfer,
+            returns,
+        };
+        (self.on_transfer)(&explained);
+        self.transfers.borrow_mut().push(explained);
+    }
+}
+
+impl RegisterBus for ExplainBus {
+    fn read_byte(&self, addr: u16) -> Result<u8, DongleError> {
+        let value = self.registers.read_byte(addr)?;
+        self.push(self.protocol.read_transfer(addr), Some(value));
+        Ok(value)
+    }
+
+    fn write_byte(&self, addr: u16, value: u8) -> Result<(), DongleError> {
+        self.push(self.protocol.write_transfer(addr, value), None);
+        self.registers.write_byte(addr, value)
+    }
+
+    fn policy(&self) -> Policy {
+        self.registers.policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dongle_hal_revb::{PowerPath, dev_power_ctl_via, pcb_revision};
+    use crate::usb4604_ral::{Gpio0_7Dir, REQUEST_TYPE_VENDOR_INTERFACE_OUT};
+
+    #[test]
+    fn writes_become_vendor_out_transfers_with_the_address_in_wvalue() {
+        let bus = ExplainBus::new(PcbRevision::RevC, ControlProtocol::DEFAULT, |_| {});
+        assert_eq!(pcb_revision(&bus).unwrap(), PcbRevision::RevC);
+        dev_power_ctl_via(&bus, PowerPath::Board, false).unwrap();
+        let transfers = bus.into_transfers();
+        assert_eq!(transfers[0].transfer.request_type, 0xC1);
+        assert_eq!(transfers[0].register, Some("Gpio8_10Input"));
+        assert!(transfers[0].returns.is_some());
+        let dir_write = transfers
+            .iter()
+            .find(|t| t.transfer.request_type == REQUEST_TYPE_VENDOR_INTERFACE_OUT)
+            .unwrap();
+        assert_eq!(dir_write.register, Some("Gpio0_7Dir"));
+        assert_eq!(dir_write.transfer.value, Gpio0_7Dir::ADDR);
+        assert_eq!(dir_write.transfer.request, 3);
+        assert_eq!(dir_write.transfer.data.len(), 1);
+    }
+}
@@ -0,0 +1,166 @@
+//! Running a user command while the dongle holds a state, e.g. `detach --run ./capture.sh --then-attach`,
+//! holding a state until Ctrl-C, and keeping command messages out of machine readable output.
+
+use std::io;
+use std::process::{Command, ExitStatus};
+
+/// Runs `command` through the shell and waits for it to exit.
+///
+/// Ctrl-C and SIGTERM still reach the command, but are ignored by this process while it runs, so the
+/// caller gets to restore the dongle state afterwards.
+pub fn run_shell_command(command: &str) -> io::Result<ExitStatus> {
+    let mut child = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).spawn()?
+    } else {
+        Command::new("sh").args(["-c", command]).spawn()?
+    };
+    // Ignored only after spawning, ignored signals would be inherited by the command otherwise
+    let _ignore = IgnoreTermination::new();
+    child.wait()
+}
+
+/// Exit code to propagate for `status`, 128 + signal number if the command was killed (shell convention).
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128 + signal;
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Ignores SIGINT and SIGTERM until dropped.
+struct IgnoreTermination {
+    #[cfg(unix)]
+    previous: [libc::sighandler_t; 2],
+}
+
+#[cfg(unix)]
+const TERMINATION_SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGTERM];
+
+impl IgnoreTermination {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            // SAFETY: only swaps the signal disposition, no handler code runs
+            let previous = TERMINATION_SIGNALS.map(|s| unsafe { libc::signal(s, libc::SIG_IGN) });
+            IgnoreTermination { previous }
+        }
+        #[cfg(not(unix))]
+        IgnoreTermination {}
+    }
+}
+
+impl Drop for IgnoreTermination {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        for (signal, previous) in TERMINATION_SIGNALS.iter().zip(self.previous) {
+            // SAFETY: restores the disposition returned by libc::signal in new()
+            unsafe { libc::signal(*signal, previous) };
+        }
+    }
+}
+
+#[cfg(unix)]
+static TERMINATION_CAUGHT: std::sync::atomic::AtomicBool =
+    std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn note_termination(_: libc::c_int) {
+    TERMINATION_CAUGHT.store(true, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Turns SIGINT and SIGTERM into a flag polled with [CatchTermination::caught] until dropped, so a resident
+/// command can restore the dongle state on Ctrl-C instead of being killed.
+pub struct CatchTermination {
+    #[cfg(unix)]
+    previous: [libc::sighandler_t; 2],
+}
+
+impl CatchTermination {
+    #[allow(clippy::new_without_default)] // installs signal handlers, not a plain default value
+    pub fn new() -> Self {
+        #[cfg(unix)]
+        {
+            TERMINATION_CAUGHT.store(false, std::sync::atomic::Ordering::Relaxed);
+            let handler = note_termination as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+            let previous = TERMINATION_SIGNALS.map(|s| unsafe { libc::signal(s, handler) });
+            CatchTermination { previous }
+        }
+        #[cfg(not(unix))]
+        CatchTermination {}
+    }
+
+    /// True once SIGINT or SIGTERM was received, always false on other platforms.
+    pub fn caught(&self) -> bool {
+        #[cfg(unix)]
+        return TERMINATION_CAUGHT.load(std::sync::atomic::Ordering::Relaxed);
+        #[cfg(not(unix))]
+        false
+    }
+}
+
+impl Drop for CatchTermination {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        for (signal, previous) in TERMINATION_SIGNALS.iter().zip(self.previous) {
+            // SAFETY: restores the disposition returned by libc::signal in new()
+            unsafe { libc::signal(*signal, previous) };
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_code_propagates() {
+        assert_eq!(exit_code(run_shell_command("exit 3").unwrap()), 3);
+        assert_eq!(exit_code(run_shell_command("kill -9 $$").unwrap()), 137);
+    }
+}
+
+/// Sends everything printed to stdout to stderr until dropped, so a command's messages do not end up in
+/// machine readable output printed afterwards. Does nothing on other platforms.
+pub struct StdoutToStderr {
+    #[cfg(unix)]
+    saved: Option<libc::c_int>,
+}
+
+impl StdoutToStderr {
+    #[allow(clippy::new_without_default)] // redirects a file descriptor, not a plain default value
+    pub fn new() -> Self {
+        let _ = io::Write::flush(&mut io::stdout());
+        #[cfg(unix)]
+        {
+            // SAFETY: dup and dup2 on the standard descriptors, the copy is restored and closed in drop()
+            let saved = unsafe {
+                let saved = libc::dup(libc::STDOUT_FILENO);
+                if saved >= 0 && libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+                    libc::close(saved);
+                    None
+                } else {
+                    (saved >= 0).then_some(saved)
+                }
+            };
+            StdoutToStderr { saved }
+        }
+        #[cfg(not(unix))]
+        StdoutToStderr {}
+    }
+}
+
+impl Drop for StdoutToStderr {
+    fn drop(&mut self) {
+        let _ = io::Write::flush(&mut io::stdout());
+        #[cfg(unix)]
+        if let Some(saved) = self.saved {
+            // SAFETY: restores the descriptor saved in new()
+            unsafe {
+                libc::dup2(saved, libc::STDOUT_FILENO);
+                libc::close(saved);
+            }
+        }
+    }
+}
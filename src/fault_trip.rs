@@ -0,0 +1,102 @@
+//! `fault-test`: how long the board power switch takes to report an overcurrent on PWR_FAIL_N (PIO10), measured
+//! with a deliberate short on the output, to characterize the protection circuit.
+//!
+//! Power is switched on by a single write to the PIO0 output latch, the direction and the off level are set up
+//! before, and the time is taken when that write returned. PIO10 is then read back to back, without sleeping,
+//! until it reports the fault, so the resolution is one control transfer (about 1ms, see
+//! [FaultTrip::poll_interval]); the switch itself usually trips much faster. Power is turned off right after,
+//! the same way `emergency-off` does it, also when the fault never came or a transfer failed.
+//!
+//! Only the board switch ([crate::dongle_hal_revb::PowerPath::Board]) reports faults.
+
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::dongle_hal_revb::{emergency_power_off, is_dev_pwr_fault};
+use crate::error::DongleError;
+use crate::signals::{ElectricalLevel, Signal};
+use crate::usb4604_ral::{Gpio0_7Output, Gpio8_10Input, RegisterBus, read_reg, write_reg};
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct FaultTrip {
+    /// Time from power on until the fault was seen, `None` if it was not within the timeout
+    pub trip_time: Option<Duration>,
+    /// Number of PIO10 reads after power on
+    pub polls: u32,
+    /// Average time between two reads, the resolution of `trip_time`
+    pub poll_interval: Duration,
+}
+
+fn poll_fault(
+    bus: &dyn RegisterBus,
+    start: Instant,
+    timeout: Duration,
+) -> Result<FaultTrip, DongleError> {
+    let mut polls = 0;
+    loop {
+        let level = ElectricalLevel::from_bit(read_reg::<Gpio8_10Input>(bus)?.gpio10_in());
+        polls += 1;
+        let elapsed = start.elapsed();
+        let tripped = Signal::PowerFault.is_active(bus.policy().polarity, level);
+        if tripped || elapsed >= timeout {
+            return Ok(FaultTrip {
+                trip_time: tripped.then_some(elapsed),
+                polls,
+                poll_interval: elapsed / polls,
+            });
+        }
+    }
+}
+
+/// Turns power on through the board switch, measures how long it takes until a fault is reported and turns power
+/// off again. Fails without switching power on if a fault is reported already while it is off.
+pub fn measure_fault_trip(
+    bus: &dyn RegisterBus,
+    timeout: Duration,
+) -> Result<FaultTrip, DongleError> {
+    emergency_power_off(bus)?;
+    if is_dev_pwr_fault(bus)? {
+        return Err(DongleError::Unsupported(
+            "PWR_FAIL_N reports a fault while power is off, the trip time can not be measured"
+                .into(),
+        ));
+    }
+    let on_level = Signal::PowerOn.level(bus.policy().polarity, true);
+    let on = read_reg::<Gpio0_7Output>(bus)?.with_gpio0_out(on_level.bit());
+    let trip = write_reg(bus, on).map(|_| poll_fault(bus, Instant::now(), timeout));
+    let off = emergency_power_off(bus);
+    let trip = trip??;
+    off?;
+    Ok(trip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb4604_ral::{MockBus, SmscReg};
+
+    #[test]
+    fn fault_is_timed_from_power_on_and_power_ends_off() {
+        let bus = MockBus::new();
+        let no_fault = Gpio8_10Input::new().with_gpio10_in(true).value();
+        bus.set(Gpio8_10Input::ADDR, 0);
+        // precheck and two polls without fault, then PWR_FAIL_N low
+        bus.script_reads(Gpio8_10Input::ADDR, &[no_fault, no_fault, no_fault]);
+
+        let trip = measure_fault_trip(&bus, Duration::from_secs(1)).unwrap();
+        assert!(trip.trip_time.is_some());
+        assert_eq!(trip.polls, 3);
+        let power_on = Signal::PowerOn.level(bus.policy().polarity, true).bit();
+        let on_writes = bus
+            .writes()
+            .into_iter()
+            .filter(|(addr, value)| {
+                *addr == Gpio0_7Output::ADDR
+                    && Gpio0_7Output::from_value(*value).gpio0_out() == power_on
+            })
+            .count();
+        assert_eq!(on_writes, 1);
+        assert_eq!(bus.reg::<Gpio0_7Output>().gpio0_out(), !power_on);
+    }
+}
@@ -0,0 +1,557 @@
+//! Declarative fixture state: describe desired power, switch, SDP/CC and header pin state in a TOML or JSON file
+//! and drive the dongle into it with as few writes as possible.
+//!
+//! Example `fixture.toml`:
+//! ```toml
+//! power_on = true
+//! usb_switch_connected = true
+//! forcing_sdp = false
+//! forcing_cc_low = false
+//!
+//! [p0]
+//! mode = "output"
+//! state = "high"
+//!
+//! [p1]
+//! mode = "input"
+//! ```
+//! All fields are optional, omitted ones are left untouched by [apply] and not checked by [verify].
+//!
+//! The order of fields in the file does not matter, [apply] always configures header pin modes before it writes
+//! levels. A level for a pin that stays an input is refused before anything is written, unless `auto_config`
+//! (`apply --auto-config`) switches the pin to output first.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dongle_hal_revb::{PcbRevision, dev_power_ctl, is_dev_power_on};
+use crate::dongle_hal_revc::{
+    HeaderPin, PinMode, PinState, SlgPin, gpio_header_get, gpio_header_get_mode, gpio_header_set,
+    gpio_header_set_mode, slg_io_get, slg_io_set, slg_io_set_mode, usb_switch_configure,
+    usb_switch_is_connected, usb_switch_set,
+};
+use crate::error::DongleError;
+use crate::status::{HeaderPinStatus, StatusReport};
+use crate::usb4604_ral::RegisterBus;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredPin {
+    pub mode: Option<PinMode>,
+    pub state: Option<PinState>,
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredState {
+    pub power_on: Option<bool>,
+    pub usb_switch_connected: Option<bool>,
+    pub forcing_sdp: Option<bool>,
+    pub forcing_cc_low: Option<bool>,
+    pub p0: Option<DesiredPin>,
+    pub p1: Option<DesiredPin>,
+}
+
+#[derive(Debug)]
+pub enum FixtureError {
+    Io(std::io::Error),
+    Dongle(DongleError),
+    Parse(String),
+    /// Fixture sets fields that only exist on PCB RevC and up
+    RequiresRevC,
+    /// Fixture sets the level of a header pin that is an input and is not configured as output by it
+    LevelOnInputPin {
+        pin: &'static str,
+    },
+    /// Value read back after a write does not match the requested one
+    VerifyFailed {
+        field: &'static str,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for FixtureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FixtureError::Io(e) => write!(f, "Failed to read fixture file: {e}"),
+            FixtureError::Dongle(e) => write!(f, "{e}"),
+            FixtureError::Parse(e) => write!(f, "Failed to parse fixture file: {e}"),
+            FixtureError::RequiresRevC => write!(
+                f,
+                "Fixture sets USB switch, SDP, CC or header pins, which are not supported on PCB RevA or B"
+            ),
+            FixtureError::LevelOnInputPin { pin } => write!(
+                f,
+                "Fixture sets the level of {pin}, which is an input, add mode = \"output\" for it \
+                 or use --auto-config; nothing was written"
+            ),
+            FixtureError::VerifyFailed {
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Readback of {field} failed: expected {expected}, got {actual}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FixtureError {}
+
+impl From<DongleError> for FixtureError {
+    fn from(e: DongleError) -> Self {
+        FixtureError::Dongle(e)
+    }
+}
+
+/// One field that was (or is to be) changed.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Change {
+    pub field: &'static str,
+    pub from: String,
+    pub to: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.field, self.from, self.to)
+    }
+}
+
+/// One field whose current value differs from the desired one.
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct Mismatch {
+    pub field: &'static str,
+    pub expected: String,
+    /// `None` if the field does not exist on this board (RevC-only fields on RevA/B)
+    pub actual: Option<String>,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected {}, got {}",
+            self.field,
+            self.expected,
+            self.actual.as_deref().unwrap_or("(none)")
+        )
+    }
+}
+
+impl DesiredState {
+    /// Loads a fixture file, files ending with `.json` are parsed as JSON, everything else as TOML.
+    pub fn load(path: &Path) -> Result<Self, FixtureError> {
+        let contents = std::fs::read_to_string(path).map_err(FixtureError::Io)?;
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+        {
+            Self::from_json(&contents)
+        } else {
+            Self::from_toml(&contents)
+        }
+    }
+
+    pub fn from_toml(s: &str) -> Result<Self, FixtureError> {
+        toml::from_str(s).map_err(|e| FixtureError::Parse(e.to_string()))
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, FixtureError> {
+        serde_json::from_str(s).map_err(|e| FixtureError::Parse(e.to_string()))
+    }
+
+    /// The state `current` would be in after [apply], for checking a fixture before writing anything.
+    pub fn predict(&self, current: &StatusReport) -> StatusReport {
+        let mut predicted = current.clone();
+        let pin = |status: Option<HeaderPinStatus>, desired: DesiredPin| {
+            status.map(|s| HeaderPinStatus {
+                mode: desired.mode.unwrap_or(s.mode),
+                state: desired.state.unwrap_or(s.state),
+            })
+        };
+        predicted.header_p0 = pin(current.header_p0, self.pin(HeaderPin::P0));
+        predicted.header_p1 = pin(current.header_p1, self.pin(HeaderPin::P1));
+        predicted.forcing_sdp = self.forcing_sdp.or(current.forcing_sdp);
+        predicted.forcing_cc_low = self.forcing_cc_low.or(current.forcing_cc_low);
+        predicted.usb_switch_connected = self.usb_switch_connected.or(current.usb_switch_connected);
+        predicted.power_on = self.power_on.unwrap_or(current.power_on);
+        predicted
+    }
+
+    /// Adds `mode = output` for header pins that get a level but would stay inputs with `auto_config`,
+    /// fails with [FixtureError::LevelOnInputPin] without it.
+    pub fn with_output_modes(
+        &self,
+        current: &StatusReport,
+        auto_config: bool,
+    ) -> Result<Self, FixtureError> {
+        let mut completed = self.clone();
+        for (pin, name, desired) in [
+            (HeaderPin::P0, "P0", &mut completed.p0),
+            (HeaderPin::P1, "P1", &mut completed.p1),
+        ] {
+            let Some(desired) = desired else {
+                continue;
+            };
+            let mode = desired.mode.or(header_status(current, pin).map(|s| s.mode));
+            if desired.state.is_none() || mode != Some(PinMode::Input) {
+                continue;
+            }
+            if !auto_config {
+                return Err(FixtureError::LevelOnInputPin { pin: name });
+            }
+            desired.mode = Some(PinMode::Output);
+        }
+        Ok(completed)
+    }
+
+    fn requires_revc(&self) -> bool {
+        self.usb_switch_connected.is_some()
+            || self.forcing_sdp.is_some()
+            || self.forcing_cc_low.is_some()
+            || self.p0.is_some()
+            || self.p1.is_some()
+    }
+
+    fn pin(&self, pin: HeaderPin) -> DesiredPin {
+        match pin {
+            HeaderPin::P0 => self.p0,
+            HeaderPin::P1 => self.p1,
+        }
+        .unwrap_or_default()
+    }
+}
+
+fn header_status(current: &StatusReport, pin: HeaderPin) -> Option<HeaderPinStatus> {
+    match pin {
+        HeaderPin::P0 => current.header_p0,
+        HeaderPin::P1 => current.header_p1,
+    }
+}
+
+fn mode_field(pin: HeaderPin) -> &'static str {
+    match pin {
+        HeaderPin::P0 => "p0.mode",
+        HeaderPin::P1 => "p1.mode",
+    }
+}
+
+fn state_field(pin: HeaderPin) -> &'static str {
+    match pin {
+        HeaderPin::P0 => "p0.state",
+        HeaderPin::P1 => "p1.state",
+    }
+}
+
+/// Performs one write if `current` differs from `desired`, then verifies it by reading the value back.
+fn step<T: PartialEq + fmt::Debug + Copy>(
+    changes: &mut Vec<Change>,
+    field: &'static str,
+    current: Option<T>,
+    desired: Option<T>,
+    write: impl FnOnce(T) -> Result<(), DongleError>,
+    read_back: impl FnOnce() -> Result<T, DongleError>,
+) -> Result<(), FixtureError> {
+    let Some(desired) = desired else {
+        return Ok(());
+    };
+    if current == Some(desired) {
+        return Ok(());
+    }
+    write(desired)?;
+    let actual = read_back()?;
+    if actual != desired {
+        return Err(FixtureError::VerifyFailed {
+            field,
+            expected: format!("{desired:?}"),
+            actual: format!("{actual:?}"),
+        });
+    }
+    changes.push(Change {
+        field,
+        from: current.map(|c| format!("{c:?}")).unwrap_or("?".into()),
+        to: format!("{desired:?}"),
+    });
+    Ok(())
+}
+
+/// Drives the dongle from `current` into `desired` state, only writing fields that differ.
+///
+/// Order is: header pin modes, then levels (header pins, SDP, CC), then USB switch and power, power before the
+/// switch when it is turned on (as required with [crate::sequencing] enforced). Levels for pins that would stay
+/// inputs are handled by [DesiredState::with_output_modes] with `auto_config`.
+/// Every field that was changed is pushed to `changes`, also when a later step fails, so the caller can tell what
+/// was already written.
+pub fn apply(
+    bus: &dyn RegisterBus,
+    desired: &DesiredState,
+    current: &StatusReport,
+    auto_config: bool,
+    changes: &mut Vec<Change>,
+) -> Result<(), FixtureError> {
+    if current.pcb_revision == PcbRevision::RevAorB && desired.requires_revc() {
+        return Err(FixtureError::RequiresRevC);
+    }
+    let desired = &desired.with_output_modes(current, auto_config)?;
+
+    for pin in [HeaderPin::P0, HeaderPin::P1] {
+        step(
+            changes,
+            mode_field(pin),
+            header_status(current, pin).map(|s| s.mode),
+            desired.pin(pin).mode,
+            |mode| gpio_header_set_mode(bus, pin, mode),
+            || gpio_header_get_mode(bus, pin),
+        )?;
+    }
+
+    for pin in [HeaderPin::P0, HeaderPin::P1] {
+        // read again, a mode changed above turns the snapshot's pad level into the (possibly different) latch
+        let state = match header_status(current, pin) {
+            Some(_) => Some(gpio_header_get(bus, pin)?),
+            None => None,
+        };
+        step(
+            changes,
+            state_field(pin),
+            state,
+            desired.pin(pin).state,
+            |state| gpio_header_set(bus, pin, state),
+            || gpio_header_get(bus, pin),
+        )?;
+    }
+    step(
+        changes,
+        "forcing_sdp",
+        current.forcing_sdp,
+        desired.forcing_sdp,
+        |force| {
+            slg_io_set_mode(bus, SlgPin::SlgIo0, PinMode::Output)?;
+            let state = if force { PinState::High } else { PinState::Low };
+            slg_io_set(bus, SlgPin::SlgIo0, state)
+        },
+        || Ok(slg_io_get(bus, SlgPin::SlgIo0)? == PinState::High),
+    )?;
+    step(
+        changes,
+        "forcing_cc_low",
+        current.forcing_cc_low,
+        desired.forcing_cc_low,
+        |force| {
+            slg_io_set_mode(bus, SlgPin::SlgIo1, PinMode::Output)?;
+            let state = if force { PinState::Low } else { PinState::High };
+            slg_io_set(bus, SlgPin::SlgIo1, state)
+        },
+        || Ok(slg_io_get(bus, SlgPin::SlgIo1)? == PinState::Low),
+    )?;
+
+    let power = |changes: &mut Vec<Change>| {
+        step(
+            changes,
+            "power_on",
+            Some(current.power_on),
+            desired.power_on,
+            |on| dev_power_ctl(bus, on),
+            || is_dev_power_on(bus),
+        )
+    };
+    let power_first = desired.power_on == Some(true);
+    if power_first {
+        power(changes)?;
+    }
+    step(
+        changes,
+        "usb_switch_connected",
+        current.usb_switch_connected,
+        desired.usb_switch_connected,
+        |connected| {
+            usb_switch_configure(bus)?;
+            usb_switch_set(bus, connected)
+        },
+        || usb_switch_is_connected(bus),
+    )?;
+    if !power_first {
+        power(changes)?;
+    }
+
+    Ok(())
+}
+
+fn check<T: PartialEq + fmt::Debug>(
+    mismatches: &mut Vec<Mismatch>,
+    field: &'static str,
+    current: Option<T>,
+    desired: Option<T>,
+) {
+    let Some(desired) = desired else {
+        return;
+    };
+    if current.as_ref() != Some(&desired) {
+        mismatches.push(Mismatch {
+            field,
+            expected: format!("{desired:?}"),
+            actual: current.map(|c| format!("{c:?}")),
+        });
+    }
+}
+
+/// Compares `current` against `desired` without touching the dongle, in the same field order as [apply].
+///
+/// Returns the fields that differ, empty if the dongle is in the desired state.
+pub fn verify(desired: &DesiredState, current: &StatusReport) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    for pin in [HeaderPin::P0, HeaderPin::P1] {
+        check(
+            &mut mismatches,
+            mode_field(pin),
+            header_status(current, pin).map(|s| s.mode),
+            desired.pin(pin).mode,
+        );
+    }
+    for pin in [HeaderPin::P0, HeaderPin::P1] {
+        check(
+            &mut mismatches,
+            state_field(pin),
+            header_status(current, pin).map(|s| s.state),
+            desired.pin(pin).state,
+        );
+    }
+    check(
+        &mut mismatches,
+        "forcing_sdp",
+        current.forcing_sdp,
+        desired.forcing_sdp,
+    );
+    check(
+        &mut mismatches,
+        "forcing_cc_low",
+        current.forcing_cc_low,
+        desired.forcing_cc_low,
+    );
+    check(
+        &mut mismatches,
+        "usb_switch_connected",
+        current.usb_switch_connected,
+        desired.usb_switch_connected,
+    );
+    check(
+        &mut mismatches,
+        "power_on",
+        Some(current.power_on),
+        desired.power_on,
+    );
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::{DongleInfo, UsbDevice};
+    use crate::dongle_hal_revb::PowerState;
+    use crate::status::status_report;
+    use crate::usb4604_ral::{
+        Gpio8_10Input, Gpio17_20Dir, Gpio17_20Input, Gpio17_20Output, MockBus, SmscReg,
+    };
+
+    #[test]
+    fn level_for_input_pin_is_refused_or_configured_first() {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio8_10Input::ADDR,
+            Gpio8_10Input::new().with_gpio9_in(true).value(),
+        );
+        let dongle = DongleInfo {
+            bridge: UsbDevice::default(),
+            ftdi: None,
+            hub: None,
+        };
+        let current = status_report(&bus, &dongle).unwrap();
+        let writes_before = bus.writes().len();
+        let desired = DesiredState::from_toml("[p0]\nstate = \"high\"").unwrap();
+        assert!(matches!(
+            apply(&bus, &desired, &current, false, &mut Vec::new()),
+            Err(FixtureError::LevelOnInputPin { pin: "P0" })
+        ));
+        assert_eq!(bus.writes().len(), writes_before);
+
+        let mut changes = Vec::new();
+        apply(&bus, &desired, &current, true, &mut changes).unwrap();
+        let fields = changes.iter().map(|c| c.field).collect::<Vec<_>>();
+        assert_eq!(fields, vec!["p0.mode", "p0.state"]);
+        let addrs = bus.writes()[writes_before..]
+            .iter()
+            .map(|w| w.0)
+            .collect::<Vec<_>>();
+        assert_eq!(addrs, vec![Gpio17_20Dir::ADDR, Gpio17_20Output::ADDR]);
+    }
+
+    #[test]
+    fn level_is_compared_against_the_latch_after_a_mode_change() {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio8_10Input::ADDR,
+            Gpio8_10Input::new().with_gpio9_in(true).value(),
+        );
+        // P0 is an input pulled high, its latch is still low
+        bus.set(
+            Gpio17_20Input::ADDR,
+            Gpio17_20Input::new().with_gpio19_in(true).value(),
+        );
+        let dongle = DongleInfo {
+            bridge: UsbDevice::default(),
+            ftdi: None,
+            hub: None,
+        };
+        let current = status_report(&bus, &dongle).unwrap();
+        assert_eq!(current.header_p0.unwrap().state, PinState::High);
+        let desired = DesiredState::from_toml("[p0]\nmode = \"output\"\nstate = \"high\"").unwrap();
+        let mut changes = Vec::new();
+        apply(&bus, &desired, &current, false, &mut changes).unwrap();
+        let fields = changes.iter().map(|c| c.field).collect::<Vec<_>>();
+        assert_eq!(fields, vec!["p0.mode", "p0.state"]);
+        assert!(bus.reg::<Gpio17_20Output>().gpio19_out());
+    }
+
+    #[test]
+    fn verify_reports_differing_and_missing_fields() {
+        let current = StatusReport {
+            serial: "A1".into(),
+            power_state: PowerState::On,
+            power_on: true,
+            power_fault: None,
+            pcb_revision: PcbRevision::RevAorB,
+            revision: PcbRevision::RevAorB.to_string(),
+            relay_variant: false,
+            relay_count: 0,
+            usb_switch_connected: None,
+            forcing_sdp: None,
+            forcing_cc_low: None,
+            header_p0: None,
+            header_p1: None,
+        };
+        let desired = DesiredState::from_toml("power_on = true").unwrap();
+        assert!(verify(&desired, &current).is_empty());
+
+        let desired = DesiredState::from_toml("power_on = false\nforcing_sdp = false").unwrap();
+        assert_eq!(
+            verify(&desired, &current),
+            vec![
+                Mismatch {
+                    field: "forcing_sdp",
+                    expected: "false".into(),
+                    actual: None,
+                },
+                Mismatch {
+                    field: "power_on",
+                    expected: "false".into(),
+                    actual: Some("true".into()),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+//! `hub-info`: descriptor fields of the dongle's USB4604 hub, next to the result of relay variant detection, to
+//! check the descriptor programming of new units.
+//!
+//! The relay variant is detected from the hub product string ("relay", "relay2" or "dual", see
+//! [DongleInfo::relay_count]). Only the device descriptor and its strings are read, the hub is not opened, so no
+//! extra permissions are needed.
+
+use nusb::MaybeFuture;
+use serde::Serialize;
+
+use crate::discovery::DongleInfo;
+use crate::error::DongleError;
+use crate::port_diag::speed_name;
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct HubDescriptor {
+    /// USB location, e.g. `1-2`
+    pub location: String,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    /// bcdDevice, e.g. `0x0100` for 1.00
+    pub device_version: u16,
+    /// bcdUSB
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub speed: Option<&'static str>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct HubInfo {
+    /// `None` if no hub was found next to the bridge
+    pub hub: Option<HubDescriptor>,
+    /// Relays indicated by the hub product string, the relay commands also require PCB RevC, see
+    /// [crate::dongle_hal_revc::detect_relay_variant]
+    pub relay_count: u8,
+}
+
+/// Looks the hub of `dongle` up again and reads its descriptor.
+pub fn hub_info(dongle: &DongleInfo) -> Result<HubInfo, DongleError> {
+    let hub = match &dongle.hub {
+        Some(hub) => nusb::list_devices()
+            .wait()
+            .map_err(DongleError::Usb)?
+            .find(|d| {
+                d.bus_id() == hub.bus_id
+                    && d.port_chain() == hub.port_chain
+                    && d.vendor_id() == hub.vendor_id
+                    && d.product_id() == hub.product_id
+            })
+            .map(|d| HubDescriptor {
+                location: hub.location(),
+                vendor_id: d.vendor_id(),
+                product_id: d.product_id(),
+                device_version: d.device_version(),
+                usb_version: d.usb_version(),
+                class: d.class(),
+                subclass: d.subclass(),
+                protocol: d.protocol(),
+                speed: d.speed().map(speed_name),
+                manufacturer: d.manufacturer_string().map(str::to_string),
+                product: d.product_string().map(str::to_string),
+                serial: d.serial_number().map(str::to_string),
+            }),
+        None => None,
+    };
+    Ok(HubInfo {
+        hub,
+        relay_count: dongle.relay_count(),
+    })
+}
+
+/// Binary coded decimal version as `major.minor`, e.g. `0x0210` is `2.10`.
+pub fn format_bcd(version: u16) -> String {
+    format!("{:x}.{:02x}", version >> 8, version & 0xff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bcd_versions_keep_leading_minor_zero() {
+        assert_eq!(format_bcd(0x0200), "2.00");
+        assert_eq!(format_bcd(0x0210), "2.10");
+        assert_eq!(format_bcd(0x1001), "10.01");
+    }
+}
@@ -0,0 +1,82 @@
+//! Per-port power of the USB4604 hub, through the hub's own port power select registers
+//! (PORT_SEL1 to PORT_SEL4 at 0x3C00, 0x3C04, 0x3C08 and 0x3C0C, see [PORT_POWER_SELECT_ADDRS]).
+//!
+//! This is independent of the board's power switch driven by PWR_EN_N ([crate::dongle_hal_revb::dev_power_ctl]):
+//! the hub drives its PRTPWR output for the port, which only switches VBUS on variants where that output is
+//! wired to a port power switch.
+
+use serde::Serialize;
+
+use crate::error::DongleError;
+use crate::usb4604_ral::{PORT_POWER_SELECT_ADDRS, Port3PowerSelect, RegisterBus, SmscReg};
+
+/// Downstream ports of the USB4604, numbered from 1.
+pub const HUB_PORTS: u8 = PORT_POWER_SELECT_ADDRS.len() as u8;
+
+/// Hub port the device under test is connected to (its power select register is [Port3PowerSelect]).
+pub const DUT_HUB_PORT: u8 = 3;
+
+fn port_addr(port: u8) -> Result<u16, DongleError> {
+    port.checked_sub(1)
+        .and_then(|i| PORT_POWER_SELECT_ADDRS.get(usize::from(i)))
+        .copied()
+        .ok_or_else(|| {
+            DongleError::Unsupported(format!(
+                "Hub port {port} does not exist, ports are 1 to {HUB_PORTS}"
+            ))
+        })
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub enum HubPortPower {
+    /// Port power permanently on
+    On,
+    /// Port power disabled
+    Off,
+    /// Neither forced on nor disabled, port power follows the host's port power requests
+    HostControlled,
+}
+
+/// Forces the power of downstream hub `port` (1 to 4) on or off by setting the PERMANENT or DISABLED bit of
+/// its port power select register, clearing the other one.
+pub fn hub_port_power(bus: &dyn RegisterBus, port: u8, on: bool) -> Result<(), DongleError> {
+    let addr = port_addr(port)?;
+    let mut reg = Port3PowerSelect::from_value(bus.read_byte(addr)?);
+    reg.set_permanent(on);
+    reg.set_disabled(!on);
+    bus.write_byte(addr, reg.value())
+}
+
+/// Reads back what [hub_port_power] set for `port`.
+pub fn hub_port_power_get(bus: &dyn RegisterBus, port: u8) -> Result<HubPortPower, DongleError> {
+    let reg = Port3PowerSelect::from_value(bus.read_byte(port_addr(port)?)?);
+    Ok(match (reg.permanent(), reg.disabled()) {
+        (_, true) => HubPortPower::Off,
+        (true, false) => HubPortPower::On,
+        (false, false) => HubPortPower::HostControlled,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb4604_ral::MockBus;
+
+    #[test]
+    fn port_power_bits_keep_port_selection() {
+        let bus = MockBus::new();
+        bus.set(0x3C0C, 0b1000_0001);
+        assert_eq!(
+            hub_port_power_get(&bus, 4).unwrap(),
+            HubPortPower::HostControlled
+        );
+        hub_port_power(&bus, 4, false).unwrap();
+        assert_eq!(bus.get(0x3C0C), 0b1010_0001);
+        assert_eq!(hub_port_power_get(&bus, 4).unwrap(), HubPortPower::Off);
+        hub_port_power(&bus, 4, true).unwrap();
+        assert_eq!(bus.get(0x3C0C), 0b1001_0001);
+        assert_eq!(hub_port_power_get(&bus, 4).unwrap(), HubPortPower::On);
+        assert!(hub_port_power(&bus, 5, true).is_err());
+        assert!(hub_port_power(&bus, 0, true).is_err());
+    }
+}
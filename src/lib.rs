@@ -1,3 +1,48 @@
+pub mod audit;
+pub mod bench;
+pub mod board;
+pub mod build_info;
+pub mod bundle;
+pub mod caps;
+pub mod config;
+pub mod consistency;
+pub mod cycle_trace;
+pub mod dirmap;
+pub mod discovery;
+#[cfg(target_os = "linux")]
+pub mod doctor;
 pub mod dongle_hal_revb;
 pub mod dongle_hal_revc;
+pub mod error;
+pub mod explain;
+pub mod external;
+pub mod fault_trip;
+pub mod fixture;
+pub mod hub_info;
+pub mod hub_port;
+pub mod listing;
+pub mod lockout;
+pub mod monitor;
+pub mod parallel;
+#[cfg(unix)]
+pub mod persist;
+pub mod pin_snapshot;
+pub mod pinmap;
+pub mod policy;
+pub mod port_diag;
+pub mod read_only;
+pub mod relay_dwell;
+pub mod safe_state;
+pub mod sampler;
+pub mod sequencing;
+pub mod server;
+pub mod setup;
+pub mod signals;
+pub mod slg;
+pub mod slg_claim;
+pub mod status;
+pub mod timed;
+pub mod trace;
+pub mod uptime;
 pub mod usb4604_ral;
+pub mod watch;
@@ -0,0 +1,88 @@
+//! `list --with-status`: power, fault and revision of every connected dongle.
+//!
+//! Every dongle has to be opened to read its registers, this is done in parallel, see [crate::parallel].
+//! Only reads are done (same as `status --read-only`), so listing never changes pin directions.
+
+use serde::Serialize;
+
+use crate::board::BoardProfile;
+use crate::discovery::DongleInfo;
+use crate::dongle_hal_revb::{PcbRevision, PowerState};
+use crate::parallel::parallel_map;
+use crate::policy::{Policy, PolicyBus};
+use crate::status::read_only_status_report;
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DongleStatus {
+    Available {
+        power_state: PowerState,
+        /// `None` if PIO10 is not configured as input
+        power_fault: Option<bool>,
+        pcb_revision: PcbRevision,
+        /// [PcbRevision] display string
+        revision: String,
+    },
+    /// Dongle could not be opened or read, e.g. it is used by another process
+    Unavailable { error: String },
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct ListEntry {
+    pub serial: String,
+    #[serde(flatten)]
+    pub status: Option<DongleStatus>,
+}
+
+impl ListEntry {
+    /// Entry without status, nothing is opened.
+    pub fn new(info: &DongleInfo) -> Self {
+        ListEntry {
+            serial: info.display_serial(),
+            status: None,
+        }
+    }
+}
+
+fn probe(info: &DongleInfo, board: &BoardProfile, policy: Policy) -> Result<DongleStatus, String> {
+    let bridge = info
+        .open_bus(board)
+        .map_err(|e| e.to_string())?
+        .ok_or("disconnected")?;
+    let bus = PolicyBus::new(&bridge, policy);
+    let report = read_only_status_report(&bus, info).map_err(|e| e.to_string())?;
+    Ok(DongleStatus::Available {
+        power_state: report.power_state,
+        power_fault: report.power_fault,
+        pcb_revision: report.pcb_revision,
+        revision: report.revision,
+    })
+}
+
+/// Opens every dongle and reads its status with the policy `policy_for` returns for it, up to `jobs` at a time;
+/// failures are reported as [DongleStatus::Unavailable].
+pub fn list_with_status(
+    devices: &[DongleInfo],
+    jobs: usize,
+    board: &BoardProfile,
+    policy_for: impl Fn(&DongleInfo) -> Policy + Sync,
+) -> Vec<ListEntry> {
+    let probes = parallel_map(devices, jobs, |info| probe(info, board, policy_for(info)));
+    devices
+        .iter()
+        .zip(probes)
+        .map(|(info, probe)| {
+            let status = match probe {
+                Some(Ok(status)) => status,
+                Some(Err(error)) => DongleStatus::Unavailable { error },
+                None => DongleStatus::Unavailable {
+                    error: "panicked while reading status".into(),
+                },
+            };
+            ListEntry {
+                status: Some(status),
+                ..ListEntry::new(info)
+            }
+        })
+        .collect()
+}
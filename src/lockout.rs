@@ -0,0 +1,50 @@
+//! Power lockout for servicing the device under test: while a lockout file exists for a dongle,
+//! commands that would turn power on refuse to run.
+//!
+//! Lockout files live in the system temporary directory, `mchp_gpio_ctl/lockout-<serial>`, so they are
+//! shared between all users and scripts on the machine and cleared on reboot.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::discovery::DongleInfo;
+use crate::monitor::iso8601_utc;
+
+pub fn lockout_path(info: &DongleInfo) -> PathBuf {
+    std::env::temp_dir()
+        .join("mchp_gpio_ctl")
+        .join(format!("lockout-{}", info.file_id()))
+}
+
+/// Creates the lockout file, recording who engaged it and when.
+pub fn engage(info: &DongleInfo) -> io::Result<()> {
+    let path = lockout_path(info);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or("unknown user".into());
+    fs::write(
+        path,
+        format!("{user} at {}\n", iso8601_utc(SystemTime::now())),
+    )
+}
+
+/// Removes the lockout file, returns false if there was none.
+pub fn release(info: &DongleInfo) -> io::Result<bool> {
+    match fs::remove_file(lockout_path(info)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns who engaged the lockout and when, `None` if power is not locked out.
+pub fn holder(info: &DongleInfo) -> Option<String> {
+    fs::read_to_string(lockout_path(info))
+        .ok()
+        .map(|h| h.trim().to_string())
+}
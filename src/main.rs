@@ -1,81 +1,611 @@
-use nusb::MaybeFuture;
+use nusb::{Device, MaybeFuture};
+use std::io::{IsTerminal, Write as _};
+use std::path::PathBuf;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
 use mchp_gpio_ctl::dongle_hal_revc::{
-    HeaderPin, PinMode, PinState, gpio_header_get, gpio_header_get_mode, gpio_header_set,
-    gpio_header_set_mode, slg_io_get, slg_io_set, slg_io_set_mode, usb_switch_configure,
-    usb_switch_set,
+    HeaderPin, PinMode, PinState, SwitchDiag, detect_relay_variant, gpio_header_ensure,
+    gpio_header_ensure_mode, gpio_header_get, gpio_header_get_many, gpio_header_get_mode,
+    gpio_header_get_pad, gpio_header_set, gpio_header_set_latch, gpio_header_set_mode, relay_pin,
+    slg_io_ensure, slg_io_get, slg_io_get_input, slg_io_get_mode, slg_io_set, slg_io_set_mode,
+    usb_switch_configure, usb_switch_diag, usb_switch_ensure, usb_switch_set, usb_switch_swap,
 };
+#[cfg(unix)]
+use mchp_gpio_ctl::persist::{self, PersistentBus};
 use mchp_gpio_ctl::{
+    audit::AuditBus,
+    bench::{DEFAULT_ITERATIONS, FLAG_THRESHOLD, bench, compare},
+    board::{BoardProfile, load_board_profile},
+    build_info::{BuildInfo, build_info},
+    bundle::debug_bundle,
+    caps::{CommandInfo, Requirement, describe_commands, mark_available},
+    config::{Config, ConfigError, DEFAULT_SDP_SECS, EffectiveSettings, SettingFlags},
+    consistency::violations,
+    cycle_trace::{CycleEvent, trace_cycle},
+    dirmap::{PinDirection, direction_map},
+    discovery::{
+        DeviceList, DongleInfo, SelectError, SerialQuery, UsbDevice, claim_control_interface,
+        control_interface_number, device_layout, has_duplicate_serials, list_bridges_only,
+        list_dongles, list_usb_devices, select_by_hub_product, select_by_location, select_dongle,
+    },
     dongle_hal_revb::{
-        PcbRevision, dev_power_ctl, is_dev_power_on, is_dev_pwr_fault, pcb_revision,
+        PcbRevision, PowerPath, PowerState, dev_power_ctl, dev_power_ensure, emergency_power_off,
+        is_dev_power_on, is_dev_pwr_fault, pcb_revision, pwr_en_raw_get, pwr_en_raw_set,
+        read_dev_pwr_fault, soft_power_on, wait_fault_free,
+    },
+    dongle_hal_revc::SlgPin,
+    error::DongleError,
+    explain::{ExplainBus, ExplainedTransfer},
+    external::{StdoutToStderr, exit_code, run_shell_command},
+    fault_trip::measure_fault_trip,
+    fixture::{DesiredState, apply, verify},
+    hub_info::{HubInfo, format_bcd, hub_info},
+    hub_port::{HUB_PORTS, hub_port_power, hub_port_power_get},
+    listing::{DongleStatus, ListEntry, list_with_status},
+    lockout,
+    monitor::{TransitionDetector, iso8601_utc, line_is_since, parse_since},
+    parallel::{DEFAULT_JOBS, parallel_map},
+    pinmap::{
+        PinAccess, PinMapFormat, PinName, pin_config, pin_get, pin_map, pin_set, to_dot, to_table,
     },
-    dongle_hal_revc::{SlgPin, usb_switch_is_connected},
+    policy::{Policy, PolicyBus},
+    port_diag::{PortDiagnostics, port_diagnostics},
+    read_only::ReadOnlyBus,
+    relay_dwell::DwellStore,
+    safe_state::PanicGuard,
+    sampler::{ToggleStats, measure_toggle_rate, sample_pin, watch_pin},
+    sequencing,
+    server::serve,
+    setup::{setup_help, udev_rules},
+    signals::{Polarity, PolarityCheck, Signal},
+    slg::{BootMode, boot_mode, cc_pulse, parse_phase_ms, sdp_sequence, set_boot_mode, slg_config},
+    slg_claim::{ClaimStore, SlgPurpose},
+    status::{HeaderPinStatus, StatusFormat, StatusReport, read_only_status_report, status_report},
+    timed::{TimedOutcome, drive_timed, drive_timed_with_progress},
+    trace::{RecordingBus, Trace},
+    uptime,
+    usb4604_ral::{BridgeBus, RegisterBus, dump_registers, format_mchp, reset_gpio_registers},
+    watch::{DongleEvent, watch_dongles},
 };
 
-const VENDOR_SMSC: u16 = 0x0424;
-const PRODUCT_BRIDGE_DEV: u16 = 0x2530;
-const PRODUCT_USB4604_HUB: u16 = 0x4502;
-
-const VENDOR_FTDI: u16 = 0x0403;
-const PRODUCT_FT234: u16 = 0x6015;
-
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct Cli {
-    /// Serial number of a device to use, can use partial serial number if the result is unique
+    /// Serial number of a device to use (FTDI or bridge serial), can use partial serial number if the result is unique
     #[arg(short, long)]
     serial: Option<String>,
+    /// USB location of the dongle's bridge (e.g. 1-2.1, see 'list'), to tell apart dongles with identical serials
+    #[arg(long, conflicts_with_all = ["serial", "name", "index"])]
+    port: Option<String>,
+    /// Position of the dongle in the 'list' output, starting at 1
+    #[arg(long, conflicts_with_all = ["serial", "name", "hub_product"], value_parser = clap::value_parser!(u64).range(1..))]
+    index: Option<u64>,
+    /// Nickname of a device to use, assigned with 'mchp_gpio_ctl name set'
+    #[arg(short, long, conflicts_with = "serial")]
+    name: Option<String>,
+    /// Exact product string of the dongle's hub (see hub-info), only useful with hub descriptors customized
+    /// per dongle or fixture: stock hubs all report the same string
+    #[arg(long, value_name = "PRODUCT", conflicts_with_all = ["serial", "name", "port"])]
+    hub_product: Option<String>,
+    /// Run on every connected dongle in parallel, supported by off, emergency-off and check-revision
+    #[arg(long, conflicts_with_all = ["serial", "name", "port", "index", "hub_product"])]
+    all: bool,
+    /// Maximum number of dongles handled at the same time by --all and list --with-status
+    #[arg(long, default_value_t = DEFAULT_JOBS, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    jobs: usize,
+    /// Do not try to restore a safe state (USB switch connected, SDP released) if a command panics
+    #[arg(long)]
+    no_panic_recovery: bool,
+    /// USB switch enable is wired non-inverting (boards deviating from the reference schematic),
+    /// can also be set with `switch_active_high = true` in the config file; `=false` overrides a profile
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    switch_active_high: Option<bool>,
+    /// Power enable is wired non-inverting (boards deviating from the reference schematic, check with
+    /// verify-power), can also be set with `power_active_high = true` in the config file; `=false` overrides
+    /// a profile
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    power_active_high: Option<bool>,
+    /// Never leave USB data lines connected with power off: attach turns power on first, power off detaches
+    /// first; can also be set with `enforce_sequencing = true` in the config file; `=false` overrides a profile
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    enforce_sequencing: Option<bool>,
+    /// Log every register read and write to this file (JSON lines), to reproduce a bug offline with --replay
+    #[arg(long, value_name = "TRACE")]
+    record: Option<PathBuf>,
+    /// Run the command without hardware, feeding it the reads from a trace made with --record,
+    /// and fail if it does not make the same writes
+    #[arg(long, value_name = "TRACE", conflicts_with_all = ["record", "all"])]
+    replay: Option<PathBuf>,
+    /// Reuse the dongle interface kept open by a background helper, starting it if needed, to save the
+    /// device open cost in scripts; stop the helper with persist-stop (Unix only)
+    #[arg(long, conflicts_with_all = ["all", "record", "replay"])]
+    persist: bool,
+    /// Stop the --persist helper after this many seconds without commands
+    #[arg(long, default_value_t = 60, requires = "persist")]
+    persist_idle_secs: u64,
+    /// Board profile for derivative boards (USB IDs, interface, control requests, pin polarity), from
+    /// board_profiles.toml in the config directory; the stock dongle is the built-in 'reference' profile
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+    /// Power gate switched by on, off and the other power commands: the board load switch (PWR_EN_N, current
+    /// limited with fault reporting) or the hub's port power output for the DUT port (no fault reporting,
+    /// only effective where it drives a port power switch); choose one to find out which path is faulty
+    #[arg(long, value_enum, default_value_t)]
+    power_via: PowerPath,
+    /// Fail if the dongle ends up in an invalid combination of states (e.g. SDP forced with USB data
+    /// disconnected), checked after every command; apply refuses such fixtures before writing
+    #[arg(long)]
+    strict_state: bool,
+    /// Print a JSON diff of the registers the command wrote (register, bit, old, new) to stderr afterwards
+    #[arg(long, conflicts_with_all = ["all", "replay"])]
+    audit: bool,
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Copy, Clone, PartialEq, Debug, Default, ValueEnum)]
+enum ProgressFormat {
+    /// Remaining seconds, one per line
+    #[default]
+    Text,
+    /// `{"remaining": N}` JSON lines
+    Json,
+}
+
+/// Hardware the subcommands need, for `commands`; subcommands not listed work on every dongle. A test checks
+/// that every subcommand refused in [execute] on PCB RevA or B or without a relay is listed.
+const COMMAND_REQUIREMENTS: &[(&str, Requirement)] = &[
+    ("slg-status", Requirement::RevC),
+    ("sdp", Requirement::RevC),
+    ("force-sdp", Requirement::RevC),
+    ("release-sdp", Requirement::RevC),
+    ("sdp-sequence", Requirement::RevC),
+    ("cc-pulse", Requirement::RevC),
+    ("mode", Requirement::RevC),
+    ("detach", Requirement::RevC),
+    ("attach", Requirement::RevC),
+    ("full-detach", Requirement::RevC),
+    ("full-attach", Requirement::RevC),
+    ("trace-cycle", Requirement::RevC),
+    ("gpio-config", Requirement::RevC),
+    ("gpio-set", Requirement::RevC),
+    ("gpio-get", Requirement::RevC),
+    ("slg-get", Requirement::RevC),
+    ("gpio-get-all", Requirement::RevC),
+    ("gpio-stream", Requirement::RevC),
+    ("gpio-hold", Requirement::RevC),
+    ("max-toggle", Requirement::RevC),
+    ("compare", Requirement::RevC),
+    ("switch-diag", Requirement::RevC),
+    ("relay", Requirement::Relay),
+];
+
 #[derive(Subcommand)]
 enum Commands {
     /// Power on if not already on
-    On,
+    On {
+        /// Ramp power up by PWM-ing the power switch over this many milliseconds to limit inrush current
+        /// (precision is limited by USB control transfer latency), overrides the profile
+        #[arg(long)]
+        soft_start_ms: Option<u64>,
+    },
     /// Power off if not already off
     Off,
+    /// Safety kill switch: power off immediately without any checks, with --all on every dongle,
+    /// continuing past failures and printing a summary
+    EmergencyOff,
+    /// Power off, wait and power on again
+    PowerCycle {
+        /// How long to keep power off
+        #[arg(long, default_value_t = 1000)]
+        off_ms: u64,
+        /// After power on, wait until no power fault was seen for this long, so inrush faults are ignored
+        #[arg(long)]
+        wait_stable_ms: Option<u64>,
+        /// Give up waiting for stable power after this long and exit with an error
+        #[arg(long, default_value_t = 5000)]
+        timeout_ms: u64,
+    },
     /// Print dongle information (power status, IO config)
-    Status,
+    Status {
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: StatusFormat,
+        /// Do not write any registers, pin directions are left untouched;
+        /// power fault is reported as unknown if PIO10 is not configured as input
+        #[arg(long)]
+        read_only: bool,
+        /// Compare against a report saved with --format json, print only the differing fields
+        /// and exit with 1 if anything differs
+        #[arg(long, value_name = "FILE", conflicts_with = "format")]
+        baseline: Option<PathBuf>,
+        /// Fields not compared with the baseline, e.g. p0.state, or p0 for all of P0
+        #[arg(long, value_delimiter = ',', requires = "baseline")]
+        ignore: Vec<String>,
+    },
     /// List connected devices serials
-    List,
+    List {
+        /// Open every dongle (in parallel) and include power, fault and PCB revision
+        #[arg(long, conflicts_with = "fast")]
+        with_status: bool,
+        /// Only list bridge devices by USB location and bridge serial, skipping FTDI and hub pairing;
+        /// faster with many dongles, but the FTDI (label) serial is not shown
+        #[arg(long)]
+        fast: bool,
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print dongle USB details: sibling devices and the bridge configuration/interface layout
+    Info,
+    /// Write the chip reset values to the GPIO direction and output registers, un-configuring every pin, to
+    /// recover from a corrupted register state; unlike restoring a safe state this also releases PWR_EN_N,
+    /// which can switch device power
+    RegReset {
+        /// Confirm that power enable, USB switch, SDP and CC are released to their board defaults
+        #[arg(long)]
+        expert: bool,
+    },
+    /// Read and print all known registers
+    RegDump {
+        #[arg(long, value_enum, default_value_t)]
+        format: RegDumpFormat,
+    },
+    /// Print direction, level and internal pull of the SLG GreenPAK IOs and what they do (PCB RevC and up)
+    SlgStatus {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the board pin mapping: signals, PIOs and connector labels, no device needed
+    Pinmap {
+        #[arg(long, value_enum, default_value_t)]
+        format: PinMapFormat,
+        /// PCB revision to show the mapping for
+        #[arg(long, value_enum, default_value = "c")]
+        revision: PcbRevision,
+    },
+    /// Check the PCB revision for incoming inspection, exit with 1 unless it is the expected one; with --all
+    /// checks every connected dongle and prints a summary
+    CheckRevision {
+        /// Expected revision: revc (or c), reva / revb (or ab, the two can not be told apart)
+        #[arg(long, value_enum, ignore_case = true)]
+        expect: PcbRevision,
+    },
+    /// Print the direction (input / output) of every known pin, by board signal name
+    Dirmap {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print sibling device descriptors, status, all registers and the tool version as JSON for support tickets
+    DebugBundle,
 
     // Only on RevC
-    /// Force SDP for 10 seconds, then go back to USART mode, assuming switch is in USART mode (PCB RevC and up)
-    Sdp,
-    /// Force SDP mode (Amber LED will blink fast) (PCB RevC and up)
-    ForceSdp,
+    /// Force SDP for 10 seconds (or the profile's `sdp_secs`), then go back to USART mode, assuming switch is in
+    /// USART mode (PCB RevC and up)
+    Sdp {
+        /// How long to force SDP, overrides the profile
+        #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+        secs: Option<u64>,
+        /// How to report the countdown on stderr
+        #[arg(long, value_enum, default_value_t)]
+        progress: ProgressFormat,
+    },
+    /// Force SDP mode (Amber LED will blink fast), stays forced until release-sdp unless --auto-release-after
+    /// is given (PCB RevC and up)
+    ForceSdp {
+        /// Stay running and release SDP after this many seconds, or earlier on Ctrl-C / SIGTERM;
+        /// SDP stays forced if this process is killed otherwise (SIGKILL, USB disconnect)
+        #[arg(long, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..))]
+        auto_release_after: Option<u64>,
+    },
     /// Release to USART mode (Amber LED will not blink, unless switch is in SDP mode) (PCB RevC and up)
     ReleaseSdp,
+    /// Drive SLG_IO0 (SDP) through a pulse pattern for vendor specific bootloader entry, then leave it low (PCB RevC and up)
+    SdpSequence {
+        /// Comma separated durations in milliseconds of alternating high and low phases, starting high,
+        /// e.g. "50,100,50"
+        #[arg(required = true, value_delimiter = ',', value_parser = parse_phase_ms)]
+        phases: Vec<Duration>,
+    },
+    /// Force the CC lines low through SLG_IO1 for a while, then release them, without touching power or the
+    /// USB switch: the port partner sees a CC detach and re-detects the connection (PCB RevC and up)
+    CcPulse {
+        /// How long to hold CC low, in milliseconds
+        #[arg(long, default_value_t = 200, value_parser = clap::value_parser!(u64).range(1..))]
+        ms: u64,
+    },
+    /// Print the boot mode (USART or forced SDP), or set it; the board's mode switch is not readable,
+    /// in its SDP position the device boots SDP regardless (PCB RevC and up)
+    Mode { mode: Option<BootMode> },
 
     /// Disconnect USB data lines from a device via hardware switch (PCB RevC and up)
-    Detach,
+    Detach {
+        /// Detach even if a device is enumerated behind the dongle
+        #[arg(long)]
+        force: bool,
+        /// Refuse to detach while a device is enumerated behind the dongle, instead of only warning
+        #[arg(long)]
+        strict: bool,
+        /// Shell command to run once detached, its exit code is propagated
+        #[arg(long)]
+        run: Option<String>,
+        /// Restore the USB switch as it was before detaching when the --run command exits, also if it fails or
+        /// is interrupted with Ctrl-C
+        #[arg(long, requires = "run")]
+        then_attach: bool,
+    },
     /// Connect USB data lines to the device (default) (PCB RevC and up)
-    Attach,
+    Attach {
+        /// Wait for a device to enumerate behind the dongle and fail if it does not
+        #[arg(long)]
+        verify_enumeration: bool,
+        /// How long to wait for enumeration, in seconds
+        #[arg(long, default_value_t = 5.0, value_parser = parse_positive, requires = "verify_enumeration")]
+        timeout: f64,
+        /// Wait this long before exiting, so the next command can use the device; with --verify-enumeration
+        /// the wait starts once it enumerated
+        #[arg(long, value_name = "MS", default_value_t = 0)]
+        settle_ms: u64,
+    },
     /// Emulate cable detach - disconnect USB data lines, set CC lines to low and disable power to a device (PCB RevC and up)
-    FullDetach,
+    FullDetach {
+        /// Detach even if a device is enumerated behind the dongle
+        #[arg(long)]
+        force: bool,
+        /// Refuse to detach while a device is enumerated behind the dongle, instead of only warning
+        #[arg(long)]
+        strict: bool,
+        /// Read power, USB switch and CC state first and skip whatever is already detached
+        #[arg(long)]
+        ensure: bool,
+    },
     /// Emulate cable insertion - reconnect USB data lines, set CC lines according to the switch position or force-sdp command, provide power (PCB RevC and up)
-    FullAttach,
+    FullAttach {
+        /// Wait for a device to enumerate behind the dongle and fail if it does not
+        #[arg(long)]
+        verify_enumeration: bool,
+        /// How long to wait for enumeration, in seconds
+        #[arg(long, default_value_t = 5.0, value_parser = parse_positive, requires = "verify_enumeration")]
+        timeout: f64,
+        /// Read power, USB switch and CC state first and skip whatever is already attached,
+        /// so re-running on an attached device changes nothing
+        #[arg(long)]
+        ensure: bool,
+        /// Wait this long before exiting, so the next command can use the device; with --verify-enumeration
+        /// the wait starts once it enumerated
+        #[arg(long, value_name = "MS", default_value_t = 0)]
+        settle_ms: u64,
+    },
+    /// Full detach, then full attach, printing when each step happened and when the device behind the dongle
+    /// disappeared and enumerated again, to line up with scope captures (PCB RevC and up)
+    TraceCycle {
+        /// How long to stay detached, in milliseconds
+        #[arg(long, default_value_t = 1000)]
+        off_ms: u64,
+        /// How long to wait for the device to enumerate after attach, in seconds
+        #[arg(long, default_value_t = 10.0, value_parser = parse_positive)]
+        timeout: f64,
+        /// Print the timeline as a JSON array of {t_ms, event}
+        #[arg(long)]
+        json: bool,
+    },
 
     /// Configure GPIO header pin (p0 or p1) as Input or Output (e.g., gpio-config p0 output) (PCB RevC and up)
     GpioConfig {
         pin: HeaderPin,
         mode: PinMode,
+        /// Read the current mode first and only write if it differs
+        #[arg(long)]
+        ensure: bool,
     },
     /// Set GPIO header pin configured as Output to High or Low (e.g., gpio-set p0 high) (PCB RevC and up)
     GpioSet {
         pin: HeaderPin,
         state: PinState,
+        /// Read the current state first and only write if it differs
+        #[arg(long)]
+        ensure: bool,
+        /// Only write the output latch, also for pins configured as input: the pin keeps its direction
+        /// and drives the new level once it is configured as output
+        #[arg(long, conflicts_with = "ensure")]
+        latch_only: bool,
     },
     /// Read GPIO header pin state (PCB RevC and up)
     GpioGet {
         pin: HeaderPin,
     },
+    /// Read an SLG IO: the driven (latched) level for outputs, the pad level for inputs (PCB RevC and up)
+    SlgGet {
+        pin: SlgPin,
+        /// Read the level at the pad from the input register even while the IO is an output, to spot
+        /// an external pull or driver overriding it
+        #[arg(long)]
+        raw_input: bool,
+    },
+    /// Read all GPIO header pin states from a single register snapshot (PCB RevC and up)
+    GpioGetAll,
+    /// Sample a GPIO header input pin at a fixed rate and print samples with monotonic timestamps (PCB RevC and up)
+    ///
+    /// The rate is bounded by USB control transfer latency, the achieved rate is reported at the end.
+    GpioStream {
+        pin: HeaderPin,
+        /// Sample rate in Hz
+        #[arg(long, default_value_t = 50.0, value_parser = parse_positive)]
+        hz: f64,
+        /// How long to sample for, in seconds
+        #[arg(long, default_value_t = 10.0, value_parser = parse_positive)]
+        duration: f64,
+        /// Only print samples where the state changed (the first sample is always printed)
+        #[arg(long)]
+        edges: bool,
+        /// Read back to back as fast as control transfers allow instead of at --hz and print only edges,
+        /// for the best edge resolution (about 1ms, the hub has no GPIO change interrupt)
+        #[arg(long, conflicts_with_all = ["hz", "edges"])]
+        watch: bool,
+    },
+
+    /// Drive a GPIO header pin and keep checking the level at the pad, reporting when something external
+    /// overrides it; runs until Ctrl-C and leaves the pin driven (PCB RevC and up)
+    GpioHold {
+        pin: HeaderPin,
+        state: PinState,
+        /// Interval between checks, in milliseconds
+        #[arg(long, default_value_t = 200)]
+        check_ms: u64,
+    },
+
+    /// Toggle a GPIO header pin as fast as possible and report the achieved frequency, without and with
+    /// readback of every write; the pin mode and level are restored afterwards (PCB RevC and up)
+    MaxToggle {
+        pin: HeaderPin,
+        /// How long to toggle for in each run, in seconds
+        #[arg(long, default_value_t = 2.0, value_parser = parse_positive)]
+        duration: f64,
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Benchmark header pin toggle and register read latency of two dongles and print them side by side,
+    /// flagging differences over 25%; the pin mode and level are restored afterwards (PCB RevC and up)
+    Compare {
+        /// Serial of the first dongle, partial if unique
+        serial_a: String,
+        /// Serial of the second dongle, partial if unique
+        serial_b: String,
+        /// Header pin toggled on both dongles, refused if it drives a relay on either of them
+        #[arg(long, default_value = "p0")]
+        pin: HeaderPin,
+        /// Number of toggles and reads on each dongle
+        #[arg(long, default_value_t = DEFAULT_ITERATIONS, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        iterations: usize,
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Keep power to the device off for servicing: 'on' turns power off and refuses on, full-attach,
+    /// power-cycle, pin and fixture power on (from any user or script) until 'off' is run
+    Lockout { action: LockoutAction },
+    /// Show for how long power has been on (or off) since the last change made with this tool
+    Uptime,
+    /// Guided check of the power enable polarity on a new board: turns power off, then on, asking each time
+    /// whether the device is powered (LED, supply rail), and tells if --power-active-high is needed;
+    /// the previous power state is restored afterwards
+    VerifyPower,
+    /// Safety validation with a deliberate short on the device output: turns power on, measures how long the
+    /// board power switch takes to report the fault and turns power off right away
+    FaultTest {
+        /// Required, the short is applied for real and the output is powered into it
+        #[arg(long)]
+        i_understand_the_risk: bool,
+        /// Give up and turn power off if no fault was reported within this many milliseconds
+        #[arg(long, default_value_t = 100)]
+        timeout_ms: u64,
+    },
+    /// Compare the level driven on the USB switch control line with the level read back from the pin, to tell a
+    /// stuck switch control line apart from a stuck switch (PCB RevC and up); exits with 1 if they disagree
+    SwitchDiag {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show negotiated speed and hub port of the device behind the dongle, with power, USB switch, SDP and CC state
+    PortDiag {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show the string descriptors and IDs of the dongle's USB hub and the relay variant detected from them
+    /// (hub product string), to check descriptor programming of new units
+    HubInfo {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Force VBUS of one downstream port of the dongle's USB hub on or off through the hub's port power select
+    /// register (PORT_SEL1-4 at 0x3C00-0x3C0C), or show it without a state. Unlike on/off, which drive the board
+    /// power switch (PWR_EN_N), this only switches VBUS where the hub's PRTPWR output is wired to a port switch
+    HubPortPower {
+        /// Downstream port of the hub, 1 to 4
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=HUB_PORTS as i64))]
+        port: u8,
+        /// Force port power on or off, without it the current setting is shown
+        state: Option<OnOff>,
+    },
+    /// Get, set or configure a pin by name: header p0/p1, SLG io0/io1 (PCB RevC and up for those), or pwr-en
+    ///
+    /// Without an action prints the pin mode and state. gpio-* commands remain available as aliases.
+    /// pwr-en is raw low-level access bypassing inversion and safety logic, for recovery only:
+    /// levels are electrical (high turns power off, on with --power-active-high), set with --dir and --level
+    /// and confirm with --expert.
+    Pin {
+        name: PinName,
+        #[command(subcommand)]
+        action: Option<PinAction>,
+        /// pwr-en only: pin direction
+        #[arg(long)]
+        dir: Option<PinMode>,
+        /// pwr-en only: electrical output level, no inversion is applied
+        #[arg(long)]
+        level: Option<PinState>,
+        /// pwr-en only: confirm that you know this bypasses the inversion and safety logic
+        #[arg(long)]
+        expert: bool,
+    },
+    /// Control the SSR (opto-relay) on relay variants, relay 1 is P0, relay 2 (dual relay variant) is P1
+    Relay {
+        #[command(subcommand)]
+        action: RelayAction,
+        /// Minimum time between two switchings of a relay, a close or open coming earlier waits for the rest;
+        /// 0 disables it [default: from the device profile, or 100]
+        #[arg(long)]
+        min_dwell_ms: Option<u64>,
+    },
+
+    /// Poll dongle status and print only state transitions, with ISO-8601 UTC timestamps, until interrupted
+    Monitor {
+        /// Polling interval
+        #[arg(long, default_value_t = 500)]
+        interval_ms: u64,
+        /// Only print events at or after this UTC time (e.g. 2026-10-15T08:30:00Z)
+        #[arg(long, value_parser = parse_since)]
+        since: Option<String>,
+        /// Instead of polling a dongle, print events from a previously captured monitor log
+        #[arg(long)]
+        replay: Option<PathBuf>,
+        /// Never write any registers, for an observer next to another process controlling the dongle;
+        /// power fault is reported as unknown if PIO10 is not configured as input
+        #[arg(long, conflicts_with = "replay")]
+        read_only: bool,
+    },
+
+    /// Drive the dongle into the state described by a TOML or JSON fixture file, only changing what differs
+    ///
+    /// Pin modes are applied first, then levels (header pins, SDP, CC), then USB switch and power.
+    /// Every write is verified by reading the value back.
+    Apply {
+        /// Path to the fixture file, `.json` files are parsed as JSON, anything else as TOML
+        path: PathBuf,
+        /// Configure header pins as output when the fixture sets their level but leaves them inputs,
+        /// instead of refusing the fixture
+        #[arg(long)]
+        auto_config: bool,
+    },
+    /// Check that the dongle is in the state described by a fixture file, without writing anything
+    ///
+    /// Exits with 1 and prints the differing fields if anything does not match, with 2 if the file can not be read.
+    Verify {
+        /// Path to the fixture file, same format as for apply
+        path: PathBuf,
+        /// Print the differing fields as JSON
+        #[arg(long)]
+        json: bool,
+    },
 
     /// Print udev rule to the stdout, run 'mchp_gpio_ctl udev --help' for more information
     ///
@@ -88,110 +618,552 @@ enum Commands {
     #[cfg(target_os = "linux")]
     #[command(verbatim_doc_comment)]
     Udev,
+    /// List all subcommands with their arguments and hardware requirements
+    #[command(name = "commands")]
+    Subcommands {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+        /// Also show which commands are available on the selected dongle
+        #[arg(long)]
+        device: bool,
+    },
+
+    /// Check that udev rules are installed and effective, and that the dongle can be opened
+    #[cfg(target_os = "linux")]
+    Doctor,
+    /// Serve newline-delimited JSON requests, keeping the device claimed, for use from other languages
+    ///
+    /// Requests look like {"id": 1, "method": "gpio_set", "params": {"pin": "p0", "state": "high"}},
+    /// see the server module documentation for the list of methods.
+    Serve {
+        /// Read requests from stdin and write responses to stdout
+        #[arg(long, required = true)]
+        stdio: bool,
+    },
+    /// Manage device nicknames stored in the config file, to be used with --name
+    Name {
+        #[command(subcommand)]
+        action: NameAction,
+    },
+    /// Print platform specific instructions for getting access to the dongle (udev on Linux, WinUSB on Windows)
+    SetupHelp,
+    /// Print dongle arrival and removal events as they happen, starting with the connected ones, until Ctrl-C
+    WatchDevices {
+        /// Print events as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print the USB control transfers a command would issue (bmRequestType, bRequest, wValue, wIndex, data),
+    /// running it against a simulated dongle instead of the hardware, e.g. explain -- full-attach
+    ///
+    /// The simulated dongle starts with registers as after reset, see the explain module documentation.
+    Explain {
+        /// Print the transfers as a JSON array after the command finished, its messages go to stderr
+        #[arg(long)]
+        json: bool,
+        /// PCB revision of the simulated dongle
+        #[arg(long, value_enum, ignore_case = true, default_value = "c")]
+        revision: PcbRevision,
+        /// Command to explain, with its arguments and global options
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+    /// Print enabled cargo features and which platform dependent parts this binary supports
+    Features {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print version, git commit, target triple and nusb version
+    Version {
+        /// Print as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Per-device defaults from the config file
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// Stop the --persist helper of the selected dongle (same --serial/--name/--port/--index as used with --persist)
+    PersistStop,
+    /// Background helper started by --persist, keeps the dongle open and serves register accesses on a socket
+    #[command(hide = true)]
+    PersistHelper {
+        #[arg(long)]
+        socket: PathBuf,
+        #[arg(long)]
+        idle_secs: u64,
+    },
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default, ValueEnum)]
+enum RegDumpFormat {
+    /// Register name, address and value
+    #[default]
+    Text,
+    Json,
+    /// `0xBF800833=0x0B` lines with full hub register addresses (0xBF800000 + offset), sorted by address,
+    /// to diff against Microchip's configuration tool
+    Mchp,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+enum OnOff {
+    On,
+    Off,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+enum LockoutAction {
+    /// Turn power off and lock it out
+    On,
+    /// Release the lockout, power stays as is
+    Off,
+    /// Print whether power is locked out
+    Status,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Subcommand)]
+enum PinAction {
+    /// Print pin mode and state
+    Get,
+    /// Set a pin configured as output to high or low
+    Set { state: PinState },
+    /// Configure the pin as input or output
+    Config { mode: PinMode },
+}
+
+#[derive(Subcommand)]
+enum NameAction {
+    /// Assign a nickname to a device serial (FTDI or bridge serial), replacing its previous nickname
+    Set { serial: String, name: String },
+    /// List nicknames and serials
+    List,
+    /// Remove a nickname
+    Rm { name: String },
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Subcommand)]
+enum ProfileAction {
+    /// Print the selected device's effective settings and where each comes from (flag > profile > config > default)
+    Show,
+}
+
+#[derive(Subcommand)]
+enum RelayAction {
+    /// Close the relay (short the contacts by driving its pin high)
+    Close {
+        /// Relay number, 2 is only present on the dual relay variant
+        #[arg(default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+        index: u8,
+    },
+    /// Open the relay (drive its pin low)
+    Open {
+        /// Relay number, 2 is only present on the dual relay variant
+        #[arg(default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+        index: u8,
+    },
+    /// Print relay state, of all relays if no number is given
+    Status {
+        /// Relay number, 2 is only present on the dual relay variant
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=2))]
+        index: Option<u8>,
+    },
 }
 
 fn main() {
     env_logger::init();
-    let cli = Cli::parse();
-
-    let all_devices = nusb::list_devices().wait().unwrap().collect::<Vec<_>>();
-    // println!("Devices: {:#?}", all_devices);
-    let devices = all_devices
-        .iter()
-        .filter(|d| d.vendor_id() == VENDOR_SMSC && d.product_id() == PRODUCT_BRIDGE_DEV)
-        .map(|d| {
-            let same_hub = d.port_chain();
-            let same_hub = &same_hub[..same_hub.len() - 1];
-            let ftdi = all_devices.iter().find(|d| {
-                d.port_chain().starts_with(same_hub)
-                    && d.vendor_id() == VENDOR_FTDI
-                    && d.product_id() == PRODUCT_FT234
-            });
-            let serial = ftdi.and_then(|f| f.serial_number()).unwrap_or("");
-            let hub = all_devices.iter().find(|d| {
-                d.port_chain().starts_with(same_hub) && d.vendor_id() == VENDOR_SMSC && d.product_id() == PRODUCT_USB4604_HUB
-            });
-            let product_string = hub.and_then(|h| h.product_string()).unwrap_or("");
-            (d, serial, product_string)
-        })
-        .collect::<Vec<_>>();
-    // println!("{:?}", devices);
+    let mut cli = Cli::parse();
+    let board_profile = cli
+        .profile
+        .as_deref()
+        .map(|name| match load_board_profile(name) {
+            Ok(board) => board,
+            Err(e) => {
+                println!("{}", e.to_string().red());
+                std::process::exit(1);
+            }
+        });
+    let mut board = board_profile.unwrap_or_default();
 
-    if matches!(cli.command, Commands::List) {
-        println!("Connected device list:");
-        for (_di, serial, _product_string) in devices {
-            println!("{serial}");
-        }
-        return;
-    }
     #[cfg(target_os = "linux")]
     if matches!(cli.command, Commands::Udev) {
-        println!(
-            r#"SUBSYSTEMS=="usb", ATTRS{{idVendor}}=="{:04x}", ATTRS{{idProduct}}=="{:04x}", TAG+="uaccess", GROUP="plugdev", MODE="0660""#,
-            VENDOR_SMSC, PRODUCT_BRIDGE_DEV
+        println!("{}", udev_rules(&board));
+        return;
+    }
+    if let Commands::Subcommands {
+        json,
+        device: false,
+    } = cli.command
+    {
+        print_commands(
+            &describe_commands(&Cli::command(), COMMAND_REQUIREMENTS),
+            json,
         );
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    if matches!(cli.command, Commands::Doctor) {
+        let diagnostics = mchp_gpio_ctl::doctor::run_checks(&board);
+        for d in &diagnostics {
+            if d.ok {
+                println!("{} {}", "OK".green(), d.message);
+            } else {
+                println!("{} {}", "!!".red(), d.message);
+            }
+        }
+        if diagnostics.iter().any(|d| !d.ok) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if matches!(cli.command, Commands::SetupHelp) {
+        println!("{}", setup_help(&board));
+        return;
+    }
+    if let Commands::Pinmap { format, revision } = cli.command {
+        let pins = pin_map(revision);
+        match format {
+            PinMapFormat::Table => print!("{}", to_table(&pins)),
+            PinMapFormat::Json => println!("{}", serde_json::to_string_pretty(&pins).unwrap()),
+            PinMapFormat::Dot => print!("{}", to_dot(&pins)),
+        }
+        return;
+    }
+    if let Commands::WatchDevices { json } = cli.command {
+        let result = watch_dongles(&board, |event| {
+            if json {
+                println!("{}", serde_json::to_string(&event).unwrap());
+                return;
+            }
+            match event {
+                DongleEvent::Arrived(dongle) => println!(
+                    "{} {} at {}",
+                    "arrived".green(),
+                    dongle.display_serial(),
+                    dongle.bridge.location()
+                ),
+                DongleEvent::Removed(serial) => println!("{} {serial}", "removed".yellow()),
+            }
+        });
+        if let Err(e) = result {
+            println!("{}", format!("Failed to watch USB devices: {e}").red());
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Commands::Features { json } = cli.command {
+        let info = build_info();
+        if json {
+            let features =
+                serde_json::json!({"features": info.features, "capabilities": info.capabilities});
+            println!("{}", serde_json::to_string_pretty(&features).unwrap());
+        } else {
+            print_features(&info);
+        }
+        return;
+    }
+    if let Commands::Version { json } = cli.command {
+        let info = build_info();
+        if json {
+            println!("{}", serde_json::to_string_pretty(&info).unwrap());
+        } else {
+            println!("{info}");
+        }
+        return;
+    }
+
+    if let Commands::Name { action } = &cli.command {
+        if let Err(e) = run_name_command(action) {
+            println!("{}", e.to_string().red());
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Commands::Monitor {
+        since,
+        replay: Some(replay),
+        ..
+    } = &cli.command
+    {
+        let log = match std::fs::read_to_string(replay) {
+            Ok(log) => log,
+            Err(e) => {
+                println!(
+                    "{}",
+                    format!("Failed to read {}: {e}", replay.display()).red()
+                );
+                std::process::exit(1);
+            }
+        };
+        for line in log.lines() {
+            if since
+                .as_ref()
+                .is_none_or(|since| line_is_since(line, since))
+            {
+                println!("{line}");
+            }
+        }
+        return;
+    }
+
+    let serial = match &cli.name {
+        Some(name) => match Config::load().and_then(|c| c.resolve_name(name).map(str::to_string)) {
+            Ok(serial) => Some(serial),
+            Err(e) => {
+                println!("{}", e.to_string().red());
+                std::process::exit(1);
+            }
+        },
+        None => cli.serial.clone(),
+    };
+    if let Commands::List {
+        fast: true, json, ..
+    } = cli.command
+    {
+        let bridges = list_bridges_only(&board).unwrap();
+        if json {
+            println!("{}", serde_json::to_string_pretty(&bridges).unwrap());
+        } else {
+            println!("Connected bridges (location, bridge serial):");
+            for bridge in &bridges {
+                println!(
+                    "{:<12} {}",
+                    bridge.location(),
+                    bridge.serial_number.as_deref().unwrap_or("")
+                );
+            }
+        }
+        return;
+    }
+    if let Commands::Explain {
+        json,
+        revision,
+        command,
+    } = &cli.command
+    {
+        match explain(command, *revision, *json, board_profile.as_ref()) {
+            Ok(outcome) => outcome.exit_on_failure(),
+            Err(e) => {
+                println!("{}", e.to_string().red());
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if let Some(path) = cli.replay.clone() {
+        match replay(&mut cli, &path, board_profile.as_ref()) {
+            Ok(outcome) => outcome.exit_on_failure(),
+            Err(e) => {
+                println!("{}", e.to_string().red());
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if cli.all
+        && !matches!(
+            cli.command,
+            Commands::Off | Commands::EmergencyOff | Commands::CheckRevision { .. }
+        )
+    {
         println!(
-            r#"SUBSYSTEMS=="usb", ATTRS{{idVendor}}=="{:04x}", ATTRS{{idProduct}}=="{:04x}", TAG+="uaccess", GROUP="plugdev", MODE="0660""#,
-            VENDOR_FTDI, PRODUCT_FT234
+            "{}",
+            "--all is only supported by off, emergency-off and check-revision".red()
         );
+        std::process::exit(1);
+    }
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e.to_string().red());
+            std::process::exit(1);
+        }
+    };
+    if board_profile.is_none() {
+        board.control_protocol = config.control_protocol;
+    }
+    if matches!(cli.command, Commands::PersistStop) || cli.persist {
+        #[cfg(unix)]
+        let result = run_persistent(&mut cli, &config, board_profile.as_ref());
+        #[cfg(not(unix))]
+        let result: Result<Outcome, Box<dyn std::error::Error>> =
+            Err("--persist is only supported on Unix".into());
+        match result {
+            Ok(outcome) => outcome.exit_on_failure(),
+            Err(e) => {
+                println!("{}", e.to_string().red());
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    let devices = list_dongles(&board).unwrap();
+    if let Commands::CheckRevision { expect } = cli.command
+        && cli.all
+    {
+        if !check_revision_all(&devices, cli.jobs, expect, &board) {
+            std::process::exit(1);
+        }
+        return;
+    }
+    if cli.all {
+        let emergency = matches!(cli.command, Commands::EmergencyOff);
+        let policy_for = |dongle: &DongleInfo| {
+            policy(
+                &cli,
+                &effective_settings(&cli, &config, board_profile.as_ref(), Some(dongle)),
+            )
+        };
+        if !power_off_all(&devices, cli.jobs, emergency, &board, policy_for) {
+            std::process::exit(1);
+        }
         return;
     }
 
-    let (di, serial, product_string) = if devices.is_empty() {
-        println!("No devices found");
+    if let Commands::Compare {
+        serial_a,
+        serial_b,
+        pin,
+        iterations,
+        json,
+    } = &cli.command
+    {
+        let policy_for = |dongle: &DongleInfo| {
+            policy(
+                &cli,
+                &effective_settings(&cli, &config, board_profile.as_ref(), Some(dongle)),
+            )
+        };
+        let selected = [serial_a, serial_b];
+        if let Err(e) = compare_dongles(
+            &devices,
+            selected,
+            *pin,
+            *iterations,
+            *json,
+            &board,
+            policy_for,
+        ) {
+            println!("{}", e.to_string().red());
+            std::process::exit(1);
+        }
         return;
-    } else if devices.len() == 1 {
-        match cli.serial {
-            Some(serial) => {
-                if devices[0].1.contains(&serial) {
-                    (devices[0].0, devices[0].1, devices[0].2)
-                } else {
-                    println!(
-                        "Devices found, but serial provided does not match any of them, device serials:"
-                    );
-                    for (_di, serial, _product_string) in devices {
-                        println!("{serial}");
-                    }
-                    return;
-                }
-            }
-            None => (devices[0].0, devices[0].1, devices[0].2)
+    }
+
+    if let Commands::List {
+        with_status, json, ..
+    } = cli.command
+    {
+        let entries = if with_status {
+            list_with_status(&devices, cli.jobs, &board, |dongle| {
+                policy(
+                    &cli,
+                    &effective_settings(&cli, &config, board_profile.as_ref(), Some(dongle)),
+                )
+            })
+        } else {
+            devices.iter().map(ListEntry::new).collect()
+        };
+        if json {
+            println!("{}", serde_json::to_string_pretty(&entries).unwrap());
+        } else if with_status {
+            print_list(&entries);
+        } else {
+            println!("Connected device list:");
+            print_serials(&devices);
         }
+        return;
+    }
+    let selected = if let Some(port) = &cli.port {
+        select_by_location(&devices, port)
+    } else if let Some(index) = cli.index {
+        devices.get(index as usize - 1).ok_or(SelectError::NoMatch)
+    } else if let Some(product) = &cli.hub_product {
+        select_by_hub_product(&devices, product)
     } else {
-        match cli.serial {
-            Some(serial) => match devices.iter().find(|(_, s, _p)| s.contains(&serial)) {
-                Some((di, serial, product_string)) => {
-                    let total_matches = devices
-                        .iter()
-                        .filter_map(|(_, s, _p)| s.contains(serial).then_some(()))
-                        .count();
-                    if total_matches == 1 {
-                        (*di, *serial, *product_string)
-                    } else {
-                        println!("Devices found, but serial provided matches more than one device");
-                        return;
-                    }
-                }
-                None => {
-                    println!(
-                        "Devices found, but serial provided does not match any of them, device serials:"
-                    );
-                    for (_di, serial, _product_string) in devices {
-                        println!("{serial}");
-                    }
-                    return;
-                }
-            },
-            None => {
+        let query = serial
+            .as_deref()
+            .map(|serial| SerialQuery::resolve(&devices, serial));
+        select_dongle(&devices, query.as_ref())
+    };
+    let dongle = match selected {
+        Ok(dongle) => dongle,
+        Err(SelectError::NoMatch) if cli.port.is_some() || cli.index.is_some() => {
+            println!("No device at this location or index, devices:");
+            print_serials(&devices);
+            return;
+        }
+        Err(SelectError::NoDevices) => {
+            println!("No devices found");
+            return;
+        }
+        Err(e @ (SelectError::NoMatch | SelectError::Ambiguous)) if cli.hub_product.is_some() => {
+            if e == SelectError::NoMatch {
+                println!("No device with this hub product string, devices:");
+            } else {
+                println!("Several devices have this hub product string, select one with --port:");
+            }
+            for dongle in &devices {
                 println!(
-                    "Several devices connected, please provide serial to select one of them, serials:"
+                    "{:<20} at {:<12} hub: {}",
+                    dongle.display_serial(),
+                    dongle.bridge.location(),
+                    dongle.hub_product_string()
                 );
-                for (_di, serial, _product_string) in devices {
-                    println!("{serial}");
-                }
-                return;
+            }
+            return;
+        }
+        Err(SelectError::NoMatch) => {
+            println!(
+                "Devices found, but serial provided does not match any of them, device serials:"
+            );
+            print_serials(&devices);
+            return;
+        }
+        Err(SelectError::Ambiguous) => {
+            println!("Devices found, but serial provided matches more than one device");
+            return;
+        }
+        Err(SelectError::DuplicateSerial) => {
+            println!(
+                "{}",
+                "Several devices report the same serial (unprogrammed FTDI chips?)".yellow()
+            );
+            match pick_dongle(&devices) {
+                Some(dongle) => dongle,
+                None => return,
             }
         }
+        Err(SelectError::SerialRequired) if has_duplicate_serials(&devices) => {
+            println!("Several devices connected, some of them report the same serial");
+            match pick_dongle(&devices) {
+                Some(dongle) => dongle,
+                None => return,
+            }
+        }
+        Err(SelectError::SerialRequired) => {
+            println!(
+                "Several devices connected, please provide serial to select one of them, serials:"
+            );
+            print_serials(&devices);
+            return;
+        }
+    };
+    let settings = apply_settings(&mut cli, &config, board_profile.as_ref(), dongle);
+    if let Commands::Profile {
+        action: ProfileAction::Show,
+    } = cli.command
+    {
+        print_profile(config.profile_for(dongle).map(|(key, _)| key), &settings);
+        return;
+    }
+    let Some(di) = dongle.find_device_info().unwrap() else {
+        println!("Device was disconnected");
+        return;
     };
 
     let device = match di.open().wait() {
@@ -201,189 +1173,3220 @@ fn main() {
             #[cfg(target_os = "linux")]
             if e.kind() == nusb::ErrorKind::PermissionDenied || e.os_error() == Some(13) {
                 println!(
-                    "You are probably missing an udev rule, run 'mchp_gpio_ctl --help' to see how to install it"
+                    "You are probably missing an udev rule, run 'mchp_gpio_ctl doctor' to diagnose the problem"
                 );
             }
             return;
         }
     };
-    let interface = device.claim_interface(0).wait().unwrap();
-
-    let is_pwr_on = is_dev_power_on(&interface);
-    let is_pwr_fault = is_dev_pwr_fault(&interface);
-    if is_pwr_fault {
-        println!("{}", "Power FAULT detected, probably short on VBUS?".red());
+    if matches!(cli.command, Commands::Info) {
+        print_info(dongle, &device);
+        return;
     }
-    let pcb_revision = pcb_revision(&interface);
-    // if matches!(pcb_revision, PcbRevision::RevC) {
-    // println!("Detected PCB RevC");
-    // setup_revc(&interface);
-    // }
-    let is_relay_variant = product_string.contains("relay");
+    let interface = match claim_control_interface(&device, board.interface) {
+        Ok(interface) => interface,
+        Err(e) => {
+            println!("{}", e.to_string().red());
+            std::process::exit(1);
+        }
+    };
+    let bridge = BridgeBus::new(interface, board.control_protocol);
 
-    match &cli.command {
-        Commands::On => {
-            if is_pwr_on {
-                println!("Power is already ON");
-            } else {
-                println!("Turning ON...");
-                dev_power_ctl(&interface, true);
-            }
+    #[cfg(unix)]
+    if let Commands::PersistHelper { socket, idle_secs } = &cli.command {
+        let idle = Duration::from_secs(*idle_secs);
+        if let Err(e) = persist::serve(&bridge, dongle, socket, idle) {
+            println!("{}", format!("Persist helper failed: {e}").red());
+            std::process::exit(1);
         }
-        Commands::Off => {
-            if is_pwr_on {
-                println!("Turning OFF...");
-                dev_power_ctl(&interface, false);
-            } else {
-                println!("Power is already OFF");
+        return;
+    }
+    let device_bus = PolicyBus::new(&bridge, policy(&cli, &settings));
+    let read_only = is_read_only(&cli.command);
+    let read_only_bus = ReadOnlyBus::new(&device_bus);
+    let base: &dyn RegisterBus = if read_only {
+        &read_only_bus
+    } else {
+        &device_bus
+    };
+    let _guard = (!cli.no_panic_recovery && !read_only).then(|| PanicGuard::new(&device_bus));
+    let audit = cli.audit.then(|| AuditBus::new(base));
+    let audited: &dyn RegisterBus = match &audit {
+        Some(audit) => audit,
+        None => base,
+    };
+    let recorder = match &cli.record {
+        Some(path) => match RecordingBus::create(path, audited, dongle) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                println!("{}", e.to_string().red());
+                std::process::exit(1);
             }
+        },
+        None => None,
+    };
+    let bus: &dyn RegisterBus = match &recorder {
+        Some(recorder) => recorder,
+        None => audited,
+    };
+    let ctx = Context {
+        dongle,
+        strict: cli.strict_state,
+        claims: ClaimStore::system(),
+        devices: list_usb_devices,
+        dwell: DwellStore::config(),
+    };
+    let result = execute_checked(&cli.command, bus, &ctx);
+    if let Some(recorder) = recorder
+        && let Err(e) = recorder.finish()
+    {
+        println!("{}", e.to_string().red());
+    }
+    if let Some(audit) = audit {
+        print_audit(audit);
+    }
+    record_power_transition(&cli.command, &device_bus, dongle);
+    match result {
+        Ok(outcome) => outcome.exit_on_failure(),
+        Err(e) => {
+            println!("{}", e.to_string().red());
+            std::process::exit(1);
         }
-        Commands::Status => {
-            println!("Dongle serial: {serial}");
-            if is_pwr_on {
-                println!("Power is ON");
-            } else {
-                println!("Power is OFF");
-            }
-            println!("PCB revision: {pcb_revision:?}");
-            if is_relay_variant {
-                println!("SSR (opto-relay) variant");
-            }
-            if matches!(pcb_revision, PcbRevision::RevC) {
-                println!(
-                    "USB switch connected: {}",
-                    usb_switch_is_connected(&interface)
-                );
-                println!(
-                    "Is forcing SDP mode: {:?}",
-                    slg_io_get(&interface, SlgPin::SlgIo0) == PinState::High
-                );
-                println!(
-                    "Is forcing CC lines down: {:?}",
-                    slg_io_get(&interface, SlgPin::SlgIo1) == PinState::Low
-                );
-                if is_relay_variant {
-                    let mode = gpio_header_get_mode(&interface, HeaderPin::P0);
-                    if mode == PinMode::Input {
-                        println!("{}", "Relay pin p0 is configured as Input, relay won't work".yellow());
-                    } else {
-                        let state = gpio_header_get(&interface, HeaderPin::P0);
-                        if state == PinState::High {
-                            println!("Relay state: Short (p0 high)");
-                        } else {
-                            println!("Relay state: Open (p0 low)");
-                        }
-                    }
-                } else {
-                    println!(
-                        "Header pin 0 mode: {:?}, state: {:?}",
-                        gpio_header_get_mode(&interface, HeaderPin::P0),
-                        gpio_header_get(&interface, HeaderPin::P0)
-                    );
+    }
+}
+
+/// Socket key identifying the device selection, so every selection gets its own helper.
+#[cfg(unix)]
+fn persist_key(cli: &Cli) -> String {
+    if let Some(port) = &cli.port {
+        format!("port-{port}")
+    } else if let Some(index) = cli.index {
+        format!("index-{index}")
+    } else if let Some(name) = &cli.name {
+        format!("name-{name}")
+    } else if let Some(product) = &cli.hub_product {
+        format!("hub-{product}")
+    } else if let Some(serial) = &cli.serial {
+        format!("serial-{serial}")
+    } else {
+        "default".into()
+    }
+}
+
+/// Runs the command through the --persist helper, starting it first if it is not running, or stops it.
+#[cfg(unix)]
+fn run_persistent(
+    cli: &mut Cli,
+    config: &Config,
+    board: Option<&BoardProfile>,
+) -> Result<Outcome, Box<dyn std::error::Error>> {
+    use std::os::unix::process::CommandExt;
+
+    let path = persist::socket_path(&persist_key(cli))
+        .ok_or("No config directory for the helper socket")?;
+    if matches!(cli.command, Commands::PersistStop) {
+        return persist::stop(&path)
+            .map(|()| Outcome::Done)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::ResourceBusy => e.to_string().into(),
+                _ => format!("No helper running ({e})").into(),
+            });
+    }
+    if matches!(
+        cli.command,
+        Commands::Info | Commands::PersistHelper { .. } | Commands::Profile { .. }
+    ) {
+        return Err("This command can not be used with --persist".into());
+    }
+    let bus = match PersistentBus::connect(&path) {
+        Ok(bus) => bus,
+        Err(e) if e.kind() == std::io::ErrorKind::ResourceBusy => return Err(e.into()),
+        Err(_) => {
+            let mut helper = std::process::Command::new(std::env::current_exe()?);
+            let selection = [
+                ("--serial", cli.serial.clone()),
+                ("--name", cli.name.clone()),
+                ("--port", cli.port.clone()),
+                ("--index", cli.index.map(|i| i.to_string())),
+                ("--hub-product", cli.hub_product.clone()),
+                ("--profile", cli.profile.clone()),
+            ];
+            for (arg, value) in selection {
+                if let Some(value) = value {
+                    helper.args([arg, &value]);
+                }
+            }
+            helper
+                .args(["persist-helper", "--socket"])
+                .arg(&path)
+                .args(["--idle-secs", &cli.persist_idle_secs.to_string()])
+                .stdin(std::process::Stdio::null())
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                // own process group, so Ctrl-C on the calling command does not stop the helper
+                .process_group(0)
+                .spawn()?;
+            PersistentBus::connect_retry(&path, Duration::from_secs(5)).map_err(|e| {
+                format!("Helper did not start ({e}), run the command without --persist to see why")
+            })?
+        }
+    };
+    let dongle = bus.dongle().clone();
+    let settings = apply_settings(cli, config, board, &dongle);
+    let device_bus = PolicyBus::new(&bus, policy(cli, &settings));
+    let read_only = is_read_only(&cli.command);
+    let read_only_bus = ReadOnlyBus::new(&device_bus);
+    let base: &dyn RegisterBus = if read_only {
+        &read_only_bus
+    } else {
+        &device_bus
+    };
+    let _guard = (!cli.no_panic_recovery && !read_only).then(|| PanicGuard::new(&device_bus));
+    let ctx = Context {
+        dongle: &dongle,
+        strict: cli.strict_state,
+        claims: ClaimStore::system(),
+        devices: list_usb_devices,
+        dwell: DwellStore::config(),
+    };
+    let result = if cli.audit {
+        let audit = AuditBus::new(base);
+        let result = execute_checked(&cli.command, &audit, &ctx);
+        print_audit(audit);
+        result
+    } else {
+        execute_checked(&cli.command, base, &ctx)
+    };
+    record_power_transition(&cli.command, &device_bus, &dongle);
+    Ok(result?)
+}
+
+/// Resolves flag > profile > config > default for `dongle`, without a dongle only the global config applies.
+///
+/// `board` is the board profile selected with `--profile`, if any.
+fn effective_settings(
+    cli: &Cli,
+    config: &Config,
+    board: Option<&BoardProfile>,
+    dongle: Option<&DongleInfo>,
+) -> EffectiveSettings {
+    let (sdp_secs, soft_start_ms, relay_min_dwell_ms) = match &cli.command {
+        Commands::Sdp { secs, .. } => (*secs, None, None),
+        Commands::On { soft_start_ms } => (None, *soft_start_ms, None),
+        Commands::Relay { min_dwell_ms, .. } => (None, None, *min_dwell_ms),
+        _ => (None, None, None),
+    };
+    let flags = SettingFlags {
+        sdp_secs,
+        soft_start_ms,
+        switch_active_high: cli.switch_active_high,
+        power_active_high: cli.power_active_high,
+        enforce_sequencing: cli.enforce_sequencing,
+        relay_min_dwell_ms,
+    };
+    let profile = dongle.and_then(|dongle| config.profile_for(dongle));
+    if let Some((key, _)) = profile {
+        log::debug!("Using profile {key}");
+    }
+    config.effective_settings(profile.map(|(_, p)| p), &flags, board)
+}
+
+/// Resolves the settings of the selected `dongle` (see [effective_settings]) and fills the profile's timings
+/// into the command where no flag was given.
+fn apply_settings(
+    cli: &mut Cli,
+    config: &Config,
+    board: Option<&BoardProfile>,
+    dongle: &DongleInfo,
+) -> EffectiveSettings {
+    let settings = effective_settings(cli, config, board, Some(dongle));
+    match &mut cli.command {
+        Commands::Sdp { secs, .. } => *secs = Some(settings.sdp_secs.value),
+        Commands::On { soft_start_ms } => *soft_start_ms = settings.soft_start_ms.value,
+        Commands::Relay { min_dwell_ms, .. } => {
+            *min_dwell_ms = Some(settings.relay_min_dwell_ms.value)
+        }
+        _ => {}
+    }
+    settings
+}
+
+/// Policy the HAL drives a dongle with `settings` by.
+fn policy(cli: &Cli, settings: &EffectiveSettings) -> Policy {
+    Policy {
+        polarity: Polarity {
+            switch_active_high: settings.switch_active_high.value,
+            power_active_high: settings.power_active_high.value,
+        },
+        power_path: cli.power_via,
+        enforce_sequencing: settings.enforce_sequencing.value,
+    }
+}
+
+fn print_profile(key: Option<&str>, settings: &EffectiveSettings) {
+    match key {
+        Some(key) => println!("Profile: {key}"),
+        None => println!("No profile for this device, using global settings"),
+    }
+    let soft_start = match settings.soft_start_ms.value {
+        Some(ms) => ms.to_string(),
+        None => "off".into(),
+    };
+    let rows = [
+        (
+            "sdp_secs",
+            settings.sdp_secs.value.to_string(),
+            settings.sdp_secs.source,
+        ),
+        ("soft_start_ms", soft_start, settings.soft_start_ms.source),
+        (
+            "switch_active_high",
+            settings.switch_active_high.value.to_string(),
+            settings.switch_active_high.source,
+        ),
+        (
+            "power_active_high",
+            settings.power_active_high.value.to_string(),
+            settings.power_active_high.source,
+        ),
+        (
+            "enforce_sequencing",
+            settings.enforce_sequencing.value.to_string(),
+            settings.enforce_sequencing.source,
+        ),
+        (
+            "relay_min_dwell_ms",
+            settings.relay_min_dwell_ms.value.to_string(),
+            settings.relay_min_dwell_ms.source,
+        ),
+    ];
+    for (name, value, from) in rows {
+        println!("{name:<20} {value:<6} {}", format!("({from})").dimmed());
+    }
+}
+
+fn print_features(info: &BuildInfo) {
+    if info.features.is_empty() {
+        println!("Cargo features: none (no optional features defined)");
+    } else {
+        println!("Cargo features: {}", info.features.join(", "));
+    }
+    for capability in &info.capabilities {
+        let mark = if capability.enabled {
+            "yes".green()
+        } else {
+            "no".red()
+        };
+        println!("{:<8} {mark:<3} {}", capability.name, capability.note);
+    }
+}
+
+/// Prints the --audit report to stderr, keeping stdout for the command's own output.
+fn print_audit(audit: AuditBus) {
+    match audit.finish() {
+        Ok(report) => eprintln!("{}", serde_json::to_string_pretty(&report).unwrap()),
+        Err(e) => eprintln!(
+            "{}",
+            format!("Failed to read back audited registers: {e}").red()
+        ),
+    }
+}
+
+/// Re-runs the command against the reads recorded in a trace file and verifies its writes.
+fn replay(
+    cli: &mut Cli,
+    path: &std::path::Path,
+    board: Option<&BoardProfile>,
+) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let trace = Trace::load(path)?;
+    let config = Config::load()?;
+    let settings = apply_settings(cli, &config, board, &trace.dongle);
+    let bus = trace.replay_bus();
+    let ctx = Context {
+        dongle: &trace.dongle,
+        strict: cli.strict_state,
+        claims: ClaimStore::system(),
+        devices: list_usb_devices,
+        dwell: DwellStore::config(),
+    };
+    let outcome = execute_checked(
+        &cli.command,
+        &PolicyBus::new(&bus, policy(cli, &settings)),
+        &ctx,
+    )?;
+    let writes = bus.writes();
+    trace.verify_writes(&writes)?;
+    println!(
+        "{}",
+        format!("Replay matches the trace ({} writes)", writes.len()).green()
+    );
+    Ok(outcome)
+}
+
+/// Runs the command given by `args` against an [ExplainBus], printing its control transfers.
+fn explain(
+    args: &[String],
+    revision: PcbRevision,
+    json: bool,
+    board: Option<&BoardProfile>,
+) -> Result<Outcome, Box<dyn std::error::Error>> {
+    let mut cli = Cli::try_parse_from(
+        std::iter::once("mchp_gpio_ctl").chain(args.iter().map(String::as_str)),
+    )
+    .unwrap_or_else(|e| e.exit());
+    if matches!(cli.command, Commands::Explain { .. })
+        || cli.all
+        || cli.persist
+        || cli.replay.is_some()
+    {
+        return Err(
+            "explain runs a single device command, without --all, --persist or --replay".into(),
+        );
+    }
+    if let Some(effect) = host_side_effect(&cli.command) {
+        return Err(format!("explain only simulates the dongle, this command {effect}").into());
+    }
+    let config = Config::load()?;
+    let dongle = DongleInfo {
+        bridge: UsbDevice::default(),
+        ftdi: None,
+        hub: None,
+    };
+    let settings = apply_settings(&mut cli, &config, board, &dongle);
+    // no switching history is kept for the simulated relays
+    if let Commands::Relay { min_dwell_ms, .. } = &mut cli.command {
+        *min_dwell_ms = Some(0);
+    }
+    let protocol = board.map_or(config.control_protocol, |board| board.control_protocol);
+    let bus = ExplainBus::new(revision, protocol, move |t| {
+        if !json {
+            println!("{}", format_transfer(t).dimmed());
+        }
+    });
+    // claims of the simulated dongle only live as long as the command, no device is ever behind it
+    let claims_dir =
+        std::env::temp_dir().join(format!("mchp_gpio_ctl_explain_{}", std::process::id()));
+    let result = {
+        let _redirect = json.then(StdoutToStderr::new);
+        let ctx = Context {
+            dongle: &dongle,
+            strict: cli.strict_state,
+            claims: ClaimStore::new(&claims_dir),
+            devices: || Ok(Vec::new()),
+            dwell: DwellStore::config(),
+        };
+        execute_checked(
+            &cli.command,
+            &PolicyBus::new(&bus, policy(&cli, &settings)),
+            &ctx,
+        )
+    };
+    let _ = std::fs::remove_dir_all(claims_dir);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&bus.into_transfers())?);
+    }
+    Ok(result?)
+}
+
+/// What `cmd` does on the host besides accessing the dongle registers, for commands `explain` cannot simulate.
+fn host_side_effect(cmd: &Commands) -> Option<&'static str> {
+    match cmd {
+        Commands::Lockout {
+            action: LockoutAction::On | LockoutAction::Off,
+        } => Some("writes the lockout file"),
+        Commands::Detach { run: Some(_), .. } => Some("runs a command on the host"),
+        Commands::HubInfo { .. } | Commands::PortDiag { .. } => {
+            Some("reads the USB devices of the host")
+        }
+        _ => None,
+    }
+}
+
+fn format_transfer(t: &ExplainedTransfer) -> String {
+    let transfer = &t.transfer;
+    let data = match t.returns {
+        Some(value) => format!("<- 0x{value:02X}"),
+        None => format!(
+            "-> {}",
+            transfer
+                .data
+                .iter()
+                .map(|b| format!("0x{b:02X}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    };
+    format!(
+        "bmRequestType=0x{:02X} bRequest=0x{:02X} wValue=0x{:04X} wIndex=0x{:04X} wLength={} {data} {}",
+        transfer.request_type,
+        transfer.request,
+        transfer.value,
+        transfer.index,
+        transfer.length,
+        t.register.unwrap_or(""),
+    )
+}
+
+/// Powers off every dongle, up to `jobs` in parallel, continuing past failures, returns false if any failed.
+///
+/// With `emergency` no checks are done (see [emergency_power_off]), otherwise this is the same as `off`.
+/// Every dongle is driven with its own settings from `policy_for`, as when selected alone.
+fn power_off_all(
+    devices: &[DongleInfo],
+    jobs: usize,
+    emergency: bool,
+    board: &BoardProfile,
+    policy_for: impl Fn(&DongleInfo) -> Policy + Sync,
+) -> bool {
+    let results = parallel_map(devices, jobs, |dongle| {
+        let bridge = dongle
+            .open_bus(board)
+            .map_err(|e| e.to_string())?
+            .ok_or("disconnected")?;
+        let bus = PolicyBus::new(&bridge, policy_for(dongle));
+        let result = if emergency {
+            emergency_power_off(&bus)
+        } else {
+            dev_power_ctl(&bus, false)
+        };
+        result.map_err(|e| e.to_string())
+    });
+    let results = results
+        .into_iter()
+        .map(|r| r.unwrap_or_else(|| Err("panicked".to_string())));
+    let mut failed = 0;
+    for (dongle, result) in devices.iter().zip(results) {
+        match result {
+            Ok(()) => {
+                println!("{:<20} {}", dongle.display_serial(), "OFF".green());
+                if let Err(e) = uptime::record(dongle, false) {
+                    log::warn!("Failed to record power transition: {e}");
                 }
+            }
+            Err(e) => {
+                failed += 1;
                 println!(
-                    "Header pin 1 mode: {:?}, state: {:?}",
-                    gpio_header_get_mode(&interface, HeaderPin::P1),
-                    gpio_header_get(&interface, HeaderPin::P1)
+                    "{:<20} {}",
+                    dongle.display_serial(),
+                    format!("FAILED: {e}").red()
                 );
             }
         }
-        Commands::List => {}
+    }
+    println!(
+        "{} of {} dongles powered off",
+        devices.len() - failed,
+        devices.len()
+    );
+    failed == 0
+}
 
-        #[cfg(target_os = "linux")]
-        Commands::Udev => {}
+/// Reads the PCB revision of every dongle and compares it with `expect`, returns true if all match.
+fn check_revision_all(
+    devices: &[DongleInfo],
+    jobs: usize,
+    expect: PcbRevision,
+    board: &BoardProfile,
+) -> bool {
+    if devices.is_empty() {
+        println!("{}", "No devices found".red());
+        return false;
+    }
+    let results = parallel_map(devices, jobs, |dongle| {
+        let bridge = dongle
+            .open_bus(board)
+            .map_err(|e| e.to_string())?
+            .ok_or("disconnected")?;
+        pcb_revision(&bridge).map_err(|e| e.to_string())
+    });
+    let results = results
+        .into_iter()
+        .map(|r| r.unwrap_or_else(|| Err("panicked".to_string())));
+    let mut matching = 0;
+    for (dongle, result) in devices.iter().zip(results) {
+        let line = match result {
+            Ok(revision) if revision == expect => {
+                matching += 1;
+                format!("{revision}").green()
+            }
+            Ok(revision) => format!("{revision}, expected {expect}").red(),
+            Err(e) => format!("FAILED: {e}").red(),
+        };
+        println!("{:<20} {line}", dongle.display_serial());
+    }
+    println!("{matching} of {} dongles are {expect}", devices.len());
+    matching == devices.len()
+}
 
-        Commands::ForceSdp | Commands::ReleaseSdp | Commands::Sdp => {
-            if matches!(pcb_revision, PcbRevision::RevAorB) {
-                println!("{}", "ForceSDP is not supported on PCB RevA or B".red());
-                return;
+/// Updates the uptime record after commands that may have switched device power.
+fn record_power_transition(cmd: &Commands, bus: &dyn RegisterBus, dongle: &DongleInfo) {
+    let switches_power = matches!(
+        cmd,
+        Commands::On { .. }
+            | Commands::Off
+            | Commands::Attach { .. }
+            | Commands::EmergencyOff
+            | Commands::PowerCycle { .. }
+            | Commands::FullAttach { .. }
+            | Commands::FullDetach { .. }
+            | Commands::TraceCycle { .. }
+            | Commands::FaultTest { .. }
+            | Commands::RegReset { .. }
+            | Commands::Lockout {
+                action: LockoutAction::On
             }
-            slg_io_set_mode(&interface, SlgPin::SlgIo0, PinMode::Output);
-            match &cli.command {
-                Commands::ForceSdp => {
-                    slg_io_set(&interface, SlgPin::SlgIo0, PinState::High);
-                }
-                Commands::ReleaseSdp => {
-                    slg_io_set(&interface, SlgPin::SlgIo0, PinState::Low);
-                }
-                Commands::Sdp => {
-                    slg_io_set(&interface, SlgPin::SlgIo0, PinState::High);
-                    for i in (1..=10).rev() {
-                        println!("{i}");
-                        sleep(Duration::from_secs(1));
-                    }
-                    slg_io_set(&interface, SlgPin::SlgIo0, PinState::Low);
+            | Commands::Pin { .. }
+            | Commands::Apply { .. }
+    );
+    if !switches_power {
+        return;
+    }
+    let Ok(is_on) = is_dev_power_on(bus) else {
+        return;
+    };
+    let result = if matches!(cmd, Commands::PowerCycle { .. }) {
+        uptime::record(dongle, false).and_then(|_| uptime::record(dongle, is_on))
+    } else {
+        uptime::record(dongle, is_on)
+    };
+    if let Err(e) = result {
+        log::warn!("Failed to record power transition: {e}");
+    }
+}
+
+/// What a device command runs against besides the register bus.
+struct Context<'a> {
+    dongle: &'a DongleInfo,
+    /// `--strict-state`: refuse invalid combinations of states, see [mchp_gpio_ctl::consistency]
+    strict: bool,
+    claims: ClaimStore,
+    /// USB devices checked for a device behind the dongle
+    devices: DeviceList,
+    /// Last relay switchings, for `relay --min-dwell-ms`
+    dwell: DwellStore,
+}
+
+/// How a device command ended that did not fail with a [DongleError].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Outcome {
+    Done,
+    /// A check found a mismatch or a `--run` command failed: exit with this code once everything around the
+    /// command (trace, audit, uptime, explain output) is finished
+    Exit(i32),
+}
+
+impl Outcome {
+    /// Exits the process with the code of [Outcome::Exit], returns for [Outcome::Done].
+    fn exit_on_failure(self) {
+        if let Outcome::Exit(code) = self {
+            std::process::exit(code);
+        }
+    }
+}
+
+/// Runs the command with [execute], then with `--strict-state` checks the resulting state for invalid
+/// combinations.
+fn execute_checked(
+    cmd: &Commands,
+    bus: &dyn RegisterBus,
+    ctx: &Context,
+) -> Result<Outcome, DongleError> {
+    let outcome = execute(cmd, bus, ctx)?;
+    if !ctx.strict {
+        return Ok(outcome);
+    }
+    let violations = violations(&read_only_status_report(bus, ctx.dongle)?);
+    if violations.is_empty() {
+        Ok(outcome)
+    } else {
+        Err(DongleError::InconsistentState { violations })
+    }
+}
+
+/// Observer commands, run on a [ReadOnlyBus] that refuses all register writes.
+fn is_read_only(cmd: &Commands) -> bool {
+    matches!(
+        cmd,
+        Commands::Status {
+            read_only: true,
+            ..
+        } | Commands::Monitor {
+            read_only: true,
+            ..
+        } | Commands::Verify { .. }
+    )
+}
+
+/// Runs a device command against `bus`, everything that needs the device is dispatched from here.
+fn execute(cmd: &Commands, bus: &dyn RegisterBus, ctx: &Context) -> Result<Outcome, DongleError> {
+    let dongle = ctx.dongle;
+    let claims = &ctx.claims;
+    if matches!(cmd, Commands::EmergencyOff) {
+        // No status reads first, every transfer adds latency
+        emergency_power_off(bus)?;
+        println!("Power is OFF");
+        return Ok(Outcome::Done);
+    }
+    let is_pwr_on = is_dev_power_on(bus)?;
+    let is_pwr_fault = if is_read_only(cmd) {
+        read_dev_pwr_fault(bus)?.unwrap_or(false)
+    } else {
+        is_dev_pwr_fault(bus)?
+    };
+    let is_machine_output = matches!(
+        cmd,
+        Commands::Status { format, .. } if *format != StatusFormat::Text
+    ) || matches!(
+        cmd,
+        Commands::DebugBundle
+            | Commands::Dirmap { json: true }
+            | Commands::SlgStatus { json: true }
+            | Commands::PortDiag { json: true }
+            | Commands::HubInfo { json: true }
+            | Commands::SwitchDiag { json: true }
+            | Commands::TraceCycle { json: true, .. }
+            | Commands::Verify { json: true, .. }
+            | Commands::Serve { .. }
+            | Commands::RegDump {
+                format: RegDumpFormat::Json | RegDumpFormat::Mchp
+            }
+    );
+    if is_pwr_fault && !is_machine_output {
+        println!("{}", "Power FAULT detected, probably short on VBUS?".red());
+    }
+    let powers_on = matches!(
+        cmd,
+        Commands::On { .. }
+            | Commands::FullAttach { .. }
+            | Commands::TraceCycle { .. }
+            | Commands::PowerCycle { .. }
+            | Commands::VerifyPower
+            | Commands::FaultTest { .. }
+            | Commands::RegReset { expert: true }
+            | Commands::HubPortPower {
+                state: Some(OnOff::On),
+                ..
+            }
+            | Commands::Pin {
+                name: PinName::PwrEn,
+                action: None,
+                ..
+            }
+    ) || (matches!(cmd, Commands::Attach { .. })
+        && bus.policy().enforce_sequencing);
+    if powers_on && let Some(holder) = lockout::holder(dongle) {
+        return Err(DongleError::LockedOut { holder });
+    }
+    let pcb_revision = pcb_revision(bus)?;
+    // if matches!(pcb_revision, PcbRevision::RevC) {
+    // println!("Detected PCB RevC");
+    // setup_revc(&bus);
+    // }
+    let relay_count = detect_relay_variant(bus, dongle)?;
+
+    match cmd {
+        Commands::On { soft_start_ms } => {
+            if is_pwr_on {
+                println!("Power is already ON");
+            } else if let Some(ramp_ms) = soft_start_ms {
+                if bus.policy().power_path == PowerPath::Hub {
+                    return Err(DongleError::Unsupported(
+                        "--soft-start-ms PWMs the board switch, it can not be used with --power-via hub"
+                            .into(),
+                    ));
                 }
-                _ => {}
+                println!("Turning ON with {ramp_ms}ms soft-start...");
+                soft_power_on(bus, Duration::from_millis(*ramp_ms))?;
+            } else {
+                println!("Turning ON...");
+                dev_power_ctl(bus, true)?;
             }
         }
-
-        Commands::Attach | Commands::Detach => {
-            if matches!(pcb_revision, PcbRevision::RevAorB) {
-                println!(
+        Commands::EmergencyOff => {}
+        Commands::FaultTest {
+            i_understand_the_risk,
+            timeout_ms,
+        } => {
+            println!(
+                "{}",
+                "WARNING: this test powers the device output into a short circuit. Only run it on a dongle set \
+                 aside for validation, with a short that can carry the current limit, never with a device \
+                 connected. A protection that does not trip can damage the dongle, the short or the host port."
+                    .red()
+                    .bold()
+            );
+            if !i_understand_the_risk {
+                return Err(DongleError::Unsupported(
+                    "fault-test needs --i-understand-the-risk".into(),
+                ));
+            }
+            if bus.policy().power_path == PowerPath::Hub {
+                return Err(DongleError::Unsupported(
+                    "fault-test measures the board switch, only it reports faults, it can not be used with \
+                     --power-via hub"
+                        .into(),
+                ));
+            }
+            println!("Disconnect any device and apply the short to the device power output");
+            if std::io::stdin().is_terminal()
+                && ask_yes_no("Is the short applied and power allowed to be turned on?")
+                    != Some(true)
+            {
+                println!("Aborted, power was not turned on");
+                return Ok(Outcome::Done);
+            }
+            let trip = measure_fault_trip(bus, Duration::from_millis(*timeout_ms))?;
+            match trip.trip_time {
+                Some(trip_time) => println!(
                     "{}",
-                    "Attach / Detach is not supported on PCB RevA or B".red()
+                    format!(
+                        "Fault reported {:.3}ms after power on (resolution {:.3}ms, {} reads), power is OFF",
+                        trip_time.as_secs_f64() * 1000.0,
+                        trip.poll_interval.as_secs_f64() * 1000.0,
+                        trip.polls
+                    )
+                    .green()
+                ),
+                None => println!(
+                    "{}",
+                    format!(
+                        "No fault reported within {timeout_ms}ms ({} reads), power is OFF. Check the short \
+                         and the protection circuit",
+                        trip.polls
+                    )
+                    .red()
+                ),
+            }
+            if trip.trip_time.is_none() {
+                return Ok(Outcome::Exit(1));
+            }
+        }
+        Commands::VerifyPower => {
+            if !std::io::stdin().is_terminal() {
+                return Err(DongleError::Unsupported(
+                    "verify-power asks questions, run it in a terminal".into(),
+                ));
+            }
+            let configured = if Signal::PowerOn.is_active_low(bus.policy().polarity) {
+                "active low"
+            } else {
+                "active high"
+            };
+            println!(
+                "Power enable is treated as {configured}, watch the device power LED or measure its supply rail"
+            );
+            let (prior_mode, prior_level) = pwr_en_raw_get(bus)?;
+            let mut observed = [false; 2];
+            for (on, observed) in [false, true].into_iter().zip(&mut observed) {
+                dev_power_ctl(bus, on)?;
+                sleep(Duration::from_millis(500));
+                let question = format!(
+                    "Power was just turned {}, is the device powered now?",
+                    if on { "ON" } else { "OFF" }
                 );
-                return;
+                match ask_yes_no(&question) {
+                    Some(answer) => *observed = answer,
+                    None => {
+                        pwr_en_raw_set(bus, Some(prior_mode), Some(prior_level))?;
+                        println!("No answer, power state restored");
+                        return Ok(Outcome::Done);
+                    }
+                }
             }
-            usb_switch_configure(&interface);
-            match &cli.command {
-                Commands::Attach => {
-                    usb_switch_set(&interface, true);
+            pwr_en_raw_set(bus, Some(prior_mode), Some(prior_level))?;
+            match PolarityCheck::from_observations(observed[0], observed[1]) {
+                PolarityCheck::Matches => {
+                    println!("{}", format!("Power enable is {configured} as configured").green())
                 }
-                Commands::Detach => {
-                    usb_switch_set(&interface, false);
+                PolarityCheck::Inverted => {
+                    let fix = if Signal::PowerOn.is_active_low(bus.policy().polarity) {
+                        "use --power-active-high or set `power_active_high = true` in the config file"
+                    } else {
+                        "drop --power-active-high and `power_active_high` from the config file"
+                    };
+                    println!(
+                        "{}",
+                        format!("Power enable is inverted compared to the configuration, {fix}").red()
+                    );
                 }
-                _ => {}
+                PolarityCheck::NoChange { always_active } => println!(
+                    "{}",
+                    format!(
+                        "Device stayed {} either way, PIO0 does not seem to control the power switch, check the wiring",
+                        if always_active { "powered" } else { "unpowered" }
+                    )
+                    .red()
+                ),
             }
         }
-
-        Commands::FullAttach | Commands::FullDetach => {
-            if matches!(pcb_revision, PcbRevision::RevAorB) {
+        Commands::Off => {
+            if is_pwr_on {
+                println!("Turning OFF...");
+                dev_power_ctl(bus, false)?;
+            } else {
+                println!("Power is already OFF");
+            }
+        }
+        Commands::PowerCycle {
+            off_ms,
+            wait_stable_ms,
+            timeout_ms,
+        } => {
+            println!("Turning OFF...");
+            dev_power_ctl(bus, false)?;
+            sleep(Duration::from_millis(*off_ms));
+            println!("Turning ON...");
+            dev_power_ctl(bus, true)?;
+            if let Some(stable_ms) = wait_stable_ms {
+                let timeout = Duration::from_millis(*timeout_ms);
+                if !wait_fault_free(bus, Duration::from_millis(*stable_ms), timeout)? {
+                    return Err(DongleError::PowerUnstable { timeout });
+                }
+                println!("Power is stable");
+            }
+        }
+        Commands::Lockout { action } => match action {
+            LockoutAction::On => {
+                dev_power_ctl(bus, false)?;
+                if let Err(e) = lockout::engage(dongle) {
+                    println!(
+                        "{}",
+                        format!("Power is OFF, but failed to create lockout file: {e}").red()
+                    );
+                    return Ok(Outcome::Exit(1));
+                }
+                println!("Power is OFF and locked out");
+            }
+            LockoutAction::Off => match lockout::release(dongle) {
+                Ok(true) => println!("Lockout released"),
+                Ok(false) => println!("Power was not locked out"),
+                Err(e) => {
+                    println!("{}", format!("Failed to remove lockout file: {e}").red());
+                    return Ok(Outcome::Exit(1));
+                }
+            },
+            LockoutAction::Status => match lockout::holder(dongle) {
+                Some(holder) => println!("Power is locked out by {holder}"),
+                None => println!("Power is not locked out"),
+            },
+        },
+        Commands::RegReset { expert } => {
+            if !expert {
+                return Err(DongleError::ExpertRequired {
+                    consequence: "This drops the power enable configuration: PWR_EN_N is released to its board \
+                                  default, which can cut or apply device power, and the USB switch, SDP and CC \
+                                  lines are released too",
+                });
+            }
+            reset_gpio_registers(bus)?;
+            println!(
+                "GPIO direction and output registers reset, power is {}",
+                if is_dev_power_on(bus)? { "ON" } else { "OFF" }
+            );
+        }
+        Commands::Pin {
+            name: PinName::PwrEn,
+            action: None,
+            dir,
+            level,
+            expert,
+        } => {
+            if !expert {
                 println!(
                     "{}",
-                    "Full Attach / Detach is not supported on PCB RevA or B".red()
+                    "This bypasses the power switch inversion and safety logic, rerun with --expert if you are sure"
+                        .red()
                 );
-                return;
+                return Ok(Outcome::Done);
+            }
+            println!(
+                "{}",
+                "Warning: raw pin access, levels are electrical (pwr-en high = power OFF)".yellow()
+            );
+            pwr_en_raw_set(bus, *dir, *level)?;
+            let (mode, level) = pwr_en_raw_get(bus)?;
+            println!("pwr-en (PIO0): {mode:?}, {level:?}");
+        }
+        Commands::Pin {
+            name,
+            action,
+            dir,
+            level,
+            expert,
+        } => {
+            if *name != PinName::PwrEn && (dir.is_some() || level.is_some() || *expert) {
+                return Err(DongleError::Unsupported(
+                    "--dir, --level and --expert are only for pwr-en, use set or config".into(),
+                ));
+            }
+            if name.info().revc_only && matches!(pcb_revision, PcbRevision::RevAorB) {
+                return Err(DongleError::Unsupported(
+                    "Header and SLG pins are not present on PCB RevA or B".into(),
+                ));
             }
-            usb_switch_configure(&interface);
-            slg_io_set_mode(&interface, SlgPin::SlgIo1, PinMode::Output);
-            match &cli.command {
-                Commands::FullAttach => {
-                    dev_power_ctl(&interface, true);
-                    usb_switch_set(&interface, true);
-                    slg_io_set(&interface, SlgPin::SlgIo1, PinState::High);
+            if let PinAccess::Slg(slg_pin) = name.access() {
+                match action {
+                    Some(PinAction::Set { .. })
+                    | Some(PinAction::Config {
+                        mode: PinMode::Output,
+                    }) => claims.claim(dongle, slg_pin, SlgPurpose::Gpio)?,
+                    Some(PinAction::Config {
+                        mode: PinMode::Input,
+                    }) => {
+                        claims.check(dongle, slg_pin, SlgPurpose::Gpio)?;
+                        claims.release(dongle, slg_pin, SlgPurpose::Gpio);
+                    }
+                    None | Some(PinAction::Get) => {}
                 }
-                Commands::FullDetach => {
-                    dev_power_ctl(&interface, false);
-                    usb_switch_set(&interface, false);
-                    slg_io_set(&interface, SlgPin::SlgIo1, PinState::Low);
+            }
+            match action {
+                None | Some(PinAction::Get) => {
+                    let (mode, state) = pin_get(bus, *name)?;
+                    println!(
+                        "{} (PIO{}): {mode:?}, {state:?}",
+                        name.info().name,
+                        name.info().pio
+                    );
+                }
+                Some(PinAction::Set { state }) => {
+                    if let PinAccess::Header(pin) = name.access() {
+                        refuse_relay_pin(pin, relay_count, "pin set")?;
+                    }
+                    pin_set(bus, *name, *state)?
+                }
+                Some(PinAction::Config { mode }) => {
+                    if let Some(warning) = pin_config(bus, *name, *mode)? {
+                        eprintln!("{}", warning.yellow());
+                    }
                 }
-                _ => {}
             }
         }
-
-        Commands::GpioConfig { .. } | Commands::GpioSet { .. } | Commands::GpioGet { .. } => {
+        Commands::SlgGet { pin, raw_input } => {
             if matches!(pcb_revision, PcbRevision::RevAorB) {
-                println!("{}", "GPIO is not supported on PCB RevA or B".red());
-                return;
+                return Err(DongleError::Unsupported(
+                    "SLG IOs are not present on PCB RevA or B".into(),
+                ));
             }
-            match &cli.command {
-                Commands::GpioConfig { pin, mode } => {
-                    if is_relay_variant && *pin == HeaderPin::P0 && *mode == PinMode::Input {
-                        println!("{}", "Configuring relay control pin as input, relay won't work".yellow());
+            let info = PinName::from(*pin).info();
+            let mode = slg_io_get_mode(bus, *pin)?;
+            if !raw_input {
+                let state = slg_io_get(bus, *pin)?;
+                println!("{} (PIO{}): {mode:?}, {state:?}", info.name, info.pio);
+                return Ok(Outcome::Done);
+            }
+            let pad = slg_io_get_input(bus, *pin)?;
+            println!("{} (PIO{}): {mode:?}, pad {pad:?}", info.name, info.pio);
+            if mode == PinMode::Output {
+                let driven = slg_io_get(bus, *pin)?;
+                if driven != pad {
+                    println!(
+                        "{}",
+                        format!(
+                            "Driven {driven:?} but the pad reads {pad:?}, overridden externally"
+                        )
+                        .red()
+                    );
+                }
+            }
+        }
+        Commands::Status {
+            format,
+            read_only,
+            baseline,
+            ignore,
+        } => {
+            let report = if *read_only {
+                read_only_status_report(bus, dongle)?
+            } else {
+                status_report(bus, dongle)?
+            };
+            if let Some(path) = baseline {
+                let baseline = std::fs::read_to_string(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|s| {
+                        serde_json::from_str::<StatusReport>(&s).map_err(|e| e.to_string())
+                    });
+                let baseline = match baseline {
+                    Ok(baseline) => baseline,
+                    Err(e) => {
+                        println!(
+                            "{}",
+                            format!("Failed to read baseline {}: {e}", path.display()).red()
+                        );
+                        return Ok(Outcome::Exit(2));
                     }
-                    gpio_header_set_mode(&interface, *pin, *mode);
+                };
+                let diff = report.diff(&baseline, ignore);
+                if diff.is_empty() {
+                    println!("{}", "Status matches the baseline".green());
+                    return Ok(Outcome::Done);
+                }
+                for d in &diff {
+                    println!(
+                        "{}: {} -> {}",
+                        d.field,
+                        d.baseline.as_deref().unwrap_or("(none)"),
+                        d.current.as_deref().unwrap_or("(none)")
+                    );
                 }
-                Commands::GpioSet { pin, state } => {
-                    gpio_header_set(&interface, *pin, *state);
+                return Ok(Outcome::Exit(1));
+            }
+            match format {
+                StatusFormat::Text => print_status(&report),
+                StatusFormat::Prometheus => print!("{}", report.to_prometheus()),
+                StatusFormat::Env => print!("{}", report.to_env()),
+                StatusFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&report).unwrap())
                 }
-                Commands::GpioGet { pin } => {
-                    let state = gpio_header_get(&interface, *pin);
-                    println!("{pin:?} = {state:?}");
+            }
+        }
+        Commands::RegDump { format } => {
+            let values = dump_registers(bus)?;
+            match format {
+                RegDumpFormat::Text => {
+                    for v in &values {
+                        println!(
+                            "{:<20} 0x{:04X} = 0x{:02X} ({:08b})",
+                            v.name, v.addr, v.value, v.value
+                        );
+                    }
                 }
-                _ => {}
+                RegDumpFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&values).unwrap());
+                }
+                RegDumpFormat::Mchp => print!("{}", format_mchp(&values)),
+            }
+        }
+        Commands::Dirmap { json } => {
+            let map = direction_map(bus)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&map).unwrap());
+            } else {
+                print_dirmap(&map);
+            }
+        }
+        Commands::SlgStatus { json } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                return Err(DongleError::Unsupported(
+                    "SLG is not present on PCB RevA or B".into(),
+                ));
+            }
+            let config = slg_config(bus)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&config).unwrap());
+            } else {
+                for io in [config.io0, config.io1] {
+                    println!(
+                        "{} (PIO{}): {:?}, {:?} - {}; pull-{} inside SLG{}",
+                        io.name,
+                        io.pio,
+                        io.mode,
+                        io.state,
+                        io.meaning,
+                        format!("{:?}", io.pull).to_lowercase(),
+                        if io.mode == PinMode::Input {
+                            ", released"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+            }
+        }
+        Commands::DebugBundle => {
+            let bundle = debug_bundle(bus, dongle)?;
+            println!("{}", serde_json::to_string_pretty(&bundle).unwrap());
+        }
+        Commands::Serve { .. } => {
+            if let Err(e) = serve(
+                bus,
+                dongle,
+                claims,
+                std::io::stdin().lock(),
+                std::io::stdout().lock(),
+            ) {
+                eprintln!("{}", format!("Server IO error: {e}").red());
+            }
+        }
+        Commands::Uptime => match uptime::last_transition(dongle) {
+            Ok(Some(transition)) => {
+                let state = if transition.on { "ON" } else { "OFF" };
+                println!(
+                    "Power is {state} for {} (since {})",
+                    format_duration(transition.elapsed()),
+                    iso8601_utc(transition.time())
+                );
+                if transition.on != is_pwr_on {
+                    println!(
+                        "{}",
+                        "Power state changed outside of this tool, uptime is not accurate".yellow()
+                    );
+                }
+            }
+            Ok(None) => println!("No power transitions recorded for this dongle yet"),
+            Err(e) => println!("{}", e.to_string().red()),
+        },
+        Commands::HubPortPower { port, state } => {
+            if let Some(state) = state {
+                hub_port_power(bus, *port, *state == OnOff::On)?;
+            }
+            println!(
+                "Hub port {port} power: {:?}",
+                hub_port_power_get(bus, *port)?
+            );
+        }
+        Commands::PortDiag { json } => {
+            let diag = port_diagnostics(bus, dongle)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&diag).unwrap());
+            } else {
+                print_port_diag(&diag);
+            }
+        }
+        Commands::SwitchDiag { json } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                return Err(DongleError::Unsupported(
+                    "USB switch is not supported on PCB RevA or B".into(),
+                ));
+            }
+            let diag = usb_switch_diag(bus)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&diag).unwrap());
+            } else {
+                print_switch_diag(&diag);
+            }
+            if diag.mismatch {
+                return Ok(Outcome::Exit(1));
+            }
+        }
+        Commands::HubInfo { json } => {
+            let info = hub_info(dongle)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&info).unwrap());
+            } else {
+                print_hub_info(&info);
             }
         }
+        Commands::List { .. } | Commands::Info => {}
+
+        #[cfg(target_os = "linux")]
+        Commands::Udev | Commands::Doctor => {}
+        Commands::Subcommands { json, .. } => {
+            let mut commands = describe_commands(&Cli::command(), COMMAND_REQUIREMENTS);
+            let is_revc = matches!(pcb_revision, PcbRevision::RevC);
+            mark_available(&mut commands, is_revc, relay_count > 0);
+            print_commands(&commands, *json);
+        }
+        Commands::SetupHelp
+        | Commands::Name { .. }
+        | Commands::Version { .. }
+        | Commands::Features { .. }
+        | Commands::Explain { .. }
+        | Commands::WatchDevices { .. }
+        | Commands::Pinmap { .. }
+        | Commands::Compare { .. }
+        | Commands::Profile { .. }
+        | Commands::PersistStop
+        | Commands::PersistHelper { .. } => {}
+
+        Commands::ForceSdp { .. } | Commands::ReleaseSdp | Commands::Sdp { .. } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                println!("{}", "ForceSDP is not supported on PCB RevA or B".red());
+                return Ok(Outcome::Done);
+            }
+            claims.check(dongle, SlgPin::SlgIo0, SlgPurpose::Feature)?;
+            slg_io_set_mode(bus, SlgPin::SlgIo0, PinMode::Output)?;
+            match cmd {
+                Commands::ForceSdp { auto_release_after } => {
+                    slg_io_set(bus, SlgPin::SlgIo0, PinState::High)?;
+                    let Some(secs) = auto_release_after else {
+                        claims.claim(dongle, SlgPin::SlgIo0, SlgPurpose::Feature)?;
+                        println!(
+                            "{}",
+                            "SDP is forced and stays forced until 'release-sdp' is run, the device will not boot normally"
+                                .yellow()
+                        );
+                        return Ok(Outcome::Done);
+                    };
+                    println!(
+                        "SDP forced, releasing in {secs}s; keep this running, Ctrl-C releases immediately"
+                    );
+                    drive_timed(
+                        |state| slg_io_set(bus, SlgPin::SlgIo0, state),
+                        PinState::High,
+                        Duration::from_secs(*secs),
+                        PinState::Low,
+                    )?;
+                    println!("SDP released, back to USART mode");
+                }
+                Commands::ReleaseSdp => {
+                    slg_io_set(bus, SlgPin::SlgIo0, PinState::Low)?;
+                }
+                Commands::Sdp { secs, progress } => {
+                    drive_timed_with_progress(
+                        |state| slg_io_set(bus, SlgPin::SlgIo0, state),
+                        PinState::High,
+                        Duration::from_secs(secs.unwrap_or(DEFAULT_SDP_SECS)),
+                        PinState::Low,
+                        |i| match progress {
+                            ProgressFormat::Text => eprintln!("{i}"),
+                            ProgressFormat::Json => eprintln!("{{\"remaining\": {i}}}"),
+                        },
+                    )?;
+                    println!("SDP released, back to USART mode");
+                }
+                _ => {}
+            }
+            claims.release(dongle, SlgPin::SlgIo0, SlgPurpose::Feature);
+        }
+
+        Commands::CheckRevision { expect } => {
+            if pcb_revision == *expect {
+                println!("{}", format!("PCB revision is {expect}").green());
+            } else {
+                println!(
+                    "{}",
+                    format!("PCB revision is {pcb_revision}, expected {expect}").red()
+                );
+                return Ok(Outcome::Exit(1));
+            }
+        }
+        Commands::CcPulse { ms } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                return Err(DongleError::Unsupported(
+                    "CC control is not supported on PCB RevA or B".into(),
+                ));
+            }
+            claims.check(dongle, SlgPin::SlgIo1, SlgPurpose::Feature)?;
+            match cc_pulse(bus, Duration::from_millis(*ms))? {
+                TimedOutcome::Elapsed => println!("CC forced low for {ms}ms, released"),
+                TimedOutcome::Interrupted => println!("Interrupted, CC released early"),
+            }
+        }
+        Commands::SdpSequence { phases } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                return Err(DongleError::Unsupported(
+                    "SDP control is not supported on PCB RevA or B".into(),
+                ));
+            }
+            claims.check(dongle, SlgPin::SlgIo0, SlgPurpose::Feature)?;
+            let outcome = sdp_sequence(bus, phases)?;
+            claims.release(dongle, SlgPin::SlgIo0, SlgPurpose::Feature);
+            if outcome == TimedOutcome::Interrupted {
+                println!("Interrupted, SDP released early");
+            }
+        }
+        Commands::Mode { mode } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                return Err(DongleError::Unsupported(
+                    "Boot mode control is not supported on PCB RevA or B".into(),
+                ));
+            }
+            if let Some(mode) = mode {
+                claims.check(dongle, SlgPin::SlgIo0, SlgPurpose::Feature)?;
+                set_boot_mode(bus, *mode)?;
+                match mode {
+                    BootMode::Sdp => claims.claim(dongle, SlgPin::SlgIo0, SlgPurpose::Feature)?,
+                    BootMode::Usart => claims.release(dongle, SlgPin::SlgIo0, SlgPurpose::Feature),
+                }
+            }
+            match boot_mode(bus)? {
+                BootMode::Sdp => println!("Boot mode: SDP (forced)"),
+                BootMode::Usart => {
+                    println!("Boot mode: USART (SDP if the board mode switch is in SDP position)")
+                }
+            }
+        }
+
+        Commands::Attach { .. } | Commands::Detach { .. } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                println!(
+                    "{}",
+                    "Attach / Detach is not supported on PCB RevA or B".red()
+                );
+                return Ok(Outcome::Done);
+            }
+            usb_switch_configure(bus)?;
+            match cmd {
+                Commands::Attach {
+                    verify_enumeration,
+                    timeout,
+                    settle_ms,
+                } => {
+                    if bus.policy().enforce_sequencing {
+                        sequencing::before_switch_connect(bus)?;
+                    }
+                    usb_switch_set(bus, true)?;
+                    wait_ready(
+                        bus,
+                        ctx.devices,
+                        dongle,
+                        verify_enumeration.then_some(*timeout),
+                        *settle_ms,
+                    )?;
+                }
+                Commands::Detach {
+                    force,
+                    strict,
+                    run,
+                    then_attach,
+                } => {
+                    check_data_session(ctx.devices, dongle, *force, *strict)?;
+                    let was_connected = usb_switch_swap(bus, false)?;
+                    if let Some(command) = run {
+                        let status = run_shell_command(command);
+                        if *then_attach {
+                            usb_switch_swap(bus, was_connected)?;
+                        }
+                        match status {
+                            Ok(status) if status.success() => {}
+                            Ok(status) => return Ok(Outcome::Exit(exit_code(status))),
+                            Err(e) => {
+                                println!("{}", format!("Failed to run '{command}': {e}").red());
+                                return Ok(Outcome::Exit(1));
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Commands::FullAttach { .. } | Commands::FullDetach { .. } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                println!(
+                    "{}",
+                    "Full Attach / Detach is not supported on PCB RevA or B".red()
+                );
+                return Ok(Outcome::Done);
+            }
+            // detached, CC stays forced low until the next full-attach
+            if matches!(cmd, Commands::FullDetach { .. }) {
+                claims.claim(dongle, SlgPin::SlgIo1, SlgPurpose::Feature)?;
+            } else {
+                claims.check(dongle, SlgPin::SlgIo1, SlgPurpose::Feature)?;
+                claims.release(dongle, SlgPin::SlgIo1, SlgPurpose::Feature);
+            }
+            let ensure = matches!(
+                cmd,
+                Commands::FullAttach { ensure: true, .. }
+                    | Commands::FullDetach { ensure: true, .. }
+            );
+            if ensure {
+                let attach = matches!(cmd, Commands::FullAttach { .. });
+                if let Commands::FullDetach { force, strict, .. } = cmd {
+                    check_data_session(ctx.devices, dongle, *force, *strict)?;
+                }
+                let cc = if attach {
+                    PinState::High
+                } else {
+                    PinState::Low
+                };
+                let power_changed = dev_power_ensure(bus, attach)?;
+                let switch_changed = usb_switch_ensure(bus, attach)?;
+                let cc_changed = slg_io_ensure(bus, SlgPin::SlgIo1, cc)?;
+                if !(power_changed || switch_changed || cc_changed) {
+                    println!("Already in desired state, no change");
+                }
+            } else {
+                usb_switch_configure(bus)?;
+                slg_io_set_mode(bus, SlgPin::SlgIo1, PinMode::Output)?;
+            }
+            match cmd {
+                Commands::FullAttach {
+                    verify_enumeration,
+                    timeout,
+                    ensure,
+                    settle_ms,
+                } => {
+                    if !ensure {
+                        dev_power_ctl(bus, true)?;
+                        usb_switch_set(bus, true)?;
+                        slg_io_set(bus, SlgPin::SlgIo1, PinState::High)?;
+                    }
+                    wait_ready(
+                        bus,
+                        ctx.devices,
+                        dongle,
+                        verify_enumeration.then_some(*timeout),
+                        *settle_ms,
+                    )?;
+                }
+                Commands::FullDetach {
+                    force,
+                    strict,
+                    ensure: false,
+                } => {
+                    check_data_session(ctx.devices, dongle, *force, *strict)?;
+                    dev_power_ctl(bus, false)?;
+                    usb_switch_set(bus, false)?;
+                    slg_io_set(bus, SlgPin::SlgIo1, PinState::Low)?;
+                }
+                _ => {}
+            }
+        }
+
+        Commands::TraceCycle {
+            off_ms,
+            timeout,
+            json,
+        } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                return Err(DongleError::Unsupported(
+                    "trace-cycle drives the USB switch and CC, which are not supported on PCB RevA or B"
+                        .into(),
+                ));
+            }
+            claims.check(dongle, SlgPin::SlgIo1, SlgPurpose::Feature)?;
+            claims.release(dongle, SlgPin::SlgIo1, SlgPurpose::Feature);
+            let timeline = trace_cycle(
+                bus,
+                ctx.devices,
+                dongle,
+                Duration::from_millis(*off_ms),
+                Duration::from_secs_f64(*timeout),
+            )?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&timeline).unwrap());
+            } else {
+                for entry in &timeline {
+                    println!(
+                        "{:>10.3} ms  {} {}",
+                        entry.t_ms,
+                        entry.event,
+                        entry.device.as_deref().unwrap_or("")
+                    );
+                }
+            }
+            if timeline
+                .last()
+                .is_some_and(|e| e.event == CycleEvent::EnumerationTimeout)
+            {
+                return Err(DongleError::NotEnumerated {
+                    timeout: Duration::from_secs_f64(*timeout),
+                    power_fault: is_dev_pwr_fault(bus)?,
+                });
+            }
+        }
+
+        Commands::GpioConfig { .. }
+        | Commands::GpioSet { .. }
+        | Commands::GpioGet { .. }
+        | Commands::GpioGetAll
+        | Commands::GpioStream { .. }
+        | Commands::GpioHold { .. }
+        | Commands::MaxToggle { .. } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                println!("{}", "GPIO is not supported on PCB RevA or B".red());
+                return Ok(Outcome::Done);
+            }
+            match cmd {
+                Commands::GpioConfig { pin, mode, ensure } => {
+                    let is_relay_pin = (1..=relay_count).any(|i| relay_pin(i) == Some(*pin));
+                    if is_relay_pin && *mode == PinMode::Input {
+                        println!("{}", "Configuring relay control pin as input, relay won't work".yellow());
+                    }
+                    if *ensure {
+                        if !gpio_header_ensure_mode(bus, *pin, *mode)? {
+                            println!("Already in desired state, no change");
+                        }
+                    } else {
+                        gpio_header_set_mode(bus, *pin, *mode)?;
+                    }
+                }
+                Commands::GpioSet {
+                    pin,
+                    state,
+                    ensure,
+                    latch_only,
+                } => {
+                    refuse_relay_pin(*pin, relay_count, "gpio-set")?;
+                    if *latch_only {
+                        gpio_header_set_latch(bus, *pin, *state)?;
+                    } else if *ensure {
+                        if !gpio_header_ensure(bus, *pin, *state)? {
+                            println!("Already in desired state, no change");
+                        }
+                    } else {
+                        gpio_header_set(bus, *pin, *state)?;
+                    }
+                }
+                Commands::GpioGet { pin } => {
+                    let state = gpio_header_get(bus, *pin)?;
+                    println!("{pin:?} = {state:?}");
+                }
+                Commands::GpioGetAll => {
+                    for (pin, state) in gpio_header_get_many(bus, &[HeaderPin::P0, HeaderPin::P1])?
+                    {
+                        println!("{pin:?}{} = {state:?}", silkscreen_note(pin));
+                    }
+                }
+                Commands::GpioStream {
+                    pin,
+                    hz,
+                    duration,
+                    edges,
+                    watch,
+                } => {
+                    if gpio_header_get_mode(bus, *pin)? != PinMode::Input {
+                        eprintln!("{}", format!("{pin:?} is not configured as input").yellow());
+                    }
+                    if *watch {
+                        eprintln!("Watching {pin:?} for {duration}s, reading as fast as possible");
+                        let stats =
+                            watch_pin(bus, *pin, Duration::from_secs_f64(*duration), |sample| {
+                                println!("{:.6} {:?}", sample.elapsed.as_secs_f64(), sample.state);
+                            })?;
+                        eprintln!(
+                            "{} samples in {:.3}s, edge resolution {:.3}ms",
+                            stats.samples,
+                            stats.elapsed.as_secs_f64(),
+                            1000.0 / stats.achieved_hz()
+                        );
+                        return Ok(Outcome::Done);
+                    }
+                    eprintln!(
+                        "Sampling {pin:?} at {hz} Hz for {duration}s, rate is bounded by USB control transfer latency"
+                    );
+                    let mut last = None;
+                    let stats = sample_pin(
+                        bus,
+                        *pin,
+                        *hz,
+                        Duration::from_secs_f64(*duration),
+                        |sample| {
+                            if !*edges || last != Some(sample.state) {
+                                println!("{:.6} {:?}", sample.elapsed.as_secs_f64(), sample.state);
+                            }
+                            last = Some(sample.state);
+                        },
+                    )?;
+                    eprintln!(
+                        "{} samples in {:.3}s, achieved rate {:.1} Hz",
+                        stats.samples,
+                        stats.elapsed.as_secs_f64(),
+                        stats.achieved_hz()
+                    );
+                    if stats.achieved_hz() < hz * 0.95 {
+                        eprintln!(
+                            "{}",
+                            "Requested rate could not be achieved, USB latency is the limit".yellow()
+                        );
+                    }
+                }
+                Commands::GpioHold {
+                    pin,
+                    state,
+                    check_ms,
+                } => {
+                    refuse_relay_pin(*pin, relay_count, "gpio-hold")?;
+                    gpio_header_set_mode(bus, *pin, PinMode::Output)?;
+                    gpio_header_set(bus, *pin, *state)?;
+                    println!(
+                        "Holding {pin:?} {state:?}, checking every {check_ms}ms, Ctrl-C to stop"
+                    );
+                    let mut diverged = false;
+                    let mut first = true;
+                    loop {
+                        let pad = gpio_header_get_pad(bus, *pin)?;
+                        let timestamp = iso8601_utc(SystemTime::now());
+                        if pad != *state && !diverged {
+                            let message = format!(
+                                "{timestamp} {pin:?} reads {pad:?} while driven {state:?}, overridden externally{}",
+                                if first { " (first divergence)" } else { "" }
+                            );
+                            println!("{}", message.red());
+                            first = false;
+                        } else if pad == *state && diverged {
+                            println!("{timestamp} {pin:?} back to {state:?}");
+                        }
+                        diverged = pad != *state;
+                        sleep(Duration::from_millis(*check_ms));
+                    }
+                }
+                Commands::MaxToggle {
+                    pin,
+                    duration,
+                    json,
+                } => {
+                    refuse_relay_pin(*pin, relay_count, "max-toggle")?;
+                    let duration = Duration::from_secs_f64(*duration);
+                    let without = measure_toggle_rate(bus, *pin, duration, false)?;
+                    let with = measure_toggle_rate(bus, *pin, duration, true)?;
+                    if *json {
+                        let stats = |s: &ToggleStats| {
+                            serde_json::json!({
+                                "toggles": s.toggles,
+                                "elapsed_s": s.elapsed.as_secs_f64(),
+                                "frequency_hz": s.frequency_hz(),
+                                "average_period_us": s.average_period().as_secs_f64() * 1e6,
+                                "mismatches": s.mismatches,
+                            })
+                        };
+                        let report = serde_json::json!({
+                            "pin": pin,
+                            "without_readback": stats(&without),
+                            "with_readback": stats(&with),
+                        });
+                        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+                    } else {
+                        for (name, s) in [("without readback", without), ("with readback", with)] {
+                            println!(
+                                "{name:<16}: {} toggles, {:.1} Hz, average period {:.1}us",
+                                s.toggles,
+                                s.frequency_hz(),
+                                s.average_period().as_secs_f64() * 1e6
+                            );
+                        }
+                        if with.mismatches > 0 {
+                            println!(
+                                "{}",
+                                format!("{} writes did not read back as written", with.mismatches)
+                                    .red()
+                            );
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Commands::Relay {
+            action,
+            min_dwell_ms,
+        } => {
+            if matches!(pcb_revision, PcbRevision::RevAorB) {
+                return Err(DongleError::Unsupported(
+                    "Relay is not supported on PCB RevA or B".into(),
+                ));
+            }
+            if relay_count == 0 {
+                return Err(DongleError::NotRelayVariant);
+            }
+            let indices = match action {
+                RelayAction::Close { index } | RelayAction::Open { index } => vec![*index],
+                RelayAction::Status { index: Some(index) } => vec![*index],
+                RelayAction::Status { index: None } => (1..=relay_count).collect(),
+            };
+            let mut relays = Vec::new();
+            for index in indices {
+                match relay_pin(index) {
+                    Some(pin) if index <= relay_count => relays.push((index, pin)),
+                    _ => {
+                        return Err(DongleError::Unsupported(format!(
+                            "Relay {index} is only present on the dual relay variant"
+                        )));
+                    }
+                }
+            }
+            for (index, pin) in relays {
+                match action {
+                    RelayAction::Close { .. } | RelayAction::Open { .. } => {
+                        let state = if matches!(action, RelayAction::Close { .. }) {
+                            PinState::High
+                        } else {
+                            PinState::Low
+                        };
+                        // Writing the level the relay already has is no switching
+                        let mut min_dwell =
+                            min_dwell_ms.filter(|ms| *ms > 0).map(Duration::from_millis);
+                        if min_dwell.is_some()
+                            && gpio_header_get_mode(bus, pin)? == PinMode::Output
+                            && gpio_header_get(bus, pin)? == state
+                        {
+                            min_dwell = None;
+                        }
+                        if let Some(min_dwell) = min_dwell {
+                            wait_relay_dwell(&ctx.dwell, dongle, index, relay_count, min_dwell);
+                        }
+                        gpio_header_set_mode(bus, pin, PinMode::Output)?;
+                        gpio_header_set(bus, pin, state)?;
+                        if min_dwell.is_some()
+                            && let Err(e) = ctx.dwell.record(dongle, index)
+                        {
+                            log::warn!("Failed to record relay switching: {e}");
+                        }
+                    }
+                    RelayAction::Status { .. } => {
+                        let status = HeaderPinStatus {
+                            mode: gpio_header_get_mode(bus, pin)?,
+                            state: gpio_header_get(bus, pin)?,
+                        };
+                        print_relay(&relay_name(index, relay_count), pin, status);
+                    }
+                }
+            }
+        }
+
+        Commands::Monitor {
+            interval_ms,
+            since,
+            read_only,
+            ..
+        } => {
+            let mut detector = TransitionDetector::new();
+            loop {
+                let report = if *read_only {
+                    read_only_status_report(bus, dongle)?
+                } else {
+                    status_report(bus, dongle)?
+                };
+                let timestamp = iso8601_utc(SystemTime::now());
+                if since.as_ref().is_none_or(|since| timestamp >= *since) {
+                    for change in detector.update(report) {
+                        println!("{timestamp} {change}");
+                    }
+                } else {
+                    detector.update(report);
+                }
+                sleep(Duration::from_millis(*interval_ms));
+            }
+        }
+
+        Commands::Apply { path, auto_config } => {
+            let desired = match DesiredState::load(path) {
+                Ok(desired) => desired,
+                Err(e) => {
+                    println!("{}", e.to_string().red());
+                    return Ok(Outcome::Exit(2));
+                }
+            };
+            if desired.power_on == Some(true)
+                && let Some(holder) = lockout::holder(dongle)
+            {
+                return Err(DongleError::LockedOut { holder });
+            }
+            // SDP and CC force use the SLG IOs as the features do
+            let forces = [
+                (SlgPin::SlgIo0, desired.forcing_sdp),
+                (SlgPin::SlgIo1, desired.forcing_cc_low),
+            ];
+            for (pin, force) in forces {
+                if force.is_some() {
+                    claims.check(dongle, pin, SlgPurpose::Feature)?;
+                }
+            }
+            let current = status_report(bus, dongle)?;
+            if ctx.strict {
+                let violations = violations(&desired.predict(&current));
+                if !violations.is_empty() {
+                    return Err(DongleError::InconsistentState { violations });
+                }
+            }
+            let mut changes = Vec::new();
+            let result = apply(bus, &desired, &current, *auto_config, &mut changes);
+            for ((pin, force), field) in forces.into_iter().zip(["forcing_sdp", "forcing_cc_low"]) {
+                // on failure only the forces that were already written are recorded
+                if result.is_err() && !changes.iter().any(|c| c.field == field) {
+                    continue;
+                }
+                match force {
+                    Some(true) => claims.claim(dongle, pin, SlgPurpose::Feature)?,
+                    Some(false) => claims.release(dongle, pin, SlgPurpose::Feature),
+                    None => {}
+                }
+            }
+            if result.is_ok() && changes.is_empty() {
+                println!("Already in desired state, no changes made");
+            }
+            for change in &changes {
+                println!("{change}");
+            }
+            if let Err(e) = result {
+                println!("{}", e.to_string().red());
+                return Ok(Outcome::Exit(2));
+            }
+        }
+        Commands::Verify { path, json } => {
+            let desired = match DesiredState::load(path) {
+                Ok(desired) => desired,
+                Err(e) => {
+                    println!("{}", e.to_string().red());
+                    return Ok(Outcome::Exit(2));
+                }
+            };
+            let current = read_only_status_report(bus, dongle)?;
+            let mismatches = verify(&desired, &current);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&mismatches).unwrap());
+            } else if mismatches.is_empty() {
+                println!("{}", "Dongle is in the desired state".green());
+            } else {
+                for mismatch in &mismatches {
+                    println!("{mismatch}");
+                }
+            }
+            if !mismatches.is_empty() {
+                return Ok(Outcome::Exit(1));
+            }
+        }
+    }
+    Ok(Outcome::Done)
+}
+
+/// Formats `d` as e.g. `1d 2h 3m 4s`, leading zero units are omitted.
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (days, hours, minutes) = (secs / 86_400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m {}s", secs % 60)
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m {}s", secs % 60)
+    } else if minutes > 0 {
+        format!("{minutes}m {}s", secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
+fn describe_device(device: &UsbDevice) -> String {
+    format!(
+        "{:04x}:{:04x} {}",
+        device.vendor_id,
+        device.product_id,
+        device.product_string.as_deref().unwrap_or("")
+    )
+    .trim_end()
+    .to_string()
+}
+
+/// Best effort guard against detaching a device in the middle of a transfer: the device activity is not
+/// visible, only whether it is enumerated behind the dongle. Warns, or fails with `strict`, unless `force`.
+fn check_data_session(
+    devices: DeviceList,
+    dongle: &DongleInfo,
+    force: bool,
+    strict: bool,
+) -> Result<(), DongleError> {
+    if force {
+        return Ok(());
+    }
+    let all_devices = match devices() {
+        Ok(all_devices) => all_devices,
+        Err(e) => {
+            log::debug!("Cannot check for enumerated devices: {e}");
+            return Ok(());
+        }
+    };
+    let Some(device) = dongle.downstream_devices(&all_devices).first().copied() else {
+        return Ok(());
+    };
+    let device = describe_device(device);
+    if strict {
+        return Err(DongleError::DeviceEnumerated { device });
+    }
+    println!(
+        "{}",
+        format!("Device {device} is enumerated, detaching anyway (use --strict to refuse)")
+            .yellow()
+    );
+    Ok(())
+}
+
+/// Waits until the device behind an attached dongle is usable: for it to enumerate within `enumeration_timeout`
+/// seconds if given, then another `settle_ms`.
+fn wait_ready(
+    bus: &dyn RegisterBus,
+    devices: DeviceList,
+    dongle: &DongleInfo,
+    enumeration_timeout: Option<f64>,
+    settle_ms: u64,
+) -> Result<(), DongleError> {
+    if let Some(timeout) = enumeration_timeout {
+        wait_enumeration(bus, devices, dongle, Duration::from_secs_f64(timeout))?;
+    }
+    if settle_ms > 0 {
+        sleep(Duration::from_millis(settle_ms));
+    }
+    Ok(())
+}
+
+/// Polls the USB device list until a device shows up behind the dongle's hub.
+fn wait_enumeration(
+    bus: &dyn RegisterBus,
+    devices: DeviceList,
+    dongle: &DongleInfo,
+    timeout: Duration,
+) -> Result<(), DongleError> {
+    let start = Instant::now();
+    loop {
+        let all_devices = devices().map_err(DongleError::Usb)?;
+        if let Some(device) = dongle.downstream_devices(&all_devices).first() {
+            println!(
+                "Device {} enumerated after {}ms",
+                describe_device(device),
+                start.elapsed().as_millis()
+            );
+            return Ok(());
+        }
+        if start.elapsed() >= timeout {
+            return Err(DongleError::NotEnumerated {
+                timeout,
+                power_fault: is_dev_pwr_fault(bus)?,
+            });
+        }
+        sleep(Duration::from_millis(100));
+    }
+}
+
+fn run_name_command(action: &NameAction) -> Result<(), ConfigError> {
+    let mut config = Config::load()?;
+    match action {
+        NameAction::Set { serial, name } => {
+            config.set_name(name, serial)?;
+            config.save()?;
+        }
+        NameAction::List => {
+            for (name, serial) in &config.names {
+                println!("{name:<16} {serial}");
+            }
+        }
+        NameAction::Rm { name } => {
+            config.remove_name(name)?;
+            config.save()?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_positive(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        _ => Err(format!("invalid value '{s}', expected a positive number")),
+    }
+}
+
+fn print_info(dongle: &DongleInfo, device: &Device) {
+    println!("Dongle serial: {}", dongle.display_serial());
+    if let Some(serial) = dongle.dongle_serial() {
+        println!("Bridge serial: {serial}");
+    }
+    println!(
+        "Bridge: bus {}, port chain {:?}",
+        dongle.bridge.bus_id, dongle.bridge.port_chain
+    );
+    println!("Hub product string: {}", dongle.hub_product_string());
+    #[cfg(target_os = "linux")]
+    if let Some(note) = mchp_gpio_ctl::doctor::ftdi_port_note(dongle) {
+        println!("{note}");
+    }
+    let layout = device_layout(device);
+    for configuration in &layout {
+        let active = if configuration.active { " (active)" } else { "" };
+        println!("Configuration {}{active}:", configuration.configuration_value);
+        for interface in &configuration.interfaces {
+            println!("  {interface}");
+        }
+    }
+    match control_interface_number(&layout) {
+        Ok(number) => println!("Register access interface: {number}"),
+        Err(e) => println!("{}", e.to_string().red()),
+    }
+}
+
+fn print_commands(commands: &[CommandInfo], json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(commands).unwrap());
+        return;
+    }
+    for c in commands {
+        let requirement = if c.requires_relay {
+            "relay variant"
+        } else if c.requires_revc {
+            "PCB RevC and up"
+        } else {
+            "any"
+        };
+        match c.available {
+            Some(true) => println!("{:<16} {requirement:<16} available", c.name),
+            Some(false) => println!("{:<16} {requirement:<16} not available", c.name),
+            None => println!("{:<16} {requirement}", c.name),
+        }
+    }
+}
+
+/// Prints serials, numbered and with USB location if some of them are identical.
+fn print_serials(devices: &[DongleInfo]) {
+    if !has_duplicate_serials(devices) {
+        for dongle in devices {
+            println!("{}", dongle.display_serial());
+        }
+        return;
+    }
+    for (i, dongle) in devices.iter().enumerate() {
+        println!(
+            "{:>3}. {} at {}",
+            i + 1,
+            dongle.display_serial(),
+            dongle.bridge.location()
+        );
+    }
+}
+
+/// Asks a yes/no question on the terminal until answered, `None` if stdin is closed.
+fn ask_yes_no(question: &str) -> Option<bool> {
+    loop {
+        print!("{question} [y/n]: ");
+        let _ = std::io::stdout().flush();
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer).ok()? == 0 {
+            return None;
+        }
+        match answer.trim().to_ascii_lowercase().as_str() {
+            "y" | "yes" => return Some(true),
+            "n" | "no" => return Some(false),
+            _ => {}
+        }
+    }
+}
+
+/// Lets the user pick one of `devices` by number when running interactively, otherwise explains how to
+/// select one with --port or --index.
+fn pick_dongle(devices: &[DongleInfo]) -> Option<&DongleInfo> {
+    print_serials(devices);
+    if !std::io::stdin().is_terminal() {
+        println!("Select one with --port <location> or --index <number>");
+        return None;
+    }
+    print!("Select device [1-{}]: ", devices.len());
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer).ok()?;
+    let picked = answer
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| devices.get(i.checked_sub(1)?));
+    if picked.is_none() {
+        println!("Invalid selection, use --port <location> or --index <number>");
+    }
+    picked
+}
+
+fn print_list(entries: &[ListEntry]) {
+    println!("{:<20} {:<8} {:<8} REVISION", "SERIAL", "POWER", "FAULT");
+    for entry in entries {
+        match &entry.status {
+            Some(DongleStatus::Available {
+                power_state,
+                power_fault,
+                pcb_revision,
+                ..
+            }) => {
+                // Padded before colorizing, escape codes would break the alignment
+                let fault = match power_fault {
+                    Some(true) => format!("{:<8}", "FAULT").red(),
+                    Some(false) => format!("{:<8}", "no").normal(),
+                    None => format!("{:<8}", "unknown").normal(),
+                };
+                println!(
+                    "{:<20} {:<8} {fault} {pcb_revision}",
+                    entry.serial,
+                    format!("{power_state:?}")
+                );
+            }
+            Some(DongleStatus::Unavailable { error }) => {
+                println!(
+                    "{:<20} {}",
+                    entry.serial,
+                    format!("unavailable: {error}").yellow()
+                );
+            }
+            None => println!("{}", entry.serial),
+        }
+    }
+}
+
+fn print_status(report: &StatusReport) {
+    println!("Dongle serial: {}", report.serial);
+    match report.power_state {
+        PowerState::On => println!("Power is ON"),
+        PowerState::Off => println!("Power is OFF"),
+        PowerState::Unknown => println!("Power: Unknown (not yet configured)"),
+    }
+    if report.power_fault.is_none() {
+        println!("Power fault: unknown (PIO10 is not an input, left untouched in read-only mode)");
+    }
+    println!("PCB revision: {}", report.pcb_revision);
+    if report.relay_variant {
+        println!("SSR (opto-relay) variant");
+    }
+    if let Some(connected) = report.usb_switch_connected {
+        println!("USB switch connected: {connected}");
+    }
+    if let Some(forcing_sdp) = report.forcing_sdp {
+        println!(
+            "Is forcing SDP mode{}: {forcing_sdp:?}",
+            silkscreen_note(SlgPin::SlgIo0)
+        );
+    }
+    if let Some(forcing_cc_low) = report.forcing_cc_low {
+        println!(
+            "Is forcing CC lines down{}: {forcing_cc_low:?}",
+            silkscreen_note(SlgPin::SlgIo1)
+        );
+    }
+    let pins = [
+        (HeaderPin::P0, report.header_p0),
+        (HeaderPin::P1, report.header_p1),
+    ];
+    for (index, (pin, status)) in (1..).zip(pins) {
+        let Some(status) = status else {
+            continue;
+        };
+        if index <= report.relay_count {
+            print_relay(&relay_name(index, report.relay_count), pin, status);
+        } else {
+            println!(
+                "Header pin {}{} mode: {:?}, state: {:?}",
+                index - 1,
+                silkscreen_note(pin),
+                status.mode,
+                status.state
+            );
+        }
+    }
+}
+
+/// Waits until relay `index` has kept its state for `min_dwell`, see [mchp_gpio_ctl::relay_dwell].
+fn wait_relay_dwell(
+    dwell: &DwellStore,
+    dongle: &DongleInfo,
+    index: u8,
+    relay_count: u8,
+    min_dwell: Duration,
+) {
+    match dwell.remaining(dongle, index, min_dwell) {
+        Ok(remaining) if !remaining.is_zero() => {
+            println!(
+                "{}",
+                format!(
+                    "{} switched less than {}ms ago, waiting {}ms",
+                    relay_name(index, relay_count),
+                    min_dwell.as_millis(),
+                    remaining.as_millis()
+                )
+                .yellow()
+            );
+            sleep(remaining);
+        }
+        Ok(_) => {}
+        Err(e) => log::warn!("Failed to read last relay switching, not waiting: {e}"),
+    }
+}
+
+/// Refuses driving a header pin that switches a relay outside of `relay`, which would bypass the minimum dwell,
+/// see [mchp_gpio_ctl::relay_dwell].
+fn refuse_relay_pin(pin: HeaderPin, relay_count: u8, command: &str) -> Result<(), DongleError> {
+    match (1..=relay_count).find(|&i| relay_pin(i) == Some(pin)) {
+        Some(index) => Err(DongleError::Unsupported(format!(
+            "{pin:?} drives {}, '{command}' would switch it without the minimum dwell, use 'relay' instead",
+            relay_name(index, relay_count).to_lowercase()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// "Relay" on single relay boards, "Relay N" on the dual relay variant.
+fn relay_name(index: u8, relay_count: u8) -> String {
+    if relay_count > 1 {
+        format!("Relay {index}")
+    } else {
+        "Relay".to_string()
+    }
+}
+
+/// Opens both dongles, benchmarks them one after the other and prints the comparison.
+fn compare_dongles(
+    devices: &[DongleInfo],
+    serials: [&String; 2],
+    pin: HeaderPin,
+    iterations: usize,
+    json: bool,
+    board: &BoardProfile,
+    policy_for: impl Fn(&DongleInfo) -> Policy,
+) -> Result<(), DongleError> {
+    let mut selected = Vec::new();
+    for serial in serials {
+        let query = SerialQuery::resolve(devices, serial);
+        let dongle = select_dongle(devices, Some(&query)).map_err(|e| {
+            let reason = match e {
+                SelectError::NoDevices => "no devices found",
+                SelectError::NoMatch | SelectError::SerialRequired => "no device matches",
+                SelectError::Ambiguous => "matches more than one device",
+                SelectError::DuplicateSerial => "several devices report this serial",
+            };
+            DongleError::Unsupported(format!("Can not select dongle '{serial}': {reason}"))
+        })?;
+        // toggling a relay pin would click the relay and switch whatever is wired to it
+        if let Some(index) = (1..=dongle.relay_count()).find(|&i| relay_pin(i) == Some(pin)) {
+            return Err(DongleError::Unsupported(format!(
+                "{pin:?} drives relay {index} on dongle '{serial}', pick a header pin without a relay with --pin"
+            )));
+        }
+        selected.push((serial, dongle));
+    }
+    let mut results = Vec::new();
+    for (serial, dongle) in selected {
+        let bridge = dongle
+            .open_bus(board)?
+            .ok_or(DongleError::Unsupported(format!(
+                "Dongle '{serial}' disconnected"
+            )))?;
+        let bus = PolicyBus::new(&bridge, policy_for(dongle));
+        if matches!(pcb_revision(&bus)?, PcbRevision::RevAorB) {
+            return Err(DongleError::Unsupported(format!(
+                "Dongle '{serial}' is PCB RevA or B, compare needs header pins (RevC and up)"
+            )));
+        }
+        results.push((dongle.display_serial(), bench(&bus, pin, iterations)?));
+    }
+    let [(serial_a, a), (serial_b, b)] = results.try_into().unwrap();
+    let comparison = compare(&a, &b);
+    if json {
+        let report = serde_json::json!({
+            "pin": pin,
+            "iterations": iterations,
+            "a": { "serial": serial_a, "result": a },
+            "b": { "serial": serial_b, "result": b },
+            "comparison": comparison,
+        });
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        return Ok(());
+    }
+    println!("{:<16} {:>14} {:>14} {:>9}", "", serial_a, serial_b, "diff");
+    for c in &comparison {
+        let line = format!(
+            "{:<16} {:>12.1}us {:>12.1}us {:>+8.1}%",
+            c.metric,
+            c.a_us,
+            c.b_us,
+            c.relative * 100.0
+        );
+        if c.flagged {
+            println!("{}", format!("{line} !").yellow());
+        } else {
+            println!("{line}");
+        }
+    }
+    for (name, a, b) in [
+        ("toggle_outliers", a.toggle.outliers, b.toggle.outliers),
+        ("read_outliers", a.read.outliers, b.read.outliers),
+    ] {
+        let line = format!("{name:<16} {a:>14} {b:>14}");
+        if a != b {
+            println!("{}", line.yellow());
+        } else {
+            println!("{line}");
+        }
+    }
+    if comparison.iter().any(|c| c.flagged) {
+        println!(
+            "{}",
+            format!(
+                "Latencies differ by more than {:.0}%",
+                FLAG_THRESHOLD * 100.0
+            )
+            .yellow()
+        );
+    }
+    Ok(())
+}
+
+fn print_switch_diag(diag: &SwitchDiag) {
+    let connected = |is_connected: bool| {
+        if is_connected {
+            "connected"
+        } else {
+            "disconnected"
+        }
+    };
+    println!("USB_SWITCH_EN (PIO1): {:?}", diag.mode);
+    match diag.intended_connected {
+        Some(intended) => println!("Driven:    {:?} ({})", diag.latched, connected(intended)),
+        None => println!(
+            "Driven:    nothing, pin is an input (latch {:?})",
+            diag.latched
+        ),
+    }
+    println!(
+        "Read back: {:?} ({})",
+        diag.pad,
+        connected(diag.read_back_connected)
+    );
+    if diag.mismatch {
+        println!(
+            "{}",
+            "Read back level differs from the driven one, the switch control line is shorted or overdriven"
+                .red()
+        );
+    } else if diag.intended_connected.is_some() {
+        println!("{}", "Control line follows the driven level".green());
+    }
+}
+
+fn print_hub_info(info: &HubInfo) {
+    match &info.hub {
+        Some(hub) => {
+            println!(
+                "Hub {:04x}:{:04x} at {}, bcdDevice {}, USB {}, class {:02x}/{:02x}/{:02x}, speed: {}",
+                hub.vendor_id,
+                hub.product_id,
+                hub.location,
+                format_bcd(hub.device_version),
+                format_bcd(hub.usb_version),
+                hub.class,
+                hub.subclass,
+                hub.protocol,
+                hub.speed.unwrap_or("unknown")
+            );
+            let strings = [
+                ("Manufacturer", &hub.manufacturer),
+                ("Product", &hub.product),
+                ("Serial", &hub.serial),
+            ];
+            for (name, value) in strings {
+                match value {
+                    Some(value) => println!("{name:<13} \"{value}\""),
+                    None => println!("{name:<13} {}", "(none)".dimmed()),
+                }
+            }
+        }
+        None => println!("{}", "Hub not found next to the bridge".yellow()),
+    }
+    match info.relay_count {
+        0 => println!(
+            "Detected: no relay variant (no 'relay', 'relay2' or 'dual' in the product string)"
+        ),
+        count => println!("{}", format!("Detected: {count} relay(s)").green()),
+    }
+}
+
+fn print_port_diag(diag: &PortDiagnostics) {
+    println!(
+        "Dongle link speed: {}",
+        diag.bridge_speed.unwrap_or("unknown")
+    );
+    if diag.devices.is_empty() {
+        println!("{}", "No device enumerated behind the dongle".yellow());
+    }
+    for device in &diag.devices {
+        println!(
+            "Device {:04x}:{:04x} {} at {} (hub port {}), speed: {}",
+            device.vendor_id,
+            device.product_id,
+            device.product.as_deref().unwrap_or(""),
+            device.location,
+            device.port.map_or("?".into(), |p| p.to_string()),
+            device.speed.unwrap_or("unknown")
+        );
+    }
+    let fault = match diag.power_fault {
+        Some(true) => "FAULT".red(),
+        Some(false) => "no fault".normal(),
+        None => "fault unknown".normal(),
+    };
+    println!(
+        "Power: {}, {fault}",
+        if diag.power_on { "on" } else { "off" }
+    );
+    let optional = [
+        ("USB switch connected", diag.usb_switch_connected),
+        ("Forcing SDP", diag.forcing_sdp),
+        ("Forcing CC low", diag.forcing_cc_low),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            println!("{name}: {value}");
+        }
+    }
+}
+
+fn print_dirmap(map: &[PinDirection]) {
+    let mut group = None;
+    for pin in map {
+        if group != Some(pin.group) {
+            println!("{:?}:", pin.group);
+            group = Some(pin.group);
+        }
+        let mode = match pin.mode {
+            PinMode::Output => "output".yellow(),
+            PinMode::Input => "input".green(),
+        };
+        println!("  {:<14} PIO{:<3} {mode}", pin.name, pin.pio);
+    }
+}
+
+/// ` (silkscreen "N")` for pins with a board label, empty otherwise.
+fn silkscreen_note(pin: impl Into<PinName>) -> String {
+    match pin.into().silkscreen() {
+        Some(label) => format!(" (silkscreen \"{label}\")"),
+        None => String::new(),
+    }
+}
+
+fn print_relay(name: &str, pin: HeaderPin, status: HeaderPinStatus) {
+    let pin = format!("{pin:?}").to_lowercase();
+    if status.mode == PinMode::Input {
+        println!(
+            "{}",
+            format!("Relay pin {pin} is configured as Input, relay won't work").yellow()
+        );
+    } else if status.state == PinState::High {
+        println!("{name} state: Short ({pin} high)");
+    } else {
+        println!("{name} state: Open ({pin} low)");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mchp_gpio_ctl::discovery::{
+        PRODUCT_BRIDGE_DEV, PRODUCT_USB4604_HUB, UsbDevice, VENDOR_SMSC,
+    };
+    use mchp_gpio_ctl::dongle_hal_revc::usb_switch_is_connected;
+    use mchp_gpio_ctl::usb4604_ral::{
+        Gpio0_7Dir, Gpio0_7Input, Gpio0_7Output, Gpio8_10Dir, Gpio8_10Input, Gpio17_20Dir,
+        Gpio17_20Output, MockBus, SmscReg,
+    };
+
+    fn dongle() -> DongleInfo {
+        DongleInfo {
+            bridge: UsbDevice {
+                bus_id: "1".into(),
+                port_chain: vec![1, 2],
+                vendor_id: VENDOR_SMSC,
+                product_id: PRODUCT_BRIDGE_DEV,
+                manufacturer_string: None,
+                product_string: None,
+                serial_number: Some("BRIDGE0".into()),
+            },
+            ftdi: None,
+            hub: None,
+        }
+    }
+
+    /// Context with a claim store of its own for every test (thread), claims persist within the test.
+    fn ctx(dongle: &DongleInfo) -> Context<'_> {
+        let thread = format!("{:?}", std::thread::current().id());
+        let dir = std::env::temp_dir().join(format!(
+            "mchp_claims_{}_{}",
+            std::process::id(),
+            thread.trim_start_matches("ThreadId(").trim_end_matches(')')
+        ));
+        Context {
+            dongle,
+            strict: false,
+            dwell: DwellStore::new(dir.join("relay.toml")),
+            claims: ClaimStore::new(dir),
+            devices: || Ok(Vec::new()),
+        }
+    }
+
+    fn relay_dongle(hub_product: &str) -> DongleInfo {
+        DongleInfo {
+            hub: Some(UsbDevice {
+                bus_id: "1".into(),
+                port_chain: vec![1],
+                vendor_id: VENDOR_SMSC,
+                product_id: PRODUCT_USB4604_HUB,
+                manufacturer_string: None,
+                product_string: Some(hub_product.into()),
+                serial_number: None,
+            }),
+            ..dongle()
+        }
+    }
+
+    /// Bus of a dongle without power fault, RevC or RevA/B depending on `is_revc`.
+    fn bus(is_revc: bool) -> MockBus {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio8_10Input::ADDR,
+            Gpio8_10Input::new()
+                .with_gpio9_in(is_revc)
+                .with_gpio10_in(true)
+                .value(),
+        );
+        bus
+    }
+
+    fn full_attach() -> Commands {
+        Commands::FullAttach {
+            verify_enumeration: false,
+            timeout: 5.0,
+            ensure: false,
+            settle_ms: 0,
+        }
+    }
+
+    #[test]
+    fn full_attach_drives_power_switch_and_cc() {
+        let bus = bus(true);
+        execute(&full_attach(), &bus, &ctx(&dongle())).unwrap();
+        // PIO0 (power), PIO1 (USB switch) and PIO3 (SLG_IO1) are outputs
+        assert_eq!(bus.get(Gpio0_7Dir::ADDR), 0b0000_1011);
+        // power and switch are active low, CC released (high)
+        assert_eq!(bus.get(Gpio0_7Output::ADDR), 0b0000_1000);
+    }
+
+    #[test]
+    fn full_detach_after_attach() {
+        let bus = bus(true);
+        execute(&full_attach(), &bus, &ctx(&dongle())).unwrap();
+        execute(
+            &Commands::FullDetach {
+                force: true,
+                strict: false,
+                ensure: false,
+            },
+            &bus,
+            &ctx(&dongle()),
+        )
+        .unwrap();
+        assert_eq!(bus.get(Gpio0_7Dir::ADDR), 0b0000_1011);
+        assert_eq!(bus.get(Gpio0_7Output::ADDR), 0b0000_0011);
+    }
+
+    #[test]
+    fn full_attach_ensure_matches_plain_attach_and_repeats_as_no_op() {
+        let attach = Commands::FullAttach {
+            verify_enumeration: false,
+            timeout: 5.0,
+            ensure: true,
+            settle_ms: 0,
+        };
+        let bus = bus(true);
+        execute(&attach, &bus, &ctx(&dongle())).unwrap();
+        assert_eq!(bus.get(Gpio0_7Dir::ADDR), 0b0000_1011);
+        assert_eq!(bus.get(Gpio0_7Output::ADDR), 0b0000_1000);
+
+        assert!(!dev_power_ensure(&bus, true).unwrap());
+        assert!(!usb_switch_ensure(&bus, true).unwrap());
+        assert!(!slg_io_ensure(&bus, SlgPin::SlgIo1, PinState::High).unwrap());
+        assert!(slg_io_ensure(&bus, SlgPin::SlgIo1, PinState::Low).unwrap());
+    }
+
+    #[test]
+    fn off_turns_power_switch_off() {
+        let bus = bus(false);
+        execute(&Commands::Off, &bus, &ctx(&dongle())).unwrap();
+        let output: Gpio0_7Output = bus.reg();
+        assert!(bus.reg::<Gpio0_7Dir>().gpio0_out_en());
+        assert!(output.gpio0_out());
+    }
+
+    #[test]
+    fn detach_is_rejected_on_rev_a_or_b() {
+        let bus = bus(false);
+        execute(
+            &Commands::Detach {
+                force: false,
+                strict: true,
+                run: None,
+                then_attach: false,
+            },
+            &bus,
+            &ctx(&dongle()),
+        )
+        .unwrap();
+        assert_eq!(bus.writes(), vec![]);
+    }
+
+    #[test]
+    fn gpio_set_requires_output_mode() {
+        let bus = bus(true);
+        let set = Commands::GpioSet {
+            pin: HeaderPin::P1,
+            state: PinState::High,
+            ensure: false,
+            latch_only: false,
+        };
+        let err = execute(&set, &bus, &ctx(&dongle())).unwrap_err();
+        assert!(matches!(
+            err,
+            DongleError::PinInInputMode {
+                pin: "P1",
+                pull: None
+            }
+        ));
+        assert_eq!(bus.writes(), vec![]);
+
+        let config = Commands::GpioConfig {
+            pin: HeaderPin::P1,
+            mode: PinMode::Output,
+            ensure: false,
+        };
+        execute(&config, &bus, &ctx(&dongle())).unwrap();
+        execute(&set, &bus, &ctx(&dongle())).unwrap();
+        assert!(bus.reg::<Gpio17_20Dir>().gpio20_out_en());
+        assert!(bus.reg::<Gpio17_20Output>().gpio20_out());
+    }
+
+    #[test]
+    fn gpio_config_ensure_skips_redundant_write() {
+        let bus = bus(true);
+        let config = Commands::GpioConfig {
+            pin: HeaderPin::P0,
+            mode: PinMode::Output,
+            ensure: true,
+        };
+        execute(&config, &bus, &ctx(&dongle())).unwrap();
+        execute(&config, &bus, &ctx(&dongle())).unwrap();
+        let dir = Gpio17_20Dir::new().with_gpio19_out_en(true);
+        assert_eq!(bus.writes(), vec![(Gpio17_20Dir::ADDR, dir.value())]);
+    }
+
+    #[test]
+    fn relay_2_drives_p1_on_dual_variant() {
+        let bus = bus(true);
+        let close = Commands::Relay {
+            action: RelayAction::Close { index: 2 },
+            min_dwell_ms: None,
+        };
+        execute(&close, &bus, &ctx(&relay_dongle("USB4604 relay2"))).unwrap();
+        assert!(bus.reg::<Gpio17_20Dir>().gpio20_out_en());
+        assert!(bus.reg::<Gpio17_20Output>().gpio20_out());
+        assert!(!bus.reg::<Gpio17_20Dir>().gpio19_out_en());
+    }
+
+    #[test]
+    fn relay_dwell_waits_only_for_a_switching() {
+        let bus = bus(true);
+        let dongle = relay_dongle("USB4604 relay");
+        let ctx = ctx(&dongle);
+        let relay = |action| Commands::Relay {
+            action,
+            min_dwell_ms: Some(300),
+        };
+        let timed = |cmd: Commands| {
+            let start = Instant::now();
+            execute(&cmd, &bus, &ctx).unwrap();
+            start.elapsed()
+        };
+        timed(relay(RelayAction::Close { index: 1 }));
+        // same level again is no switching, nothing to wait for
+        assert!(timed(relay(RelayAction::Close { index: 1 })) < Duration::from_millis(200));
+        // opening right after the close waits for the rest of the dwell
+        assert!(timed(relay(RelayAction::Open { index: 1 })) >= Duration::from_millis(200));
+        assert!(!bus.reg::<Gpio17_20Output>().gpio19_out());
+    }
+
+    #[test]
+    fn relay_2_is_rejected_on_single_relay_variant() {
+        let bus = bus(true);
+        let close = Commands::Relay {
+            action: RelayAction::Close { index: 2 },
+            min_dwell_ms: None,
+        };
+        let result = execute(&close, &bus, &ctx(&relay_dongle("USB4604 relay")));
+        assert!(matches!(result, Err(DongleError::Unsupported(_))));
+        assert_eq!(bus.writes(), vec![]);
+
+        let close = Commands::Relay {
+            action: RelayAction::Close { index: 1 },
+            min_dwell_ms: None,
+        };
+        execute(&close, &bus, &ctx(&relay_dongle("USB4604 relay"))).unwrap();
+        assert!(bus.reg::<Gpio17_20Output>().gpio19_out());
+    }
+
+    #[test]
+    fn power_cycle_waits_for_stable_power() {
+        let cycle = Commands::PowerCycle {
+            off_ms: 0,
+            wait_stable_ms: Some(20),
+            timeout_ms: 100,
+        };
+        let bus = bus(false);
+        execute(&cycle, &bus, &ctx(&dongle())).unwrap();
+        assert!(!bus.reg::<Gpio0_7Output>().gpio0_out());
+
+        // PWR_FAIL_N stuck low
+        let bus = MockBus::new();
+        let result = execute(&cycle, &bus, &ctx(&dongle()));
+        assert!(matches!(result, Err(DongleError::PowerUnstable { .. })));
+    }
+
+    #[test]
+    fn read_only_status_does_not_write() {
+        let bus = bus(true);
+        bus.set(
+            Gpio8_10Dir::ADDR,
+            Gpio8_10Dir::new().with_gpio10_out_en(true).value(),
+        );
+        let status = Commands::Status {
+            format: StatusFormat::Text,
+            read_only: true,
+            baseline: None,
+            ignore: Vec::new(),
+        };
+        execute(&status, &bus, &ctx(&dongle())).unwrap();
+        assert_eq!(bus.writes(), vec![]);
+    }
+
+    #[test]
+    fn max_toggle_restores_pin() {
+        let bus = bus(true);
+        let toggle = Commands::MaxToggle {
+            pin: HeaderPin::P1,
+            duration: 0.01,
+            json: true,
+        };
+        execute(&toggle, &bus, &ctx(&dongle())).unwrap();
+        assert!(!bus.reg::<Gpio17_20Dir>().gpio20_out_en());
+        assert!(!bus.reg::<Gpio17_20Output>().gpio20_out());
+        assert!(bus.writes().len() > 4);
+    }
+
+    #[test]
+    fn raw_pin_requires_expert_and_skips_inversion() {
+        let bus = bus(false);
+        let mut pin = Commands::Pin {
+            name: PinName::PwrEn,
+            action: None,
+            dir: Some(PinMode::Output),
+            level: Some(PinState::High),
+            expert: false,
+        };
+        execute(&pin, &bus, &ctx(&dongle())).unwrap();
+        assert_eq!(bus.writes(), vec![]);
+
+        if let Commands::Pin { expert, .. } = &mut pin {
+            *expert = true;
+        }
+        execute(&pin, &bus, &ctx(&dongle())).unwrap();
+        assert!(bus.reg::<Gpio0_7Dir>().gpio0_out_en());
+        assert!(bus.reg::<Gpio0_7Output>().gpio0_out());
+    }
+
+    #[test]
+    fn cc_pulse_is_rejected_on_rev_b() {
+        let bus = bus(false);
+        let result = execute(&Commands::CcPulse { ms: 1 }, &bus, &ctx(&dongle()));
+        assert!(matches!(result, Err(DongleError::Unsupported(_))));
+        assert_eq!(bus.writes(), vec![]);
+    }
+
+    #[test]
+    fn relay_is_rejected_on_plain_dongle() {
+        let bus = bus(true);
+        let actions = [
+            RelayAction::Close { index: 1 },
+            RelayAction::Open { index: 1 },
+            RelayAction::Status { index: None },
+        ];
+        for action in actions {
+            let result = execute(
+                &Commands::Relay {
+                    action,
+                    min_dwell_ms: None,
+                },
+                &bus,
+                &ctx(&dongle()),
+            );
+            let err = result.unwrap_err();
+            assert!(matches!(err, DongleError::NotRelayVariant));
+            assert_eq!(err.kind(), "not_relay_variant");
+        }
+        assert_eq!(bus.writes(), vec![]);
+    }
+
+    #[test]
+    fn relay_variant_is_detected_from_the_hub_product_string_on_revc() {
+        let relay = relay_dongle("USB4604 relay2");
+        assert_eq!(detect_relay_variant(&bus(true), &relay).unwrap(), 2);
+        assert_eq!(detect_relay_variant(&bus(false), &relay).unwrap(), 0);
+        assert_eq!(detect_relay_variant(&bus(true), &dongle()).unwrap(), 0);
+    }
+
+    #[test]
+    fn lockout_refuses_power_on() {
+        let bus = bus(false);
+        let mut dongle = dongle();
+        dongle.bridge.serial_number = Some(format!("LOCKOUT-TEST-{}", std::process::id()));
+        let lockout = |action| Commands::Lockout { action };
+
+        execute(&lockout(LockoutAction::On), &bus, &ctx(&dongle)).unwrap();
+        assert!(bus.reg::<Gpio0_7Output>().gpio0_out());
+        let on = Commands::On {
+            soft_start_ms: None,
+        };
+        let result = execute(&on, &bus, &ctx(&dongle));
+        assert!(matches!(result, Err(DongleError::LockedOut { .. })));
+        assert!(bus.reg::<Gpio0_7Output>().gpio0_out());
+
+        execute(&lockout(LockoutAction::Off), &bus, &ctx(&dongle)).unwrap();
+        execute(&on, &bus, &ctx(&dongle)).unwrap();
+        assert!(!bus.reg::<Gpio0_7Output>().gpio0_out());
+    }
+
+    #[test]
+    fn policy_flags_can_be_set_back_to_false() {
+        let cli = Cli::try_parse_from(["mchp_gpio_ctl", "--power-active-high", "off"]).unwrap();
+        assert_eq!(cli.power_active_high, Some(true));
+        assert!(matches!(cli.command, Commands::Off));
+        let cli =
+            Cli::try_parse_from(["mchp_gpio_ctl", "--power-active-high=false", "off"]).unwrap();
+        assert_eq!(cli.power_active_high, Some(false));
+        assert_eq!(cli.switch_active_high, None);
+    }
+
+    #[test]
+    fn uptime_duration_omits_leading_zero_units() {
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(3600 + 5)), "1h 0m 5s");
+        assert_eq!(format_duration(Duration::from_secs(90_061)), "1d 1h 1m 1s");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn detach_run_then_attach_restores_the_previous_state() {
+        let bus = bus(true);
+        let detach = Commands::Detach {
+            force: true,
+            strict: false,
+            run: Some("true".into()),
+            then_attach: true,
+        };
+        execute(&full_attach(), &bus, &ctx(&dongle())).unwrap();
+        execute(&detach, &bus, &ctx(&dongle())).unwrap();
+        assert!(usb_switch_is_connected(&bus).unwrap());
+
+        usb_switch_set(&bus, false).unwrap();
+        execute(&detach, &bus, &ctx(&dongle())).unwrap();
+        assert!(!usb_switch_is_connected(&bus).unwrap());
+    }
+
+    #[test]
+    fn sequencing_never_powers_on_past_lockout() {
+        let bus = bus(true);
+        bus.set_policy(Policy {
+            enforce_sequencing: true,
+            ..Policy::default()
+        });
+        let mut dongle = dongle();
+        dongle.bridge.serial_number = Some(format!("SEQ-LOCKOUT-TEST-{}", std::process::id()));
+        execute(&Commands::Off, &bus, &ctx(&dongle)).unwrap();
+        execute(
+            &Commands::Lockout {
+                action: LockoutAction::On,
+            },
+            &bus,
+            &ctx(&dongle),
+        )
+        .unwrap();
+
+        let attach = Commands::Attach {
+            verify_enumeration: false,
+            timeout: 5.0,
+            settle_ms: 0,
+        };
+        let result = execute(&attach, &bus, &ctx(&dongle));
+        assert!(matches!(result, Err(DongleError::LockedOut { .. })));
+        let detach = Commands::Detach {
+            force: true,
+            strict: false,
+            run: Some("true".into()),
+            then_attach: true,
+        };
+        // restores the detached state it found, never powers on
+        execute(&detach, &bus, &ctx(&dongle)).unwrap();
+        assert!(!is_dev_power_on(&bus).unwrap());
+        assert!(!usb_switch_is_connected(&bus).unwrap());
+
+        lockout::release(&dongle).unwrap();
+        execute(&attach, &bus, &ctx(&dongle)).unwrap();
+        assert!(is_dev_power_on(&bus).unwrap());
+        assert!(usb_switch_is_connected(&bus).unwrap());
+    }
+
+    #[test]
+    fn compare_refuses_to_toggle_a_relay_pin() {
+        let mut relay = relay_dongle("dongle relay");
+        relay.bridge.serial_number = Some("RELAY0".into());
+        let devices = [dongle(), relay];
+        let [a, b] = ["BRIDGE0".to_string(), "RELAY0".to_string()];
+        let result = compare_dongles(
+            &devices,
+            [&a, &b],
+            HeaderPin::P0,
+            1,
+            false,
+            &BoardProfile::REFERENCE,
+            |_| Policy::default(),
+        );
+        assert!(
+            matches!(result, Err(DongleError::Unsupported(message)) if message.contains("relay 1"))
+        );
+    }
+
+    #[test]
+    fn direct_pin_commands_refuse_relay_pins() {
+        let bus = bus(true);
+        let dongle = relay_dongle("USB4604 relay");
+        let ctx = ctx(&dongle);
+        let commands = [
+            Commands::GpioSet {
+                pin: HeaderPin::P0,
+                state: PinState::High,
+                ensure: false,
+                latch_only: false,
+            },
+            Commands::MaxToggle {
+                pin: HeaderPin::P0,
+                duration: 0.01,
+                json: true,
+            },
+            Commands::Pin {
+                name: PinName::P0,
+                action: Some(PinAction::Set {
+                    state: PinState::High,
+                }),
+                dir: None,
+                level: None,
+                expert: false,
+            },
+        ];
+        for cmd in &commands {
+            assert!(matches!(
+                execute(cmd, &bus, &ctx),
+                Err(DongleError::Unsupported(message)) if message.contains("use 'relay'")
+            ));
+        }
+        assert_eq!(bus.writes(), vec![]);
+    }
+
+    #[test]
+    fn strict_state_accepts_power_off_from_the_default_state() {
+        let bus = bus(true);
+        // SLG_IO1 is released to the pull-up inside the SLG, CC not forced
+        bus.set(
+            Gpio0_7Input::ADDR,
+            Gpio0_7Input::new().with_gpio3_in(true).value(),
+        );
+        let dongle = dongle();
+        let ctx = Context {
+            strict: true,
+            ..ctx(&dongle)
+        };
+        execute_checked(&Commands::Off, &bus, &ctx).unwrap();
+        execute_checked(&Commands::EmergencyOff, &bus, &ctx).unwrap();
+        assert!(!is_dev_power_on(&bus).unwrap());
+    }
+
+    #[test]
+    fn failed_checks_return_an_exit_code_instead_of_exiting() {
+        let bus = bus(true);
+        let check = |expect| Commands::CheckRevision { expect };
+        let outcome = execute(&check(PcbRevision::RevC), &bus, &ctx(&dongle())).unwrap();
+        assert_eq!(outcome, Outcome::Done);
+        let outcome = execute(&check(PcbRevision::RevAorB), &bus, &ctx(&dongle())).unwrap();
+        assert_eq!(outcome, Outcome::Exit(1));
+
+        let detach = Commands::Detach {
+            force: true,
+            strict: false,
+            run: Some("exit 3".into()),
+            then_attach: true,
+        };
+        let outcome = execute(&detach, &bus, &ctx(&dongle())).unwrap();
+        assert_eq!(outcome, Outcome::Exit(3));
+        assert!(usb_switch_is_connected(&bus).unwrap());
+    }
+
+    #[test]
+    fn strict_detach_refuses_while_a_device_is_enumerated() {
+        let bus = bus(true);
+        let dongle = dongle();
+        // a device behind the dongle's hub (bus 1, port 1), next to the bridge on port 1.2
+        let ctx = Context {
+            devices: || {
+                Ok(vec![UsbDevice {
+                    bus_id: "1".into(),
+                    port_chain: vec![1, 3],
+                    vendor_id: 0x1234,
+                    product_id: 0x0001,
+                    ..UsbDevice::default()
+                }])
+            },
+            ..ctx(&dongle)
+        };
+        let detach = |strict| Commands::Detach {
+            force: false,
+            strict,
+            run: None,
+            then_attach: false,
+        };
+        execute(&full_attach(), &bus, &ctx).unwrap();
+        assert!(matches!(
+            execute(&detach(true), &bus, &ctx),
+            Err(DongleError::DeviceEnumerated { .. })
+        ));
+        assert!(usb_switch_is_connected(&bus).unwrap());
+        execute(&detach(false), &bus, &ctx).unwrap();
+        assert!(!usb_switch_is_connected(&bus).unwrap());
+    }
+
+    #[test]
+    fn explain_refuses_host_side_effects() {
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+        for line in ["lockout on", "detach --run true", "hub-info"] {
+            assert!(explain(&args(line), PcbRevision::RevC, false, None).is_err());
+        }
+        let outcome = explain(&args("full-detach"), PcbRevision::RevC, false, None).unwrap();
+        assert_eq!(outcome, Outcome::Done);
+    }
+
+    #[test]
+    fn reg_reset_without_expert_fails_without_writing() {
+        let bus = bus(true);
+        execute(&full_attach(), &bus, &ctx(&dongle())).unwrap();
+        let output = bus.get(Gpio0_7Output::ADDR);
+        let result = execute(&Commands::RegReset { expert: false }, &bus, &ctx(&dongle()));
+        assert!(matches!(result, Err(DongleError::ExpertRequired { .. })));
+        assert_eq!(bus.get(Gpio0_7Output::ADDR), output);
+    }
+
+    #[test]
+    fn command_requirements_name_existing_commands_and_match_the_help() {
+        let cli = Cli::command();
+        for (name, _) in COMMAND_REQUIREMENTS {
+            assert!(cli.find_subcommand(name).is_some(), "{name}");
+        }
+        for cmd in cli.get_subcommands() {
+            let about = cmd.get_about().map(|a| a.to_string()).unwrap_or_default();
+            let declared = COMMAND_REQUIREMENTS.iter().any(|(name, requirement)| {
+                *name == cmd.get_name() && *requirement == Requirement::RevC
+            });
+            assert_eq!(
+                about.contains("(PCB RevC and up)"),
+                declared,
+                "{}",
+                cmd.get_name()
+            );
+        }
+    }
+
+    #[test]
+    fn command_requirements_cover_every_hardware_check_in_execute() {
+        let source = include_str!("main.rs");
+        let start = source.find("\nfn execute(").unwrap();
+        let end = start + source[start..].find("\n}\n").unwrap();
+        let kebab = |name: &str| {
+            let mut out = String::new();
+            for (i, c) in name.chars().enumerate() {
+                if c.is_ascii_uppercase() && i > 0 {
+                    out.push('-');
+                }
+                out.push(c.to_ascii_lowercase());
+            }
+            out
+        };
+        // top level match arms of execute: the variants of the pattern and the lines of the body
+        let mut arms: Vec<(Vec<String>, Vec<&str>)> = Vec::new();
+        let mut in_pattern = false;
+        for line in source[start..end].lines() {
+            let is_pattern = line.starts_with("        Commands::")
+                || (in_pattern && line.starts_with("        | Commands::"));
+            if line.starts_with("        Commands::") {
+                arms.push((Vec::new(), Vec::new()));
+            }
+            let Some((variants, body)) = arms.last_mut() else {
+                continue;
+            };
+            if is_pattern || in_pattern {
+                let pattern = line.split("=>").next().unwrap();
+                for part in pattern.split("Commands::").skip(1) {
+                    let name = part
+                        .split(|c: char| !c.is_ascii_alphanumeric())
+                        .next()
+                        .unwrap();
+                    variants.push(kebab(name));
+                }
+                in_pattern = !line.contains("=>");
+            } else {
+                body.push(line.trim());
+            }
+        }
+        for (variants, body) in &arms {
+            let expected = if body.contains(&"return Err(DongleError::NotRelayVariant);") {
+                Requirement::Relay
+            } else if body.contains(&"if matches!(pcb_revision, PcbRevision::RevAorB) {") {
+                Requirement::RevC
+            } else {
+                continue;
+            };
+            for name in variants {
+                let declared = COMMAND_REQUIREMENTS
+                    .iter()
+                    .find(|(n, _)| n == name)
+                    .map(|(_, r)| *r);
+                assert_eq!(declared, Some(expected), "{name}");
+            }
+        }
+    }
+
+    #[test]
+    fn apply_claims_the_forced_sdp_line() {
+        let bus = bus(true);
+        let dongle = dongle();
+        let ctx = ctx(&dongle);
+        let path = std::env::temp_dir().join(format!("mchp_apply_{}.toml", std::process::id()));
+        std::fs::write(&path, "forcing_sdp = true").unwrap();
+        let apply = Commands::Apply {
+            path: path.clone(),
+            auto_config: true,
+        };
+        ctx.claims
+            .claim(&dongle, SlgPin::SlgIo0, SlgPurpose::Gpio)
+            .unwrap();
+        assert!(matches!(
+            execute(&apply, &bus, &ctx),
+            Err(DongleError::SlgPinClaimed { .. })
+        ));
+        ctx.claims
+            .release(&dongle, SlgPin::SlgIo0, SlgPurpose::Gpio);
+        execute(&apply, &bus, &ctx).unwrap();
+        assert_eq!(
+            ctx.claims.claimed(&dongle, SlgPin::SlgIo0),
+            Some(SlgPurpose::Feature)
+        );
+        std::fs::write(&path, "forcing_sdp = false").unwrap();
+        execute(&apply, &bus, &ctx).unwrap();
+        assert_eq!(ctx.claims.claimed(&dongle, SlgPin::SlgIo0), None);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failed_apply_exits_non_zero() {
+        let bus = bus(true);
+        let dongle = dongle();
+        let ctx = ctx(&dongle);
+        let path =
+            std::env::temp_dir().join(format!("mchp_apply_fail_{}.toml", std::process::id()));
+        let apply = Commands::Apply {
+            path: path.clone(),
+            auto_config: false,
+        };
+        assert_eq!(execute(&apply, &bus, &ctx).unwrap(), Outcome::Exit(2));
+        // P0 is an input, setting its level without --auto-config fails
+        std::fs::write(&path, "[p0]\nstate = \"high\"").unwrap();
+        assert_eq!(execute(&apply, &bus, &ctx).unwrap(), Outcome::Exit(2));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn emergency_off_latches_level_before_direction() {
+        let bus = bus(true);
+        execute(&Commands::EmergencyOff, &bus, &ctx(&dongle())).unwrap();
+        assert_eq!(
+            bus.writes(),
+            vec![
+                (Gpio0_7Output::ADDR, 0b0000_0001),
+                (Gpio0_7Dir::ADDR, 0b0000_0001)
+            ]
+        );
     }
 }
@@ -0,0 +1,102 @@
+//! Transition detection over periodically polled status reports.
+//!
+//! Instead of logging every poll, only the fields that changed since the previous report are emitted,
+//! each prefixed with an ISO-8601 UTC timestamp, e.g.:
+//! ```text
+//! 2026-10-15T08:30:12.345Z power_on: true -> false
+//! ```
+//! Timestamps are fixed width, so logs can be filtered by comparing them as strings.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fixture::Change;
+use crate::status::StatusReport;
+
+/// Remembers the last report and produces the changed fields for every new one.
+#[derive(Default)]
+pub struct TransitionDetector {
+    last: Option<StatusReport>,
+}
+
+impl TransitionDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the fields that differ from the previous report, all fields (from `?`) on the first call.
+    pub fn update(&mut self, report: StatusReport) -> Vec<Change> {
+        let old_fields = self.last.as_ref().map(|r| r.fields()).unwrap_or_default();
+        let changes = report
+            .fields()
+            .into_iter()
+            .filter_map(|(field, value)| {
+                let old_value = old_fields
+                    .iter()
+                    .find(|(f, _)| *f == field)
+                    .map(|(_, v)| v.clone());
+                if old_value.as_ref() == Some(&value) {
+                    return None;
+                }
+                Some(Change {
+                    field,
+                    from: old_value.unwrap_or("?".into()),
+                    to: value,
+                })
+            })
+            .collect();
+        self.last = Some(report);
+        changes
+    }
+}
+
+/// Formats `time` as an ISO-8601 UTC timestamp with millisecond precision.
+pub fn iso8601_utc(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Validates a `--since` argument, accepts any prefix of the timestamp format starting with a full date,
+/// e.g. `2026-10-15` or `2026-10-15T08:30`.
+pub fn parse_since(since: &str) -> Result<String, String> {
+    let template = "0000-00-00T00:00:00.000";
+    let trimmed = since.strip_suffix('Z').unwrap_or(since);
+    let valid = trimmed.len() >= 10
+        && trimmed.len() <= template.len()
+        && trimmed
+            .chars()
+            .zip(template.chars())
+            .all(|(c, t)| if t == '0' { c.is_ascii_digit() } else { c == t });
+    if valid {
+        Ok(trimmed.to_string())
+    } else {
+        Err(format!(
+            "invalid timestamp '{since}', expected UTC time like 2026-10-15T08:30:00Z"
+        ))
+    }
+}
+
+/// Returns true if a log line produced by the monitor happened at or after `since`.
+pub fn line_is_since(line: &str, since: &str) -> bool {
+    let timestamp = line.split_whitespace().next().unwrap_or("");
+    timestamp >= since
+}
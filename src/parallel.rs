@@ -0,0 +1,68 @@
+//! Bounded parallelism for commands touching several dongles (`--all`, `list --with-status`).
+//!
+//! Every dongle has its own USB interface, so dongles are handled on separate threads, at most `jobs` at a time.
+//! Results come back in input order and are printed by the caller, so output of different dongles never
+//! interleaves.
+
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Default for `--jobs`.
+pub const DEFAULT_JOBS: usize = 8;
+
+/// Runs `f` on every item on up to `jobs` worker threads, returning results in input order;
+/// `None` for items where `f` panicked.
+pub fn parallel_map<T: Sync, R: Send>(
+    items: &[T],
+    jobs: usize,
+    f: impl Fn(&T) -> R + Sync,
+) -> Vec<Option<R>> {
+    let next = AtomicUsize::new(0);
+    let results = items.iter().map(|_| Mutex::new(None)).collect::<Vec<_>>();
+    std::thread::scope(|s| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            s.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(i) else {
+                        break;
+                    };
+                    let result = catch_unwind(AssertUnwindSafe(|| f(item))).ok();
+                    *results[i].lock().unwrap() = result;
+                }
+            });
+        }
+    });
+    results
+        .into_iter()
+        .map(|r| r.into_inner().unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn results_keep_input_order_with_bounded_workers() {
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        let items = (0..16).collect::<Vec<u32>>();
+        let results = parallel_map(&items, 4, |&i| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(Duration::from_millis(u64::from(16 - i)));
+            running.fetch_sub(1, Ordering::SeqCst);
+            if i == 3 {
+                panic!("dongle 3 failed");
+            }
+            i * 2
+        });
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        assert_eq!(results[3], None);
+        assert_eq!(results[15], Some(30));
+        assert_eq!(results.iter().flatten().count(), 15);
+    }
+}
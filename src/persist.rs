@@ -0,0 +1,339 @@
+//! `--persist`: keep the dongle interface open in a background helper process, so scripted back-to-back
+//! commands do not pay the device open and interface claim cost every time.
+//!
+//! The first command run with `--persist` starts a helper (`mchp_gpio_ctl persist-helper`, with the same
+//! device selection arguments) that opens the dongle and listens on a Unix socket in the config directory.
+//! The command itself, and every later one with `--persist` and the same selection, runs in its own process
+//! as usual but forwards register reads and writes to the helper, so any command works unchanged. Clients are
+//! served one at a time, a command started while another one is connected fails with "helper busy".
+//!
+//! The helper exits after `--persist-idle-secs` without requests, or when stopped explicitly with
+//! `mchp_gpio_ctl [--serial ...] persist-stop`. Other tools can not open the dongle while the helper runs.
+//!
+//! Protocol, one JSON object per line: on connect the helper sends `{"dongle": {...}}` with the selected
+//! dongle, then answers every request line:
+//! ```text
+//! {"op":"read","addr":12296}            -> {"value":1}
+//! {"op":"write","addr":12296,"value":3} -> "done"
+//! {"op":"stop"}                         -> "done", then the helper exits
+//! ```
+//! Failed accesses are answered with `{"error":"message"}`.
+
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::config_dir;
+use crate::discovery::DongleInfo;
+use crate::error::DongleError;
+use crate::policy::Policy;
+use crate::usb4604_ral::RegisterBus;
+
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long a client waits for a response before giving up on the helper.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client waits for the hello after connecting. An idle helper sends it within
+/// [ACCEPT_POLL_INTERVAL], a connected socket without it means the helper is serving another client.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum BusRequest {
+    Read { addr: u16 },
+    Write { addr: u16, value: u8 },
+    Stop,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BusResponse {
+    Value(u8),
+    Done,
+    Error(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    dongle: DongleInfo,
+}
+
+/// Socket of the helper for one device selection, `key` is made of the selection arguments, e.g. `serial-A1B2`.
+pub fn socket_path(key: &str) -> Option<PathBuf> {
+    let key: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Some(config_dir()?.join(format!("persist-{key}.sock")))
+}
+
+fn write_line<T: Serialize>(stream: &mut impl Write, value: &T) -> io::Result<()> {
+    writeln!(stream, "{}", serde_json::to_string(value).unwrap())
+}
+
+fn read_line<T: for<'de> Deserialize<'de>>(reader: &mut impl BufRead) -> io::Result<Option<T>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    serde_json::from_str(&line)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Register bus forwarding every access to a running helper.
+pub struct PersistentBus {
+    reader: RefCell<BufReader<UnixStream>>,
+    writer: RefCell<UnixStream>,
+    dongle: DongleInfo,
+}
+
+/// Connects to the helper on `path` and reads its hello, fails with [io::ErrorKind::ResourceBusy] if it is
+/// serving another client.
+fn open(path: &Path) -> io::Result<(BufReader<UnixStream>, UnixStream, Hello)> {
+    let stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let hello = read_line(&mut reader).map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            "helper busy, it is serving another command",
+        ),
+        _ => e,
+    })?;
+    let hello = hello.ok_or(io::ErrorKind::UnexpectedEof)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    Ok((reader, stream, hello))
+}
+
+impl PersistentBus {
+    /// Connects to the helper listening on `path`, fails if there is none or it is busy, see [open].
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        let (reader, stream, hello) = open(path)?;
+        Ok(Self {
+            reader: RefCell::new(reader),
+            writer: RefCell::new(stream),
+            dongle: hello.dongle,
+        })
+    }
+
+    /// Retries [PersistentBus::connect] until `timeout`, for a helper that was just started.
+    pub fn connect_retry(path: &Path, timeout: Duration) -> io::Result<Self> {
+        let start = Instant::now();
+        loop {
+            match Self::connect(path) {
+                Err(e) if e.kind() != io::ErrorKind::ResourceBusy && start.elapsed() < timeout => {
+                    sleep(ACCEPT_POLL_INTERVAL)
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The dongle the helper has open.
+    pub fn dongle(&self) -> &DongleInfo {
+        &self.dongle
+    }
+
+    fn call(&self, request: &BusRequest) -> Result<BusResponse, DongleError> {
+        let to_error = |e: io::Error| DongleError::Persist(e.to_string());
+        write_line(&mut *self.writer.borrow_mut(), request).map_err(to_error)?;
+        read_line(&mut *self.reader.borrow_mut())
+            .map_err(to_error)?
+            .ok_or(DongleError::Persist("helper closed the connection".into()))
+    }
+}
+
+fn unexpected(response: BusResponse) -> DongleError {
+    match response {
+        BusResponse::Error(message) => DongleError::Persist(message),
+        other => DongleError::Persist(format!("unexpected response {other:?}")),
+    }
+}
+
+impl RegisterBus for PersistentBus {
+    fn read_byte(&self, addr: u16) -> Result<u8, DongleError> {
+        match self.call(&BusRequest::Read { addr })? {
+            BusResponse::Value(value) => Ok(value),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn write_byte(&self, addr: u16, value: u8) -> Result<(), DongleError> {
+        match self.call(&BusRequest::Write { addr, value })? {
+            BusResponse::Done => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+    /// The helper only forwards register accesses, wrap the bus in [crate::policy::PolicyBus] to drive the dongle
+    /// with its settings.
+    fn policy(&self) -> Policy {
+        Policy::default()
+    }
+}
+
+/// Asks the helper on `path` to exit.
+pub fn stop(path: &Path) -> io::Result<()> {
+    let (mut reader, mut stream, _) = open(path)?;
+    write_line(&mut stream, &BusRequest::Stop)?;
+    let _: Option<BusResponse> = read_line(&mut reader)?;
+    Ok(())
+}
+
+/// Serves one client until it disconnects or goes quiet for `idle`, returns true if it asked to stop.
+fn handle_client(
+    bus: &dyn RegisterBus,
+    dongle: &DongleInfo,
+    stream: UnixStream,
+    idle: Duration,
+) -> io::Result<bool> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(idle))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    write_line(
+        &mut writer,
+        &Hello {
+            dongle: dongle.clone(),
+        },
+    )?;
+    while let Some(request) = read_line::<BusRequest>(&mut reader)? {
+        let response = match request {
+            BusRequest::Read { addr } => bus
+                .read_byte(addr)
+                .map_or_else(|e| BusResponse::Error(e.to_string()), BusResponse::Value),
+            BusRequest::Write { addr, value } => bus
+                .write_byte(addr, value)
+                .map_or_else(|e| BusResponse::Error(e.to_string()), |_| BusResponse::Done),
+            BusRequest::Stop => {
+                write_line(&mut writer, &BusResponse::Done)?;
+                return Ok(true);
+            }
+        };
+        write_line(&mut writer, &response)?;
+    }
+    Ok(false)
+}
+
+/// Runs the helper: serves clients on `path` one at a time until stopped or idle for `idle`.
+pub fn serve(
+    bus: &dyn RegisterBus,
+    dongle: &DongleInfo,
+    path: &Path,
+    idle: Duration,
+) -> io::Result<()> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "another helper is already running",
+        ));
+    }
+    let _ = fs::remove_file(path);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    listener.set_nonblocking(true)?;
+    let mut last_activity = Instant::now();
+    let result = loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                match handle_client(bus, dongle, stream, idle) {
+                    Ok(true) => break Ok(()),
+                    Ok(false) => {}
+                    // client went away mid-request or stayed silent, keep serving others
+                    Err(e) => log::debug!("Persist client error: {e}"),
+                }
+                last_activity = Instant::now();
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if last_activity.elapsed() >= idle {
+                    break Ok(());
+                }
+                sleep(ACCEPT_POLL_INTERVAL);
+            }
+            Err(e) => break Err(e),
+        }
+    };
+    let _ = fs::remove_file(path);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::UsbDevice;
+    use crate::usb4604_ral::MockBus;
+
+    #[test]
+    fn accesses_are_forwarded_to_the_helper() {
+        let path = std::env::temp_dir().join(format!("mchp_persist_{}.sock", std::process::id()));
+        let dongle = DongleInfo {
+            bridge: UsbDevice {
+                serial_number: Some("BRIDGE1".into()),
+                ..UsbDevice::default()
+            },
+            ftdi: None,
+            hub: None,
+        };
+        let helper = {
+            let (path, dongle) = (path.clone(), dongle.clone());
+            std::thread::spawn(move || {
+                let bus = MockBus::new();
+                bus.set(0x3000, 0x42);
+                serve(&bus, &dongle, &path, Duration::from_secs(5)).unwrap();
+                bus.writes()
+            })
+        };
+
+        let client = PersistentBus::connect_retry(&path, Duration::from_secs(5)).unwrap();
+        assert_eq!(client.dongle().display_serial(), dongle.display_serial());
+        assert_eq!(client.read_byte(0x3000).unwrap(), 0x42);
+        client.write_byte(0x3001, 7).unwrap();
+        drop(client);
+
+        stop(&path).unwrap();
+        assert_eq!(helper.join().unwrap(), vec![(0x3001, 7)]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn second_client_is_told_the_helper_is_busy() {
+        let path =
+            std::env::temp_dir().join(format!("mchp_persist_busy_{}.sock", std::process::id()));
+        let dongle = DongleInfo {
+            bridge: UsbDevice::default(),
+            ftdi: None,
+            hub: None,
+        };
+        let helper = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                serve(&MockBus::new(), &dongle, &path, Duration::from_secs(5)).unwrap()
+            })
+        };
+
+        let first = PersistentBus::connect_retry(&path, Duration::from_secs(5)).unwrap();
+        let busy = PersistentBus::connect(&path).err().unwrap();
+        assert_eq!(busy.kind(), io::ErrorKind::ResourceBusy);
+        drop(first);
+
+        stop(&path).unwrap();
+        helper.join().unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! Mode, level and pull of every pin in one call, for generic tooling (UIs, snapshots) that should not need to
+//! know which getter serves which pin.
+//!
+//! Each bank's direction, output, input and pull registers are read once, so the result is a consistent
+//! snapshot: 10 reads on RevA/B, 15 on RevC, where the PIO17-20 bank is connected. Levels are electrical,
+//! without the inversion of active low signals (PWR_EN_N high means power off): the latched level for outputs,
+//! the pad level for inputs.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::dongle_hal_revb::PcbRevision;
+use crate::dongle_hal_revc::{PinMode, PinState, SlgPin};
+use crate::error::DongleError;
+use crate::pinmap::{PIN_MAP, PinInfo};
+use crate::slg::Pull;
+use crate::usb4604_ral::{
+    Gpio0_7Dir, Gpio0_7Input, Gpio0_7Output, Gpio0_7PullDown, Gpio0_7PullUp, Gpio8_10Dir,
+    Gpio8_10Input, Gpio8_10Output, Gpio8_10PullDown, Gpio8_10PullUp, Gpio17_20Dir, Gpio17_20Input,
+    Gpio17_20Output, Gpio17_20PullDown, Gpio17_20PullUp, RegisterBus, read_reg,
+};
+
+/// Every pin in [PIN_MAP], the serialized names are stable.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PinId {
+    /// PIO0 - PWR_EN_N
+    PwrEn,
+    /// PIO10 - PWR_FAIL_N
+    PwrFail,
+    /// PIO1 - USB_SWITCH_EN
+    UsbSwitch,
+    /// PIO19 - GPIO header 0
+    P0,
+    /// PIO20 - GPIO header 1
+    P1,
+    /// PIO8 - SLG_IO0
+    SlgIo0,
+    /// PIO3 - SLG_IO1
+    SlgIo1,
+    /// PIO9 - PCB revision strap
+    RevcStrap,
+}
+
+impl PinId {
+    pub const ALL: [PinId; 8] = [
+        PinId::PwrEn,
+        PinId::PwrFail,
+        PinId::UsbSwitch,
+        PinId::P0,
+        PinId::P1,
+        PinId::SlgIo0,
+        PinId::SlgIo1,
+        PinId::RevcStrap,
+    ];
+
+    /// Entry of the pin in [PIN_MAP].
+    pub fn info(self) -> &'static PinInfo {
+        let name = match self {
+            PinId::PwrEn => "PWR_EN_N",
+            PinId::PwrFail => "PWR_FAIL_N",
+            PinId::UsbSwitch => "USB_SWITCH_EN",
+            PinId::P0 => "P0",
+            PinId::P1 => "P1",
+            PinId::SlgIo0 => "SLG_IO0",
+            PinId::SlgIo1 => "SLG_IO1",
+            PinId::RevcStrap => "REVC_STRAP",
+        };
+        PIN_MAP
+            .iter()
+            .find(|p| p.name == name)
+            .expect("every pin id is in the pin map")
+    }
+
+    fn slg_pin(self) -> Option<SlgPin> {
+        match self {
+            PinId::SlgIo0 => Some(SlgPin::SlgIo0),
+            PinId::SlgIo1 => Some(SlgPin::SlgIo1),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PinSnapshot {
+    pub mode: PinMode,
+    pub state: PinState,
+    /// Pull acting on the pin as input: the hub's internal pull, for SLG IOs the fixed pull inside the GreenPAK
+    pub pull: Option<Pull>,
+}
+
+/// Register bits of one PIO: direction, output latch, input, pull-up and pull-down enable.
+type PioBits = (bool, bool, bool, bool, bool);
+
+/// Reads every pin connected on `revision`, see the module documentation.
+pub fn read_all_pins(
+    bus: &dyn RegisterBus,
+    revision: PcbRevision,
+) -> Result<BTreeMap<PinId, PinSnapshot>, DongleError> {
+    let dir0_7 = read_reg::<Gpio0_7Dir>(bus)?;
+    let out0_7 = read_reg::<Gpio0_7Output>(bus)?;
+    let in0_7 = read_reg::<Gpio0_7Input>(bus)?;
+    let pu0_7 = read_reg::<Gpio0_7PullUp>(bus)?;
+    let pd0_7 = read_reg::<Gpio0_7PullDown>(bus)?;
+    let dir8_10 = read_reg::<Gpio8_10Dir>(bus)?;
+    let out8_10 = read_reg::<Gpio8_10Output>(bus)?;
+    let in8_10 = read_reg::<Gpio8_10Input>(bus)?;
+    let pu8_10 = read_reg::<Gpio8_10PullUp>(bus)?;
+    let pd8_10 = read_reg::<Gpio8_10PullDown>(bus)?;
+    let bank17_20 = if revision == PcbRevision::RevC {
+        Some((
+            read_reg::<Gpio17_20Dir>(bus)?,
+            read_reg::<Gpio17_20Output>(bus)?,
+            read_reg::<Gpio17_20Input>(bus)?,
+            read_reg::<Gpio17_20PullUp>(bus)?,
+            read_reg::<Gpio17_20PullDown>(bus)?,
+        ))
+    } else {
+        None
+    };
+    let bits = |pio: u8| -> PioBits {
+        match (pio, &bank17_20) {
+            (0, _) => (
+                dir0_7.gpio0_out_en(),
+                out0_7.gpio0_out(),
+                in0_7.gpio0_in(),
+                pu0_7.gpio0_pu(),
+                pd0_7.gpio0_pd(),
+            ),
+            (1, _) => (
+                dir0_7.gpio1_out_en(),
+                out0_7.gpio1_out(),
+                in0_7.gpio1_in(),
+                pu0_7.gpio1_pu(),
+                pd0_7.gpio1_pd(),
+            ),
+            (3, _) => (
+                dir0_7.gpio3_out_en(),
+                out0_7.gpio3_out(),
+                in0_7.gpio3_in(),
+                pu0_7.gpio3_pu(),
+                pd0_7.gpio3_pd(),
+            ),
+            (8, _) => (
+                dir8_10.gpio8_out_en(),
+                out8_10.gpio8_out(),
+                in8_10.gpio8_in(),
+                pu8_10.gpio8_pu(),
+                pd8_10.gpio8_pd(),
+            ),
+            (9, _) => (
+                dir8_10.gpio9_out_en(),
+                out8_10.gpio9_out(),
+                in8_10.gpio9_in(),
+                pu8_10.gpio9_pu(),
+                pd8_10.gpio9_pd(),
+            ),
+            (10, _) => (
+                dir8_10.gpio10_out_en(),
+                out8_10.gpio10_out(),
+                in8_10.gpio10_in(),
+                pu8_10.gpio10_pu(),
+                pd8_10.gpio10_pd(),
+            ),
+            (19, Some((dir, out, input, pu, pd))) => (
+                dir.gpio19_out_en(),
+                out.gpio19_out(),
+                input.gpio19_in(),
+                pu.gpio19_pu(),
+                pd.gpio19_pd(),
+            ),
+            (20, Some((dir, out, input, pu, pd))) => (
+                dir.gpio20_out_en(),
+                out.gpio20_out(),
+                input.gpio20_in(),
+                pu.gpio20_pu(),
+                pd.gpio20_pd(),
+            ),
+            _ => unreachable!("PIO{pio} is not in the pin table for {revision}"),
+        }
+    };
+    Ok(PinId::ALL
+        .into_iter()
+        .filter(|id| revision == PcbRevision::RevC || !id.info().revc_only)
+        .map(|id| {
+            let (is_output, out, input, pull_up, pull_down) = bits(id.info().pio);
+            let is_high = if is_output { out } else { input };
+            let hub_pull = match (pull_up, pull_down) {
+                (true, _) => Some(Pull::Up),
+                (false, true) => Some(Pull::Down),
+                (false, false) => None,
+            };
+            let snapshot = PinSnapshot {
+                mode: if is_output {
+                    PinMode::Output
+                } else {
+                    PinMode::Input
+                },
+                state: if is_high {
+                    PinState::High
+                } else {
+                    PinState::Low
+                },
+                pull: id.slg_pin().map(SlgPin::pull).or(hub_pull),
+            };
+            (id, snapshot)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dongle_hal_revc::{HeaderPin, gpio_header_set, gpio_header_set_mode};
+    use crate::usb4604_ral::{MockBus, SmscReg};
+
+    #[test]
+    fn snapshot_covers_the_revision_pins_and_matches_the_getters() {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio8_10Input::ADDR,
+            Gpio8_10Input::new().with_gpio10_in(true).value(),
+        );
+        bus.set(
+            Gpio8_10PullUp::ADDR,
+            Gpio8_10PullUp::new().with_gpio10_pu(true).value(),
+        );
+        let pins = read_all_pins(&bus, PcbRevision::RevAorB).unwrap();
+        assert_eq!(
+            pins.keys().copied().collect::<Vec<_>>(),
+            [PinId::PwrEn, PinId::PwrFail, PinId::RevcStrap]
+        );
+        assert_eq!(
+            pins[&PinId::PwrFail],
+            PinSnapshot {
+                mode: PinMode::Input,
+                state: PinState::High,
+                pull: Some(Pull::Up)
+            }
+        );
+        assert!(bus.writes().is_empty());
+
+        gpio_header_set_mode(&bus, HeaderPin::P1, PinMode::Output).unwrap();
+        gpio_header_set(&bus, HeaderPin::P1, PinState::High).unwrap();
+        let pins = read_all_pins(&bus, PcbRevision::RevC).unwrap();
+        assert_eq!(pins.len(), PIN_MAP.len());
+        assert_eq!(pins[&PinId::P1].mode, PinMode::Output);
+        assert_eq!(pins[&PinId::P1].state, PinState::High);
+        assert_eq!(pins[&PinId::SlgIo1].pull, Some(Pull::Up));
+        assert_eq!(
+            serde_json::to_value(&pins).unwrap()["slg_io0"]["pull"],
+            "down"
+        );
+    }
+}
@@ -0,0 +1,453 @@
+//! Static model of the board wiring: logical signal, hub PIO and, for header pins, the connector label.
+//!
+//! Everything printing pins (`pinmap`, `dirmap`) is derived from [PIN_MAP], so adding a pin here is enough.
+//! The `pin` command addresses pins by [PinName] and dispatches to the header, SLG or raw power switch
+//! functions through [PinName::access].
+
+use std::fmt::Write;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::dongle_hal_revb::{PcbRevision, pwr_en_raw_get};
+use crate::dongle_hal_revc::{
+    HeaderPin, PinMode, PinState, SlgPin, gpio_header_get, gpio_header_get_mode, gpio_header_set,
+    gpio_header_set_mode, slg_io_get, slg_io_get_mode, slg_io_set, slg_io_set_mode,
+};
+use crate::error::DongleError;
+use crate::usb4604_ral::RegisterBus;
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PinGroup {
+    Power,
+    UsbSwitch,
+    Header,
+    Strap,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct PinInfo {
+    pub group: PinGroup,
+    /// Board signal name
+    pub name: &'static str,
+    pub pio: u8,
+    pub function: &'static str,
+    /// External connector label, for pins that are brought out
+    pub connector: Option<&'static str>,
+    /// Label printed next to the pin on the board, SLG IOs are the unmarked header positions 2 and 3
+    pub silkscreen: Option<&'static str>,
+    /// Only connected on PCB RevC and up
+    pub revc_only: bool,
+}
+
+const fn pin(
+    group: PinGroup,
+    name: &'static str,
+    pio: u8,
+    function: &'static str,
+    connector: Option<&'static str>,
+    silkscreen: Option<&'static str>,
+    revc_only: bool,
+) -> PinInfo {
+    PinInfo {
+        group,
+        name,
+        pio,
+        function,
+        connector,
+        silkscreen,
+        revc_only,
+    }
+}
+
+/// Every pin the crate knows, in display order.
+pub const PIN_MAP: &[PinInfo] = &[
+    pin(
+        PinGroup::Power,
+        "PWR_EN_N",
+        0,
+        "Device power switch enable, active low",
+        None,
+        None,
+        false,
+    ),
+    pin(
+        PinGroup::Power,
+        "PWR_FAIL_N",
+        10,
+        "Device power switch fault, active low",
+        None,
+        None,
+        false,
+    ),
+    pin(
+        PinGroup::UsbSwitch,
+        "USB_SWITCH_EN",
+        1,
+        "USB data lines switch, active low",
+        None,
+        None,
+        true,
+    ),
+    pin(
+        PinGroup::Header,
+        "P0",
+        19,
+        "GPIO, relay 1 on relay variants",
+        Some("GPIO header 0"),
+        Some("0"),
+        true,
+    ),
+    pin(
+        PinGroup::Header,
+        "P1",
+        20,
+        "GPIO, relay 2 on dual relay variants",
+        Some("GPIO header 1"),
+        Some("1"),
+        true,
+    ),
+    pin(
+        PinGroup::Header,
+        "SLG_IO0",
+        8,
+        "Force SDP via SLG, pulled down",
+        Some("GPIO header 2 (not marked)"),
+        Some("2"),
+        true,
+    ),
+    pin(
+        PinGroup::Header,
+        "SLG_IO1",
+        3,
+        "Force CC low via SLG, pulled up",
+        Some("GPIO header 3 (not marked)"),
+        Some("3"),
+        true,
+    ),
+    pin(
+        PinGroup::Strap,
+        "REVC_STRAP",
+        9,
+        "PCB revision strap, high on RevC",
+        None,
+        None,
+        false,
+    ),
+];
+
+/// Pins connected on the given board revision.
+pub fn pin_map(revision: PcbRevision) -> Vec<PinInfo> {
+    PIN_MAP
+        .iter()
+        .filter(|p| revision == PcbRevision::RevC || !p.revc_only)
+        .copied()
+        .collect()
+}
+
+/// Pins addressable by name from the `pin` command.
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum)]
+pub enum PinName {
+    /// PIO0 - PWR_EN_N, power switch enable, active low; raw access only
+    PwrEn,
+    /// PIO19 - GPIO header 0
+    P0,
+    /// PIO20 - GPIO header 1
+    P1,
+    /// PIO8 - SLG_IO0, force SDP
+    Io0,
+    /// PIO3 - SLG_IO1, force CC low
+    Io1,
+}
+
+/// HAL functions a [PinName] is accessed through.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PinAccess {
+    /// Raw electrical access bypassing inversion, see [crate::dongle_hal_revb::pwr_en_raw_set]
+    Raw,
+    Header(HeaderPin),
+    Slg(SlgPin),
+}
+
+impl PinName {
+    pub fn access(self) -> PinAccess {
+        match self {
+            PinName::PwrEn => PinAccess::Raw,
+            PinName::P0 => PinAccess::Header(HeaderPin::P0),
+            PinName::P1 => PinAccess::Header(HeaderPin::P1),
+            PinName::Io0 => PinAccess::Slg(SlgPin::SlgIo0),
+            PinName::Io1 => PinAccess::Slg(SlgPin::SlgIo1),
+        }
+    }
+
+    /// Label next to the pin on the board, if any.
+    pub fn silkscreen(self) -> Option<&'static str> {
+        self.info().silkscreen
+    }
+
+    /// Entry of the pin in [PIN_MAP].
+    pub fn info(self) -> &'static PinInfo {
+        let name = match self {
+            PinName::PwrEn => "PWR_EN_N",
+            PinName::P0 => "P0",
+            PinName::P1 => "P1",
+            PinName::Io0 => "SLG_IO0",
+            PinName::Io1 => "SLG_IO1",
+        };
+        PIN_MAP
+            .iter()
+            .find(|p| p.name == name)
+            .expect("every pin name is in the pin map")
+    }
+}
+
+impl From<HeaderPin> for PinName {
+    fn from(pin: HeaderPin) -> Self {
+        match pin {
+            HeaderPin::P0 => PinName::P0,
+            HeaderPin::P1 => PinName::P1,
+        }
+    }
+}
+
+impl From<SlgPin> for PinName {
+    fn from(pin: SlgPin) -> Self {
+        match pin {
+            SlgPin::SlgIo0 => PinName::Io0,
+            SlgPin::SlgIo1 => PinName::Io1,
+        }
+    }
+}
+
+/// Reads pin mode and state, the state is the driven level for outputs.
+pub fn pin_get(bus: &dyn RegisterBus, pin: PinName) -> Result<(PinMode, PinState), DongleError> {
+    match pin.access() {
+        PinAccess::Raw => pwr_en_raw_get(bus),
+        PinAccess::Header(pin) => Ok((gpio_header_get_mode(bus, pin)?, gpio_header_get(bus, pin)?)),
+        PinAccess::Slg(pin) => Ok((slg_io_get_mode(bus, pin)?, slg_io_get(bus, pin)?)),
+    }
+}
+
+fn raw_only() -> DongleError {
+    DongleError::Unsupported(
+        "pwr-en is only accessible raw: pin pwr-en --dir/--level --expert, or use on / off".into(),
+    )
+}
+
+/// Sets the level of a pin configured as output.
+pub fn pin_set(bus: &dyn RegisterBus, pin: PinName, state: PinState) -> Result<(), DongleError> {
+    match pin.access() {
+        PinAccess::Raw => Err(raw_only()),
+        PinAccess::Header(pin) => gpio_header_set(bus, pin, state),
+        PinAccess::Slg(pin) => slg_io_set(bus, pin, state),
+    }
+}
+
+/// Sets the direction of a pin, returns the warning of [slg_io_set_mode] when releasing an SLG IO changes its level.
+pub fn pin_config(
+    bus: &dyn RegisterBus,
+    pin: PinName,
+    mode: PinMode,
+) -> Result<Option<String>, DongleError> {
+    match pin.access() {
+        PinAccess::Raw => Err(raw_only()),
+        PinAccess::Header(pin) => gpio_header_set_mode(bus, pin, mode).map(|_| None),
+        PinAccess::Slg(pin) => slg_io_set_mode(bus, pin, mode),
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Default, ValueEnum)]
+pub enum PinMapFormat {
+    /// Signal, PIO, connector, silkscreen label and function columns
+    #[default]
+    Table,
+    Json,
+    /// Graphviz graph from signals to PIOs to connector labels, render with `dot -Tsvg`
+    Dot,
+}
+
+pub fn to_table(pins: &[PinInfo]) -> String {
+    let mut out = format!(
+        "{:<14} {:<6} {:<28} {:<6} FUNCTION\n",
+        "SIGNAL", "PIO", "CONNECTOR", "LABEL"
+    );
+    for p in pins {
+        let pio = format!("PIO{}", p.pio);
+        let _ = writeln!(
+            out,
+            "{:<14} {pio:<6} {:<28} {:<6} {}",
+            p.name,
+            p.connector.unwrap_or("-"),
+            p.silkscreen.unwrap_or("-"),
+            p.function
+        );
+    }
+    out
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Graphviz DOT graph: signal (with its function) -> PIO -> connector label.
+pub fn to_dot(pins: &[PinInfo]) -> String {
+    let mut out = String::from("digraph pinmap {\n    rankdir=LR;\n    node [shape=box];\n");
+    for p in pins {
+        let _ = writeln!(
+            out,
+            "    \"{}\" [label=\"{}\\n{}\"];",
+            p.name,
+            p.name,
+            escape_dot(p.function)
+        );
+        let _ = writeln!(out, "    \"{}\" -> \"PIO{}\";", p.name, p.pio);
+        if let Some(connector) = p.connector {
+            let _ = writeln!(
+                out,
+                "    \"PIO{}\" -> \"{}\" [style=dashed];",
+                p.pio,
+                escape_dot(connector)
+            );
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dongle_hal_revb::pwr_en_raw_set;
+    use crate::dongle_hal_revc::{usb_switch_configure, usb_switch_is_connected, usb_switch_set};
+    use crate::usb4604_ral::{MockBus, REGISTERS};
+    use proptest::prelude::*;
+
+    #[test]
+    fn dot_links_signals_to_pios_and_connectors() {
+        let dot = to_dot(&pin_map(PcbRevision::RevC));
+        assert!(dot.starts_with("digraph pinmap {"));
+        assert!(dot.contains("\"P0\" -> \"PIO19\";"));
+        assert!(dot.contains("\"PIO19\" -> \"GPIO header 0\" [style=dashed];"));
+        assert!(!to_dot(&pin_map(PcbRevision::RevAorB)).contains("GPIO header"));
+    }
+
+    #[test]
+    fn pin_names_dispatch_by_registry() {
+        let bus = MockBus::new();
+        for pin in PinName::value_variants() {
+            assert!(pin.info().pio <= 20);
+        }
+        pin_config(&bus, PinName::Io1, PinMode::Output).unwrap();
+        pin_set(&bus, PinName::Io1, PinState::High).unwrap();
+        assert_eq!(
+            pin_get(&bus, PinName::Io1).unwrap(),
+            (PinMode::Output, PinState::High)
+        );
+        assert_eq!(
+            pin_get(&bus, PinName::Io0).unwrap(),
+            (PinMode::Input, PinState::Low)
+        );
+        assert!(pin_set(&bus, PinName::PwrEn, PinState::High).is_err());
+    }
+
+    #[test]
+    fn header_and_slg_pins_have_silkscreen_labels() {
+        assert_eq!(PinName::from(HeaderPin::P1).silkscreen(), Some("1"));
+        assert_eq!(PinName::from(SlgPin::SlgIo1).silkscreen(), Some("3"));
+        assert_eq!(PinName::PwrEn.silkscreen(), None);
+        assert!(to_table(&pin_map(PcbRevision::RevC)).contains("GPIO header 2 (not marked)   2 "));
+    }
+
+    /// Every pin with a setter, the USB switch is output only.
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    enum AnyPin {
+        Named(PinName),
+        UsbSwitch,
+    }
+
+    fn any_pin() -> impl Strategy<Value = AnyPin> {
+        prop_oneof![
+            proptest::sample::select(PinName::value_variants()).prop_map(AnyPin::Named),
+            Just(AnyPin::UsbSwitch),
+        ]
+    }
+
+    fn any_mode() -> impl Strategy<Value = PinMode> {
+        prop_oneof![Just(PinMode::Input), Just(PinMode::Output)]
+    }
+
+    fn any_state() -> impl Strategy<Value = PinState> {
+        prop_oneof![Just(PinState::Low), Just(PinState::High)]
+    }
+
+    fn set(bus: &dyn RegisterBus, pin: AnyPin, mode: PinMode, state: PinState) {
+        match pin {
+            AnyPin::Named(PinName::PwrEn) => pwr_en_raw_set(bus, Some(mode), Some(state)).unwrap(),
+            AnyPin::Named(name) => {
+                pin_config(bus, name, mode).unwrap();
+                if mode == PinMode::Output {
+                    pin_set(bus, name, state).unwrap();
+                }
+            }
+            AnyPin::UsbSwitch => {
+                usb_switch_configure(bus).unwrap();
+                usb_switch_set(bus, state == PinState::High).unwrap();
+            }
+        }
+    }
+
+    fn get(bus: &dyn RegisterBus, pin: AnyPin) -> (PinMode, PinState) {
+        match pin {
+            AnyPin::Named(name) => pin_get(bus, name).unwrap(),
+            AnyPin::UsbSwitch => {
+                let connected = usb_switch_is_connected(bus).unwrap();
+                let state = if connected {
+                    PinState::High
+                } else {
+                    PinState::Low
+                };
+                (PinMode::Output, state)
+            }
+        }
+    }
+
+    fn all_pins() -> Vec<AnyPin> {
+        let mut pins: Vec<_> = PinName::value_variants()
+            .iter()
+            .map(|&p| AnyPin::Named(p))
+            .collect();
+        pins.push(AnyPin::UsbSwitch);
+        pins
+    }
+
+    proptest! {
+        /// Catches copy-paste errors in per-pin register bit accessors: every setter must round-trip through
+        /// its getter and leave all other pins alone, starting from arbitrary register contents.
+        #[test]
+        fn pin_set_get_round_trips_without_disturbing_other_pins(
+            initial in proptest::collection::vec(any::<u8>(), REGISTERS.len()),
+            ops in proptest::collection::vec((any_pin(), any_mode(), any_state()), 1..16),
+        ) {
+            let bus = MockBus::new();
+            for (&(_, addr), value) in REGISTERS.iter().zip(initial) {
+                bus.set(addr, value);
+            }
+            for (pin, mode, state) in ops {
+                let others: Vec<_> = all_pins().into_iter().filter(|&p| p != pin).collect();
+                let before: Vec<_> = others.iter().map(|&p| get(&bus, p)).collect();
+                set(&bus, pin, mode, state);
+
+                let (actual_mode, actual_state) = get(&bus, pin);
+                if pin != AnyPin::UsbSwitch {
+                    prop_assert_eq!(actual_mode, mode, "{:?}", pin);
+                }
+                if mode == PinMode::Output || pin == AnyPin::Named(PinName::PwrEn) || pin == AnyPin::UsbSwitch {
+                    prop_assert_eq!(actual_state, state, "{:?}", pin);
+                }
+                let after: Vec<_> = others.iter().map(|&p| get(&bus, p)).collect();
+                prop_assert_eq!(before, after, "setting {:?} disturbed other pins", pin);
+            }
+        }
+    }
+}
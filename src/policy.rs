@@ -0,0 +1,47 @@
+//! How the HAL drives one dongle: enable polarity, power gate and power / USB switch sequencing.
+//!
+//! The policy is carried by the register bus ([RegisterBus::policy]) instead of being set for the whole
+//! process, so every dongle is driven with its own settings, also several of them in one process (`--all`).
+//! Bus wrappers forward the policy of the bus they wrap, [PolicyBus] replaces it.
+
+use crate::dongle_hal_revb::PowerPath;
+use crate::error::DongleError;
+use crate::signals::Polarity;
+use crate::usb4604_ral::RegisterBus;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Policy {
+    /// USB switch and power enable polarity
+    pub polarity: Polarity,
+    /// Gate switched by [crate::dongle_hal_revb::dev_power_ctl] and read by
+    /// [crate::dongle_hal_revb::is_dev_power_on]
+    pub power_path: PowerPath,
+    /// Never leave USB data connected with power off, see [crate::sequencing]
+    pub enforce_sequencing: bool,
+}
+
+/// Forwards register accesses to another bus, driving the dongle with `policy`.
+pub struct PolicyBus<'a> {
+    inner: &'a dyn RegisterBus,
+    policy: Policy,
+}
+
+impl<'a> PolicyBus<'a> {
+    pub fn new(inner: &'a dyn RegisterBus, policy: Policy) -> Self {
+        Self { inner, policy }
+    }
+}
+
+impl RegisterBus for PolicyBus<'_> {
+    fn read_byte(&self, addr: u16) -> Result<u8, DongleError> {
+        self.inner.read_byte(addr)
+    }
+
+    fn write_byte(&self, addr: u16, value: u8) -> Result<(), DongleError> {
+        self.inner.write_byte(addr, value)
+    }
+
+    fn policy(&self) -> Policy {
+        self.policy
+    }
+}
@@ -0,0 +1,105 @@
+//! `port-diag`: negotiated USB speed and port of the device behind the dongle, next to the dongle's
+//! electrical state (power, USB switch, SDP, CC), to correlate a wrong enumeration speed with how the
+//! dongle was configured.
+//!
+//! Only reads are done, same as `status --read-only`.
+
+use nusb::{MaybeFuture, Speed};
+use serde::Serialize;
+
+use crate::discovery::{DongleInfo, UsbDevice};
+use crate::error::DongleError;
+use crate::status::read_only_status_report;
+use crate::usb4604_ral::RegisterBus;
+
+/// Stable lowercase name of a USB speed.
+pub fn speed_name(speed: Speed) -> &'static str {
+    match speed {
+        Speed::Low => "low",
+        Speed::Full => "full",
+        Speed::High => "high",
+        Speed::Super => "super",
+        Speed::SuperPlus => "super_plus",
+        _ => "unknown",
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct EnumeratedDevice {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub product: Option<String>,
+    /// USB location, e.g. `1-2.3`
+    pub location: String,
+    /// Port of the dongle's hub the device is connected to
+    pub port: Option<u8>,
+    /// `None` if the OS does not report it
+    pub speed: Option<&'static str>,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct PortDiagnostics {
+    /// Speed of the dongle's own bridge device, the upstream link limits downstream speeds
+    pub bridge_speed: Option<&'static str>,
+    /// Devices enumerated behind the dongle, empty if none
+    pub devices: Vec<EnumeratedDevice>,
+    pub power_on: bool,
+    /// `None` if PIO10 is not configured as input
+    pub power_fault: Option<bool>,
+    /// RevC and up only, `None` on older boards
+    pub usb_switch_connected: Option<bool>,
+    pub forcing_sdp: Option<bool>,
+    pub forcing_cc_low: Option<bool>,
+}
+
+/// Lists the devices behind `dongle` with their speed and reads the dongle state, without any writes.
+pub fn port_diagnostics(
+    bus: &dyn RegisterBus,
+    dongle: &DongleInfo,
+) -> Result<PortDiagnostics, DongleError> {
+    let all_devices = nusb::list_devices()
+        .wait()
+        .map_err(DongleError::Usb)?
+        .collect::<Vec<_>>();
+    let usb_devices = all_devices.iter().map(UsbDevice::from).collect::<Vec<_>>();
+    let speed_of = |device: &UsbDevice| {
+        all_devices
+            .iter()
+            .find(|d| d.bus_id() == device.bus_id && d.port_chain() == device.port_chain)
+            .and_then(|d| d.speed())
+            .map(speed_name)
+    };
+    let devices = dongle
+        .downstream_devices(&usb_devices)
+        .into_iter()
+        .map(|d| EnumeratedDevice {
+            vendor_id: d.vendor_id,
+            product_id: d.product_id,
+            product: d.product_string.clone(),
+            location: d.location(),
+            port: d.port_chain.last().copied(),
+            speed: speed_of(d),
+        })
+        .collect();
+    let report = read_only_status_report(bus, dongle)?;
+    Ok(PortDiagnostics {
+        bridge_speed: speed_of(&dongle.bridge),
+        devices,
+        power_on: report.power_on,
+        power_fault: report.power_fault,
+        usb_switch_connected: report.usb_switch_connected,
+        forcing_sdp: report.forcing_sdp,
+        forcing_cc_low: report.forcing_cc_low,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_names_are_stable() {
+        assert_eq!(speed_name(Speed::High), "high");
+        assert_eq!(speed_name(Speed::SuperPlus), "super_plus");
+    }
+}
@@ -0,0 +1,74 @@
+//! Read-only access for observers (`status --read-only`, `monitor --read-only`, `verify`) running next to a
+//! process controlling the same dongle: register writes are refused, so the observer can never race the
+//! controller's read-modify-write cycles.
+//!
+//! The getters used by observers only read and assume pin directions are already configured:
+//! - power fault: [crate::dongle_hal_revb::read_dev_pwr_fault] instead of `is_dev_pwr_fault`, unknown while PIO10
+//!   is an output (it is an input after reset and this tool never changes that)
+//! - power: [crate::dongle_hal_revb::PowerState::Unknown] while PIO0 was not configured as output yet
+//! - PCB revision (PIO9): read from the input register, the pin is never an output
+//! - USB switch, SDP, CC and header pins: the latched level for outputs, the pad level for inputs
+//!
+//! Anything still trying to write fails with [DongleError::ReadOnly] instead of reaching the dongle.
+
+use crate::error::DongleError;
+use crate::policy::Policy;
+use crate::usb4604_ral::RegisterBus;
+
+/// Forwards reads to another bus and refuses all writes.
+pub struct ReadOnlyBus<'a> {
+    inner: &'a dyn RegisterBus,
+}
+
+impl<'a> ReadOnlyBus<'a> {
+    pub fn new(inner: &'a dyn RegisterBus) -> Self {
+        Self { inner }
+    }
+}
+
+impl RegisterBus for ReadOnlyBus<'_> {
+    fn read_byte(&self, addr: u16) -> Result<u8, DongleError> {
+        self.inner.read_byte(addr)
+    }
+
+    fn write_byte(&self, addr: u16, _value: u8) -> Result<(), DongleError> {
+        Err(DongleError::ReadOnly { addr })
+    }
+
+    fn policy(&self) -> Policy {
+        self.inner.policy()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::{DongleInfo, UsbDevice};
+    use crate::status::{read_only_status_report, status_report};
+    use crate::usb4604_ral::{Gpio8_10Dir, MockBus, SmscReg};
+
+    #[test]
+    fn observer_report_reads_only_and_fault_is_unknown_while_pio10_is_output() {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio8_10Dir::ADDR,
+            Gpio8_10Dir::new().with_gpio10_out_en(true).value(),
+        );
+        let dongle = DongleInfo {
+            bridge: UsbDevice::default(),
+            ftdi: None,
+            hub: None,
+        };
+        let read_only = ReadOnlyBus::new(&bus);
+
+        let report = read_only_status_report(&read_only, &dongle).unwrap();
+        assert_eq!(report.power_fault, None);
+        assert!(matches!(
+            status_report(&read_only, &dongle),
+            Err(DongleError::ReadOnly {
+                addr: Gpio8_10Dir::ADDR
+            })
+        ));
+        assert!(bus.writes().is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+//! Minimum time between two switchings of the same relay, so scripts calling `relay close` / `relay open` in a
+//! tight loop can not chatter the SSR.
+//!
+//! Every command runs in its own process, so the time of the last switching is kept per dongle and relay in
+//! `relay.toml` in the config directory. A command switching a relay too early waits for the rest of the
+//! dwell. Switchings made over `serve` or by other tools are not seen, `gpio-set`, `gpio-hold`, `pin set` and
+//! `max-toggle` refuse the relay pins.
+//!
+//! The file is locked while it is read and rewritten, so commands switching relays of several dongles in
+//! parallel do not drop each other's switchings.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigError, config_dir};
+use crate::discovery::DongleInfo;
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+struct RelayLog {
+    /// Milliseconds since the Unix epoch, keyed by `<file_id>/<relay index>`, see [DongleInfo::file_id]
+    #[serde(default)]
+    last_switch: BTreeMap<String, u64>,
+}
+
+/// File the last switchings are kept in.
+pub struct DwellStore {
+    path: Option<PathBuf>,
+}
+
+/// Reads the log from `file`, which is locked by the caller.
+fn read_log(mut file: &File) -> Result<RelayLog, ConfigError> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)
+        .map_err(ConfigError::Io)?;
+    toml::from_str(&contents).map_err(|e| ConfigError::Parse(e.to_string()))
+}
+
+fn key(info: &DongleInfo, index: u8) -> String {
+    format!("{}/{index}", info.file_id())
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Part of `min_dwell` still to wait after a switching at `last_ms`, zero if the clock went backwards.
+fn remaining_after(last_ms: Option<u64>, now_ms: u64, min_dwell: Duration) -> Duration {
+    match last_ms {
+        Some(last_ms) if last_ms <= now_ms => {
+            min_dwell.saturating_sub(Duration::from_millis(now_ms - last_ms))
+        }
+        _ => Duration::ZERO,
+    }
+}
+
+impl DwellStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+        }
+    }
+
+    /// `relay.toml` in the config directory, shared by all processes of the user.
+    pub fn config() -> Self {
+        Self {
+            path: config_dir().map(|dir| dir.join("relay.toml")),
+        }
+    }
+
+    fn path(&self) -> Result<&PathBuf, ConfigError> {
+        self.path.as_ref().ok_or(ConfigError::NoConfigDir)
+    }
+
+    /// How long relay `index` of `info` has to stay as it is to honor `min_dwell`.
+    pub fn remaining(
+        &self,
+        info: &DongleInfo,
+        index: u8,
+        min_dwell: Duration,
+    ) -> Result<Duration, ConfigError> {
+        let file = match File::open(self.path()?) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Duration::ZERO),
+            Err(e) => return Err(ConfigError::Io(e)),
+        };
+        file.lock_shared().map_err(ConfigError::Io)?;
+        let last = read_log(&file)?.last_switch.get(&key(info, index)).copied();
+        Ok(remaining_after(last, now_ms(), min_dwell))
+    }
+
+    /// Records that relay `index` of `info` switched now.
+    pub fn record(&self, info: &DongleInfo, index: u8) -> Result<(), ConfigError> {
+        let path = self.path()?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(ConfigError::Io)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(ConfigError::Io)?;
+        // held until the file is closed, over the whole read-modify-write
+        file.lock().map_err(ConfigError::Io)?;
+        let mut log = read_log(&file)?;
+        log.last_switch.insert(key(info, index), now_ms());
+        let contents = toml::to_string(&log).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        file.set_len(0).map_err(ConfigError::Io)?;
+        file.rewind().map_err(ConfigError::Io)?;
+        file.write_all(contents.as_bytes()).map_err(ConfigError::Io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_rest_of_the_dwell_is_waited() {
+        let dwell = Duration::from_millis(100);
+        assert_eq!(remaining_after(None, 1_000, dwell), Duration::ZERO);
+        assert_eq!(
+            remaining_after(Some(960), 1_000, dwell),
+            Duration::from_millis(60)
+        );
+        assert_eq!(remaining_after(Some(800), 1_000, dwell), Duration::ZERO);
+        assert_eq!(remaining_after(Some(2_000), 1_000, dwell), Duration::ZERO);
+    }
+
+    #[test]
+    fn parallel_records_are_all_kept() {
+        let path = std::env::temp_dir().join(format!("mchp_relay_{}.toml", std::process::id()));
+        let info = |serial: usize| DongleInfo {
+            bridge: crate::discovery::UsbDevice {
+                serial_number: Some(format!("RELAY{serial}")),
+                ..Default::default()
+            },
+            ftdi: None,
+            hub: None,
+        };
+        std::thread::scope(|scope| {
+            for serial in 0..8 {
+                let store = DwellStore::new(&path);
+                scope.spawn(move || store.record(&info(serial), 1).unwrap());
+            }
+        });
+        let store = DwellStore::new(&path);
+        for serial in 0..8 {
+            let remaining = store
+                .remaining(&info(serial), 1, Duration::from_secs(60))
+                .unwrap();
+            assert!(!remaining.is_zero());
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! Best-effort restore of the dongle to a safe state (USB switch connected, SDP released)
+//! when a command panics halfway through a multi-step sequence.
+//!
+//! This is best effort only: if the panic was caused by the device going away, restoring will fail
+//! as well, failures are logged to stderr and otherwise ignored.
+
+use std::thread;
+
+use crate::dongle_hal_revb::{PcbRevision, pcb_revision};
+use crate::dongle_hal_revc::{
+    PinMode, PinState, SlgPin, slg_io_set, slg_io_set_mode, usb_switch_configure, usb_switch_set,
+};
+use crate::error::DongleError;
+use crate::usb4604_ral::RegisterBus;
+
+/// Connects the USB switch and releases SDP, on PCB RevC and up (nothing to do on older boards).
+pub fn restore_safe_state(bus: &dyn RegisterBus) -> Vec<(&'static str, Result<(), DongleError>)> {
+    match pcb_revision(bus) {
+        Ok(PcbRevision::RevC) => {}
+        Ok(PcbRevision::RevAorB) => return Vec::new(),
+        Err(e) => return vec![("read PCB revision", Err(e))],
+    }
+    vec![
+        (
+            "connect USB switch",
+            usb_switch_set(bus, true).and_then(|_| usb_switch_configure(bus)),
+        ),
+        (
+            "release SDP",
+            slg_io_set_mode(bus, SlgPin::SlgIo0, PinMode::Output)
+                .and_then(|_| slg_io_set(bus, SlgPin::SlgIo0, PinState::Low)),
+        ),
+    ]
+}
+
+/// Calls [restore_safe_state] when dropped during a panic, keep it alive for the duration of a command.
+pub struct PanicGuard<'a> {
+    bus: &'a dyn RegisterBus,
+}
+
+impl<'a> PanicGuard<'a> {
+    pub fn new(bus: &'a dyn RegisterBus) -> Self {
+        PanicGuard { bus }
+    }
+}
+
+impl Drop for PanicGuard<'_> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+        eprintln!("Command panicked, restoring safe state (best effort)");
+        for (action, result) in restore_safe_state(self.bus) {
+            match result {
+                Ok(()) => eprintln!("  {action}: ok"),
+                Err(e) => eprintln!("  {action}: failed: {e}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    use super::*;
+    use crate::dongle_hal_revc::{slg_io_get, usb_switch_is_connected};
+    use crate::usb4604_ral::{Gpio8_10Input, MockBus, SmscReg};
+
+    #[test]
+    fn guard_restores_only_on_panic() {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio8_10Input::ADDR,
+            Gpio8_10Input::new().with_gpio9_in(true).value(),
+        );
+        usb_switch_configure(&bus).unwrap();
+        usb_switch_set(&bus, false).unwrap();
+
+        drop(PanicGuard::new(&bus));
+        assert!(!usb_switch_is_connected(&bus).unwrap());
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            let _guard = PanicGuard::new(&bus);
+            slg_io_set_mode(&bus, SlgPin::SlgIo0, PinMode::Output).unwrap();
+            slg_io_set(&bus, SlgPin::SlgIo0, PinState::High).unwrap();
+            panic!("flap interrupted");
+        }));
+        assert!(result.is_err());
+        assert!(usb_switch_is_connected(&bus).unwrap());
+        assert_eq!(slg_io_get(&bus, SlgPin::SlgIo0).unwrap(), PinState::Low);
+    }
+}
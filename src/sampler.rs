@@ -0,0 +1,260 @@
+//! Periodic sampling of a header input pin, a poor man's logic analyzer for slow signals,
+//! and measurement of the fastest achievable output toggle rate.
+//!
+//! Every sample or edge is a separate control transfer, so the achievable rate is bounded by USB latency
+//! (typically around 1 kHz at best, less on busy hubs). The actual rate is reported in [SampleStats]
+//! and [ToggleStats].
+//!
+//! The USB4604 has no GPIO change notification: GPIOs are only accessible through register reads over vendor
+//! control requests (AN1940), and the hub's interrupt endpoint only reports port status changes. [watch_pin]
+//! therefore polls back to back, which is the best resolution available: one transfer per sample, so edges
+//! are timestamped to within about 1ms on an idle bus and pulses shorter than that can be missed entirely.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::dongle_hal_revc::{
+    HeaderPin, PinMode, PinState, gpio_header_get, gpio_header_get_mode, gpio_header_set_mode,
+};
+use crate::error::DongleError;
+use crate::usb4604_ral::{
+    Gpio17_20Input, Gpio17_20Output, RegisterBus, SmscReg, read_reg, write_reg,
+};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Sample {
+    /// Monotonic time since sampling started
+    pub elapsed: Duration,
+    pub state: PinState,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct SampleStats {
+    pub samples: u64,
+    pub elapsed: Duration,
+}
+
+impl SampleStats {
+    /// Samples per second that were actually achieved.
+    pub fn achieved_hz(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.samples as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Samples `pin` at `rate_hz` for `duration`, passing every sample to `sink`.
+///
+/// If reads take longer than the sample period, sampling continues as fast as possible without
+/// trying to catch up on missed samples.
+pub fn sample_pin(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    rate_hz: f64,
+    duration: Duration,
+    mut sink: impl FnMut(Sample),
+) -> Result<SampleStats, DongleError> {
+    let period = Duration::from_secs_f64(1.0 / rate_hz);
+    let start = Instant::now();
+    let mut next = start;
+    let mut samples = 0;
+    while next.duration_since(start) < duration {
+        let now = Instant::now();
+        if next > now {
+            sleep(next - now);
+        } else {
+            next = now;
+        }
+        let state = gpio_header_get(bus, pin)?;
+        sink(Sample {
+            elapsed: start.elapsed(),
+            state,
+        });
+        samples += 1;
+        next += period;
+    }
+    Ok(SampleStats {
+        samples,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Reports every level change of input `pin` to `on_edge` for `duration`, the first sample always counts as a
+/// change; returns how many samples were taken.
+///
+/// Reads the input register back to back without a fixed rate, one transfer per sample (the pin mode is not
+/// re-read as in [sample_pin]). See the module documentation for the achievable resolution.
+pub fn watch_pin(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    duration: Duration,
+    mut on_edge: impl FnMut(Sample),
+) -> Result<SampleStats, DongleError> {
+    let start = Instant::now();
+    let mut last = None;
+    let mut samples = 0;
+    while start.elapsed() < duration {
+        let input = read_reg::<Gpio17_20Input>(bus)?;
+        let high = match pin {
+            HeaderPin::P0 => input.gpio19_in(),
+            HeaderPin::P1 => input.gpio20_in(),
+        };
+        let state = if high { PinState::High } else { PinState::Low };
+        samples += 1;
+        if last != Some(state) {
+            last = Some(state);
+            on_edge(Sample {
+                elapsed: start.elapsed(),
+                state,
+            });
+        }
+    }
+    Ok(SampleStats {
+        samples,
+        elapsed: start.elapsed(),
+    })
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ToggleStats {
+    /// Number of level changes written
+    pub toggles: u64,
+    pub elapsed: Duration,
+    /// Writes that did not read back as written, always 0 without readback
+    pub mismatches: u64,
+}
+
+impl ToggleStats {
+    /// Frequency of the resulting square wave, two toggles per period.
+    pub fn frequency_hz(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+        self.toggles as f64 / 2.0 / self.elapsed.as_secs_f64()
+    }
+
+    /// Average time between two toggles.
+    pub fn average_period(&self) -> Duration {
+        if self.toggles == 0 {
+            return Duration::ZERO;
+        }
+        self.elapsed.div_f64(self.toggles as f64)
+    }
+}
+
+pub(crate) fn set_header_out(out: &mut Gpio17_20Output, pin: HeaderPin, high: bool) {
+    match pin {
+        HeaderPin::P0 => out.set_gpio19_out(high),
+        HeaderPin::P1 => out.set_gpio20_out(high),
+    }
+}
+
+/// Toggles `pin` as fast as possible for `duration`, one register write per toggle,
+/// plus a read to verify every write if `readback` is set.
+///
+/// The pin is temporarily switched to output, its mode and level are restored afterwards, also when a
+/// transfer fails midway.
+pub fn measure_toggle_rate(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    duration: Duration,
+    readback: bool,
+) -> Result<ToggleStats, DongleError> {
+    let prior_mode = gpio_header_get_mode(bus, pin)?;
+    let prior_out = read_reg::<Gpio17_20Output>(bus)?;
+    let result = toggle(bus, pin, prior_out, duration, readback);
+    let restored =
+        write_reg(bus, prior_out).and_then(|_| gpio_header_set_mode(bus, pin, prior_mode));
+    let stats = result?;
+    restored?;
+    Ok(stats)
+}
+
+fn toggle(
+    bus: &dyn RegisterBus,
+    pin: HeaderPin,
+    mut out: Gpio17_20Output,
+    duration: Duration,
+    readback: bool,
+) -> Result<ToggleStats, DongleError> {
+    gpio_header_set_mode(bus, pin, PinMode::Output)?;
+    let mut high = gpio_header_get(bus, pin)? == PinState::High;
+    let mut toggles = 0;
+    let mut mismatches = 0;
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        high = !high;
+        set_header_out(&mut out, pin, high);
+        write_reg(bus, out)?;
+        if readback && read_reg::<Gpio17_20Output>(bus)?.value() != out.value() {
+            mismatches += 1;
+        }
+        toggles += 1;
+    }
+    Ok(ToggleStats {
+        toggles,
+        elapsed: start.elapsed(),
+        mismatches,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::Policy;
+    use crate::usb4604_ral::{Gpio17_20Dir, MockBus};
+    use std::cell::Cell;
+
+    /// Fails the `n`th write of the header output register, counting from 1.
+    struct FailingWrite {
+        bus: MockBus,
+        n: Cell<u32>,
+    }
+
+    impl RegisterBus for FailingWrite {
+        fn read_byte(&self, addr: u16) -> Result<u8, DongleError> {
+            self.bus.read_byte(addr)
+        }
+
+        fn write_byte(&self, addr: u16, value: u8) -> Result<(), DongleError> {
+            if addr == Gpio17_20Output::ADDR {
+                if self.n.get() == 1 {
+                    self.bus.script_stalls(addr, 1);
+                }
+                self.n.set(self.n.get().wrapping_sub(1));
+            }
+            self.bus.write_byte(addr, value)
+        }
+
+        fn policy(&self) -> Policy {
+            self.bus.policy()
+        }
+    }
+
+    #[test]
+    fn toggle_failure_restores_pin() {
+        let bus = FailingWrite {
+            bus: MockBus::new(),
+            n: Cell::new(3),
+        };
+        let result = measure_toggle_rate(&bus, HeaderPin::P1, Duration::from_secs(5), true);
+        assert!(result.is_err());
+        assert!(!bus.bus.reg::<Gpio17_20Dir>().gpio20_out_en());
+        assert!(!bus.bus.reg::<Gpio17_20Output>().gpio20_out());
+    }
+
+    #[test]
+    fn watch_pin_reports_only_edges() {
+        let bus = MockBus::new();
+        let p0_high = Gpio17_20Input::new().with_gpio19_in(true).value();
+        bus.script_reads(Gpio17_20Input::ADDR, &[0, 0, p0_high, p0_high, 0]);
+        let mut edges = Vec::new();
+        let stats = watch_pin(&bus, HeaderPin::P0, Duration::from_millis(20), |s| {
+            edges.push(s.state)
+        })
+        .unwrap();
+        assert_eq!(edges, vec![PinState::Low, PinState::High, PinState::Low]);
+        assert!(stats.samples >= 5);
+    }
+}
@@ -0,0 +1,70 @@
+//! Optional power / USB switch sequencing policy, off by default.
+//!
+//! Some boards must never have the USB data lines connected while device power is off, the device would
+//! be back-powered through them. With the policy enabled ([crate::policy::Policy::enforce_sequencing], from
+//! `--enforce-sequencing` or `enforce_sequencing = true` in the config file) the HAL keeps this order:
+//! - connecting the USB switch ([usb_switch_set] with `true`) while power is off is refused with
+//!   [DongleError::SwitchNeedsPower]
+//! - turning power off ([dev_power_ctl] with `false`) disconnects the USB switch first (PCB RevC and up)
+//!
+//! The HAL never turns power on by itself, that is left to commands checking the [crate::lockout] first
+//! (`attach` calls [before_switch_connect]).
+//!
+//! [usb_switch_set]: crate::dongle_hal_revc::usb_switch_set
+//! [dev_power_ctl]: crate::dongle_hal_revb::dev_power_ctl
+
+use crate::dongle_hal_revb::{PcbRevision, dev_power_ctl, is_dev_power_on, pcb_revision};
+use crate::dongle_hal_revc::{usb_switch_configure, usb_switch_set};
+use crate::error::DongleError;
+use crate::usb4604_ral::RegisterBus;
+
+/// Turns power on if it is not, for commands connecting the USB switch after checking the power lockout.
+pub fn before_switch_connect(bus: &dyn RegisterBus) -> Result<(), DongleError> {
+    if !is_dev_power_on(bus)? {
+        dev_power_ctl(bus, true)?;
+    }
+    Ok(())
+}
+
+/// Disconnects the USB switch on boards that have one, called before power is turned off.
+pub fn before_power_off(bus: &dyn RegisterBus) -> Result<(), DongleError> {
+    if pcb_revision(bus)? == PcbRevision::RevC {
+        usb_switch_set(bus, false)?;
+        usb_switch_configure(bus)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dongle_hal_revc::usb_switch_is_connected;
+    use crate::policy::Policy;
+    use crate::usb4604_ral::{Gpio8_10Input, MockBus, SmscReg};
+
+    #[test]
+    fn power_off_disconnects_switch_and_connect_powers_on() {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio8_10Input::ADDR,
+            Gpio8_10Input::new().with_gpio9_in(true).value(),
+        );
+        bus.set_policy(Policy {
+            enforce_sequencing: true,
+            ..Policy::default()
+        });
+        dev_power_ctl(&bus, false).unwrap();
+        assert!(matches!(
+            usb_switch_set(&bus, true),
+            Err(DongleError::SwitchNeedsPower)
+        ));
+        assert!(!is_dev_power_on(&bus).unwrap());
+        before_switch_connect(&bus).unwrap();
+        assert!(is_dev_power_on(&bus).unwrap());
+
+        usb_switch_set(&bus, true).unwrap();
+        usb_switch_configure(&bus).unwrap();
+        before_power_off(&bus).unwrap();
+        assert!(!usb_switch_is_connected(&bus).unwrap());
+    }
+}
@@ -0,0 +1,342 @@
+//! Newline-delimited JSON request/response server for driving the dongle from other languages
+//! while keeping one interface claimed.
+//!
+//! Every request is one line:
+//! ```text
+//! {"id": 1, "method": "gpio_set", "params": {"pin": "p0", "state": "high"}}
+//! ```
+//! `id` is optional and echoed back as is. Every request produces exactly one response line, either
+//! `{"id": 1, "result": ...}` or `{"id": 1, "error": {"kind": "...", "message": "..."}}`.
+//!
+//! Methods, `params` and `result`:
+//!
+//! | method            | params                                  | result                              |
+//! |-------------------|-----------------------------------------|-------------------------------------|
+//! | `status`          |                                         | status report object                |
+//! | `power_set`       | `{"on": bool}`                          | `null`                              |
+//! | `power_state`     |                                         | `"On"`, `"Off"` or `"Unknown"`      |
+//! | `usb_switch_set`  | `{"connected": bool}`                   | previous state, bool (RevC)         |
+//! | `usb_switch_get`  |                                         | bool (RevC)                         |
+//! | `sdp_force`       | `{"force": bool}`                       | `null` (RevC)                       |
+//! | `cc_force_low`    | `{"force": bool}`                       | `null` (RevC)                       |
+//! | `gpio_config`     | `{"pin": "p0"/"p1", "mode": "input"/"output"}` | `null` (RevC)          |
+//! | `gpio_get_mode`   | `{"pin": "p0"/"p1"}`                    | `"input"` or `"output"` (RevC)      |
+//! | `gpio_set`        | `{"pin": "p0"/"p1", "state": "high"/"low"}` | `null` (RevC)                   |
+//! | `gpio_get`        | `{"pin": "p0"/"p1"}`                    | `"high"` or `"low"` (RevC)          |
+//! | `registers`       |                                         | `[{"name", "addr", "value"}, ...]`  |
+//!
+//! `sdp_force` and `cc_force_low` claim their SLG IO like `force-sdp` and `full-detach` do, see
+//! [crate::slg_claim].
+//!
+//! Error kinds are `parse` (malformed request or unknown method), `unsupported` (method needs PCB RevC)
+//! and [DongleError::kind] for device errors, e.g. `pin_in_input_mode` for setting a pin configured as input.
+
+use std::io::{self, BufRead, Write};
+
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::discovery::DongleInfo;
+use crate::dongle_hal_revb::{PcbRevision, dev_power_ctl, pcb_revision, power_state};
+use crate::dongle_hal_revc::{
+    HeaderPin, PinMode, PinState, SlgPin, gpio_header_get, gpio_header_get_mode, gpio_header_set,
+    gpio_header_set_mode, slg_io_set, slg_io_set_mode, usb_switch_is_connected, usb_switch_swap,
+};
+use crate::error::DongleError;
+use crate::lockout;
+use crate::slg_claim::{ClaimStore, SlgPurpose};
+use crate::status::status_report;
+use crate::usb4604_ral::{RegisterBus, dump_registers};
+
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum Request {
+    Status,
+    PowerSet { on: bool },
+    PowerState,
+    UsbSwitchSet { connected: bool },
+    UsbSwitchGet,
+    SdpForce { force: bool },
+    CcForceLow { force: bool },
+    GpioConfig { pin: HeaderPin, mode: PinMode },
+    GpioGetMode { pin: HeaderPin },
+    GpioSet { pin: HeaderPin, state: PinState },
+    GpioGet { pin: HeaderPin },
+    Registers,
+}
+
+impl Request {
+    fn requires_revc(&self) -> bool {
+        !matches!(
+            self,
+            Request::Status | Request::PowerSet { .. } | Request::PowerState | Request::Registers
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    #[serde(default)]
+    id: Value,
+    #[serde(flatten)]
+    request: Request,
+}
+
+/// Error response, `kind` is a stable machine readable identifier.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ErrorResponse {
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl From<DongleError> for ErrorResponse {
+    fn from(e: DongleError) -> Self {
+        ErrorResponse {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+}
+
+fn to_value<T: serde::Serialize>(value: T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+/// Claims `pin` for the feature while it is forced, releases it otherwise.
+fn force_claim(
+    claims: &ClaimStore,
+    info: &DongleInfo,
+    pin: SlgPin,
+    force: bool,
+) -> Result<(), DongleError> {
+    if force {
+        claims.claim(info, pin, SlgPurpose::Feature)
+    } else {
+        claims.release(info, pin, SlgPurpose::Feature);
+        Ok(())
+    }
+}
+
+/// Executes one request.
+pub fn execute(
+    bus: &dyn RegisterBus,
+    info: &DongleInfo,
+    claims: &ClaimStore,
+    request: &Request,
+) -> Result<Value, ErrorResponse> {
+    if request.requires_revc() && pcb_revision(bus)? == PcbRevision::RevAorB {
+        return Err(ErrorResponse {
+            kind: "unsupported",
+            message: "Not supported on PCB RevA or B".into(),
+        });
+    }
+    let result = match request {
+        Request::Status => to_value(status_report(bus, info)?),
+        Request::PowerSet { on } => {
+            if *on && let Some(holder) = lockout::holder(info) {
+                return Err(DongleError::LockedOut { holder }.into());
+            }
+            dev_power_ctl(bus, *on)?;
+            Value::Null
+        }
+        Request::PowerState => to_value(power_state(bus)?),
+        Request::UsbSwitchSet { connected } => Value::Bool(usb_switch_swap(bus, *connected)?),
+        Request::UsbSwitchGet => Value::Bool(usb_switch_is_connected(bus)?),
+        Request::SdpForce { force } => {
+            claims.check(info, SlgPin::SlgIo0, SlgPurpose::Feature)?;
+            slg_io_set_mode(bus, SlgPin::SlgIo0, PinMode::Output)?;
+            let state = if *force {
+                PinState::High
+            } else {
+                PinState::Low
+            };
+            slg_io_set(bus, SlgPin::SlgIo0, state)?;
+            force_claim(claims, info, SlgPin::SlgIo0, *force)?;
+            Value::Null
+        }
+        Request::CcForceLow { force } => {
+            claims.check(info, SlgPin::SlgIo1, SlgPurpose::Feature)?;
+            slg_io_set_mode(bus, SlgPin::SlgIo1, PinMode::Output)?;
+            let state = if *force {
+                PinState::Low
+            } else {
+                PinState::High
+            };
+            slg_io_set(bus, SlgPin::SlgIo1, state)?;
+            force_claim(claims, info, SlgPin::SlgIo1, *force)?;
+            Value::Null
+        }
+        Request::GpioConfig { pin, mode } => {
+            gpio_header_set_mode(bus, *pin, *mode)?;
+            Value::Null
+        }
+        Request::GpioGetMode { pin } => to_value(gpio_header_get_mode(bus, *pin)?),
+        Request::GpioSet { pin, state } => {
+            gpio_header_set(bus, *pin, *state)?;
+            Value::Null
+        }
+        Request::GpioGet { pin } => to_value(gpio_header_get(bus, *pin)?),
+        Request::Registers => to_value(dump_registers(bus)?),
+    };
+    Ok(result)
+}
+
+/// Parses and executes one request line, producing one response line (without the newline).
+pub fn handle_line(
+    bus: &dyn RegisterBus,
+    info: &DongleInfo,
+    claims: &ClaimStore,
+    line: &str,
+) -> String {
+    let (id, result) = match serde_json::from_str::<Envelope>(line) {
+        Ok(envelope) => (envelope.id, execute(bus, info, claims, &envelope.request)),
+        Err(e) => {
+            // still echo the id if the envelope is valid JSON
+            let id = serde_json::from_str::<Value>(line)
+                .ok()
+                .and_then(|v| v.get("id").cloned())
+                .unwrap_or(Value::Null);
+            let error = ErrorResponse {
+                kind: "parse",
+                message: e.to_string(),
+            };
+            (id, Err(error))
+        }
+    };
+    let response = match result {
+        Ok(result) => json!({ "id": id, "result": result }),
+        Err(e) => json!({ "id": id, "error": { "kind": e.kind, "message": e.message } }),
+    };
+    response.to_string()
+}
+
+/// Serves requests from `input` until EOF, skipping empty lines.
+pub fn serve(
+    bus: &dyn RegisterBus,
+    info: &DongleInfo,
+    claims: &ClaimStore,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        writeln!(output, "{}", handle_line(bus, info, claims, &line))?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::UsbDevice;
+    use crate::usb4604_ral::{Gpio8_10Input, MockBus, SmscReg};
+
+    fn revc_bus() -> MockBus {
+        let bus = MockBus::new();
+        let input = Gpio8_10Input::new()
+            .with_gpio9_in(true)
+            .with_gpio10_in(true);
+        bus.set(Gpio8_10Input::ADDR, input.value());
+        bus
+    }
+
+    /// Claim store of the calling test thread, tests run in parallel
+    fn store() -> ClaimStore {
+        ClaimStore::new(std::env::temp_dir().join(format!(
+            "mchp_server_claims_{}_{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        )))
+    }
+
+    fn info() -> DongleInfo {
+        DongleInfo {
+            bridge: UsbDevice::default(),
+            ftdi: None,
+            hub: None,
+        }
+    }
+
+    #[test]
+    fn gpio_requests() {
+        let bus = revc_bus();
+        let set = r#"{"id": 1, "method": "gpio_set", "params": {"pin": "p0", "state": "high"}}"#;
+        let response: Value =
+            serde_json::from_str(&handle_line(&bus, &info(), &store(), set)).unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["error"]["kind"], "pin_in_input_mode");
+
+        let config = r#"{"method": "gpio_config", "params": {"pin": "p0", "mode": "output"}}"#;
+        assert_eq!(
+            handle_line(&bus, &info(), &store(), config),
+            r#"{"id":null,"result":null}"#
+        );
+        handle_line(&bus, &info(), &store(), set);
+        let get = r#"{"id": "a", "method": "gpio_get", "params": {"pin": "p0"}}"#;
+        assert_eq!(
+            handle_line(&bus, &info(), &store(), get),
+            r#"{"id":"a","result":"high"}"#
+        );
+    }
+
+    #[test]
+    fn malformed_requests() {
+        let bus = revc_bus();
+        for line in [
+            r#"{"id": 7, "method": "explode"}"#,
+            r#"{"id": 7, "method": "gpio_get", "params": {"pin": "p9"}}"#,
+        ] {
+            let response: Value =
+                serde_json::from_str(&handle_line(&bus, &info(), &store(), line)).unwrap();
+            assert_eq!(response["id"], 7);
+            assert_eq!(response["error"]["kind"], "parse");
+        }
+        let response: Value =
+            serde_json::from_str(&handle_line(&bus, &info(), &store(), "not json")).unwrap();
+        assert_eq!(response["error"]["kind"], "parse");
+    }
+
+    #[test]
+    fn revc_methods_are_rejected_on_rev_a_or_b() {
+        let bus = MockBus::new();
+        let line = r#"{"method": "usb_switch_get"}"#;
+        let response: Value =
+            serde_json::from_str(&handle_line(&bus, &info(), &store(), line)).unwrap();
+        assert_eq!(response["error"]["kind"], "unsupported");
+        let line = r#"{"method": "power_set", "params": {"on": true}}"#;
+        assert_eq!(
+            handle_line(&bus, &info(), &store(), line),
+            r#"{"id":null,"result":null}"#
+        );
+    }
+
+    #[test]
+    fn sdp_force_is_refused_while_the_pin_is_claimed_as_gpio() {
+        let bus = revc_bus();
+        let claims = store();
+        claims
+            .claim(&info(), SlgPin::SlgIo0, SlgPurpose::Gpio)
+            .unwrap();
+        let line = r#"{"method": "sdp_force", "params": {"force": true}}"#;
+        let response: Value =
+            serde_json::from_str(&handle_line(&bus, &info(), &claims, line)).unwrap();
+        assert_eq!(response["error"]["kind"], "slg_pin_claimed");
+        claims.release(&info(), SlgPin::SlgIo0, SlgPurpose::Gpio);
+
+        assert_eq!(
+            handle_line(&bus, &info(), &claims, line),
+            r#"{"id":null,"result":null}"#
+        );
+        assert_eq!(
+            claims.claimed(&info(), SlgPin::SlgIo0),
+            Some(SlgPurpose::Feature)
+        );
+        let line = r#"{"method": "sdp_force", "params": {"force": false}}"#;
+        handle_line(&bus, &info(), &claims, line);
+        assert_eq!(claims.claimed(&info(), SlgPin::SlgIo0), None);
+    }
+}
@@ -0,0 +1,94 @@
+//! Platform specific instructions for getting access to the dongle.
+
+use crate::board::BoardProfile;
+
+/// udev rules giving the logged-in user access to the bridge and FTDI devices of `board`.
+pub fn udev_rules(board: &BoardProfile) -> String {
+    [board.bridge, board.ftdi]
+        .iter()
+        .map(|id| {
+            let (vid, pid) = (id.vendor_id, id.product_id);
+            format!(
+                r#"SUBSYSTEMS=="usb", ATTRS{{idVendor}}=="{vid:04x}", ATTRS{{idProduct}}=="{pid:04x}", TAG+="uaccess", GROUP="plugdev", MODE="0660""#
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(target_os = "linux")]
+pub fn setup_help(board: &BoardProfile) -> String {
+    format!(
+        "Linux: access to USB devices is granted via udev rules.\n\
+         \n\
+         Create udev rule:\n\
+         mchp_gpio_ctl udev | sudo tee /etc/udev/rules.d/70-rm_dongle.rules\n\
+         \n\
+         Reload rules and trigger:\n\
+         sudo udevadm control --reload-rules\n\
+         sudo udevadm trigger\n\
+         \n\
+         Rules that will be installed:\n\
+         {}",
+        udev_rules(board)
+    )
+}
+
+#[cfg(target_os = "windows")]
+pub fn setup_help(board: &BoardProfile) -> String {
+    use nusb::MaybeFuture;
+
+    let bridge = board.bridge;
+    let (vid, pid) = (bridge.vendor_id, bridge.product_id);
+    let mut help = format!(
+        "Windows: the bridge device (VID {vid:04x}, PID {pid:04x}) needs the WinUSB driver.\n\
+         Install it with Zadig (https://zadig.akeo.ie): Options -> List All Devices, select the device \
+         with VID {vid:04X} and PID {pid:04X}, pick WinUSB and press Install Driver.\n\
+         Do not replace the driver of the FTDI device, it is used as a regular COM port.\n"
+    );
+    let bridges = match nusb::list_devices().wait() {
+        Ok(devices) => devices
+            .filter(|d| bridge.matches(d.vendor_id(), d.product_id()))
+            .collect::<Vec<_>>(),
+        Err(e) => {
+            help.push_str(&format!("\nFailed to list USB devices: {e}\n"));
+            return help;
+        }
+    };
+    if bridges.is_empty() {
+        help.push_str("\nNo bridge devices found, connect a dongle and run this command again.\n");
+    }
+    for bridge in bridges {
+        let port_chain = bridge.port_chain();
+        match bridge.driver() {
+            Some(driver) if driver.eq_ignore_ascii_case("winusb") => {
+                help.push_str(&format!(
+                    "\nBridge at port {port_chain:?}: WinUSB driver is bound, no action required.\n"
+                ));
+            }
+            Some(driver) => {
+                help.push_str(&format!(
+                    "\nBridge at port {port_chain:?}: driver '{driver}' is bound, replace it with WinUSB using Zadig.\n"
+                ));
+            }
+            None => {
+                help.push_str(&format!(
+                    "\nBridge at port {port_chain:?}: no driver is bound, install WinUSB using Zadig.\n"
+                ));
+            }
+        }
+    }
+    help
+}
+
+#[cfg(target_os = "macos")]
+pub fn setup_help(_board: &BoardProfile) -> String {
+    "macOS: no driver installation or permissions setup is required, the dongle should work out of the box.\n\
+     If the device cannot be opened, make sure no other application is using it."
+        .to_string()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+pub fn setup_help(_board: &BoardProfile) -> String {
+    "No setup instructions are available for this platform.".to_string()
+}
@@ -0,0 +1,142 @@
+//! Logical board signals and their electrical polarity.
+//!
+//! HAL functions take and return the logical meaning (power on, USB switch connected), the inversion
+//! done by the board is applied here, in one place, instead of as `!` scattered over the register accesses.
+//!
+//! Boards deviating from the reference schematic can flip the USB switch and power enable polarity, per
+//! device: the [Polarity] is part of the [crate::policy::Policy] carried by the register bus.
+
+/// Polarity of the enables that can differ between boards, the default is the reference schematic
+/// (both active low).
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Polarity {
+    /// USB_SWITCH_EN is wired non-inverting, for custom carriers
+    pub switch_active_high: bool,
+    /// PWR_EN is wired non-inverting (non-inverting power switch), for custom carriers
+    pub power_active_high: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ElectricalLevel {
+    High,
+    Low,
+}
+
+impl ElectricalLevel {
+    pub fn from_bit(bit: bool) -> Self {
+        if bit {
+            ElectricalLevel::High
+        } else {
+            ElectricalLevel::Low
+        }
+    }
+
+    pub fn bit(self) -> bool {
+        self == ElectricalLevel::High
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Signal {
+    /// PIO0 - PWR_EN_N
+    PowerOn,
+    /// PIO1 - USB_SWITCH_EN, RevC and up
+    SwitchConnected,
+    /// PIO10 - PWR_FAIL_N
+    PowerFault,
+}
+
+impl Signal {
+    /// Inversion table, true if the signal is active at low electrical level on a board with `polarity`.
+    pub fn is_active_low(self, polarity: Polarity) -> bool {
+        match self {
+            Signal::PowerOn => !polarity.power_active_high,
+            Signal::SwitchConnected => !polarity.switch_active_high,
+            Signal::PowerFault => true,
+        }
+    }
+
+    /// Electrical level to drive for the signal to be `active`.
+    pub fn level(self, polarity: Polarity, active: bool) -> ElectricalLevel {
+        ElectricalLevel::from_bit(active != self.is_active_low(polarity))
+    }
+
+    /// Whether the signal is active at the given electrical `level`.
+    pub fn is_active(self, polarity: Polarity, level: ElectricalLevel) -> bool {
+        level.bit() != self.is_active_low(polarity)
+    }
+}
+
+/// Outcome of checking a signal's polarity against what was observed on the board.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PolarityCheck {
+    /// Board follows the configured polarity
+    Matches,
+    /// Board does the opposite of what was requested, the polarity has to be flipped
+    Inverted,
+    /// Nothing changed between the two levels, the pin does not seem to control the signal
+    NoChange { always_active: bool },
+}
+
+impl PolarityCheck {
+    /// From whether the signal was observed active after requesting it inactive, and after requesting it active.
+    pub fn from_observations(active_when_off: bool, active_when_on: bool) -> Self {
+        match (active_when_off, active_when_on) {
+            (false, true) => PolarityCheck::Matches,
+            (true, false) => PolarityCheck::Inverted,
+            (always_active, _) => PolarityCheck::NoChange { always_active },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn active_low_signals_round_trip() {
+        let polarity = Polarity::default();
+        for signal in [Signal::PowerOn, Signal::SwitchConnected, Signal::PowerFault] {
+            assert_eq!(signal.level(polarity, true), ElectricalLevel::Low);
+            assert_eq!(signal.level(polarity, false), ElectricalLevel::High);
+            for active in [true, false] {
+                assert_eq!(
+                    signal.is_active(polarity, signal.level(polarity, active)),
+                    active
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn flipped_polarity_only_affects_its_signal() {
+        let polarity = Polarity {
+            switch_active_high: false,
+            power_active_high: true,
+        };
+        assert_eq!(Signal::PowerOn.level(polarity, true), ElectricalLevel::High);
+        assert_eq!(
+            Signal::SwitchConnected.level(polarity, true),
+            ElectricalLevel::Low
+        );
+        assert!(Signal::PowerFault.is_active(polarity, ElectricalLevel::Low));
+    }
+
+    #[test]
+    fn polarity_check_from_observations() {
+        assert_eq!(
+            PolarityCheck::from_observations(false, true),
+            PolarityCheck::Matches
+        );
+        assert_eq!(
+            PolarityCheck::from_observations(true, false),
+            PolarityCheck::Inverted
+        );
+        assert_eq!(
+            PolarityCheck::from_observations(true, true),
+            PolarityCheck::NoChange {
+                always_active: true
+            }
+        );
+    }
+}
@@ -0,0 +1,293 @@
+//! Configuration of the two SLG GreenPAK IOs, as seen from the hub.
+//!
+//! The GreenPAK has fixed internal pulls on both IOs, they are not configurable from the hub: SLG_IO0
+//! is pulled down, SLG_IO1 is pulled up. Only the hub side direction and level can be changed. When the hub
+//! pin is an input the IO is released and the pull decides the level, so e.g. SLG_IO1 reading low as an
+//! input means something is actively driving it low.
+
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::dongle_hal_revc::{
+    PinMode, PinState, SlgPin, slg_io_get, slg_io_get_mode, slg_io_set, slg_io_set_mode,
+};
+use crate::error::DongleError;
+use crate::timed::{TimedOutcome, drive_sequence, drive_timed};
+use crate::usb4604_ral::RegisterBus;
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pull {
+    Up,
+    Down,
+}
+
+impl SlgPin {
+    pub fn signal_name(self) -> &'static str {
+        match self {
+            SlgPin::SlgIo0 => "SLG_IO0",
+            SlgPin::SlgIo1 => "SLG_IO1",
+        }
+    }
+
+    pub fn pio(self) -> u8 {
+        match self {
+            SlgPin::SlgIo0 => 8,
+            SlgPin::SlgIo1 => 3,
+        }
+    }
+
+    /// Pull inside the GreenPAK, fixed in its configuration.
+    pub fn pull(self) -> Pull {
+        match self {
+            SlgPin::SlgIo0 => Pull::Down,
+            SlgPin::SlgIo1 => Pull::Up,
+        }
+    }
+
+    /// Level the IO settles at when released (hub pin as input).
+    pub fn pull_level(self) -> PinState {
+        match self.pull() {
+            Pull::Up => PinState::High,
+            Pull::Down => PinState::Low,
+        }
+    }
+
+    /// Warning for releasing the IO while it was driven to `driven`, `None` if the pull keeps the same level.
+    pub fn release_warning(self, driven: PinState) -> Option<String> {
+        let level = self.pull_level();
+        (driven != level).then(|| {
+            format!(
+                "{} as input is pulled {} inside SLG and reads {level:?}, not {driven:?}: {}",
+                self.signal_name(),
+                format!("{:?}", self.pull()).to_lowercase(),
+                self.meaning(level)
+            )
+        })
+    }
+
+    /// What the GreenPAK does with the IO at `state`.
+    pub fn meaning(self, state: PinState) -> &'static str {
+        match (self, state) {
+            (SlgPin::SlgIo0, PinState::High) => "forcing SDP",
+            (SlgPin::SlgIo0, PinState::Low) => "SDP not forced, switch position decides",
+            (SlgPin::SlgIo1, PinState::Low) => "forcing CC lines low",
+            (SlgPin::SlgIo1, PinState::High) => "CC lines released",
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct SlgIoConfig {
+    pub name: &'static str,
+    pub pio: u8,
+    pub pull: Pull,
+    /// Hub side direction, input releases the IO to the GreenPAK pull
+    pub mode: PinMode,
+    pub state: PinState,
+    pub meaning: &'static str,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize)]
+pub struct SlgConfig {
+    pub io0: SlgIoConfig,
+    pub io1: SlgIoConfig,
+}
+
+fn io_config(bus: &dyn RegisterBus, pin: SlgPin) -> Result<SlgIoConfig, DongleError> {
+    let state = slg_io_get(bus, pin)?;
+    Ok(SlgIoConfig {
+        name: pin.signal_name(),
+        pio: pin.pio(),
+        pull: pin.pull(),
+        mode: slg_io_get_mode(bus, pin)?,
+        state,
+        meaning: pin.meaning(state),
+    })
+}
+
+/// Reads the direction and level of both SLG IOs, without any writes.
+pub fn slg_config(bus: &dyn RegisterBus) -> Result<SlgConfig, DongleError> {
+    Ok(SlgConfig {
+        io0: io_config(bus, SlgPin::SlgIo0)?,
+        io1: io_config(bus, SlgPin::SlgIo1)?,
+    })
+}
+
+/// Releases both IOs to their GreenPAK pulls (hub pins as inputs): SDP not forced, CC lines released.
+pub fn slg_release(bus: &dyn RegisterBus) -> Result<(), DongleError> {
+    slg_io_set_mode(bus, SlgPin::SlgIo0, PinMode::Input)?;
+    slg_io_set_mode(bus, SlgPin::SlgIo1, PinMode::Input)?;
+    Ok(())
+}
+
+/// Boot mode as selected from software through SLG_IO0.
+///
+/// The board's mode switch is not readable from the hub: in its SDP position the device boots SDP
+/// regardless, so [BootMode::Usart] only means SDP is not forced.
+#[derive(Copy, Clone, PartialEq, Debug, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BootMode {
+    /// Serial download protocol, SLG_IO0 driven high
+    Sdp,
+    /// Normal boot, SLG_IO0 released or driven low
+    Usart,
+}
+
+/// Current boot mode, without any writes.
+pub fn boot_mode(bus: &dyn RegisterBus) -> Result<BootMode, DongleError> {
+    let forced = slg_io_get_mode(bus, SlgPin::SlgIo0)? == PinMode::Output
+        && slg_io_get(bus, SlgPin::SlgIo0)? == PinState::High;
+    Ok(if forced {
+        BootMode::Sdp
+    } else {
+        BootMode::Usart
+    })
+}
+
+/// Forces SDP or releases it, same as `force-sdp` / `release-sdp`.
+pub fn set_boot_mode(bus: &dyn RegisterBus, mode: BootMode) -> Result<(), DongleError> {
+    slg_io_set_mode(bus, SlgPin::SlgIo0, PinMode::Output)?;
+    let state = match mode {
+        BootMode::Sdp => PinState::High,
+        BootMode::Usart => PinState::Low,
+    };
+    slg_io_set(bus, SlgPin::SlgIo0, state)
+}
+
+/// Parses one phase duration of [sdp_sequence] in milliseconds.
+pub fn parse_phase_ms(ms: &str) -> Result<Duration, String> {
+    match ms.trim().parse::<u64>() {
+        Ok(ms) if ms > 0 => Ok(Duration::from_millis(ms)),
+        _ => Err(format!(
+            "invalid duration '{ms}', expected a positive number of milliseconds"
+        )),
+    }
+}
+
+/// Drives SLG_IO0 through alternating high/low phases starting with high, each lasting the given duration,
+/// then leaves it low (SDP released). Released early on Ctrl-C, see [crate::timed].
+pub fn sdp_sequence(
+    bus: &dyn RegisterBus,
+    phases: &[Duration],
+) -> Result<TimedOutcome, DongleError> {
+    slg_io_set_mode(bus, SlgPin::SlgIo0, PinMode::Output)?;
+    let phases = [PinState::High, PinState::Low]
+        .into_iter()
+        .cycle()
+        .zip(phases.iter().copied())
+        .collect::<Vec<_>>();
+    drive_sequence(
+        |state| slg_io_set(bus, SlgPin::SlgIo0, state),
+        &phases,
+        PinState::Low,
+    )
+}
+
+/// Forces the CC lines low through SLG_IO1 for `duration`, then releases them (SLG_IO1 driven high),
+/// leaving power and the USB switch as they are. Released early on Ctrl-C, see [crate::timed].
+///
+/// Pulling CC low makes the port partner see a detach and re-detect the connection once released, so this
+/// simulates a CC disconnect without cutting VBUS or the data lines.
+pub fn cc_pulse(bus: &dyn RegisterBus, duration: Duration) -> Result<TimedOutcome, DongleError> {
+    slg_io_set_mode(bus, SlgPin::SlgIo1, PinMode::Output)?;
+    drive_timed(
+        |state| slg_io_set(bus, SlgPin::SlgIo1, state),
+        PinState::Low,
+        duration,
+        PinState::High,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb4604_ral::{Gpio0_7Input, MockBus, SmscReg};
+
+    #[test]
+    fn released_ios_read_their_pulls() {
+        let bus = MockBus::new();
+        bus.set(
+            Gpio0_7Input::ADDR,
+            Gpio0_7Input::new().with_gpio3_in(true).value(),
+        );
+        slg_release(&bus).unwrap();
+        let config = slg_config(&bus).unwrap();
+        assert_eq!(config.io0.mode, PinMode::Input);
+        assert_eq!(config.io0.state, PinState::Low);
+        assert_eq!(config.io0.pull, Pull::Down);
+        assert_eq!(config.io1.state, PinState::High);
+        assert_eq!(config.io1.meaning, "CC lines released");
+    }
+
+    #[test]
+    fn boot_mode_follows_slg_io0() {
+        let bus = MockBus::new();
+        assert_eq!(boot_mode(&bus).unwrap(), BootMode::Usart);
+        set_boot_mode(&bus, BootMode::Sdp).unwrap();
+        assert_eq!(boot_mode(&bus).unwrap(), BootMode::Sdp);
+        set_boot_mode(&bus, BootMode::Usart).unwrap();
+        assert_eq!(boot_mode(&bus).unwrap(), BootMode::Usart);
+    }
+
+    #[test]
+    fn sdp_sequence_alternates_and_ends_low() {
+        use crate::usb4604_ral::Gpio8_10Output;
+
+        assert!(parse_phase_ms("0").is_err());
+        assert!(parse_phase_ms("").is_err());
+        let phases = ["1", " 2", "1"].map(|ms| parse_phase_ms(ms).unwrap());
+        assert_eq!(phases[1], Duration::from_millis(2));
+
+        let bus = MockBus::new();
+        sdp_sequence(&bus, &phases).unwrap();
+        let levels = bus
+            .writes()
+            .into_iter()
+            .filter(|(addr, _)| *addr == Gpio8_10Output::ADDR)
+            .map(|(_, value)| Gpio8_10Output::from_value(value).gpio8_out())
+            .collect::<Vec<_>>();
+        assert_eq!(levels, vec![true, false, true, false]);
+    }
+
+    #[test]
+    fn cc_pulse_forces_low_then_releases() {
+        use crate::usb4604_ral::{Gpio0_7Dir, Gpio0_7Output};
+
+        let bus = MockBus::new();
+        bus.set(
+            Gpio0_7Output::ADDR,
+            Gpio0_7Output::new().with_gpio3_out(true).value(),
+        );
+        cc_pulse(&bus, Duration::from_millis(1)).unwrap();
+        let levels = bus
+            .writes()
+            .into_iter()
+            .filter(|(addr, _)| *addr == Gpio0_7Output::ADDR)
+            .map(|(_, value)| Gpio0_7Output::from_value(value).gpio3_out())
+            .collect::<Vec<_>>();
+        assert_eq!(levels, vec![false, true]);
+        assert!(bus.reg::<Gpio0_7Dir>().gpio3_out_en());
+        assert_eq!(slg_config(&bus).unwrap().io1.meaning, "CC lines released");
+    }
+
+    #[test]
+    fn releasing_against_the_pull_warns() {
+        assert!(SlgPin::SlgIo0.release_warning(PinState::Low).is_none());
+        let warning = SlgPin::SlgIo0.release_warning(PinState::High).unwrap();
+        assert!(warning.contains("pulled down"), "{warning}");
+        assert!(SlgPin::SlgIo1.release_warning(PinState::Low).is_some());
+
+        let bus = MockBus::new();
+        set_boot_mode(&bus, BootMode::Sdp).unwrap();
+        let warning = slg_io_set_mode(&bus, SlgPin::SlgIo0, PinMode::Input).unwrap();
+        assert!(warning.unwrap().contains("pulled down"));
+        assert!(
+            slg_io_set_mode(&bus, SlgPin::SlgIo0, PinMode::Input)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
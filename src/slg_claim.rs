@@ -0,0 +1,174 @@
+//! Claimed purpose of the two dual-use SLG IOs, so the features using them and generic pin access do not
+//! silently clobber each other.
+//!
+//! Both IOs are one wire each, used for two things:
+//! - SLG_IO0 (PIO8) is the SDP force line, driving it high forces SDP, and it is brought out as the unmarked
+//!   GPIO header position 2 (`pin io0`)
+//! - SLG_IO1 (PIO3) is the CC force line, driving it low forces the CC lines low, and it is brought out as the
+//!   unmarked GPIO header position 3 (`pin io1`)
+//!
+//! Driving the pin from one side changes what the other side sees, e.g. `pin io0 set low` releases a forced
+//! SDP. A feature keeping its line asserted after the command ends (`force-sdp`, `mode sdp`, `full-detach`
+//! for CC) claims the pin for [SlgPurpose::Feature] until it is released (`release-sdp`, `mode usart`,
+//! `full-attach`); configuring the pin as output or setting it with `pin` claims it for [SlgPurpose::Gpio]
+//! until it is configured as input again. Commands using the pin for the other purpose fail with
+//! [DongleError::SlgPinClaimed] meanwhile.
+//!
+//! Claims are files in a [ClaimStore], by default next to the lockout files in the system temporary directory
+//! ([ClaimStore::system], `mchp_gpio_ctl/slg-<file_id>-io0`), so they are shared between processes and cleared
+//! on reboot. The SDP and CC force of `apply` fixtures and of `serve` requests claim like the commands do, only
+//! other tools driving the pins are not seen.
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use log::warn;
+
+use crate::discovery::DongleInfo;
+use crate::dongle_hal_revc::SlgPin;
+use crate::error::DongleError;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SlgPurpose {
+    /// SDP force for SLG_IO0, CC force for SLG_IO1
+    Feature,
+    /// Generic GPIO through `pin io0` / `pin io1`
+    Gpio,
+}
+
+impl SlgPurpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            SlgPurpose::Feature => "feature",
+            SlgPurpose::Gpio => "gpio",
+        }
+    }
+
+    /// What the pin is used as, e.g. "SDP force line".
+    pub fn describe(self, pin: SlgPin) -> &'static str {
+        match (pin, self) {
+            (SlgPin::SlgIo0, SlgPurpose::Feature) => "SDP force line",
+            (SlgPin::SlgIo1, SlgPurpose::Feature) => "CC force line",
+            (SlgPin::SlgIo0, SlgPurpose::Gpio) => "generic GPIO (pin io0)",
+            (SlgPin::SlgIo1, SlgPurpose::Gpio) => "generic GPIO (pin io1)",
+        }
+    }
+
+    /// Command releasing the claim.
+    pub fn release_hint(self, pin: SlgPin) -> &'static str {
+        match (pin, self) {
+            (SlgPin::SlgIo0, SlgPurpose::Feature) => "release-sdp",
+            (SlgPin::SlgIo1, SlgPurpose::Feature) => "full-attach",
+            (SlgPin::SlgIo0, SlgPurpose::Gpio) => "pin io0 config input",
+            (SlgPin::SlgIo1, SlgPurpose::Gpio) => "pin io1 config input",
+        }
+    }
+}
+
+/// Directory holding the claim files, one per dongle and pin.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ClaimStore {
+    dir: PathBuf,
+}
+
+impl ClaimStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Store shared by all processes on the machine, see the module documentation.
+    pub fn system() -> Self {
+        Self::new(std::env::temp_dir().join("mchp_gpio_ctl"))
+    }
+
+    fn path(&self, info: &DongleInfo, pin: SlgPin) -> PathBuf {
+        let pin = match pin {
+            SlgPin::SlgIo0 => "io0",
+            SlgPin::SlgIo1 => "io1",
+        };
+        self.dir.join(format!("slg-{}-{pin}", info.file_id()))
+    }
+
+    /// Purpose `pin` is currently claimed for, `None` if it is free.
+    pub fn claimed(&self, info: &DongleInfo, pin: SlgPin) -> Option<SlgPurpose> {
+        match fs::read_to_string(self.path(info, pin)).ok()?.trim() {
+            "feature" => Some(SlgPurpose::Feature),
+            "gpio" => Some(SlgPurpose::Gpio),
+            _ => None,
+        }
+    }
+
+    /// Fails if `pin` is claimed for another purpose than `purpose`.
+    pub fn check(
+        &self,
+        info: &DongleInfo,
+        pin: SlgPin,
+        purpose: SlgPurpose,
+    ) -> Result<(), DongleError> {
+        match self.claimed(info, pin) {
+            Some(by) if by != purpose => Err(DongleError::SlgPinClaimed { pin, by }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Claims `pin` for `purpose`, see [ClaimStore::check]. Failing to write the claim is only logged.
+    pub fn claim(
+        &self,
+        info: &DongleInfo,
+        pin: SlgPin,
+        purpose: SlgPurpose,
+    ) -> Result<(), DongleError> {
+        self.check(info, pin, purpose)?;
+        let path = self.path(info, pin);
+        let result = fs::create_dir_all(&self.dir).and_then(|_| fs::write(&path, purpose.as_str()));
+        if let Err(e) = result {
+            warn!("Failed to record {} claim: {e}", pin.signal_name());
+        }
+        Ok(())
+    }
+
+    /// Drops the claim of `pin` if it is held for `purpose`.
+    pub fn release(&self, info: &DongleInfo, pin: SlgPin, purpose: SlgPurpose) {
+        if self.claimed(info, pin) != Some(purpose) {
+            return;
+        }
+        match fs::remove_file(self.path(info, pin)) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Failed to release {} claim: {e}", pin.signal_name()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::UsbDevice;
+
+    #[test]
+    fn pin_claimed_by_one_purpose_refuses_the_other_until_released() {
+        let store = ClaimStore::new(
+            std::env::temp_dir().join(format!("mchp_slg_claim_{}", std::process::id())),
+        );
+        let info = DongleInfo {
+            bridge: UsbDevice::default(),
+            ftdi: None,
+            hub: None,
+        };
+        let pin = SlgPin::SlgIo0;
+        store.claim(&info, pin, SlgPurpose::Feature).unwrap();
+        assert_eq!(store.claimed(&info, pin), Some(SlgPurpose::Feature));
+        assert!(store.check(&info, SlgPin::SlgIo1, SlgPurpose::Gpio).is_ok());
+        let err = store.claim(&info, pin, SlgPurpose::Gpio).unwrap_err();
+        assert!(err.to_string().contains("release-sdp"), "{err}");
+
+        store.release(&info, pin, SlgPurpose::Gpio);
+        assert_eq!(store.claimed(&info, pin), Some(SlgPurpose::Feature));
+        store.release(&info, pin, SlgPurpose::Feature);
+        store.claim(&info, pin, SlgPurpose::Gpio).unwrap();
+        store.release(&info, pin, SlgPurpose::Gpio);
+        assert_eq!(store.claimed(&info, pin), None);
+        fs::remove_dir_all(&store.dir).unwrap();
+    }
+}
@@ -0,0 +1,386 @@
+use std::fmt::Write;
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::DongleInfo;
+use crate::dongle_hal_revb::{
+    PcbRevision, PowerPath, PowerState, is_dev_power_on_via, is_dev_pwr_fault, pcb_revision,
+    power_state, read_dev_pwr_fault,
+};
+use crate::dongle_hal_revc::{
+    HeaderPin, PinMode, PinState, SlgPin, detect_relay_variant, gpio_header_get_many,
+    gpio_header_get_mode, slg_io_get, usb_switch_is_connected,
+};
+use crate::error::DongleError;
+use crate::usb4604_ral::RegisterBus;
+
+#[derive(Copy, Clone, PartialEq, Debug, Default, ValueEnum)]
+pub enum StatusFormat {
+    /// Human readable output
+    #[default]
+    Text,
+    /// Prometheus textfile collector format
+    Prometheus,
+    /// `export MCHP_...='...'` lines for `eval "$(mchp_gpio_ctl status --format env)"`
+    Env,
+    /// The whole report as JSON, also the format `status --baseline` compares against
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct HeaderPinStatus {
+    pub mode: PinMode,
+    pub state: PinState,
+}
+
+/// Snapshot of everything the `status` command reports.
+///
+/// Fields that only exist on PCB RevC and up are `None` on older boards.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StatusReport {
+    pub serial: String,
+    pub power_state: PowerState,
+    /// Power state with [PowerState::Unknown] resolved from the pulled PIO0 pad level
+    pub power_on: bool,
+    /// `None` in read-only mode if PIO10 is not configured as input
+    pub power_fault: Option<bool>,
+    pub pcb_revision: PcbRevision,
+    /// [PcbRevision] display string, "Rev A/B" or "Rev C"
+    pub revision: String,
+    pub relay_variant: bool,
+    /// Number of relays, relay 1 is driven by P0, relay 2 by P1
+    pub relay_count: u8,
+    pub usb_switch_connected: Option<bool>,
+    pub forcing_sdp: Option<bool>,
+    pub forcing_cc_low: Option<bool>,
+    pub header_p0: Option<HeaderPinStatus>,
+    pub header_p1: Option<HeaderPinStatus>,
+}
+
+/// Status of P0 and P1, states are read from a single register snapshot.
+fn header_pins_status(bus: &dyn RegisterBus) -> Result<[HeaderPinStatus; 2], DongleError> {
+    let states = gpio_header_get_many(bus, &[HeaderPin::P0, HeaderPin::P1])?;
+    let status = |(pin, state)| {
+        Ok(HeaderPinStatus {
+            mode: gpio_header_get_mode(bus, pin)?,
+            state,
+        })
+    };
+    Ok([status(states[0])?, status(states[1])?])
+}
+
+/// Runs `f` only on RevC boards, producing `None` otherwise.
+fn revc_only<T>(
+    is_revc: bool,
+    f: impl FnOnce() -> Result<T, DongleError>,
+) -> Result<Option<T>, DongleError> {
+    if is_revc { f().map(Some) } else { Ok(None) }
+}
+
+/// Reads everything the `status` command reports, without printing anything.
+///
+/// PIO10 (power fault) is switched to input if it is not already.
+pub fn status_report(
+    bus: &dyn RegisterBus,
+    info: &DongleInfo,
+) -> Result<StatusReport, DongleError> {
+    report(bus, info, false)
+}
+
+/// Same as [status_report], but without any register writes.
+///
+/// Power fault is unknown (`None`) if PIO10 is not configured as input, all other fields are the same.
+pub fn read_only_status_report(
+    bus: &dyn RegisterBus,
+    info: &DongleInfo,
+) -> Result<StatusReport, DongleError> {
+    report(bus, info, true)
+}
+
+fn report(
+    bus: &dyn RegisterBus,
+    info: &DongleInfo,
+    read_only: bool,
+) -> Result<StatusReport, DongleError> {
+    let pcb_revision = pcb_revision(bus)?;
+    let is_revc = matches!(pcb_revision, PcbRevision::RevC);
+    let power_state = power_state(bus)?;
+    let header = revc_only(is_revc, || header_pins_status(bus))?;
+    let relay_count = detect_relay_variant(bus, info)?;
+    Ok(StatusReport {
+        serial: info.display_serial(),
+        power_state,
+        power_on: is_dev_power_on_via(bus, PowerPath::Board)?,
+        power_fault: if read_only {
+            read_dev_pwr_fault(bus)?
+        } else {
+            Some(is_dev_pwr_fault(bus)?)
+        },
+        pcb_revision,
+        revision: pcb_revision.to_string(),
+        relay_variant: relay_count > 0,
+        relay_count,
+        usb_switch_connected: revc_only(is_revc, || usb_switch_is_connected(bus))?,
+        forcing_sdp: revc_only(is_revc, || {
+            Ok(slg_io_get(bus, SlgPin::SlgIo0)? == PinState::High)
+        })?,
+        forcing_cc_low: revc_only(is_revc, || {
+            Ok(slg_io_get(bus, SlgPin::SlgIo1)? == PinState::Low)
+        })?,
+        header_p0: header.map(|h| h[0]),
+        header_p1: header.map(|h| h[1]),
+    })
+}
+
+/// One field differing from the baseline, `None` where the field does not exist (RevC-only fields).
+#[derive(Clone, PartialEq, Debug)]
+pub struct FieldDiff {
+    pub field: &'static str,
+    pub baseline: Option<String>,
+    pub current: Option<String>,
+}
+
+/// Quotes `value` for POSIX shells: single quoted, embedded single quotes as `'\''`.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Escapes a label value according to the Prometheus text exposition format.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl StatusReport {
+    /// Flattened `(field, value)` pairs, RevC-only fields are omitted on older boards.
+    pub fn fields(&self) -> Vec<(&'static str, String)> {
+        let mut fields = vec![
+            ("serial", self.serial.clone()),
+            ("power_on", self.power_on.to_string()),
+            (
+                "power_fault",
+                self.power_fault
+                    .map(|f| f.to_string())
+                    .unwrap_or("unknown".into()),
+            ),
+            ("pcb_revision", self.pcb_revision.label().to_string()),
+            ("relay_variant", self.relay_variant.to_string()),
+        ];
+        let optional = [
+            ("usb_switch_connected", self.usb_switch_connected),
+            ("forcing_sdp", self.forcing_sdp),
+            ("forcing_cc_low", self.forcing_cc_low),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                fields.push((name, value.to_string()));
+            }
+        }
+        let pins = [
+            ("p0.mode", "p0.state", self.header_p0),
+            ("p1.mode", "p1.state", self.header_p1),
+        ];
+        for (mode_name, state_name, pin) in pins {
+            if let Some(pin) = pin {
+                fields.push((mode_name, format!("{:?}", pin.mode)));
+                fields.push((state_name, format!("{:?}", pin.state)));
+            }
+        }
+        fields
+    }
+
+    /// Fields of [StatusReport::fields] that differ from `baseline`, skipping those named in `ignore`.
+    ///
+    /// `ignore` entries match a field name or a prefix of it, e.g. `p0` ignores both `p0.mode` and `p0.state`.
+    pub fn diff(&self, baseline: &StatusReport, ignore: &[String]) -> Vec<FieldDiff> {
+        let ignored = |field: &str| {
+            ignore.iter().any(|i| {
+                field == i
+                    || field
+                        .strip_prefix(i.as_str())
+                        .is_some_and(|rest| rest.starts_with('.'))
+            })
+        };
+        let before = baseline.fields();
+        let after = self.fields();
+        let mut names = before.iter().map(|f| f.0).collect::<Vec<_>>();
+        for (name, _) in &after {
+            if !names.contains(name) {
+                names.push(name);
+            }
+        }
+        let value = |fields: &[(&str, String)], name| {
+            fields.iter().find(|f| f.0 == name).map(|f| f.1.clone())
+        };
+        names
+            .into_iter()
+            .filter(|name| !ignored(name))
+            .map(|name| FieldDiff {
+                field: name,
+                baseline: value(&before, name),
+                current: value(&after, name),
+            })
+            .filter(|d| d.baseline != d.current)
+            .collect()
+    }
+
+    /// Renders the report in the Prometheus text format, suitable for node_exporter's textfile collector.
+    ///
+    /// RevC-only metrics are omitted on older boards.
+    pub fn to_prometheus(&self) -> String {
+        let metrics = [
+            (
+                "mchp_dongle_power_on",
+                "Whether power to the connected device is on",
+                Some(self.power_on),
+            ),
+            (
+                "mchp_dongle_fault",
+                "Whether a power fault is detected (most likely a short on VBUS)",
+                self.power_fault,
+            ),
+            (
+                "mchp_dongle_usb_switch_connected",
+                "Whether USB data lines are connected to the device",
+                self.usb_switch_connected,
+            ),
+            (
+                "mchp_dongle_forcing_sdp",
+                "Whether SDP mode is being forced",
+                self.forcing_sdp,
+            ),
+            (
+                "mchp_dongle_forcing_cc_low",
+                "Whether CC lines are being forced low",
+                self.forcing_cc_low,
+            ),
+        ];
+        let serial = escape_label_value(&self.serial);
+        let mut out = String::new();
+        for (name, help, value) in metrics {
+            let Some(value) = value else {
+                continue;
+            };
+            let _ = writeln!(out, "# HELP {name} {help}");
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            let _ = writeln!(out, "{name}{{serial=\"{serial}\"}} {}", value as u8);
+        }
+        out
+    }
+
+    /// Renders the report as shell `export` statements, all prefixed with `MCHP_`; booleans are 1 or 0,
+    /// unknown power fault is an empty string. RevC-only variables are omitted on older boards.
+    pub fn to_env(&self) -> String {
+        let flag = |b: bool| if b { "1" } else { "0" }.to_string();
+        let mut vars = vec![
+            ("SERIAL", self.serial.clone()),
+            ("POWER_ON", flag(self.power_on)),
+            ("FAULT", self.power_fault.map(flag).unwrap_or_default()),
+            ("REVISION", self.pcb_revision.to_string()),
+            ("RELAY_COUNT", self.relay_count.to_string()),
+        ];
+        let optional = [
+            ("SWITCH_CONNECTED", self.usb_switch_connected),
+            ("FORCING_SDP", self.forcing_sdp),
+            ("FORCING_CC_LOW", self.forcing_cc_low),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                vars.push((name, flag(value)));
+            }
+        }
+        let pins = [
+            ("P0_MODE", "P0_STATE", self.header_p0),
+            ("P1_MODE", "P1_STATE", self.header_p1),
+        ];
+        for (mode_name, state_name, pin) in pins {
+            if let Some(pin) = pin {
+                vars.push((mode_name, format!("{:?}", pin.mode).to_lowercase()));
+                vars.push((state_name, format!("{:?}", pin.state).to_lowercase()));
+            }
+        }
+        let mut out = String::new();
+        for (name, value) in vars {
+            let _ = writeln!(out, "export MCHP_{name}={}", shell_quote(&value));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> StatusReport {
+        StatusReport {
+            serial: "it's".into(),
+            power_state: PowerState::On,
+            power_on: true,
+            power_fault: None,
+            pcb_revision: PcbRevision::RevAorB,
+            revision: PcbRevision::RevAorB.to_string(),
+            relay_variant: false,
+            relay_count: 0,
+            usb_switch_connected: None,
+            forcing_sdp: None,
+            forcing_cc_low: None,
+            header_p0: None,
+            header_p1: None,
+        }
+    }
+
+    #[test]
+    fn revision_field_uses_the_stable_label() {
+        let fields = report().fields();
+        assert!(fields.contains(&("pcb_revision", "RevAorB".to_string())));
+    }
+
+    #[test]
+    fn env_output_is_quoted_and_prefixed() {
+        let report = report();
+        assert_eq!(
+            report.to_env(),
+            "export MCHP_SERIAL='it'\\''s'\n\
+             export MCHP_POWER_ON='1'\n\
+             export MCHP_FAULT=''\n\
+             export MCHP_REVISION='Rev A/B'\n\
+             export MCHP_RELAY_COUNT='0'\n"
+        );
+    }
+
+    #[test]
+    fn baseline_diff_reports_changed_fields_and_honours_ignore() {
+        let baseline = StatusReport {
+            header_p0: Some(HeaderPinStatus {
+                mode: PinMode::Input,
+                state: PinState::Low,
+            }),
+            ..report()
+        };
+        let json = serde_json::to_string(&baseline).unwrap();
+        let baseline: StatusReport = serde_json::from_str(&json).unwrap();
+        let current = StatusReport {
+            power_on: false,
+            header_p0: Some(HeaderPinStatus {
+                mode: PinMode::Input,
+                state: PinState::High,
+            }),
+            ..baseline.clone()
+        };
+        assert!(baseline.diff(&baseline, &[]).is_empty());
+        let diff = current.diff(&baseline, &[]);
+        assert_eq!(
+            diff.iter().map(|d| d.field).collect::<Vec<_>>(),
+            ["power_on", "p0.state"]
+        );
+        assert_eq!(diff[1].baseline.as_deref(), Some("Low"));
+        let diff = current.diff(&baseline, &["p0".into(), "power".into()]);
+        assert_eq!(
+            diff.iter().map(|d| d.field).collect::<Vec<_>>(),
+            ["power_on"]
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Driving a pin to a level for a fixed time and restoring it afterwards, shared by `sdp`,
+//! `force-sdp --auto-release-after`, `sdp-sequence` and `cc-pulse`.
+//!
+//! The restore level is written when the time is up, on Ctrl-C / SIGTERM (which end the wait early), when a
+//! write fails and when the caller's code panics, so no command can forget it. It can not run if the process
+//! is killed with SIGKILL or the dongle is unplugged.
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use crate::dongle_hal_revc::PinState;
+use crate::error::DongleError;
+use crate::external::CatchTermination;
+
+/// How often the wait checks for Ctrl-C / SIGTERM.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum TimedOutcome {
+    /// Full duration elapsed
+    Elapsed,
+    /// Ended early by Ctrl-C or SIGTERM
+    Interrupted,
+}
+
+/// Writes the restore level when dropped, unless it was already written.
+struct Restore<F: Fn(PinState) -> Result<(), DongleError>> {
+    set: F,
+    state: PinState,
+    armed: bool,
+}
+
+impl<F: Fn(PinState) -> Result<(), DongleError>> Restore<F> {
+    fn finish(mut self) -> Result<(), DongleError> {
+        self.armed = false;
+        (self.set)(self.state)
+    }
+}
+
+impl<F: Fn(PinState) -> Result<(), DongleError>> Drop for Restore<F> {
+    fn drop(&mut self) {
+        if self.armed
+            && let Err(e) = (self.set)(self.state)
+        {
+            log::warn!("Failed to restore pin to {:?}: {e}", self.state);
+        }
+    }
+}
+
+/// Drives a pin with `set` to `active` for `duration`, then to `restore`, see the module documentation.
+pub fn drive_timed(
+    set: impl Fn(PinState) -> Result<(), DongleError>,
+    active: PinState,
+    duration: Duration,
+    restore: PinState,
+) -> Result<TimedOutcome, DongleError> {
+    drive_timed_with_progress(set, active, duration, restore, |_| {})
+}
+
+/// [drive_timed], calling `progress` with the remaining whole seconds at the start of every second.
+pub fn drive_timed_with_progress(
+    set: impl Fn(PinState) -> Result<(), DongleError>,
+    active: PinState,
+    duration: Duration,
+    restore: PinState,
+    progress: impl FnMut(u64),
+) -> Result<TimedOutcome, DongleError> {
+    let termination = CatchTermination::new();
+    let guard = Restore {
+        set,
+        state: restore,
+        armed: true,
+    };
+    (guard.set)(active)?;
+    let outcome = wait(duration, &termination, progress);
+    guard.finish()?;
+    Ok(outcome)
+}
+
+/// Drives a pin with `set` through `phases` of level and duration in order, then to `restore`. Ctrl-C / SIGTERM
+/// end the whole sequence, the restore level is written in the same cases as for [drive_timed].
+pub fn drive_sequence(
+    set: impl Fn(PinState) -> Result<(), DongleError>,
+    phases: &[(PinState, Duration)],
+    restore: PinState,
+) -> Result<TimedOutcome, DongleError> {
+    let termination = CatchTermination::new();
+    let guard = Restore {
+        set,
+        state: restore,
+        armed: true,
+    };
+    let mut outcome = TimedOutcome::Elapsed;
+    for (level, duration) in phases {
+        (guard.set)(*level)?;
+        outcome = wait(*duration, &termination, |_| {});
+        if outcome == TimedOutcome::Interrupted {
+            break;
+        }
+    }
+    guard.finish()?;
+    Ok(outcome)
+}
+
+fn wait(
+    duration: Duration,
+    termination: &CatchTermination,
+    mut progress: impl FnMut(u64),
+) -> TimedOutcome {
+    let deadline = Instant::now() + duration;
+    let mut reported = None;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return TimedOutcome::Elapsed;
+        }
+        if termination.caught() {
+            return TimedOutcome::Interrupted;
+        }
+        let remaining = deadline - now;
+        let secs = remaining.as_secs_f64().ceil() as u64;
+        if reported != Some(secs) {
+            reported = Some(secs);
+            progress(secs);
+        }
+        sleep(remaining.min(POLL_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+
+    #[test]
+    fn restore_runs_when_the_wait_or_the_setter_panics() {
+        let levels = RefCell::new(Vec::new());
+        let set = |state| {
+            levels.borrow_mut().push(state);
+            Ok(())
+        };
+        let outcome = drive_timed(
+            set,
+            PinState::High,
+            Duration::from_millis(10),
+            PinState::Low,
+        )
+        .unwrap();
+        assert_eq!(outcome, TimedOutcome::Elapsed);
+        assert_eq!(*levels.borrow(), vec![PinState::High, PinState::Low]);
+
+        levels.borrow_mut().clear();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            drive_timed_with_progress(
+                set,
+                PinState::High,
+                Duration::from_secs(5),
+                PinState::Low,
+                |_| panic!("progress failed"),
+            )
+        }));
+        assert!(result.is_err());
+        assert_eq!(*levels.borrow(), vec![PinState::High, PinState::Low]);
+
+        levels.borrow_mut().clear();
+        let panicking_set = |state| {
+            levels.borrow_mut().push(state);
+            assert_ne!(state, PinState::High, "write failed");
+            Ok(())
+        };
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            drive_timed(
+                panicking_set,
+                PinState::High,
+                Duration::from_secs(5),
+                PinState::Low,
+            )
+        }));
+        assert!(result.is_err());
+        assert_eq!(*levels.borrow(), vec![PinState::High, PinState::Low]);
+    }
+}
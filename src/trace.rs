@@ -0,0 +1,266 @@
+//! Recording and replaying register transactions, to make bug reports reproducible without the hardware.
+//!
+//! `--record trace.jsonl` logs every register access a command makes, `--replay trace.jsonl` re-runs the same
+//! command against a [MockBus] that returns the recorded reads and then checks that the same writes were made.
+//!
+//! Trace files are JSON lines. The first line is a header with the dongle the trace was recorded on, every
+//! following line is one successful register access:
+//! ```text
+//! {"dongle":{"bridge":{"bus_id":"1","port_chain":[2,1],...},"ftdi":null,"hub":null}}
+//! {"t_us":0,"op":"read","addr":12296,"value":1}
+//! {"t_us":1843,"op":"write","addr":12296,"value":129}
+//! ```
+//! `t_us` is the time since recording started in microseconds, `addr` is the bridge register address and
+//! `value` the byte read or written, both as decimal numbers. Failed accesses are not recorded.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::discovery::DongleInfo;
+use crate::error::DongleError;
+use crate::policy::Policy;
+use crate::usb4604_ral::{MockBus, RegisterBus};
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Microseconds since recording started
+    pub t_us: u64,
+    pub op: Op,
+    pub addr: u16,
+    pub value: u8,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TraceHeader {
+    dongle: DongleInfo,
+}
+
+#[derive(Debug)]
+pub enum TraceError {
+    Io(std::io::Error),
+    Parse {
+        line: usize,
+        message: String,
+    },
+    MissingHeader,
+    /// Replayed command made a different write than the recorded one, `None` if one of the sequences ended early
+    WriteMismatch {
+        index: usize,
+        expected: Option<(u16, u8)>,
+        actual: Option<(u16, u8)>,
+    },
+}
+
+fn describe_write(write: Option<(u16, u8)>) -> String {
+    match write {
+        Some((addr, value)) => format!("0x{value:02x} to 0x{addr:04x}"),
+        None => "nothing".into(),
+    }
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Io(e) => write!(f, "Trace file error: {e}"),
+            TraceError::Parse { line, message } => {
+                write!(f, "Failed to parse trace file, line {line}: {message}")
+            }
+            TraceError::MissingHeader => write!(f, "Trace file is empty"),
+            TraceError::WriteMismatch {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Write #{} differs from the trace: expected {}, got {}",
+                index + 1,
+                describe_write(*expected),
+                describe_write(*actual)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+/// Forwards all accesses to another bus, logging successful ones to a trace file.
+pub struct RecordingBus<'a> {
+    inner: &'a dyn RegisterBus,
+    out: RefCell<BufWriter<File>>,
+    start: Instant,
+}
+
+impl<'a> RecordingBus<'a> {
+    /// Creates (or truncates) the trace file at `path` and writes the header for `dongle`.
+    pub fn create(
+        path: &Path,
+        inner: &'a dyn RegisterBus,
+        dongle: &DongleInfo,
+    ) -> Result<Self, TraceError> {
+        let mut out = BufWriter::new(File::create(path).map_err(TraceError::Io)?);
+        let header = TraceHeader {
+            dongle: dongle.clone(),
+        };
+        writeln!(out, "{}", serde_json::to_string(&header).unwrap()).map_err(TraceError::Io)?;
+        Ok(Self {
+            inner,
+            out: RefCell::new(out),
+            start: Instant::now(),
+        })
+    }
+
+    /// Flushes the trace file, has to be called before exiting the process as that skips destructors.
+    pub fn finish(self) -> Result<(), TraceError> {
+        self.out.into_inner().flush().map_err(TraceError::Io)
+    }
+
+    fn log(&self, op: Op, addr: u16, value: u8) {
+        let entry = TraceEntry {
+            t_us: self.start.elapsed().as_micros() as u64,
+            op,
+            addr,
+            value,
+        };
+        let line = serde_json::to_string(&entry).unwrap();
+        if let Err(e) = writeln!(self.out.borrow_mut(), "{line}") {
+            log::warn!("Failed to write trace entry: {e}");
+        }
+    }
+}
+
+impl RegisterBus for RecordingBus<'_> {
+    fn read_byte(&self, addr: u16) -> Result<u8, DongleError> {
+        let value = self.inner.read_byte(addr)?;
+        self.log(Op::Read, addr, value);
+        Ok(value)
+    }
+
+    fn write_byte(&self, addr: u16, value: u8) -> Result<(), DongleError> {
+        self.inner.write_byte(addr, value)?;
+        self.log(Op::Write, addr, value);
+        Ok(())
+    }
+
+    fn policy(&self) -> Policy {
+        self.inner.policy()
+    }
+}
+
+/// A parsed trace file.
+#[derive(Clone, Debug)]
+pub struct Trace {
+    pub dongle: DongleInfo,
+    pub entries: Vec<TraceEntry>,
+}
+
+impl Trace {
+    pub fn load(path: &Path) -> Result<Self, TraceError> {
+        Self::parse(&std::fs::read_to_string(path).map_err(TraceError::Io)?)
+    }
+
+    pub fn parse(s: &str) -> Result<Self, TraceError> {
+        let mut lines = s
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let parse_error = |line: usize, e: serde_json::Error| TraceError::Parse {
+            line: line + 1,
+            message: e.to_string(),
+        };
+        let (n, header) = lines.next().ok_or(TraceError::MissingHeader)?;
+        let header: TraceHeader = serde_json::from_str(header).map_err(|e| parse_error(n, e))?;
+        let entries = lines
+            .map(|(n, line)| serde_json::from_str(line).map_err(|e| parse_error(n, e)))
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            dongle: header.dongle,
+            entries,
+        })
+    }
+
+    /// Bus returning the recorded reads in order, per address; reads past the recorded ones return the last
+    /// written value, see [MockBus::script_reads].
+    pub fn replay_bus(&self) -> MockBus {
+        let bus = MockBus::new();
+        for entry in self.entries.iter().filter(|e| e.op == Op::Read) {
+            bus.script_reads(entry.addr, &[entry.value]);
+        }
+        bus
+    }
+
+    /// Recorded writes as `(address, value)` pairs, comparable to [MockBus::writes].
+    pub fn writes(&self) -> Vec<(u16, u8)> {
+        self.entries
+            .iter()
+            .filter(|e| e.op == Op::Write)
+            .map(|e| (e.addr, e.value))
+            .collect()
+    }
+
+    /// Checks that `actual` writes are exactly the recorded ones, in the same order.
+    pub fn verify_writes(&self, actual: &[(u16, u8)]) -> Result<(), TraceError> {
+        let expected = self.writes();
+        let len = expected.len().max(actual.len());
+        match (0..len).find(|&i| expected.get(i) != actual.get(i)) {
+            Some(index) => Err(TraceError::WriteMismatch {
+                index,
+                expected: expected.get(index).copied(),
+                actual: actual.get(index).copied(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::UsbDevice;
+    use crate::usb4604_ral::{Gpio8_10Output, SmscReg, modify_reg};
+
+    #[test]
+    fn recorded_trace_replays_with_the_same_writes() {
+        let path = std::env::temp_dir().join(format!("mchp_trace_{}.jsonl", std::process::id()));
+        let dongle = DongleInfo {
+            bridge: UsbDevice::default(),
+            ftdi: None,
+            hub: None,
+        };
+        let hardware = MockBus::new();
+        hardware.set(Gpio8_10Output::ADDR, 0b100);
+        let recorder = RecordingBus::create(&path, &hardware, &dongle).unwrap();
+        modify_reg::<Gpio8_10Output, _>(&recorder, |r| r.set_gpio8_out(true)).unwrap();
+        recorder.finish().unwrap();
+
+        let trace = Trace::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(trace.writes(), hardware.writes());
+
+        let replay = trace.replay_bus();
+        modify_reg::<Gpio8_10Output, _>(&replay, |r| r.set_gpio8_out(true)).unwrap();
+        trace.verify_writes(&replay.writes()).unwrap();
+
+        let err = trace.verify_writes(&[]).unwrap_err();
+        assert!(matches!(
+            err,
+            TraceError::WriteMismatch {
+                index: 0,
+                actual: None,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,85 @@
+//! Per-dongle record of the last power transition made through this tool, for power-on hour tracking.
+//!
+//! This is an approximation: only power changes made by CLI commands (on, off, power-cycle, full-attach/detach,
+//! lockout, pin, apply) are recorded. Unplugging the dongle, changes made over `serve` or by other tools are
+//! not seen, and the first command touching power after installing starts the count. The record is kept in
+//! `power.toml` in the config directory.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{ConfigError, config_dir};
+use crate::discovery::DongleInfo;
+
+#[derive(Copy, Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Transition {
+    pub on: bool,
+    /// Seconds since the Unix epoch
+    pub at: u64,
+}
+
+impl Transition {
+    pub fn time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.at)
+    }
+
+    /// Time since the transition, zero if the clock went backwards.
+    pub fn elapsed(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.time())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+struct PowerLog {
+    /// Keyed by [DongleInfo::file_id]
+    #[serde(default)]
+    last_transition: BTreeMap<String, Transition>,
+}
+
+fn power_log_path() -> Result<PathBuf, ConfigError> {
+    Ok(config_dir()
+        .ok_or(ConfigError::NoConfigDir)?
+        .join("power.toml"))
+}
+
+fn load() -> Result<PowerLog, ConfigError> {
+    match std::fs::read_to_string(power_log_path()?) {
+        Ok(contents) => toml::from_str(&contents).map_err(|e| ConfigError::Parse(e.to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(PowerLog::default()),
+        Err(e) => Err(ConfigError::Io(e)),
+    }
+}
+
+/// Records that power of `info` is `on` now, the previous timestamp is kept if the state did not change.
+pub fn record(info: &DongleInfo, on: bool) -> Result<(), ConfigError> {
+    let mut log = load()?;
+    if log
+        .last_transition
+        .get(&info.file_id())
+        .is_some_and(|t| t.on == on)
+    {
+        return Ok(());
+    }
+    let at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    log.last_transition
+        .insert(info.file_id(), Transition { on, at });
+    let path = power_log_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(ConfigError::Io)?;
+    }
+    let contents = toml::to_string(&log).map_err(|e| ConfigError::Parse(e.to_string()))?;
+    std::fs::write(path, contents).map_err(ConfigError::Io)
+}
+
+/// Last recorded transition of `info`, `None` if none was recorded yet.
+pub fn last_transition(info: &DongleInfo) -> Result<Option<Transition>, ConfigError> {
+    Ok(load()?.last_transition.get(&info.file_id()).copied())
+}
@@ -1,13 +1,19 @@
 //! [GPIO Register docs: AN1940](https://ww1.microchip.com/downloads/aemDocuments/documents/OTH/ApplicationNotes/ApplicationNotes/00001940C.pdf)
 //! [Register docs](https://ww1.microchip.com/downloads/aemDocuments/documents/OTH/ApplicationNotes/ApplicationNotes/00001801C.pdf)
 
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, VecDeque};
 use std::time::Duration;
 
 use bitfield_struct::bitfield;
 use nusb::{
     Interface, MaybeFuture,
-    transfer::{ControlIn, ControlOut, ControlType, Recipient},
+    transfer::{ControlIn, ControlOut, ControlType, Recipient, TransferError},
 };
+use serde::{Deserialize, Serialize};
+
+use crate::error::DongleError;
+use crate::policy::Policy;
 
 pub trait SmscReg {
     const ADDR: u16;
@@ -15,51 +21,328 @@ pub trait SmscReg {
     fn value(&self) -> u8;
 }
 
+/// Vendor request writing one register byte: wValue is the register address, the data stage the byte.
 const CMD_REG_WRITE: u8 = 3;
+/// Vendor request reading one register byte: wValue is the register address, 1 byte is returned.
 const CMD_REG_READ: u8 = 4;
 
-pub fn read_reg<R: SmscReg>(interface: &Interface) -> R {
+/// Vendor control requests used to access bridge registers, for firmware variants using different codes.
+///
+/// Requests are always vendor requests to the interface recipient, defaults match the stock bridge firmware.
+/// Can be set in the `[control_protocol]` table of the config file (see [crate::config]) or a board profile
+/// (see [crate::board]), [BridgeBus] sends them.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlProtocol {
+    /// bRequest of a register read, default [CMD_REG_READ]
+    pub request_read: u8,
+    /// bRequest of a register write, default [CMD_REG_WRITE]
+    pub request_write: u8,
+    /// wIndex of both requests, default 0
+    pub index: u16,
+    /// Timeout of every transfer, default 500ms
+    pub timeout_ms: u64,
+}
+
+impl ControlProtocol {
+    pub const DEFAULT: Self = Self {
+        request_read: CMD_REG_READ,
+        request_write: CMD_REG_WRITE,
+        index: 0,
+        timeout_ms: 500,
+    };
+
+    pub fn is_default(&self) -> bool {
+        *self == Self::DEFAULT
+    }
+
+    /// Control transfer reading register `addr`, 1 byte is returned in the data stage.
+    pub fn read_transfer(&self, addr: u16) -> ControlTransfer {
+        ControlTransfer {
+            request_type: REQUEST_TYPE_VENDOR_INTERFACE_IN,
+            request: self.request_read,
+            value: addr,
+            index: self.index,
+            length: 1,
+            data: Vec::new(),
+        }
+    }
+
+    /// Control transfer writing `value` to register `addr`.
+    pub fn write_transfer(&self, addr: u16, value: u8) -> ControlTransfer {
+        ControlTransfer {
+            request_type: REQUEST_TYPE_VENDOR_INTERFACE_OUT,
+            request: self.request_write,
+            value: addr,
+            index: self.index,
+            length: 1,
+            data: vec![value],
+        }
+    }
+}
+
+/// bmRequestType of register reads: device to host, vendor, interface recipient.
+pub const REQUEST_TYPE_VENDOR_INTERFACE_IN: u8 = 0xC1;
+/// bmRequestType of register writes: host to device, vendor, interface recipient.
+pub const REQUEST_TYPE_VENDOR_INTERFACE_OUT: u8 = 0x41;
+
+/// Setup packet and data stage of one register access, as built by [ControlProtocol::read_transfer] and
+/// [ControlProtocol::write_transfer] and sent by [BridgeBus].
+#[derive(Clone, PartialEq, Eq, Debug, Serialize)]
+pub struct ControlTransfer {
+    /// bmRequestType
+    pub request_type: u8,
+    /// bRequest
+    pub request: u8,
+    /// wValue, the register address
+    pub value: u16,
+    /// wIndex
+    pub index: u16,
+    /// wLength
+    pub length: u16,
+    /// Data stage sent to the device, empty for reads
+    pub data: Vec<u8>,
+}
+
+impl Default for ControlProtocol {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Byte-wide register access, implemented for the bridge control interface ([BridgeBus]) and for [MockBus] in
+/// tests.
+pub trait RegisterBus {
+    fn read_byte(&self, addr: u16) -> Result<u8, DongleError>;
+    fn write_byte(&self, addr: u16, value: u8) -> Result<(), DongleError>;
+    /// How the HAL drives the dongle behind this bus, wrappers return the policy of the bus they wrap.
+    fn policy(&self) -> Policy;
+}
+
+/// The bridge control [Interface], accessed with the requests of a [ControlProtocol].
+///
+/// Drives the dongle with the default [Policy], wrap it in [crate::policy::PolicyBus] for another one.
+pub struct BridgeBus {
+    interface: Interface,
+    protocol: ControlProtocol,
+}
+
+impl BridgeBus {
+    pub fn new(interface: Interface, protocol: ControlProtocol) -> Self {
+        Self {
+            interface,
+            protocol,
+        }
+    }
+}
+
+/// Runs a register access again if the bridge STALLed it, which it occasionally does for the first transfer
+/// after some state changes (e.g. right after full-attach); a second STALL is [DongleError::Stalled].
+///
+/// A STALL of the control endpoint is cleared by the next SETUP packet, so the retry itself clears it,
+/// there is no halt to clear with CLEAR_FEATURE as for bulk endpoints.
+pub fn retry_on_stall<T>(
+    addr: u16,
+    mut access: impl FnMut() -> Result<T, DongleError>,
+) -> Result<T, DongleError> {
+    let is_stall = |result: &Result<T, DongleError>| {
+        matches!(
+            result,
+            Err(DongleError::Transfer {
+                source: TransferError::Stall,
+                ..
+            })
+        )
+    };
+    let result = access();
+    if !is_stall(&result) {
+        return result;
+    }
+    log::debug!("Control transfer to 0x{addr:04X} STALLed, retrying");
+    let result = access();
+    if is_stall(&result) {
+        return Err(DongleError::Stalled { addr });
+    }
+    result
+}
+
+impl RegisterBus for BridgeBus {
+    fn read_byte(&self, addr: u16) -> Result<u8, DongleError> {
+        retry_on_stall(addr, || control_read(&self.interface, &self.protocol, addr))
+    }
+
+    fn write_byte(&self, addr: u16, value: u8) -> Result<(), DongleError> {
+        retry_on_stall(addr, || {
+            control_write(&self.interface, &self.protocol, addr, value)
+        })
+    }
+
+    fn policy(&self) -> Policy {
+        Policy::default()
+    }
+}
+
+fn control_read(
+    interface: &Interface,
+    protocol: &ControlProtocol,
+    addr: u16,
+) -> Result<u8, DongleError> {
+    let transfer = protocol.read_transfer(addr);
     let read = interface
         .control_in(
             ControlIn {
                 control_type: ControlType::Vendor,
                 recipient: Recipient::Interface,
-                request: CMD_REG_READ,
-                value: R::ADDR,
-                index: 0,
-                length: 1,
+                request: transfer.request,
+                value: transfer.value,
+                index: transfer.index,
+                length: transfer.length,
             },
-            Duration::from_millis(500),
+            Duration::from_millis(protocol.timeout_ms),
         )
         .wait()
-        .unwrap();
-    R::from_value(read[0])
+        .map_err(|source| DongleError::Transfer { addr, source })?;
+    read.first()
+        .copied()
+        .ok_or(DongleError::EmptyResponse { addr })
 }
 
-pub fn write_reg<R: SmscReg>(interface: &Interface, value: R) {
+fn control_write(
+    interface: &Interface,
+    protocol: &ControlProtocol,
+    addr: u16,
+    value: u8,
+) -> Result<(), DongleError> {
+    let transfer = protocol.write_transfer(addr, value);
     interface
         .control_out(
             ControlOut {
                 control_type: ControlType::Vendor,
                 recipient: Recipient::Interface,
-                request: CMD_REG_WRITE,
-                value: R::ADDR,
-                index: 0,
-                data: &[value.value()],
+                request: transfer.request,
+                value: transfer.value,
+                index: transfer.index,
+                data: &transfer.data,
             },
-            Duration::from_millis(500),
+            Duration::from_millis(protocol.timeout_ms),
         )
         .wait()
-        .unwrap();
+        .map_err(|source| DongleError::Transfer { addr, source })
+}
+
+/// In-memory register file, registers that were never written read as 0.
+///
+/// All writes are recorded in order, so tests can assert both the final register values and the access sequence.
+#[derive(Default)]
+pub struct MockBus {
+    registers: RefCell<BTreeMap<u16, u8>>,
+    writes: RefCell<Vec<(u16, u8)>>,
+    scripted_reads: RefCell<BTreeMap<u16, VecDeque<u8>>>,
+    stalls: RefCell<BTreeMap<u16, u32>>,
+    policy: Cell<Policy>,
 }
 
-pub fn modify_reg<R: SmscReg, F: FnMut(&mut R)>(interface: &Interface, mut f: F) {
-    let mut value: R = read_reg(interface);
+impl MockBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Presets a register value without recording it as a write, e.g. to emulate input pin levels.
+    pub fn set(&self, addr: u16, value: u8) {
+        self.registers.borrow_mut().insert(addr, value);
+    }
+
+    pub fn get(&self, addr: u16) -> u8 {
+        self.registers.borrow().get(&addr).copied().unwrap_or(0)
+    }
+
+    /// Typed variant of [MockBus::get].
+    pub fn reg<R: SmscReg>(&self) -> R {
+        R::from_value(self.get(R::ADDR))
+    }
+
+    /// Queues values returned by the next reads of `addr`, one per read, before falling back to the register
+    /// value, e.g. to emulate a glitching input.
+    pub fn script_reads(&self, addr: u16, values: &[u8]) {
+        self.scripted_reads
+            .borrow_mut()
+            .entry(addr)
+            .or_default()
+            .extend(values);
+    }
+
+    /// Makes the next `count` accesses (reads or writes) of `addr` fail with a STALL, like the bridge does.
+    pub fn script_stalls(&self, addr: u16, count: u32) {
+        *self.stalls.borrow_mut().entry(addr).or_default() += count;
+    }
+
+    fn stall(&self, addr: u16) -> Result<(), DongleError> {
+        match self.stalls.borrow_mut().get_mut(&addr) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                Err(DongleError::Transfer {
+                    addr,
+                    source: TransferError::Stall,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Policy returned by [RegisterBus::policy], the default one until set, e.g. to test a board with
+    /// flipped polarity.
+    pub fn set_policy(&self, policy: Policy) {
+        self.policy.set(policy);
+    }
+
+    /// All writes performed so far, as `(address, value)` pairs.
+    pub fn writes(&self) -> Vec<(u16, u8)> {
+        self.writes.borrow().clone()
+    }
+}
+
+impl RegisterBus for MockBus {
+    fn read_byte(&self, addr: u16) -> Result<u8, DongleError> {
+        self.stall(addr)?;
+        let scripted = self
+            .scripted_reads
+            .borrow_mut()
+            .get_mut(&addr)
+            .and_then(|q| q.pop_front());
+        Ok(scripted.unwrap_or_else(|| self.get(addr)))
+    }
+
+    fn write_byte(&self, addr: u16, value: u8) -> Result<(), DongleError> {
+        self.stall(addr)?;
+        self.writes.borrow_mut().push((addr, value));
+        self.set(addr, value);
+        Ok(())
+    }
+
+    fn policy(&self) -> Policy {
+        self.policy.get()
+    }
+}
+
+pub fn read_reg<R: SmscReg>(bus: &dyn RegisterBus) -> Result<R, DongleError> {
+    Ok(R::from_value(bus.read_byte(R::ADDR)?))
+}
+
+pub fn write_reg<R: SmscReg>(bus: &dyn RegisterBus, value: R) -> Result<(), DongleError> {
+    bus.write_byte(R::ADDR, value.value())
+}
+
+pub fn modify_reg<R: SmscReg, F: FnMut(&mut R)>(
+    bus: &dyn RegisterBus,
+    mut f: F,
+) -> Result<(), DongleError> {
+    let mut value: R = read_reg(bus)?;
     let old_value = value.value();
     f(&mut value);
     if old_value != value.value() {
-        write_reg(interface, value);
+        write_reg(bus, value)?;
     }
+    Ok(())
 }
 
 macro_rules! impl_smsc_reg {
@@ -103,6 +386,99 @@ impl_smsc_reg!(Gpio8_10PullUp, 0x083E);
 impl_smsc_reg!(Gpio17_20PullUp, 0x083D);
 impl_smsc_reg!(Gpio41_45PullUp, 0x093E);
 
+/// All registers with a typed definition, as `(name, address)`.
+pub const REGISTERS: &[(&str, u16)] = &[
+    ("Gpio0_7PullDown", Gpio0_7PullDown::ADDR),
+    ("Gpio8_10PullDown", Gpio8_10PullDown::ADDR),
+    ("Gpio17_20PullDown", Gpio17_20PullDown::ADDR),
+    ("Gpio41_45PullDown", Gpio41_45PullDown::ADDR),
+    ("Gpio0_7Dir", Gpio0_7Dir::ADDR),
+    ("Gpio8_10Dir", Gpio8_10Dir::ADDR),
+    ("Gpio17_20Dir", Gpio17_20Dir::ADDR),
+    ("Gpio41_45Dir", Gpio41_45Dir::ADDR),
+    ("Gpio0_7Output", Gpio0_7Output::ADDR),
+    ("Gpio8_10Output", Gpio8_10Output::ADDR),
+    ("Gpio17_20Output", Gpio17_20Output::ADDR),
+    ("Gpio41_45Output", Gpio41_45Output::ADDR),
+    ("Gpio0_7Input", Gpio0_7Input::ADDR),
+    ("Gpio8_10Input", Gpio8_10Input::ADDR),
+    ("Gpio17_20Input", Gpio17_20Input::ADDR),
+    ("Gpio41_45Input", Gpio41_45Input::ADDR),
+    ("Gpio0_7PullUp", Gpio0_7PullUp::ADDR),
+    ("Gpio8_10PullUp", Gpio8_10PullUp::ADDR),
+    ("Gpio17_20PullUp", Gpio17_20PullUp::ADDR),
+    ("Gpio41_45PullUp", Gpio41_45PullUp::ADDR),
+    ("Port3PowerSelect", Port3PowerSelect::ADDR),
+    ("HubConfigurationDB0", HubConfigurationDB0::ADDR),
+];
+
+#[derive(Clone, PartialEq, Debug, Serialize)]
+pub struct RegisterValue {
+    pub name: &'static str,
+    pub addr: u16,
+    pub value: u8,
+}
+
+/// Reads every register in [REGISTERS].
+pub fn dump_registers(bus: &dyn RegisterBus) -> Result<Vec<RegisterValue>, DongleError> {
+    REGISTERS
+        .iter()
+        .map(|&(name, addr)| {
+            Ok(RegisterValue {
+                name,
+                addr,
+                value: bus.read_byte(addr)?,
+            })
+        })
+        .collect()
+}
+
+/// Chip reset values of the direction and output registers of the GPIO banks the crate uses (PIO0-10 and
+/// PIO17-20): every pin an input, every latch low. Directions come first, so no pin briefly drives a
+/// cleared latch on its way to input.
+pub const GPIO_RESET_VALUES: &[(u16, u8)] = &[
+    (Gpio0_7Dir::ADDR, 0x00),
+    (Gpio8_10Dir::ADDR, 0x00),
+    (Gpio17_20Dir::ADDR, 0x00),
+    (Gpio0_7Output::ADDR, 0x00),
+    (Gpio8_10Output::ADDR, 0x00),
+    (Gpio17_20Output::ADDR, 0x00),
+];
+
+/// Writes [GPIO_RESET_VALUES], also to registers already holding them, un-configuring every pin.
+///
+/// Released pins follow the board: PWR_EN_N its pull (device power on with the reference schematic), the USB
+/// switch, SDP and CC lines their defaults.
+pub fn reset_gpio_registers(bus: &dyn RegisterBus) -> Result<(), DongleError> {
+    for &(addr, value) in GPIO_RESET_VALUES {
+        bus.write_byte(addr, value)?;
+    }
+    Ok(())
+}
+
+/// Base of the hub register space as documented in the USB4604 datasheet and used by Microchip's
+/// configuration tool. The 16-bit addresses used by the bridge vendor requests (and [SmscReg::ADDR])
+/// are offsets into it, e.g. `Gpio0_7Dir` at 0x0833 is hub register 0xBF800833.
+pub const HUB_REGISTER_BASE: u32 = 0xBF80_0000;
+
+impl RegisterValue {
+    /// Full hub register address, see [HUB_REGISTER_BASE].
+    pub fn hub_address(&self) -> u32 {
+        HUB_REGISTER_BASE + u32::from(self.addr)
+    }
+}
+
+/// Formats register values as `0xBF800833=0x0B` lines, sorted by address, to be diffed against
+/// register listings from Microchip's configuration tool.
+pub fn format_mchp(values: &[RegisterValue]) -> String {
+    let mut values = values.to_vec();
+    values.sort_by_key(|v| v.addr);
+    values
+        .iter()
+        .map(|v| format!("0x{:08X}=0x{:02X}\n", v.hub_address(), v.value))
+        .collect()
+}
+
 #[bitfield(u8, order = Msb)]
 pub struct Gpio0_7Dir {
     #[bits(1)]
@@ -476,6 +852,10 @@ pub struct Port3PowerSelect {
 }
 impl_smsc_reg!(Port3PowerSelect, 0x3C08);
 
+/// Port power select registers (PORT_SEL1 to PORT_SEL4) of downstream ports 1 to 4, all laid out as
+/// [Port3PowerSelect].
+pub const PORT_POWER_SELECT_ADDRS: [u16; 4] = [0x3C00, 0x3C04, Port3PowerSelect::ADDR, 0x3C0C];
+
 #[bitfield(u8, order = Msb)]
 pub struct HubConfigurationDB0 {
     #[bits(1, access = RO)]
@@ -494,3 +874,68 @@ pub struct HubConfigurationDB0 {
     pub port_pwr: bool,
 }
 impl_smsc_reg!(HubConfigurationDB0, 0x3006);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gpio_reset_releases_pins_before_clearing_latches() {
+        let bus = MockBus::new();
+        for &(addr, _) in GPIO_RESET_VALUES {
+            bus.set(addr, 0xff);
+        }
+        reset_gpio_registers(&bus).unwrap();
+        let writes = bus.writes();
+        assert_eq!(writes, GPIO_RESET_VALUES);
+        assert_eq!(writes[0].0, Gpio0_7Dir::ADDR);
+        assert!(
+            GPIO_RESET_VALUES
+                .iter()
+                .all(|&(addr, _)| bus.get(addr) == 0)
+        );
+    }
+
+    #[test]
+    fn single_stall_is_retried_and_persistent_stall_is_classified() {
+        let bus = MockBus::new();
+        bus.set(Gpio0_7Output::ADDR, 0x5a);
+        bus.script_stalls(Gpio0_7Output::ADDR, 1);
+        let mut attempts = 0;
+        let value = retry_on_stall(Gpio0_7Output::ADDR, || {
+            attempts += 1;
+            bus.read_byte(Gpio0_7Output::ADDR)
+        });
+        assert_eq!(value.unwrap(), 0x5a);
+        assert_eq!(attempts, 2);
+
+        bus.script_stalls(Gpio0_7Output::ADDR, 2);
+        let result = retry_on_stall(Gpio0_7Output::ADDR, || {
+            bus.write_byte(Gpio0_7Output::ADDR, 0)
+        });
+        assert!(matches!(
+            result,
+            Err(DongleError::Stalled {
+                addr: Gpio0_7Output::ADDR
+            })
+        ));
+        assert!(bus.writes().is_empty());
+    }
+
+    #[test]
+    fn mchp_format_uses_hub_addresses() {
+        let values = [
+            RegisterValue {
+                name: "Gpio0_7Output",
+                addr: 0x0837,
+                value: 0x08,
+            },
+            RegisterValue {
+                name: "Gpio0_7Dir",
+                addr: 0x0833,
+                value: 0x0B,
+            },
+        ];
+        assert_eq!(format_mchp(&values), "0xBF800833=0x0B\n0xBF800837=0x08\n");
+    }
+}
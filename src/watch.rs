@@ -0,0 +1,125 @@
+//! Dongle arrival and removal events, built on nusb hotplug notifications instead of polling.
+//!
+//! A dongle is three USB devices (hub, bridge, FTDI) that enumerate one after another, so arrival is
+//! reported once the bridge shows up and [SETTLE_TIME] has passed, to give the siblings time to appear.
+
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread, sleep};
+use std::time::Duration;
+
+use futures_core::Stream;
+use nusb::hotplug::HotplugEvent;
+use nusb::{DeviceId, MaybeFuture};
+use serde::Serialize;
+
+use crate::board::BoardProfile;
+use crate::discovery::{DongleInfo, UsbDevice, list_usb_devices, pair_dongles};
+
+/// How long to wait after a bridge arrived before pairing it with its FTDI and hub siblings.
+pub const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", content = "dongle", rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)] // events are rare, boxing would only complicate matching
+pub enum DongleEvent {
+    Arrived(DongleInfo),
+    /// [DongleInfo::display_serial] of the removed dongle, as it was shown on arrival
+    Removed(String),
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Blocks the current thread until `stream` yields the next item.
+fn next_blocking<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match Pin::new(&mut *stream).poll_next(&mut cx) {
+            Poll::Ready(item) => return item,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+/// Pairs the bridge at `bridge` with its siblings from a fresh device list.
+fn dongle_at(bridge: &UsbDevice, board: &BoardProfile) -> Result<Option<DongleInfo>, nusb::Error> {
+    let all_devices = list_usb_devices()?;
+    Ok(pair_dongles(&all_devices, board)
+        .into_iter()
+        .find(|d| d.bridge.bus_id == bridge.bus_id && d.bridge.port_chain == bridge.port_chain))
+}
+
+/// Removal of `dongle`, identified as on arrival.
+fn removed(dongle: &DongleInfo) -> DongleEvent {
+    DongleEvent::Removed(dongle.display_serial())
+}
+
+/// Calls `callback` for every dongle of `board` plugged in or removed, never returns unless watching fails.
+///
+/// Dongles already connected are reported as [DongleEvent::Arrived] first.
+pub fn watch_dongles(
+    board: &BoardProfile,
+    mut callback: impl FnMut(DongleEvent),
+) -> Result<(), nusb::Error> {
+    let is_bridge = |d: &nusb::DeviceInfo| board.bridge.matches(d.vendor_id(), d.product_id());
+    // Watch before listing, so nothing plugged in in between is missed
+    let mut watch = nusb::watch_devices()?;
+    let mut known = HashMap::<DeviceId, DongleInfo>::new();
+    for bridge in nusb::list_devices().wait()?.filter(is_bridge) {
+        if let Some(dongle) = dongle_at(&UsbDevice::from(&bridge), board)? {
+            known.insert(bridge.id(), dongle.clone());
+            callback(DongleEvent::Arrived(dongle));
+        }
+    }
+    while let Some(event) = next_blocking(&mut watch) {
+        match event {
+            HotplugEvent::Connected(device) if is_bridge(&device) => {
+                sleep(SETTLE_TIME);
+                if let Some(dongle) = dongle_at(&UsbDevice::from(&device), board)? {
+                    known.insert(device.id(), dongle.clone());
+                    callback(DongleEvent::Arrived(dongle));
+                }
+            }
+            HotplugEvent::Connected(_) => {}
+            HotplugEvent::Disconnected(id) => {
+                if let Some(dongle) = known.remove(&id) {
+                    callback(removed(&dongle));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removal_is_reported_with_the_serial_shown_on_arrival() {
+        let dongle = DongleInfo {
+            bridge: UsbDevice {
+                serial_number: Some("BRIDGE0".into()),
+                ..UsbDevice::default()
+            },
+            ftdi: Some(UsbDevice {
+                serial_number: Some("LABEL0".into()),
+                ..UsbDevice::default()
+            }),
+            hub: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&removed(&dongle)).unwrap(),
+            r#"{"event":"removed","dongle":"LABEL0"}"#
+        );
+    }
+}